@@ -18,6 +18,15 @@ PROVIDER_ENV_MAP = {
     "moonshot": "MOONSHOT_API_KEY",
 }
 
+PROXY_ENV_KEYS = ("HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy")
+
+# 桌面端从系统钥匙串注入的密钥/代理；重新加载配置时会清空环境变量，需保留这份快照
+_INHERITED_ENV = {
+    key: os.environ[key]
+    for key in (*PROVIDER_ENV_MAP.values(), *PROXY_ENV_KEYS)
+    if os.environ.get(key, "").strip()
+}
+
 logger = logging.getLogger(__name__)
 
 
@@ -97,6 +106,15 @@ class LLMClient:
             except Exception:
                 logger.warning("Failed to load API keys from database", exc_info=True)
 
+            # 数据库未配置的 provider 回退到桌面端注入的环境变量
+            for p, env_key in PROVIDER_ENV_MAP.items():
+                inherited = _INHERITED_ENV.get(env_key)
+                if inherited and not str(self._provider_keys.get(p, {}).get("api_key") or "").strip():
+                    self._provider_keys[p] = {"api_key": inherited}
+                    os.environ[env_key] = inherited
+                    if p == "openai":
+                        litellm.api_key = inherited
+
             # 加载自定义中转站
             self._custom_relays: list[dict] = []
             try:
@@ -111,8 +129,10 @@ class LLMClient:
     def _load_global_config(self):
         """从global_settings表加载通用配置"""
         with self._reload_lock:
-            for key in ("HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"):
+            for key in PROXY_ENV_KEYS:
                 os.environ.pop(key, None)
+                if key in _INHERITED_ENV:
+                    os.environ[key] = _INHERITED_ENV[key]
             try:
                 with get_db_with_path(self.db_path) as db:
                     rows = db.execute("SELECT key, value FROM global_settings").fetchall()
@@ -81,27 +81,36 @@ class TestKeyRequest(BaseModel):
     relay_id: Optional[str] = None
 
 
-def _load_project_runtime(project_id: str) -> tuple[str, float]:
-    default_model = "claude-sonnet-4"
-    default_temp = 0.7
+def _load_project_runtime(project_id: str) -> dict:
+    runtime = {
+        "model": "claude-sonnet-4",
+        "temperature": 0.7,
+        "top_p": 1.0,
+        "max_tokens": 4096,
+        "system_prompt_template": "",
+    }
     db_path = _get_db_path()
     if not db_path:
-        return default_model, default_temp
+        return runtime
     try:
         with get_db_with_path(db_path) as db:
-            row = db.execute(
-                "SELECT model_main, temperature FROM projects WHERE id = ?",
-                (project_id,),
-            ).fetchone()
+            # SELECT *：017 迁移之前的库没有生成参数列
+            row = db.execute("SELECT * FROM projects WHERE id = ?", (project_id,)).fetchone()
         if not row:
-            return default_model, default_temp
-        model = str((row["model_main"] or "")).strip() or default_model
-        temp_raw = row["temperature"]
-        temp = float(temp_raw) if temp_raw is not None else default_temp
-        return model, temp
+            return runtime
+        row = dict(row)
+        runtime["model"] = str((row.get("model_main") or "")).strip() or runtime["model"]
+        if row.get("temperature") is not None:
+            runtime["temperature"] = float(row["temperature"])
+        if row.get("top_p") is not None and 0 <= float(row["top_p"]) <= 1:
+            runtime["top_p"] = float(row["top_p"])
+        if row.get("max_tokens") is not None and int(row["max_tokens"]) > 0:
+            runtime["max_tokens"] = int(row["max_tokens"])
+        runtime["system_prompt_template"] = str(row.get("system_prompt_template") or "")
+        return runtime
     except Exception:
         logger.warning("Failed to load project runtime config: project_id=%s", project_id, exc_info=True)
-        return default_model, default_temp
+        return runtime
 
 
 @agent_router.post("/invoke", response_model=AgentResponse)
@@ -130,7 +139,9 @@ async def invoke_agent(req: AgentRequest):
             exc_info=True,
         )
 
-    project_model, project_temp = _load_project_runtime(req.project_id)
+    project_runtime = _load_project_runtime(req.project_id)
+    project_model = project_runtime["model"]
+    project_temp = project_runtime["temperature"]
     request_model = str(req.model or "").strip()
     request_temp = req.temperature
 
@@ -147,6 +158,9 @@ async def invoke_agent(req: AgentRequest):
         "agent_type": req.agent_type,
         "model": resolved_model,
         "temperature": float(request_temp if request_temp is not None else project_temp),
+        "top_p": project_runtime["top_p"],
+        "max_tokens": project_runtime["max_tokens"],
+        "system_prompt_template": project_runtime["system_prompt_template"],
         "user_message": req.message,
         "chapter_id": req.chapter_id,
         "context_chunks": [],
@@ -28,6 +28,9 @@ class NovelState(TypedDict):
     agent_type: str
     model: str
     temperature: float
+    top_p: float
+    max_tokens: int
+    system_prompt_template: str
     user_message: str
     chapter_id: Optional[str]
     context_chunks: list[dict]
@@ -69,6 +72,25 @@ def _load_agent_config(db_path: str, project_id: str, agent_type: str) -> dict |
     return None
 
 
+def _load_library_prompt(db_path: str, project_id: str, name: str) -> str:
+    """从 prompts 提示词库读取同名提示词，项目内覆盖优先于全局"""
+    if not db_path:
+        return ""
+    try:
+        with get_db_with_path(db_path) as db:
+            row = db.execute(
+                "SELECT content FROM prompts WHERE name = ? AND (project_id = ? OR project_id IS NULL) "
+                "ORDER BY project_id IS NULL LIMIT 1",
+                (name, project_id),
+            ).fetchone()
+        if row and str(row["content"] or "").strip():
+            return str(row["content"])
+    except Exception:
+        # 018 迁移之前没有 prompts 表
+        logger.debug("Failed to load library prompt: name=%s", name, exc_info=True)
+    return ""
+
+
 def _clip(text: str, limit: int) -> str:
     s = (text or "").strip()
     if len(s) <= limit:
@@ -728,12 +750,34 @@ async def retrieve_context(state: NovelState) -> NovelState:
     return {**state, "context_chunks": results}
 
 
+def _apply_system_prompt_template(system_prompt: str, template: str) -> str:
+    """项目级系统提示模板：含 {prompt} 时替换为 Agent 自身提示，否则追加在其后。"""
+    template = (template or "").strip()
+    if not template:
+        return system_prompt
+    if "{prompt}" in template:
+        return template.replace("{prompt}", system_prompt)
+    return f"{system_prompt}\n\n{template}"
+
+
+def _sampling_kwargs(state: NovelState) -> dict:
+    """top_p 为 1 时不下发，交给服务商默认值。"""
+    top_p = state.get("top_p")
+    if top_p is None or float(top_p) >= 1:
+        return {}
+    return {"top_p": float(top_p)}
+
+
 def _resolve_runtime(state: NovelState, agent_type: str, db_path: str) -> tuple[str, str, float, int]:
     agent_cfg = _load_agent_config(db_path, state["project_id"], agent_type)
 
-    system_prompt = PROMPT_MAP.get(agent_type, prompts.CHAPTER_WRITER)
+    system_prompt = (
+        _load_library_prompt(db_path, state["project_id"], agent_type)
+        or PROMPT_MAP.get(agent_type, prompts.CHAPTER_WRITER)
+    )
     if agent_cfg and agent_cfg.get("system_prompt"):
         system_prompt = agent_cfg["system_prompt"]
+    system_prompt = _apply_system_prompt_template(system_prompt, state.get("system_prompt_template", ""))
 
     model = state.get("model", "claude-sonnet-4")
     if agent_cfg and agent_cfg.get("model"):
@@ -743,7 +787,10 @@ def _resolve_runtime(state: NovelState, agent_type: str, db_path: str) -> tuple[
     if agent_cfg and agent_cfg.get("temperature") is not None and agent_cfg["temperature"] >= 0:
         temperature = agent_cfg["temperature"]
 
+    # Agent 单独配置优先，其次项目级 max_tokens
     raw_max_tokens = (agent_cfg or {}).get("max_tokens")
+    if raw_max_tokens is None:
+        raw_max_tokens = state.get("max_tokens")
     try:
         parsed_max_tokens = int(float(raw_max_tokens))
     except Exception:
@@ -1002,6 +1049,7 @@ async def generate_draft(state: NovelState) -> NovelState:
             ],
             temperature=temperature,
             max_tokens=max_tokens,
+            **_sampling_kwargs(state),
         )
 
         if draft_only_mode:
@@ -1104,6 +1152,7 @@ async def generate_draft(state: NovelState) -> NovelState:
         messages=messages,
         temperature=temperature,
         max_tokens=max_tokens,
+        **_sampling_kwargs(state),
     )
     return {**state, "user_message": normalized_user_message, "draft": draft, "metadata": metadata}
 
@@ -4,7 +4,9 @@ from pydantic import BaseModel
 from typing import Optional
 import hashlib
 import time
+from datetime import date
 from db import get_db
+from text_count import count_words
 from api.content import auto_extract_entity_candidates_background
 
 router = APIRouter()
@@ -12,6 +14,19 @@ router = APIRouter()
 _AUTO_ENTITY_EXTRACT_MIN_INTERVAL_SEC = 180
 _AUTO_ENTITY_EXTRACT_MIN_GROWTH_CHARS = 600
 _auto_entity_extract_state: dict[str, dict[str, object]] = {}
+# 每章保留的版本数，与 src-tauri/src/db.rs 的 MAX_REVISIONS 一致
+_MAX_REVISIONS = 50
+# 章节状态，与 src-tauri/src/chapter_status.rs 及 schema.sql 中的触发器一致
+CHAPTER_STATUSES = ("draft", "revised", "final")
+_LEGACY_CHAPTER_STATUSES = {"reviewing": "revised", "done": "final", "written": "final"}
+
+
+def normalize_chapter_status(value: object) -> str:
+    """把导入数据中的旧状态值归并为 draft / revised / final，未知值视为 draft。"""
+    status = str(value or "").strip().lower()
+    if status in CHAPTER_STATUSES:
+        return status
+    return _LEGACY_CHAPTER_STATUSES.get(status, "draft")
 
 
 class ChapterCreate(BaseModel):
@@ -26,6 +41,7 @@ class ChapterUpdate(BaseModel):
     title: Optional[str] = None
     phase: Optional[str] = None
     synopsis: Optional[str] = None
+    # 仅用于拒绝：状态流转（含 force）只能走 Rust 的 set_chapter_status 命令
     status: Optional[str] = None
     sort_order: Optional[int] = None
 
@@ -36,12 +52,21 @@ class ChapterBatchDeleteRequest(BaseModel):
 
 
 @router.get("/")
-def list_chapters(project_id: str):
+def list_chapters(project_id: str, status: Optional[str] = None):
+    """status 可选，按状态过滤（draft / revised / final）。"""
+    if status is not None and status not in CHAPTER_STATUSES:
+        raise HTTPException(400, f"无效的章节状态: {status}")
     with get_db() as db:
-        rows = db.execute(
-            "SELECT * FROM chapters WHERE project_id = ? ORDER BY sort_order",
-            (project_id,),
-        ).fetchall()
+        if status is None:
+            rows = db.execute(
+                "SELECT * FROM chapters WHERE project_id = ? ORDER BY sort_order",
+                (project_id,),
+            ).fetchall()
+        else:
+            rows = db.execute(
+                "SELECT * FROM chapters WHERE project_id = ? AND status = ? ORDER BY sort_order",
+                (project_id, status),
+            ).fetchall()
         return [dict(r) for r in rows]
 
 
@@ -78,6 +103,8 @@ def get_chapter(chapter_id: str):
 
 @router.put("/{chapter_id}")
 def update_chapter(chapter_id: str, req: ChapterUpdate):
+    if req.status is not None:
+        raise HTTPException(400, "章节状态请通过 set_chapter_status 命令修改")
     updates, values = [], []
     for field, val in req.model_dump(exclude_none=True).items():
         updates.append(f"{field} = ?")
@@ -160,6 +187,42 @@ class ParagraphSave(BaseModel):
     paragraphs: list[dict]  # [{para_index, content, scene_tag?, pov_char_id?}]
 
 
+def _record_revision(db, chapter_id: str, word_count: int):
+    """保存后记录整章文本版本（与上一版相同则跳过），超出上限时删除最旧的"""
+    rows = db.execute(
+        "SELECT COALESCE(content, '') AS content FROM chapter_paragraphs "
+        "WHERE chapter_id = ? ORDER BY para_index",
+        (chapter_id,),
+    ).fetchall()
+    content = "\n".join(str(r["content"]) for r in rows)
+    latest = db.execute(
+        "SELECT content FROM chapter_revisions WHERE chapter_id = ? ORDER BY id DESC LIMIT 1",
+        (chapter_id,),
+    ).fetchone()
+    if latest and latest["content"] == content:
+        return
+    db.execute(
+        "INSERT INTO chapter_revisions (chapter_id, content, word_count, reason) VALUES (?, ?, ?, 'save')",
+        (chapter_id, content, word_count),
+    )
+    db.execute(
+        "DELETE FROM chapter_revisions WHERE chapter_id = ?1 AND id NOT IN "
+        "(SELECT id FROM chapter_revisions WHERE chapter_id = ?1 ORDER BY id DESC LIMIT ?2)",
+        (chapter_id, _MAX_REVISIONS),
+    )
+
+
+def _record_words_written(db, project_id: str, words: int):
+    """把本次保存的字数变化计入当天（本地日期）的写作量，与 src-tauri/src/db.rs 一致。"""
+    if words == 0:
+        return
+    db.execute(
+        "INSERT INTO writing_days (project_id, day, words) VALUES (?, ?, ?) "
+        "ON CONFLICT (project_id, day) DO UPDATE SET words = words + excluded.words",
+        (project_id, date.today().isoformat(), words),
+    )
+
+
 @router.post("/paragraphs/save")
 async def save_paragraphs(req: ParagraphSave, background_tasks: BackgroundTasks):
     """批量保存章节段落 (全量替换)"""
@@ -167,7 +230,7 @@ async def save_paragraphs(req: ParagraphSave, background_tasks: BackgroundTasks)
     project_id = ""
     with get_db() as db:
         chapter_row = db.execute(
-            "SELECT project_id FROM chapters WHERE id = ?",
+            "SELECT project_id, COALESCE(word_count, 0) AS word_count FROM chapters WHERE id = ?",
             (req.chapter_id,),
         ).fetchone()
         project_id = str(chapter_row["project_id"]) if chapter_row else ""
@@ -183,12 +246,14 @@ async def save_paragraphs(req: ParagraphSave, background_tasks: BackgroundTasks)
                 (req.chapter_id, p["para_index"], content, len(content),
                  p.get("scene_tag"), p.get("pov_char_id")),
             )
-        # 更新章节字数
-        total = db.execute(
-            "SELECT COALESCE(SUM(char_count),0) as total FROM chapter_paragraphs WHERE chapter_id = ?",
-            (req.chapter_id,),
-        ).fetchone()["total"]
+        # 更新章节字数，按 text_count 的规则统计
+        total = sum(count_words(str(p.get("content", "") or "")) for p in req.paragraphs)
         db.execute("UPDATE chapters SET word_count = ? WHERE id = ?", (total, req.chapter_id))
+        # 章节内容已变，向量索引需要重建
+        db.execute("UPDATE embedding_index SET stale = 1 WHERE project_id = ?", (project_id,))
+        if chapter_row:
+            _record_revision(db, req.chapter_id, total)
+            _record_words_written(db, project_id, total - int(chapter_row["word_count"]))
 
     queued = False
     if req.auto_extract and project_id and source_parts:
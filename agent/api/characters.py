@@ -2,6 +2,7 @@
 import json
 import logging
 import re
+import sqlite3
 from fastapi import APIRouter, HTTPException
 from pydantic import BaseModel, Field
 from typing import Any, Optional
@@ -239,12 +240,15 @@ def create_character(req: CharacterCreate):
     age = _normalize_age_value(req.age)
     category = _normalize_category_value(req.category)
     with get_db() as db:
-        db.execute(
-            "INSERT INTO characters (project_id, name, category, gender, age, identity, "
-            "appearance, personality, motivation, backstory, arc, usage_notes) VALUES (?,?,?,?,?,?,?,?,?,?,?,?)",
-            (req.project_id, req.name, category, gender, age, req.identity,
-             req.appearance, req.personality, req.motivation, req.backstory, req.arc, req.usage_notes),
-        )
+        try:
+            db.execute(
+                "INSERT INTO characters (project_id, name, category, gender, age, identity, "
+                "appearance, personality, motivation, backstory, arc, usage_notes) VALUES (?,?,?,?,?,?,?,?,?,?,?,?)",
+                (req.project_id, req.name, category, gender, age, req.identity,
+                 req.appearance, req.personality, req.motivation, req.backstory, req.arc, req.usage_notes),
+            )
+        except sqlite3.IntegrityError:
+            raise HTTPException(409, f"角色「{req.name}」已存在")
         row = db.execute("SELECT * FROM characters WHERE rowid = last_insert_rowid()").fetchone()
         return _normalize_character_row(dict(row))
 
@@ -482,7 +486,10 @@ def update_character(char_id: str, req: CharacterUpdate):
         raise HTTPException(400, "无更新字段")
     values.append(char_id)
     with get_db() as db:
-        db.execute(f"UPDATE characters SET {', '.join(updates)} WHERE id = ?", values)
+        try:
+            db.execute(f"UPDATE characters SET {', '.join(updates)} WHERE id = ?", values)
+        except sqlite3.IntegrityError:
+            raise HTTPException(409, f"角色「{payload.get('name', '')}」已存在")
         row = db.execute("SELECT * FROM characters WHERE id = ?", (char_id,)).fetchone()
         if not row:
             raise HTTPException(404, "角色不存在")
@@ -500,6 +507,15 @@ def replace_character_relations(char_id: str, req: CharacterRelationsUpdate):
             raise HTTPException(404, "角色不存在")
         project_id = str(owner["project_id"])
 
+        # 桌面端设置的关系强弱（strength）在整体替换后保留
+        strengths = {
+            (str(r["character_b_id"]), str(r["relation_type"] or "")): int(r["strength"] or 0)
+            for r in db.execute(
+                "SELECT character_b_id, relation_type, strength FROM character_relations "
+                "WHERE character_a_id = ?",
+                (char_id,),
+            ).fetchall()
+        }
         db.execute("DELETE FROM character_relations WHERE character_a_id = ?", (char_id,))
 
         seen_target_ids = set()
@@ -514,14 +530,16 @@ def replace_character_relations(char_id: str, req: CharacterRelationsUpdate):
             if not target:
                 continue
             seen_target_ids.add(target_id)
+            relation_type = str(rel.relation_type or "").strip()[:60]
             db.execute(
-                "INSERT INTO character_relations (character_a_id, character_b_id, relation_type, description) "
-                "VALUES (?,?,?,?)",
+                "INSERT INTO character_relations (character_a_id, character_b_id, relation_type, description, strength) "
+                "VALUES (?,?,?,?,?)",
                 (
                     char_id,
                     target_id,
-                    str(rel.relation_type or "").strip()[:60],
+                    relation_type,
                     str(rel.description or "").strip()[:260],
+                    strengths.get((target_id, relation_type), 0),
                 ),
             )
 
@@ -2,15 +2,19 @@
 from __future__ import annotations
 
 import json
+import os
 import re
+import shutil
 from datetime import datetime
 from typing import Any, Literal, Optional
 
 from fastapi import APIRouter, File, Form, HTTPException, UploadFile
-from pydantic import BaseModel
+from pydantic import BaseModel, Field
 
-from db import get_db
+from db import get_db, get_db_path
+from text_count import count_words
 from agents import router as agent_router
+from api.chapters import normalize_chapter_status
 
 router = APIRouter()
 
@@ -28,6 +32,9 @@ class ProjectCreate(BaseModel):
     temperature: float = 0.7
     embedding_dim: int = 3072
     word_target: int = 100000
+    top_p: float = Field(default=1.0, ge=0, le=1)
+    max_tokens: int = Field(default=4096, gt=0)
+    system_prompt_template: str = ""
 
 
 class ProjectUpdate(BaseModel):
@@ -44,6 +51,9 @@ class ProjectUpdate(BaseModel):
     temperature: Optional[float] = None
     embedding_dim: Optional[int] = None
     word_target: Optional[int] = None
+    top_p: Optional[float] = Field(default=None, ge=0, le=1)
+    max_tokens: Optional[int] = Field(default=None, gt=0)
+    system_prompt_template: Optional[str] = None
 
 
 class GenerateFromChaptersRequest(BaseModel):
@@ -152,6 +162,11 @@ def _load_project_bundle(project_id: str) -> dict[str, Any]:
             (project_id,),
         ).fetchall()] if _table_exists(db, "entity_candidates") else []
 
+        story_events = [dict(r) for r in db.execute(
+            "SELECT * FROM story_events WHERE project_id = ? ORDER BY narrative_order ASC, created_at ASC",
+            (project_id,),
+        ).fetchall()] if _table_exists(db, "story_events") else []
+
         planning_state = None
         if _table_exists(db, "planning_studio_states"):
             planning_state_row = db.execute(
@@ -200,6 +215,7 @@ def _load_project_bundle(project_id: str) -> dict[str, Any]:
             "outlines": outlines,
             "worldbuilding": worldbuilding,
             "foreshadowing": foreshadowing,
+            "story_events": story_events,
             "reviews": reviews,
             "entity_candidates": entity_candidates,
             "planning_state": planning_state,
@@ -308,6 +324,7 @@ def _insert_project_from_bundle(bundle: dict[str, Any], override_name: str = "")
         "outlines": 0,
         "worldbuilding": 0,
         "foreshadowing": 0,
+        "story_events": 0,
     }
     chapter_id_map: dict[str, str] = {}
     character_id_map: dict[str, str] = {}
@@ -320,6 +337,7 @@ def _insert_project_from_bundle(bundle: dict[str, Any], override_name: str = "")
     outlines = list(bundle.get("outlines", []) or [])
     world_items = list(bundle.get("worldbuilding", []) or [])
     foreshadowing = list(bundle.get("foreshadowing", []) or [])
+    story_events = list(bundle.get("story_events", []) or [])
 
     with get_db() as db:
         project_name = str(override_name or "").strip() or str(project_data.get("name", "")).strip() or "导入项目"
@@ -355,7 +373,7 @@ def _insert_project_from_bundle(bundle: dict[str, Any], override_name: str = "")
             title = str(ch.get("title", "")).strip() or f"第{chapter_num}章"
             synopsis = str(ch.get("synopsis", "") or "").strip()
             phase = str(ch.get("phase", "") or "")
-            status = str(ch.get("status", "draft") or "draft")
+            status = normalize_chapter_status(ch.get("status"))
             sort_order = int(ch.get("sort_order", chapter_num) or chapter_num)
 
             inserted = db.execute(
@@ -399,7 +417,7 @@ def _insert_project_from_bundle(bundle: dict[str, Any], override_name: str = "")
                     )
 
             full_chapter_text = "\n\n".join(paragraph_texts).strip()
-            chapter_word_count = len(full_chapter_text)
+            chapter_word_count = count_words(full_chapter_text)
             db.execute("UPDATE chapters SET word_count = ? WHERE id = ?", (chapter_word_count, new_chapter_id))
             chapter_texts_for_memory.append((new_chapter_id, full_chapter_text))
 
@@ -482,12 +500,14 @@ def _insert_project_from_bundle(bundle: dict[str, Any], override_name: str = "")
             if not new_a or not new_b or new_a == new_b:
                 continue
             db.execute(
-                "INSERT INTO character_relations (character_a_id, character_b_id, relation_type, description) VALUES (?,?,?,?)",
+                "INSERT INTO character_relations (character_a_id, character_b_id, relation_type, description, strength) "
+                "VALUES (?,?,?,?,?)",
                 (
                     new_a,
                     new_b,
                     str(rel.get("relation_type", "") or ""),
                     str(rel.get("description", "") or ""),
+                    int(rel.get("strength", 0) or 0),
                 ),
             )
             imported_counts["relations"] += 1
@@ -513,6 +533,32 @@ def _insert_project_from_bundle(bundle: dict[str, Any], override_name: str = "")
             )
             imported_counts["foreshadowing"] += 1
 
+        # 时间线事件：章节与角色按新 id 重新关联，找不到的关联直接丢弃
+        for e_idx, ev in enumerate(story_events, start=1):
+            old_chapter = str(ev.get("chapter_id", "") or "").strip()
+            try:
+                old_characters = json.loads(ev.get("character_ids") or "[]")
+            except (TypeError, ValueError):
+                old_characters = []
+            new_characters = [
+                character_id_map[str(cid)]
+                for cid in old_characters if str(cid) in character_id_map
+            ]
+            db.execute(
+                "INSERT INTO story_events (project_id, title, description, story_date, narrative_order, "
+                "chapter_id, character_ids) VALUES (?,?,?,?,?,?,?)",
+                (
+                    project_id,
+                    str(ev.get("title", "") or f"事件{e_idx}"),
+                    str(ev.get("description", "") or ""),
+                    str(ev.get("story_date", "") or ""),
+                    e_idx,
+                    chapter_id_map.get(old_chapter) if old_chapter else None,
+                    json.dumps(new_characters, ensure_ascii=False),
+                ),
+            )
+            imported_counts["story_events"] += 1
+
     _rebuild_import_memory(project_id, chapter_texts_for_memory)
     return {
         "project_id": project_id,
@@ -572,7 +618,7 @@ def _insert_plain_text_as_project(raw_text: str, project_name: str = "") -> dict
                     "INSERT INTO chapter_paragraphs (chapter_id, para_index, content, char_count) VALUES (?,?,?,?)",
                     (chapter_id, p_idx, para, len(para)),
                 )
-            db.execute("UPDATE chapters SET word_count = ? WHERE id = ?", (len(body_text), chapter_id))
+            db.execute("UPDATE chapters SET word_count = ? WHERE id = ?", (count_words(body_text), chapter_id))
             chapter_texts_for_memory.append((chapter_id, body_text))
             imported_counts["chapters"] += 1
 
@@ -1393,8 +1439,9 @@ def list_projects():
     with get_db() as db:
         rows = db.execute(
             "SELECT id, name, genre, description, structure, custom_structure, chapter_words, priority, "
-            "status, word_target, model_main, model_secondary, temperature, created_at, updated_at "
-            "FROM projects ORDER BY updated_at DESC"
+            "status, word_target, model_main, model_secondary, temperature, "
+            "top_p, max_tokens, system_prompt_template, created_at, updated_at "
+            "FROM projects WHERE trashed_at IS NULL ORDER BY updated_at DESC"
         ).fetchall()
         projects = []
         for r in rows:
@@ -1507,7 +1554,8 @@ def create_project(req: ProjectCreate):
     with get_db() as db:
         db.execute(
             "INSERT INTO projects (name, genre, description, structure, custom_structure, chapter_words, priority, "
-            "model_main, model_secondary, temperature, embedding_dim, word_target) VALUES (?,?,?,?,?,?,?,?,?,?,?,?)",
+            "model_main, model_secondary, temperature, embedding_dim, word_target, "
+            "top_p, max_tokens, system_prompt_template) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)",
             (
                 req.name,
                 req.genre,
@@ -1521,6 +1569,9 @@ def create_project(req: ProjectCreate):
                 req.temperature,
                 req.embedding_dim,
                 req.word_target,
+                req.top_p,
+                req.max_tokens,
+                req.system_prompt_template,
             ),
         )
         row = db.execute("SELECT * FROM projects ORDER BY created_at DESC LIMIT 1").fetchone()
@@ -1566,4 +1617,31 @@ def delete_project(project_id: str):
 
     with get_db() as db:
         db.execute("DELETE FROM projects WHERE id = ?", (project_id,))
-        return {"ok": True}
+
+    _remove_cover_files(project_id)
+    _remove_attachment_files(project_id)
+    return {"ok": True}
+
+
+# 与桌面端 covers.rs 的文件命名保持一致
+_COVER_FILE_SUFFIXES = ("png", "jpg", "webp", "thumb.png")
+
+
+def _remove_cover_files(project_id: str):
+    """删除项目封面及其缩略图；文件不存在时忽略。"""
+    if not re.fullmatch(r"[A-Za-z0-9_-]+", project_id or ""):
+        return
+    covers_dir = os.path.join(os.path.dirname(get_db_path()), "covers")
+    for suffix in _COVER_FILE_SUFFIXES:
+        try:
+            os.remove(os.path.join(covers_dir, f"{project_id}.{suffix}"))
+        except OSError:
+            pass
+
+
+def _remove_attachment_files(project_id: str):
+    """删除项目附件目录（与桌面端 attachments.rs 的目录结构一致）；目录不存在时忽略。"""
+    if not re.fullmatch(r"[A-Za-z0-9_-]+", project_id or ""):
+        return
+    attachments_dir = os.path.join(os.path.dirname(get_db_path()), "attachments", project_id)
+    shutil.rmtree(attachments_dir, ignore_errors=True)
@@ -83,6 +83,16 @@ def init_db(schema_path: str | None = None):
 
     db = sqlite3.connect(db_path)
     if os.path.exists(schema_path):
+        # schema.sql 会建立角色名唯一索引，旧库需先按迁移 037 给重名角色改名
+        dedupe_path = os.path.join(
+            os.path.dirname(schema_path), "migrations", "037_characters_unique_name.sql"
+        )
+        has_characters = db.execute(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'characters'"
+        ).fetchone()
+        if has_characters and os.path.exists(dedupe_path):
+            with open(dedupe_path, "r", encoding="utf-8") as f:
+                db.executescript(f.read())
         with open(schema_path, "r", encoding="utf-8") as f:
             db.executescript(f.read())
     db.close()
@@ -1,5 +1,10 @@
 """焱书 Agent Service - FastAPI 入口"""
+import hmac
+import json
 import os
+import platform
+import signal
+import threading
 from contextlib import asynccontextmanager
 from fastapi import FastAPI, Request
 from fastapi.middleware.cors import CORSMiddleware
@@ -23,8 +28,27 @@ from api.pipeline import router as pipeline_router
 from api.knowledge import router as knowledge_router
 from api.graph import router as graph_router
 
+AGENT_VERSION = "0.1.0"
+# 对外声明的能力，Tauri 端据此判断新接口是否可用
+AGENT_FEATURES = [
+    "rag",
+    "rag_reindex",
+    "shutdown",
+    "knowledge",
+    "graph",
+    "pipeline",
+    "debate",
+    "butterfly",
+    "ner",
+    "conflict",
+]
+
 LOCAL_TOKEN_HEADER = "X-Sanhuoai-Token"
 LOCAL_TOKEN_ENV_KEY = "SANHUOAI_LOCAL_API_TOKEN"
+# 由 Tauri 启动时生成并注入的会话令牌；存在时所有接口（含 /health）均需鉴权
+AGENT_TOKEN_ENV_KEY = "SANHUOAI_AGENT_TOKEN"
+# Tauri 同时把 { port, token, pid } 写入数据目录，供重载后或外部工具重新发现
+AGENT_RUNTIME_FILE = "agent-runtime.json"
 LOCAL_TOKEN_DB_ENABLED_KEY = "local_api_auth_enabled"
 LOCAL_TOKEN_DB_TOKEN_KEY = "local_api_auth_token"
 AUTH_EXEMPT_PATHS = {"/health"}
@@ -88,7 +112,7 @@ async def lifespan(app: FastAPI):
         close_services()
 
 
-app = FastAPI(title="焱书 Agent Service", version="0.1.0", lifespan=lifespan)
+app = FastAPI(title="焱书 Agent Service", version=AGENT_VERSION, lifespan=lifespan)
 
 app.add_middleware(
     CORSMiddleware,
@@ -102,7 +126,27 @@ def _is_truthy(value: str) -> bool:
     return str(value or "").strip().lower() in {"1", "true", "yes", "on"}
 
 
+def _managed_agent_token() -> str:
+    """Tauri 下发的会话令牌：优先环境变量，其次运行时文件（仅当记录的 pid 是本进程或其父进程）"""
+    token = os.environ.get(AGENT_TOKEN_ENV_KEY, "").strip()
+    if token:
+        return token
+    try:
+        with open(os.path.join(get_data_dir(), AGENT_RUNTIME_FILE), encoding="utf-8") as f:
+            runtime = json.load(f)
+    except (OSError, ValueError):
+        return ""
+    # uvicorn --reload 时实际处理请求的是子进程
+    if runtime.get("pid") in (os.getpid(), os.getppid()):
+        return str(runtime.get("token") or "").strip()
+    return ""
+
+
 def _resolve_local_api_token() -> tuple[str, bool]:
+    managed_token = _managed_agent_token()
+    if managed_token:
+        return managed_token, True
+
     env_token = os.environ.get(LOCAL_TOKEN_ENV_KEY, "").strip()
     if env_token:
         return env_token, True
@@ -135,15 +179,22 @@ async def local_api_auth_middleware(request: Request, call_next):
     if not enabled:
         return await call_next(request)
 
-    if request.method == "OPTIONS" or request.url.path in AUTH_EXEMPT_PATHS:
+    managed = bool(_managed_agent_token())
+    if request.method == "OPTIONS" or (not managed and request.url.path in AUTH_EXEMPT_PATHS):
         return await call_next(request)
 
     provided = request.headers.get(LOCAL_TOKEN_HEADER, "").strip()
-    if not provided or provided != token:
+    if not provided:
+        # Tauri 端以 Authorization: Bearer 发送会话令牌
+        scheme, _, credentials = request.headers.get("Authorization", "").partition(" ")
+        if scheme.lower() == "bearer":
+            provided = credentials.strip()
+    if not provided or not hmac.compare_digest(provided.encode(), token.encode()):
         return JSONResponse(
             status_code=401,
             content={
                 "status": "error",
+                "agent": "sanhuoai",
                 "message": f"Missing or invalid {LOCAL_TOKEN_HEADER}",
             },
         )
@@ -175,12 +226,40 @@ app.include_router(graph_router, prefix="/api/graph", tags=["graph"])
 def health_check():
     """健康检查端点，供 Tauri 轮询判断 Agent 是否就绪"""
     if getattr(app.state, "startup_ok", False):
-        return {"status": "ok", "version": "0.1.0"}
+        return {"status": "ok", "version": AGENT_VERSION}
     return JSONResponse(
         status_code=503,
         content={
             "status": "error",
-            "version": "0.1.0",
+            "version": AGENT_VERSION,
             "message": getattr(app.state, "startup_error", "startup not ready"),
         },
     )
+
+
+@app.get("/info")
+def agent_info():
+    """版本与能力信息，供 Tauri 端做兼容性检查"""
+    models: list[str] = []
+    try:
+        with get_db_with_path(get_db_path()) as db:
+            rows = db.execute(
+                "SELECT model_id FROM model_configs WHERE visible = 1 ORDER BY provider, sort_order"
+            ).fetchall()
+        models = [row["model_id"] for row in rows]
+    except Exception:
+        pass
+    return {
+        "version": AGENT_VERSION,
+        "python_version": platform.python_version(),
+        "models": models,
+        "features": AGENT_FEATURES,
+    }
+
+
+@app.post("/shutdown")
+def shutdown():
+    """优雅退出：Windows 上 Tauri 无法发送 SIGTERM，改由此端点触发 uvicorn 正常关闭流程"""
+    # 稍作延迟，保证响应先返回
+    threading.Timer(0.2, signal.raise_signal, args=(signal.SIGINT,)).start()
+    return {"status": "shutting_down"}
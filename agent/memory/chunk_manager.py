@@ -110,6 +110,20 @@ class ChunkManager:
                 # 兜底：某些集合可能不存在该过滤字段或当前无数据
                 continue
 
+    def delete_source(self, project_id: str, source_type: str) -> None:
+        """删除某项目某来源类型的全部记忆块（SQLite + Chroma），用于重建索引。"""
+        with get_db_with_path(self.db_path) as db:
+            db.execute(
+                "DELETE FROM memory_chunks WHERE project_id = ? AND source_type = ?",
+                (project_id, source_type),
+            )
+        try:
+            self._get_collection(source_type).delete(
+                where={"$and": [{"project_id": project_id}, {"source_type": source_type}]}
+            )
+        except Exception:
+            pass
+
     def _get_collection(self, source_type: str):
         """根据来源类型返回对应的ChromaDB集合"""
         mapping = {
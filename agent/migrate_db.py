@@ -1,4 +1,5 @@
 """数据库迁移工具"""
+import json
 import sqlite3
 from pathlib import Path
 
@@ -194,6 +195,176 @@ def _apply_characters_usage_notes_migration(db: sqlite3.Connection):
     )
 
 
+def _apply_projects_generation_settings_migration(db: sqlite3.Connection):
+    """017 迁移：为 projects 表补充 top_p / max_tokens / system_prompt_template（桌面端可能已先行补列）。"""
+    cols = {
+        row[1]
+        for row in db.execute("PRAGMA table_info(projects)").fetchall()
+    }
+    if "top_p" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN top_p REAL DEFAULT 1.0")
+    if "max_tokens" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN max_tokens INTEGER DEFAULT 4096")
+    if "system_prompt_template" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN system_prompt_template TEXT DEFAULT ''")
+
+    db.execute(
+        """
+        UPDATE projects
+        SET top_p = 1.0
+        WHERE top_p IS NULL OR top_p < 0 OR top_p > 1
+        """
+    )
+    db.execute(
+        """
+        UPDATE projects
+        SET max_tokens = 4096
+        WHERE max_tokens IS NULL OR max_tokens <= 0
+        """
+    )
+    db.execute(
+        """
+        UPDATE projects
+        SET system_prompt_template = ''
+        WHERE system_prompt_template IS NULL
+        """
+    )
+
+
+# 内置提示词：名称与 agent_type 一致，工作流按名称查找
+BUILTIN_PROMPTS = (
+    ("outline_writer", "OUTLINE_WRITER_SYSTEM_PROMPT"),
+    ("character_designer", "CHARACTER_DESIGNER_SYSTEM_PROMPT"),
+    ("chapter_writer", "CHAPTER_WRITER_SYSTEM_PROMPT"),
+    ("reviewer", "REVIEWER_SYSTEM_PROMPT"),
+    ("editor", "EDITOR_SYSTEM_PROMPT"),
+)
+
+
+def _apply_prompts_library_migration(db: sqlite3.Connection):
+    """018 迁移：创建 prompts 提示词库并写入内置默认提示词。"""
+    from agents import default_prompts
+
+    migration = Path(__file__).parent.parent / "database" / "migrations" / "018_prompts_library.sql"
+    db.executescript(migration.read_text(encoding="utf-8"))
+    for name, attr in BUILTIN_PROMPTS:
+        content = getattr(default_prompts, attr)
+        db.execute(
+            "INSERT OR IGNORE INTO prompts (name, category, content, is_builtin, default_content) "
+            "VALUES (?, 'agent', ?, 1, ?)",
+            (name, content, content),
+        )
+
+
+def _apply_projects_last_opened_migration(db: sqlite3.Connection):
+    """021 迁移：projects 增加 last_opened_at 与项目级 settings(JSON)，兼容桌面端已先行加列。"""
+    cols = {
+        row[1]
+        for row in db.execute("PRAGMA table_info(projects)").fetchall()
+    }
+    if "last_opened_at" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN last_opened_at TEXT")
+    if "settings" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN settings TEXT DEFAULT '{}'")
+
+    db.execute(
+        """
+        UPDATE projects
+        SET settings = '{}'
+        WHERE settings IS NULL OR TRIM(settings) = ''
+        """
+    )
+
+
+def _apply_projects_cover_migration(db: sqlite3.Connection):
+    """022 迁移：projects 增加 cover_path（相对数据目录的封面路径），兼容桌面端已先行加列。"""
+    cols = {
+        row[1]
+        for row in db.execute("PRAGMA table_info(projects)").fetchall()
+    }
+    if "cover_path" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN cover_path TEXT")
+
+
+def _apply_characters_aliases_migration(db: sqlite3.Connection):
+    """024 迁移：characters 增加 aliases（JSON 字符串数组的别名），兼容桌面端已先行加列。"""
+    cols = {
+        row[1]
+        for row in db.execute("PRAGMA table_info(characters)").fetchall()
+    }
+    if "aliases" not in cols:
+        db.execute("ALTER TABLE characters ADD COLUMN aliases TEXT DEFAULT '[]'")
+
+
+def _apply_project_templates_migration(db: sqlite3.Connection):
+    """025 迁移：创建 project_templates 并写入内置模板，桌面端可能已先行写入。"""
+    base_dir = Path(__file__).parent.parent / "database"
+    migration = base_dir / "migrations" / "025_project_templates.sql"
+    db.executescript(migration.read_text(encoding="utf-8"))
+    templates = json.loads((base_dir / "project_templates.json").read_text(encoding="utf-8"))
+    for template in templates:
+        meta = ("id", "name", "genre", "description")
+        content = {key: value for key, value in template.items() if key not in meta}
+        db.execute(
+            "INSERT OR IGNORE INTO project_templates (id, name, genre, description, content, is_builtin) "
+            "VALUES (?, ?, ?, ?, ?, 1)",
+            (
+                template["id"],
+                template["name"],
+                template.get("genre", ""),
+                template.get("description", ""),
+                json.dumps(content, ensure_ascii=False),
+            ),
+        )
+
+
+def _apply_chapter_word_counts_migration(db: sqlite3.Connection):
+    """029 迁移：按 text_count 的规则（汉字逐字、英文按词、不计标点）重算已有章节字数。"""
+    from text_count import count_words
+
+    totals: dict[str, int] = {}
+    rows = db.execute("SELECT chapter_id, content FROM chapter_paragraphs").fetchall()
+    for chapter_id, content in rows:
+        totals[chapter_id] = totals.get(chapter_id, 0) + count_words(content or "")
+    chapter_ids = [row[0] for row in db.execute("SELECT id FROM chapters").fetchall()]
+    db.executemany(
+        "UPDATE chapters SET word_count = ? WHERE id = ?",
+        [(totals.get(chapter_id, 0), chapter_id) for chapter_id in chapter_ids],
+    )
+
+
+def _apply_character_relations_strength_migration(db: sqlite3.Connection):
+    """032 迁移：character_relations 增加 strength（0-10）并补充索引，兼容桌面端已先行加列。"""
+    cols = {
+        row[1]
+        for row in db.execute("PRAGMA table_info(character_relations)").fetchall()
+    }
+    if "strength" not in cols:
+        db.execute("ALTER TABLE character_relations ADD COLUMN strength INTEGER DEFAULT 0")
+    db.execute(
+        "CREATE INDEX IF NOT EXISTS idx_character_relations_a ON character_relations(character_a_id)"
+    )
+    db.execute(
+        "CREATE INDEX IF NOT EXISTS idx_character_relations_b ON character_relations(character_b_id)"
+    )
+
+
+def _apply_project_trash_migration(db: sqlite3.Connection):
+    """034 迁移：projects 增加 trashed_at（回收站），兼容桌面端已先行加列。"""
+    cols = {row[1] for row in db.execute("PRAGMA table_info(projects)").fetchall()}
+    if "trashed_at" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN trashed_at TEXT")
+
+
+def _apply_project_pinning_migration(db: sqlite3.Connection):
+    """036 迁移：projects 增加 pinned / manual_order（置顶与手动排序），兼容桌面端已先行加列。"""
+    cols = {row[1] for row in db.execute("PRAGMA table_info(projects)").fetchall()}
+    if "pinned" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN pinned INTEGER DEFAULT 0")
+    if "manual_order" not in cols:
+        db.execute("ALTER TABLE projects ADD COLUMN manual_order INTEGER")
+
+
 def run_migrations(db_path: str):
     """执行所有未执行的迁移"""
     # 创建迁移记录表
@@ -264,6 +435,66 @@ def run_migrations(db_path: str):
             db.commit()
             print(f"Migration completed: {version}")
             continue
+        if version == "017_projects_generation_settings":
+            _apply_projects_generation_settings_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "018_prompts_library":
+            _apply_prompts_library_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "021_projects_last_opened":
+            _apply_projects_last_opened_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "022_projects_cover":
+            _apply_projects_cover_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "024_characters_aliases":
+            _apply_characters_aliases_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "025_project_templates":
+            _apply_project_templates_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "029_chapter_word_counts":
+            _apply_chapter_word_counts_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "032_character_relations_strength":
+            _apply_character_relations_strength_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "034_project_trash":
+            _apply_project_trash_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
+        if version == "036_project_pinning":
+            _apply_project_pinning_migration(db)
+            db.execute("INSERT INTO schema_migrations (version) VALUES (?)", (version,))
+            db.commit()
+            print(f"Migration completed: {version}")
+            continue
 
         with open(migration_file, "r", encoding="utf-8") as f:
             sql = f.read()
@@ -1,6 +1,8 @@
 """RAG混合检索模块 - BM25 + 向量语义检索"""
+import hashlib
 import sqlite3
-from fastapi import APIRouter
+import threading
+from fastapi import APIRouter, HTTPException
 from pydantic import BaseModel
 from typing import Optional
 
@@ -159,3 +161,138 @@ async def hybrid_search(req: SearchRequest):
             for r in merged
         ],
     )
+
+
+# ========== 章节向量索引重建 ==========
+
+_REINDEX_CHUNK_CHARS = 1500
+# ChromaDB 集合未指定 embedding_function，使用其默认模型
+_EMBEDDING_MODEL = "all-MiniLM-L6-v2"
+_reindex_jobs: dict[str, dict] = {}
+_reindex_lock = threading.Lock()
+
+
+class ReindexRequest(BaseModel):
+    project_id: str
+
+
+def _split_for_index(text: str, limit: int = _REINDEX_CHUNK_CHARS) -> list[str]:
+    """按段落聚合成不超过 limit 字的块"""
+    chunks: list[str] = []
+    buf = ""
+    for para in text.split("\n"):
+        para = para.strip()
+        if not para:
+            continue
+        if buf and len(buf) + len(para) + 1 > limit:
+            chunks.append(buf)
+            buf = ""
+        buf = f"{buf}\n{para}" if buf else para
+        while len(buf) > limit:
+            chunks.append(buf[:limit])
+            buf = buf[limit:]
+    if buf:
+        chunks.append(buf)
+    return chunks
+
+
+def _record_embedding_index(
+    db_path: str, project_id: str, chunk_count: int, dim: int, content_hash: str
+):
+    """记录本次重建的结果，覆盖旧记录并清除过期标记"""
+    db = sqlite3.connect(db_path)
+    try:
+        db.execute(
+            "INSERT OR REPLACE INTO embedding_index "
+            "(project_id, chunk_count, dim, model, content_hash, stale, built_at) "
+            "VALUES (?, ?, ?, ?, ?, 0, datetime('now'))",
+            (project_id, chunk_count, dim, _EMBEDDING_MODEL, content_hash),
+        )
+        db.commit()
+    finally:
+        db.close()
+
+
+def _run_reindex(project_id: str, cm: ChunkManager, db_path: str):
+    job = _reindex_jobs[project_id]
+    try:
+        db = sqlite3.connect(db_path)
+        db.row_factory = sqlite3.Row
+        try:
+            chapters = db.execute(
+                "SELECT id, chapter_num, title FROM chapters WHERE project_id = ? "
+                "ORDER BY sort_order, chapter_num, id",
+                (project_id,),
+            ).fetchall()
+            dim_row = db.execute(
+                "SELECT embedding_dim FROM projects WHERE id = ?", (project_id,)
+            ).fetchone()
+            # 与 src-tauri/src/embedding_index.rs 的 ContentHash 逐字节一致，桌面端据此判断索引是否过期
+            content_hash = hashlib.sha256()
+            texts = []
+            for ch in chapters:
+                paras = db.execute(
+                    "SELECT content FROM chapter_paragraphs WHERE chapter_id = ? ORDER BY para_index",
+                    (ch["id"],),
+                ).fetchall()
+                contents = [str(p["content"] or "") for p in paras]
+                for content in contents:
+                    content_hash.update(f"{ch['id']}\x1f{content}\x1e".encode("utf-8"))
+                texts.append((ch, "\n".join(contents)))
+        finally:
+            db.close()
+
+        job["total"] = len(texts)
+        cm.delete_source(project_id, "chapter")
+        chunk_count = 0
+        for ch, text in texts:
+            for part in _split_for_index(text):
+                chunk_count += 1
+                cm.add_chunk(
+                    project_id=project_id,
+                    source_type="chapter",
+                    source_id=ch["id"],
+                    content=part,
+                    summary=part[:200],
+                    metadata={"chapter_num": ch["chapter_num"], "title": ch["title"] or ""},
+                )
+            job["done"] += 1
+        _record_embedding_index(
+            db_path,
+            project_id,
+            chunk_count,
+            int(dim_row["embedding_dim"] or 0) if dim_row else 0,
+            content_hash.hexdigest(),
+        )
+        job["status"] = "completed"
+    except Exception as e:
+        job["status"] = "failed"
+        job["error"] = str(e)
+
+
+@rag_router.post("/reindex")
+def start_reindex(req: ReindexRequest):
+    """后台重建项目章节向量索引，立即返回；进度通过 GET /rag/reindex/{project_id} 查询"""
+    import os
+    cm = _get_chunk_manager()
+    if cm is None:
+        raise HTTPException(503, "向量库不可用")
+    with _reindex_lock:
+        job = _reindex_jobs.get(req.project_id)
+        if job and job["status"] == "running":
+            return {"accepted": True, **job}
+        job = {"status": "running", "done": 0, "total": 0, "error": ""}
+        _reindex_jobs[req.project_id] = job
+    db_path = os.path.join(get_data_dir(), "sanhuoai.db")
+    threading.Thread(
+        target=_run_reindex, args=(req.project_id, cm, db_path), daemon=True
+    ).start()
+    return {"accepted": True, **job}
+
+
+@rag_router.get("/reindex/{project_id}")
+def reindex_status(project_id: str):
+    job = _reindex_jobs.get(project_id)
+    if not job:
+        raise HTTPException(404, "无索引任务")
+    return job
@@ -0,0 +1,29 @@
+"""章节字数统计，与 src-tauri/src/text_count.rs 保持一致：
+每个汉字算一个词，其余连续的字母数字算一个词，标点与空白不计。"""
+
+
+def _is_han(ch: str) -> bool:
+    code = ord(ch)
+    return (
+        0x3400 <= code <= 0x4DBF
+        or 0x4E00 <= code <= 0x9FFF
+        or 0xF900 <= code <= 0xFAFF
+        or 0x20000 <= code <= 0x2FA1F
+    )
+
+
+def count_words(text: str) -> int:
+    """返回 chapters.word_count 应保存的字数。"""
+    words = 0
+    in_word = False
+    for ch in text or "":
+        if _is_han(ch):
+            words += 1
+            in_word = False
+        elif ch.isalnum() or (in_word and ch == "'"):
+            if not in_word:
+                words += 1
+            in_word = True
+        else:
+            in_word = False
+    return words
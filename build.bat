@@ -10,6 +10,22 @@ echo.
 echo [1/3] 安装依赖...
 call npm install
 pip install -r agent\requirements.txt
+rem 嵌入式 Python 不带 ensurepip：打包固定版本的 pip 轮子供首次安装依赖时引导 pip，
+rem 打包前校验 SHA-256，升级时两行一起改
+set PIP_WHEEL=pip-23.2.1-py3-none-any.whl
+set PIP_WHEEL_SHA256=7ccf472345f20d35bdc9d1841ff5f313260c2c33fe417f48c30ac46cccabf5be
+if exist python_embed\python.exe (
+    if not exist python_embed\%PIP_WHEEL% (
+        powershell -NoProfile -Command "Invoke-WebRequest https://files.pythonhosted.org/packages/py3/p/pip/%PIP_WHEEL% -OutFile python_embed\%PIP_WHEEL%"
+    )
+    powershell -NoProfile -Command "if ((Get-FileHash python_embed\%PIP_WHEEL% -Algorithm SHA256).Hash -ne '%PIP_WHEEL_SHA256%') { exit 1 }"
+    if errorlevel 1 (
+        echo [错误] python_embed\%PIP_WHEEL% 校验失败，已删除，请重新构建
+        del python_embed\%PIP_WHEEL%
+        pause
+        exit /b 1
+    )
+)
 echo.
 
 echo [2/3] 前端构建...
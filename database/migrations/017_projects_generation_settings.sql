@@ -0,0 +1,15 @@
+ALTER TABLE projects ADD COLUMN top_p REAL DEFAULT 1.0;
+ALTER TABLE projects ADD COLUMN max_tokens INTEGER DEFAULT 4096;
+ALTER TABLE projects ADD COLUMN system_prompt_template TEXT DEFAULT '';
+
+UPDATE projects
+SET top_p = 1.0
+WHERE top_p IS NULL OR top_p < 0 OR top_p > 1;
+
+UPDATE projects
+SET max_tokens = 4096
+WHERE max_tokens IS NULL OR max_tokens <= 0;
+
+UPDATE projects
+SET system_prompt_template = ''
+WHERE system_prompt_template IS NULL;
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS prompts (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    name        TEXT NOT NULL,
+    category    TEXT DEFAULT '',
+    content     TEXT NOT NULL DEFAULT '',
+    is_builtin  INTEGER DEFAULT 0,
+    default_content TEXT,
+    project_id  TEXT REFERENCES projects(id) ON DELETE CASCADE,
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_prompts_scope_name ON prompts(COALESCE(project_id, ''), name);
+
+-- 内置提示词由 migrate_db.py 从 agents/default_prompts.py 写入
@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS model_presets (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    provider    TEXT NOT NULL,
+    model_id    TEXT NOT NULL,
+    display_name TEXT DEFAULT '',
+    context_window INTEGER DEFAULT 0,
+    default_temperature REAL DEFAULT 0.7,
+    supports_streaming INTEGER DEFAULT 1,
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now')),
+    UNIQUE(provider, model_id)
+);
+
+-- 常用模型预设；只在迁移时写入一次，用户删除后不会再出现
+INSERT OR IGNORE INTO model_presets
+    (id, provider, model_id, display_name, context_window, default_temperature, supports_streaming)
+VALUES
+    ('anthropic-claude-sonnet-4', 'anthropic', 'claude-sonnet-4', 'Claude Sonnet 4', 200000, 0.7, 1),
+    ('anthropic-claude-haiku-3', 'anthropic', 'claude-haiku-3', 'Claude Haiku 3', 200000, 0.7, 1),
+    ('openai-gpt-4o', 'openai', 'gpt-4o', 'GPT-4o', 128000, 0.7, 1),
+    ('openai-gpt-4o-mini', 'openai', 'gpt-4o-mini', 'GPT-4o mini', 128000, 0.7, 1),
+    ('google-gemini-2.0-flash', 'google', 'gemini-2.0-flash', 'Gemini 2.0 Flash', 1048576, 0.7, 1),
+    ('deepseek-deepseek-chat', 'deepseek', 'deepseek-chat', 'DeepSeek Chat', 64000, 1.0, 1),
+    ('deepseek-deepseek-reasoner', 'deepseek', 'deepseek-reasoner', 'DeepSeek Reasoner', 64000, 1.0, 1),
+    ('qwen-qwen-plus', 'qwen', 'qwen-plus', '通义千问 Plus', 131072, 0.7, 1),
+    ('qwen-qwen-turbo', 'qwen', 'qwen-turbo', '通义千问 Turbo', 131072, 0.7, 1),
+    ('zhipu-glm-4-flash', 'zhipu', 'glm-4-flash', 'GLM-4 Flash', 128000, 0.7, 1),
+    ('moonshot-moonshot-v1-8k', 'moonshot', 'moonshot-v1-8k', 'Moonshot v1 8K', 8192, 0.7, 1);
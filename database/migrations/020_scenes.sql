@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS scenes (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    chapter_id  TEXT NOT NULL REFERENCES chapters(id) ON DELETE CASCADE,
+    summary     TEXT DEFAULT '',
+    order_index INTEGER DEFAULT 0,
+    pov_character_id TEXT REFERENCES characters(id) ON DELETE SET NULL,
+    created_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_scenes_chapter ON scenes(chapter_id, order_index);
@@ -0,0 +1,6 @@
+ALTER TABLE projects ADD COLUMN last_opened_at TEXT;
+ALTER TABLE projects ADD COLUMN settings TEXT DEFAULT '{}';
+
+UPDATE projects
+SET settings = '{}'
+WHERE settings IS NULL OR TRIM(settings) = '';
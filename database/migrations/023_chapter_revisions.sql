@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS chapter_revisions (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    chapter_id  TEXT NOT NULL REFERENCES chapters(id) ON DELETE CASCADE,
+    content     TEXT NOT NULL DEFAULT '',
+    word_count  INTEGER DEFAULT 0,
+    reason      TEXT DEFAULT '',
+    created_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_chapter_revisions_chapter ON chapter_revisions(chapter_id, id);
@@ -0,0 +1 @@
+ALTER TABLE characters ADD COLUMN aliases TEXT DEFAULT '[]';
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS project_templates (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    name        TEXT NOT NULL,
+    genre       TEXT DEFAULT '',
+    description TEXT DEFAULT '',
+    content     TEXT NOT NULL DEFAULT '{}',
+    is_builtin  INTEGER DEFAULT 0,
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+
+-- 内置模板由 migrate_db.py 从 database/project_templates.json 写入
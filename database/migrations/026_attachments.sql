@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS attachments (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    filename    TEXT NOT NULL,
+    mime        TEXT DEFAULT '',
+    size        INTEGER DEFAULT 0,
+    sha256      TEXT NOT NULL,
+    created_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_attachments_project ON attachments(project_id, sha256);
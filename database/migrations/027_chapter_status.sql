@@ -0,0 +1,21 @@
+-- 章节状态收敛为 draft / revised / final，旧值按含义归并
+UPDATE chapters SET status = CASE
+    WHEN status IN ('revised', 'reviewing') THEN 'revised'
+    WHEN status IN ('final', 'done', 'written') THEN 'final'
+    ELSE 'draft'
+END
+WHERE status IS NULL OR status NOT IN ('draft', 'revised', 'final');
+
+CREATE TRIGGER IF NOT EXISTS chapters_status_insert BEFORE INSERT ON chapters
+WHEN new.status IS NOT NULL AND new.status NOT IN ('draft', 'revised', 'final')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid chapter status');
+END;
+
+CREATE TRIGGER IF NOT EXISTS chapters_status_update BEFORE UPDATE OF status ON chapters
+WHEN new.status IS NULL OR new.status NOT IN ('draft', 'revised', 'final')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid chapter status');
+END;
+
+CREATE INDEX IF NOT EXISTS idx_chapters_project_status ON chapters(project_id, status);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS notes (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    title       TEXT DEFAULT '',
+    content     TEXT DEFAULT '',
+    pinned      INTEGER DEFAULT 0,
+    color       TEXT DEFAULT '',
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_notes_project ON notes(project_id, pinned, updated_at);
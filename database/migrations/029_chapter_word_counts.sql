@@ -0,0 +1,3 @@
+-- 章节字数改为按 text_count 的规则统计：汉字逐字计数，英文按词计数，标点与空白不计。
+-- 重算由 agent/migrate_db.py 的 _apply_chapter_word_counts_migration 完成（需逐段统计，无法用 SQL 表达）。
+SELECT 1;
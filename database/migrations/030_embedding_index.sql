@@ -0,0 +1,12 @@
+-- 向量索引记录：agent 重建章节索引后写入，桌面端据此判断索引是否过期
+CREATE TABLE IF NOT EXISTS embedding_index (
+    project_id   TEXT PRIMARY KEY REFERENCES projects(id) ON DELETE CASCADE,
+    chunk_count  INTEGER NOT NULL DEFAULT 0,
+    dim          INTEGER NOT NULL DEFAULT 0,
+    model        TEXT NOT NULL DEFAULT '',
+    -- 建索引时章节内容的 SHA-256，算法见 src-tauri/src/embedding_index.rs
+    content_hash TEXT NOT NULL DEFAULT '',
+    -- 章节内容改动后置 1，重建后清零
+    stale        INTEGER NOT NULL DEFAULT 0,
+    built_at     TEXT DEFAULT (datetime('now'))
+);
@@ -0,0 +1,26 @@
+-- 时间线事件：story_date 为故事内时间（按文本排序），narrative_order 为叙述顺序（1..n 连续）
+CREATE TABLE IF NOT EXISTS story_events (
+    id              TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    project_id      TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    title           TEXT DEFAULT '',
+    description     TEXT DEFAULT '',
+    story_date      TEXT DEFAULT '',
+    narrative_order INTEGER DEFAULT 0,
+    -- 章节或角色被删除时只解除关联，事件保留
+    chapter_id      TEXT REFERENCES chapters(id) ON DELETE SET NULL,
+    character_ids   TEXT DEFAULT '[]',
+    created_at      TEXT DEFAULT (datetime('now')),
+    updated_at      TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_story_events_project ON story_events(project_id, narrative_order);
+
+CREATE TRIGGER IF NOT EXISTS story_events_character_deleted AFTER DELETE ON characters
+BEGIN
+    UPDATE story_events
+    SET character_ids = (
+        SELECT json_group_array(value) FROM json_each(story_events.character_ids)
+        WHERE value != old.id
+    )
+    WHERE project_id = old.project_id
+      AND EXISTS (SELECT 1 FROM json_each(story_events.character_ids) WHERE value = old.id);
+END;
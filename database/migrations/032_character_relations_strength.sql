@@ -0,0 +1,3 @@
+ALTER TABLE character_relations ADD COLUMN strength INTEGER DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_character_relations_a ON character_relations(character_a_id);
+CREATE INDEX IF NOT EXISTS idx_character_relations_b ON character_relations(character_b_id);
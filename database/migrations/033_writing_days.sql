@@ -0,0 +1,9 @@
+-- day 为用户本地日期 YYYY-MM-DD；words 为当天保存带来的净增字数
+-- goal_reached_at 在当天首次达成每日目标并通知界面后写入，保证每天只提醒一次
+CREATE TABLE IF NOT EXISTS writing_days (
+    project_id      TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    day             TEXT NOT NULL,
+    words           INTEGER DEFAULT 0,
+    goal_reached_at TEXT,
+    PRIMARY KEY (project_id, day)
+);
@@ -0,0 +1,2 @@
+-- 项目回收站：trashed_at 为空表示未删除
+ALTER TABLE projects ADD COLUMN trashed_at TEXT;
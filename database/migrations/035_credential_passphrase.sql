@@ -0,0 +1,9 @@
+-- 系统钥匙串不可用时，凭据以用户口令派生的密钥加密保存
+-- 这里只存盐和用该密钥加密的校验值，用于识别口令错误；口令本身从不落盘
+CREATE TABLE IF NOT EXISTS credential_passphrase (
+    id          INTEGER PRIMARY KEY CHECK (id = 1),
+    salt        BLOB NOT NULL,
+    nonce       BLOB NOT NULL,
+    verifier    BLOB NOT NULL,
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
@@ -0,0 +1,3 @@
+-- 项目置顶与手动排序：置顶项目按 manual_order 排在首页最前
+ALTER TABLE projects ADD COLUMN pinned INTEGER DEFAULT 0;
+ALTER TABLE projects ADD COLUMN manual_order INTEGER;
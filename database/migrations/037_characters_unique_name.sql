@@ -0,0 +1,14 @@
+-- 角色名在项目内唯一：先给历史重名角色加上 id 前缀后缀改名（保留最早的一个原名），
+-- 再建立唯一索引。桌面端打开数据库时也会先执行本文件。
+UPDATE characters
+SET name = name || ' (' || substr(id, 1, 8) || ')'
+WHERE EXISTS (
+    SELECT 1 FROM characters earlier
+    WHERE earlier.project_id = characters.project_id
+      AND earlier.name = characters.name
+      AND (COALESCE(earlier.created_at, '') < COALESCE(characters.created_at, '')
+           OR (COALESCE(earlier.created_at, '') = COALESCE(characters.created_at, '')
+               AND earlier.id < characters.id))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_characters_project_name ON characters(project_id, name);
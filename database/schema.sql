@@ -20,6 +20,17 @@ CREATE TABLE IF NOT EXISTS projects (
     temperature REAL DEFAULT 0.7,
     embedding_dim INTEGER DEFAULT 3072,
     word_target INTEGER DEFAULT 100000,
+    top_p       REAL DEFAULT 1.0,
+    max_tokens  INTEGER DEFAULT 4096,
+    system_prompt_template TEXT DEFAULT '',
+    last_opened_at TEXT,
+    settings    TEXT DEFAULT '{}',
+    cover_path  TEXT,
+    -- 移入回收站的时间；为空表示未删除，超过保留天数后由桌面端彻底清除
+    trashed_at  TEXT,
+    -- 置顶项目排在首页最前，按 manual_order 从 0 起依次排列；未置顶时 manual_order 为空
+    pinned      INTEGER DEFAULT 0,
+    manual_order INTEGER,
     created_at  TEXT DEFAULT (datetime('now')),
     updated_at  TEXT DEFAULT (datetime('now'))
 );
@@ -32,12 +43,51 @@ CREATE TABLE IF NOT EXISTS chapters (
     title       TEXT DEFAULT '',
     phase       TEXT DEFAULT '',
     synopsis    TEXT DEFAULT '',
-    status      TEXT DEFAULT 'draft',
+    status      TEXT DEFAULT 'draft', -- draft | revised | final
     word_count  INTEGER DEFAULT 0,
     sort_order  INTEGER DEFAULT 0,
     created_at  TEXT DEFAULT (datetime('now')),
     updated_at  TEXT DEFAULT (datetime('now'))
 );
+CREATE INDEX IF NOT EXISTS idx_chapters_project_status ON chapters(project_id, status);
+
+-- 章节状态只允许 draft / revised / final（旧数据由迁移 027 归并）
+CREATE TRIGGER IF NOT EXISTS chapters_status_insert BEFORE INSERT ON chapters
+WHEN new.status IS NOT NULL AND new.status NOT IN ('draft', 'revised', 'final')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid chapter status');
+END;
+
+CREATE TRIGGER IF NOT EXISTS chapters_status_update BEFORE UPDATE OF status ON chapters
+WHEN new.status IS NULL OR new.status NOT IN ('draft', 'revised', 'final')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid chapter status');
+END;
+
+-- ========== 场景大纲 ==========
+CREATE TABLE IF NOT EXISTS scenes (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    chapter_id  TEXT NOT NULL REFERENCES chapters(id) ON DELETE CASCADE,
+    summary     TEXT DEFAULT '',
+    order_index INTEGER DEFAULT 0,
+    pov_character_id TEXT REFERENCES characters(id) ON DELETE SET NULL,
+    created_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_scenes_chapter ON scenes(chapter_id, order_index);
+
+-- ========== 项目笔记 ==========
+-- 不属于任何章节的随手记；pinned 的排在前面
+CREATE TABLE IF NOT EXISTS notes (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    title       TEXT DEFAULT '',
+    content     TEXT DEFAULT '',
+    pinned      INTEGER DEFAULT 0,
+    color       TEXT DEFAULT '',
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_notes_project ON notes(project_id, pinned, updated_at);
 
 -- ========== 章节段落 ==========
 CREATE TABLE IF NOT EXISTS chapter_paragraphs (
@@ -51,6 +101,29 @@ CREATE TABLE IF NOT EXISTS chapter_paragraphs (
     created_at  TEXT DEFAULT (datetime('now'))
 );
 
+-- ========== 章节版本 ==========
+-- 整章文本快照（段落以换行连接），在批量修改前写入，便于回退
+CREATE TABLE IF NOT EXISTS chapter_revisions (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    chapter_id  TEXT NOT NULL REFERENCES chapters(id) ON DELETE CASCADE,
+    content     TEXT NOT NULL DEFAULT '',
+    word_count  INTEGER DEFAULT 0,
+    reason      TEXT DEFAULT '',
+    created_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_chapter_revisions_chapter ON chapter_revisions(chapter_id, id);
+
+-- ========== 每日写作 ==========
+-- day 为用户本地日期 YYYY-MM-DD；words 为当天保存带来的净增字数
+-- goal_reached_at 在当天首次达成每日目标并通知界面后写入，保证每天只提醒一次
+CREATE TABLE IF NOT EXISTS writing_days (
+    project_id      TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    day             TEXT NOT NULL,
+    words           INTEGER DEFAULT 0,
+    goal_reached_at TEXT,
+    PRIMARY KEY (project_id, day)
+);
+
 -- ========== 章节节拍 ==========
 CREATE TABLE IF NOT EXISTS chapter_beats (
     id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
@@ -79,8 +152,12 @@ CREATE TABLE IF NOT EXISTS characters (
     usage_notes TEXT DEFAULT '',
     status      TEXT DEFAULT 'active',
     sort_order  INTEGER DEFAULT 0,
-    created_at  TEXT DEFAULT (datetime('now'))
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now')),
+    aliases     TEXT DEFAULT '[]'
 );
+-- 角色名在项目内唯一；旧库由迁移 037 去重后建立
+CREATE UNIQUE INDEX IF NOT EXISTS idx_characters_project_name ON characters(project_id, name);
 
 CREATE TABLE IF NOT EXISTS character_relations (
     id              TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
@@ -88,8 +165,40 @@ CREATE TABLE IF NOT EXISTS character_relations (
     character_b_id  TEXT NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
     relation_type   TEXT DEFAULT '',
     description     TEXT DEFAULT '',
+    -- 关系强弱 0-10，0 为未设定
+    strength        INTEGER DEFAULT 0,
     created_at      TEXT DEFAULT (datetime('now'))
 );
+CREATE INDEX IF NOT EXISTS idx_character_relations_a ON character_relations(character_a_id);
+CREATE INDEX IF NOT EXISTS idx_character_relations_b ON character_relations(character_b_id);
+
+-- ========== 时间线事件 ==========
+-- story_date 为故事内时间（按文本排序），narrative_order 为叙述顺序（1..n 连续）
+CREATE TABLE IF NOT EXISTS story_events (
+    id              TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    project_id      TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    title           TEXT DEFAULT '',
+    description     TEXT DEFAULT '',
+    story_date      TEXT DEFAULT '',
+    narrative_order INTEGER DEFAULT 0,
+    -- 章节或角色被删除时只解除关联，事件保留
+    chapter_id      TEXT REFERENCES chapters(id) ON DELETE SET NULL,
+    character_ids   TEXT DEFAULT '[]',
+    created_at      TEXT DEFAULT (datetime('now')),
+    updated_at      TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_story_events_project ON story_events(project_id, narrative_order);
+
+CREATE TRIGGER IF NOT EXISTS story_events_character_deleted AFTER DELETE ON characters
+BEGIN
+    UPDATE story_events
+    SET character_ids = (
+        SELECT json_group_array(value) FROM json_each(story_events.character_ids)
+        WHERE value != old.id
+    )
+    WHERE project_id = old.project_id
+      AND EXISTS (SELECT 1 FROM json_each(story_events.character_ids) WHERE value = old.id);
+END;
 
 -- ========== 大纲 ==========
 CREATE TABLE IF NOT EXISTS outlines (
@@ -132,6 +241,62 @@ CREATE TABLE IF NOT EXISTS worldbuilding (
     created_at  TEXT DEFAULT (datetime('now'))
 );
 
+-- ========== 提示词库 ==========
+-- project_id 为空是全局提示词，否则为项目内同名覆盖；内置项的出厂内容存于 default_content
+CREATE TABLE IF NOT EXISTS prompts (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    name        TEXT NOT NULL,
+    category    TEXT DEFAULT '',
+    content     TEXT NOT NULL DEFAULT '',
+    is_builtin  INTEGER DEFAULT 0,
+    default_content TEXT,
+    project_id  TEXT REFERENCES projects(id) ON DELETE CASCADE,
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_prompts_scope_name ON prompts(COALESCE(project_id, ''), name);
+
+-- ========== 项目附件 ==========
+-- 文件存于 数据目录/attachments/{project_id}/{id}_{filename}；同一项目内按 sha256 去重
+CREATE TABLE IF NOT EXISTS attachments (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    filename    TEXT NOT NULL,
+    mime        TEXT DEFAULT '',
+    size        INTEGER DEFAULT 0,
+    sha256      TEXT NOT NULL,
+    created_at  TEXT DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_attachments_project ON attachments(project_id, sha256);
+
+-- ========== 项目模板 ==========
+-- content 为 JSON：大纲节点、占位角色、世界观条目与提示词覆盖，不含正文；内置模板 id 以 builtin- 开头
+CREATE TABLE IF NOT EXISTS project_templates (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    name        TEXT NOT NULL,
+    genre       TEXT DEFAULT '',
+    description TEXT DEFAULT '',
+    content     TEXT NOT NULL DEFAULT '{}',
+    is_builtin  INTEGER DEFAULT 0,
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+
+-- ========== 模型预设 ==========
+-- 应用预设时把 model_id 与温度复制进项目，项目不引用预设行
+CREATE TABLE IF NOT EXISTS model_presets (
+    id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    provider    TEXT NOT NULL,
+    model_id    TEXT NOT NULL,
+    display_name TEXT DEFAULT '',
+    context_window INTEGER DEFAULT 0,
+    default_temperature REAL DEFAULT 0.7,
+    supports_streaming INTEGER DEFAULT 1,
+    created_at  TEXT DEFAULT (datetime('now')),
+    updated_at  TEXT DEFAULT (datetime('now')),
+    UNIQUE(provider, model_id)
+);
+
 -- ========== 设置 ==========
 CREATE TABLE IF NOT EXISTS api_keys (
     provider    TEXT PRIMARY KEY,
@@ -147,6 +312,33 @@ CREATE TABLE IF NOT EXISTS global_settings (
     value TEXT DEFAULT ''
 );
 
+-- Desktop app preferences (see src-tauri/src/settings.rs); values are JSON
+CREATE TABLE IF NOT EXISTS app_settings (
+    key         TEXT PRIMARY KEY,
+    value       TEXT NOT NULL,
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+
+-- Managed by the desktop app: the secret lives in the OS keychain, or here
+-- encrypted under the credentials passphrase when no keychain is available
+CREATE TABLE IF NOT EXISTS api_credentials (
+    name        TEXT PRIMARY KEY,
+    storage     TEXT NOT NULL,
+    ciphertext  BLOB,
+    nonce       BLOB,
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+
+-- Salt for the credentials passphrase and a value sealed with the key derived
+-- from it, to tell a wrong passphrase apart; the passphrase itself is never stored
+CREATE TABLE IF NOT EXISTS credential_passphrase (
+    id          INTEGER PRIMARY KEY CHECK (id = 1),
+    salt        BLOB NOT NULL,
+    nonce       BLOB NOT NULL,
+    verifier    BLOB NOT NULL,
+    updated_at  TEXT DEFAULT (datetime('now'))
+);
+
 CREATE TABLE IF NOT EXISTS provider_configs (
     id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
     provider    TEXT NOT NULL,
@@ -242,6 +434,19 @@ CREATE TABLE IF NOT EXISTS memory_chunks (
     created_at  TEXT DEFAULT (datetime('now'))
 );
 
+-- 向量索引记录：agent 重建章节索引后写入，桌面端据此判断索引是否过期
+CREATE TABLE IF NOT EXISTS embedding_index (
+    project_id   TEXT PRIMARY KEY REFERENCES projects(id) ON DELETE CASCADE,
+    chunk_count  INTEGER NOT NULL DEFAULT 0,
+    dim          INTEGER NOT NULL DEFAULT 0,
+    model        TEXT NOT NULL DEFAULT '',
+    -- 建索引时章节内容的 SHA-256，算法见 src-tauri/src/embedding_index.rs
+    content_hash TEXT NOT NULL DEFAULT '',
+    -- 章节内容改动后置 1，重建后清零
+    stale        INTEGER NOT NULL DEFAULT 0,
+    built_at     TEXT DEFAULT (datetime('now'))
+);
+
 CREATE TABLE IF NOT EXISTS reviews (
     id          TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
     project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
@@ -0,0 +1,349 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const MIN_PYTHON: (u32, u32) = (3, 10);
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+const IMPORT_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+const PIP_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+const BOOTSTRAP_PIP_TIMEOUT: Duration = Duration::from_secs(180);
+/// pip's own wheel (`pip-<version>-py3-none-any.whl`), which build.bat ships in
+/// python_embed next to the interpreter: the Windows embeddable distribution has
+/// no ensurepip
+const PIP_WHEEL_PREFIX: &str = "pip-";
+const OUTPUT_TAIL_LINES: usize = 40;
+
+/// Import names for the packages in agent/requirements.txt
+const REQUIRED_MODULES: &[&str] = &[
+    "fastapi",
+    "multipart",
+    "uvicorn",
+    "langgraph",
+    "langchain",
+    "langchain_openai",
+    "langchain_anthropic",
+    "litellm",
+    "chromadb",
+    "pydantic",
+    "jieba",
+    "numpy",
+    "pypdf",
+];
+
+// find_spec only locates modules, so the probe stays fast even for chromadb/langchain
+const IMPORT_PROBE: &str = "import importlib.util, sys\n\
+for name in sys.argv[1:]:\n    \
+    if importlib.util.find_spec(name) is None:\n        \
+        print(name)\n";
+
+#[derive(Serialize, Clone)]
+pub struct EnvironmentReport {
+    pub interpreter: String,
+    /// Set by the caller when the interpreter came from python_path_override
+    pub interpreter_from_override: bool,
+    pub interpreter_found: bool,
+    pub version: Option<String>,
+    pub version_ok: bool,
+    pub missing_modules: Vec<String>,
+    pub agent_dir: String,
+    pub agent_dir_from_override: bool,
+    pub agent_dir_ok: bool,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+pub fn validate(python: &Path, agent_dir: &Path) -> EnvironmentReport {
+    let agent_dir_ok = agent_dir.join("main.py").is_file();
+    let mut report = EnvironmentReport {
+        interpreter: python.display().to_string(),
+        interpreter_from_override: false,
+        interpreter_found: false,
+        version: None,
+        version_ok: false,
+        missing_modules: Vec::new(),
+        agent_dir: agent_dir.display().to_string(),
+        agent_dir_from_override: false,
+        agent_dir_ok,
+        ok: false,
+        error: None,
+    };
+
+    let version = match python_version(python) {
+        Ok(version) => version,
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    report.interpreter_found = true;
+    report.version_ok = version_supported(&version);
+    report.version = Some(version).filter(|v| !v.is_empty());
+    if !report.version_ok {
+        report.error = Some(unsupported_version_error());
+        return report;
+    }
+
+    let mut probe = Command::new(python);
+    probe.args(["-c", IMPORT_PROBE]).args(REQUIRED_MODULES);
+    match run_with_timeout(&mut probe, IMPORT_PROBE_TIMEOUT) {
+        Ok(output) if output.status.success() => {
+            report.missing_modules = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty())
+                .collect();
+        }
+        Ok(output) => {
+            report.error = Some(String::from_utf8_lossy(&output.stderr).trim().to_string());
+            return report;
+        }
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    }
+
+    if !agent_dir_ok {
+        report.error = Some(format!("main.py not found in {}", agent_dir.display()));
+    }
+    report.ok = report.missing_modules.is_empty() && agent_dir_ok;
+    report
+}
+
+/// Run `python --version` and return the bare version, e.g. "3.11.4"
+fn python_version(python: &Path) -> Result<String, String> {
+    let output = run_with_timeout(Command::new(python).arg("--version"), VERSION_TIMEOUT)?;
+    // Python < 3.4 printed the version on stderr
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(text.trim().trim_start_matches("Python").trim().to_string())
+}
+
+fn version_supported(version: &str) -> bool {
+    parse_version(version).is_some_and(|v| v.0 == 3 && v >= MIN_PYTHON)
+}
+
+fn unsupported_version_error() -> String {
+    format!("Python {}.{} or newer is required", MIN_PYTHON.0, MIN_PYTHON.1)
+}
+
+/// Quick pre-spawn check that `python` runs and is a supported 3.x; returns its version
+pub fn verify_python(python: &Path) -> Result<String, String> {
+    let version = python_version(python)?;
+    if !version_supported(&version) {
+        let found = if version.is_empty() { "unknown version" } else { version.as_str() };
+        return Err(format!("{} (found {})", unsupported_version_error(), found));
+    }
+    Ok(version)
+}
+
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split('.');
+    let major = parts.next()?.trim().parse().ok()?;
+    let minor = parts
+        .next()?
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>()
+        .parse()
+        .ok()?;
+    Some((major, minor))
+}
+
+/// Run a command to completion, killing it if it outlives `timeout`
+pub fn run_with_timeout(cmd: &mut Command, timeout: Duration) -> Result<Output, String> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {:?}: {}", cmd.get_program(), e))?;
+
+    // Drain the pipes on their own threads so a chatty child can't block on a full pipe
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let out_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf);
+        buf
+    });
+    let err_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "{:?} did not finish within {}s",
+                    cmd.get_program(),
+                    timeout.as_secs()
+                ));
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+
+    Ok(Output {
+        status,
+        stdout: out_reader.join().unwrap_or_default(),
+        stderr: err_reader.join().unwrap_or_default(),
+    })
+}
+
+/// Install agent/requirements.txt with the given interpreter, bootstrapping pip when
+/// it's missing (python_embed ships without it). Each output line is handed to
+/// `on_line`; on failure the error carries the last lines of output.
+pub fn install_dependencies(
+    python: &Path,
+    agent_dir: &Path,
+    timeout: Duration,
+    mut on_line: impl FnMut(&str),
+) -> Result<(), String> {
+    let requirements = agent_dir.join("requirements.txt");
+    if !requirements.is_file() {
+        return Err(format!("{} not found", requirements.display()));
+    }
+
+    let has_pip = run_with_timeout(
+        Command::new(python).args(["-m", "pip", "--version"]),
+        PIP_PROBE_TIMEOUT,
+    )
+    .map(|out| out.status.success())
+    .unwrap_or(false);
+    if !has_pip {
+        bootstrap_pip(python, &mut on_line)?;
+    }
+
+    let mut child = Command::new(python)
+        .args(["-m", "pip", "install", "--disable-pip-version-check", "--no-input"])
+        .args(["--progress-bar", "off", "-r", "requirements.txt"])
+        .current_dir(agent_dir)
+        .env("PYTHONUNBUFFERED", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run pip: {}", e))?;
+
+    let (tx, rx) = mpsc::channel::<String>();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let err_tx = tx.clone();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let _ = tx.send(line);
+        }
+    });
+    std::thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            let _ = err_tx.send(line);
+        }
+    });
+
+    let mut tail = VecDeque::with_capacity(OUTPUT_TAIL_LINES);
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            let _ = child.kill();
+            let _ = child.wait();
+            tail.push_back(format!("pip timed out after {}s", timeout.as_secs()));
+            break;
+        }
+        match rx.recv_timeout(remaining.min(Duration::from_millis(500))) {
+            Ok(line) => {
+                on_line(&line);
+                if tail.len() == OUTPUT_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // Both pipes closed: pip has exited or is about to
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let status = child.wait().map_err(|e| e.to_string())?;
+                if status.success() {
+                    return Ok(());
+                }
+                tail.push_back(format!("pip exited with {}", status));
+                break;
+            }
+        }
+    }
+    Err(Vec::from(tail).join("\n"))
+}
+
+/// Install pip from the bundled wheel, run straight out of it, or with ensurepip
+/// for an interpreter that came without it
+fn bootstrap_pip(python: &Path, on_line: &mut impl FnMut(&str)) -> Result<(), String> {
+    let python_dir = python.parent().unwrap_or(Path::new("."));
+    let (tool, mut cmd) = match bundled_pip_wheel(python_dir) {
+        Some(wheel) => {
+            enable_site_packages(python_dir)?;
+            let mut cmd = Command::new(python);
+            cmd.arg(wheel.join("pip"))
+                .args(["install", "--no-index", "--no-warn-script-location"])
+                .arg("--disable-pip-version-check")
+                .arg(&wheel);
+            ("the bundled pip wheel", cmd)
+        }
+        None => {
+            let mut cmd = Command::new(python);
+            cmd.args(["-m", "ensurepip", "--upgrade"]);
+            ("ensurepip", cmd)
+        }
+    };
+    on_line(&format!("pip not found, bootstrapping it with {}...", tool));
+    let out = run_with_timeout(&mut cmd, BOOTSTRAP_PIP_TIMEOUT)?;
+    if !out.status.success() {
+        return Err(format!("{} failed: {}", tool, String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    Ok(())
+}
+
+fn bundled_pip_wheel(python_dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(python_dir).ok()?.filter_map(|e| e.ok()).map(|e| e.path()).find(|path| {
+        path.file_name().and_then(|n| n.to_str()).is_some_and(|name| {
+            name.starts_with(PIP_WHEEL_PREFIX) && name.ends_with(".whl")
+        })
+    })
+}
+
+/// The embeddable distribution's pythonXY._pth leaves out site-packages, where pip
+/// and everything it installs go, until `import site` is uncommented
+fn enable_site_packages(python_dir: &Path) -> Result<(), String> {
+    let Ok(entries) = std::fs::read_dir(python_dir) else {
+        return Ok(());
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("_pth") {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        if !text.lines().any(|line| line.trim() == "#import site") {
+            continue;
+        }
+        let enabled: Vec<&str> = text
+            .lines()
+            .map(|line| if line.trim() == "#import site" { "import site" } else { line })
+            .collect();
+        std::fs::write(&path, enabled.join("\n") + "\n")
+            .map_err(|e| format!("Failed to update {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
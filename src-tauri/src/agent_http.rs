@@ -0,0 +1,507 @@
+//! Minimal HTTP/1.1 client for talking to the local agent without pulling in
+//! an async HTTP stack. One request per connection (`Connection: close`).
+
+use serde::Serialize;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Chapter text and imports go through here, so leave plenty of room
+pub const MAX_REQUEST_BYTES: usize = 32 * 1024 * 1024;
+pub const MAX_RESPONSE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Serialized as `{ kind, message }` so the UI can react to `agent_not_running`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum Error {
+    AgentNotRunning(String),
+    Timeout(String),
+    TooLarge(String),
+    InvalidRequest(String),
+    Transport(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::AgentNotRunning(msg)
+            | Error::Timeout(msg)
+            | Error::TooLarge(msg)
+            | Error::InvalidRequest(msg)
+            | Error::Transport(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<Error> for String {
+    fn from(e: Error) -> String {
+        e.to_string()
+    }
+}
+
+fn io_error(context: &str, e: std::io::Error) -> Error {
+    let msg = format!("{}: {}", context, e);
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout(msg),
+        _ => Error::Transport(msg),
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    /// In arrival order; names as sent by the server
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+pub fn request(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<Response, Error> {
+    request_with_limit(port, method, path, headers, body, timeout, MAX_RESPONSE_BYTES)
+}
+
+fn request_with_limit(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<&str>,
+    timeout: Duration,
+    max_response: usize,
+) -> Result<Response, Error> {
+    let mut stream = send(port, method, path, headers, body, timeout)?;
+
+    // Allow one byte past the limit so an oversized response is detectable
+    let mut raw = Vec::new();
+    (&mut stream)
+        .take(max_response as u64 + 1)
+        .read_to_end(&mut raw)
+        .map_err(|e| io_error("Failed to read agent response", e))?;
+    if raw.len() > max_response {
+        return Err(Error::TooLarge(format!(
+            "Agent response exceeds {} bytes",
+            max_response
+        )));
+    }
+    parse_response(&raw)
+}
+
+/// Connect and write the full request, leaving the response unread
+fn send(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<TcpStream, Error> {
+    let body = body.unwrap_or("");
+    if body.len() > MAX_REQUEST_BYTES {
+        return Err(Error::TooLarge(format!(
+            "Request body is {} bytes; the limit is {}",
+            body.len(),
+            MAX_REQUEST_BYTES
+        )));
+    }
+
+    let addr: SocketAddr = format!("127.0.0.1:{}", port)
+        .parse()
+        .map_err(|e| Error::InvalidRequest(format!("Invalid agent address: {}", e)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| {
+        let msg = format!("Agent not reachable on port {}: {}", port, e);
+        match e.kind() {
+            ErrorKind::ConnectionRefused => Error::AgentNotRunning(msg),
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout(msg),
+            _ => Error::Transport(msg),
+        }
+    })?;
+    stream.set_read_timeout(Some(timeout)).ok();
+    stream.set_write_timeout(Some(timeout)).ok();
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        port,
+        body.len()
+    );
+    if !body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body.as_bytes()))
+        .map_err(|e| io_error("Failed to send request to agent", e))?;
+    Ok(stream)
+}
+
+/// Response whose body is read incrementally, for SSE and other streamed replies
+pub struct StreamingResponse {
+    pub status: u16,
+    reader: BufReader<TcpStream>,
+    framing: Framing,
+}
+
+enum Framing {
+    Chunked,
+    Length(usize),
+    UntilClose,
+    Done,
+}
+
+const STREAM_READ_SIZE: usize = 8192;
+
+/// Send a request and read only the response head. The returned socket is a
+/// handle to the same connection: shutting it down from another thread makes
+/// the pending `next_chunk` return, which is how streams get cancelled.
+pub fn open_stream(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<&str>,
+    connect_timeout: Duration,
+) -> Result<(StreamingResponse, TcpStream), Error> {
+    let stream = send(port, method, path, headers, body, connect_timeout)?;
+    // Generations can pause for a long time between tokens
+    stream.set_read_timeout(None).ok();
+    let handle = stream
+        .try_clone()
+        .map_err(|e| io_error("Failed to clone agent connection", e))?;
+
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        let n = reader
+            .read_line(&mut line)
+            .map_err(|e| io_error("Failed to read agent response", e))?;
+        if n == 0 {
+            return Err(Error::Transport("Agent closed the connection".into()));
+        }
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+
+    let (status, _headers, chunked, content_length) = parse_head(&head)?;
+    let framing = match (chunked, content_length) {
+        (true, _) => Framing::Chunked,
+        (false, Some(0)) => Framing::Done,
+        (false, Some(len)) => Framing::Length(len),
+        (false, None) => Framing::UntilClose,
+    };
+    Ok((StreamingResponse { status, reader, framing }, handle))
+}
+
+impl StreamingResponse {
+    /// The next piece of body as it arrives (chunked framing removed); None at the end
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let read_err = |e| io_error("Failed to read agent stream", e);
+        match self.framing {
+            Framing::Done => Ok(None),
+            Framing::Chunked => {
+                let mut size_line = String::new();
+                if self.reader.read_line(&mut size_line).map_err(read_err)? == 0 {
+                    self.framing = Framing::Done;
+                    return Ok(None);
+                }
+                let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16)
+                    .map_err(|_| Error::Transport("Malformed chunk size".into()))?;
+                if size == 0 {
+                    // Skip trailers up to the blank line ending the body
+                    let mut line = String::new();
+                    while self.reader.read_line(&mut line).map_err(read_err)? > 0
+                        && !line.trim_end().is_empty()
+                    {
+                        line.clear();
+                    }
+                    self.framing = Framing::Done;
+                    return Ok(None);
+                }
+                let mut chunk = vec![0u8; size + 2];
+                self.reader.read_exact(&mut chunk).map_err(read_err)?;
+                chunk.truncate(size);
+                Ok(Some(chunk))
+            }
+            Framing::Length(remaining) => {
+                let mut buf = vec![0u8; remaining.min(STREAM_READ_SIZE)];
+                let n = self.reader.read(&mut buf).map_err(read_err)?;
+                if n == 0 {
+                    self.framing = Framing::Done;
+                    return Ok(None);
+                }
+                self.framing = match remaining - n {
+                    0 => Framing::Done,
+                    left => Framing::Length(left),
+                };
+                buf.truncate(n);
+                Ok(Some(buf))
+            }
+            Framing::UntilClose => {
+                let mut buf = vec![0u8; STREAM_READ_SIZE];
+                let n = self.reader.read(&mut buf).map_err(read_err)?;
+                if n == 0 {
+                    self.framing = Framing::Done;
+                    return Ok(None);
+                }
+                buf.truncate(n);
+                Ok(Some(buf))
+            }
+        }
+    }
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response, Error> {
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| Error::Transport("Malformed agent response".into()))?;
+    let head = String::from_utf8_lossy(&raw[..split]);
+    let payload = &raw[split + 4..];
+    let (status, headers, chunked, content_length) = parse_head(&head)?;
+
+    let body = if chunked {
+        decode_chunked(payload)
+    } else {
+        let end = content_length.unwrap_or(payload.len()).min(payload.len());
+        payload[..end].to_vec()
+    };
+    Ok(Response {
+        status,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+type Head = (u16, Vec<(String, String)>, bool, Option<usize>);
+
+/// Status, headers, whether the body is chunked, and its Content-Length
+fn parse_head(head: &str) -> Result<Head, Error> {
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| Error::Transport("Malformed agent status line".into()))?;
+
+    let mut headers = Vec::new();
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            }
+            headers.push((name.trim().to_string(), value.to_string()));
+        }
+    }
+    Ok((status, headers, chunked, content_length))
+}
+
+fn decode_chunked(mut data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    while let Some(line_end) = data.windows(2).position(|w| w == b"\r\n") {
+        let size_line = String::from_utf8_lossy(&data[..line_end]);
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16)
+            .unwrap_or(0);
+        if size == 0 {
+            break;
+        }
+        let start = line_end + 2;
+        let end = (start + size).min(data.len());
+        out.extend_from_slice(&data[start..end]);
+        data = &data[(end + 2).min(data.len())..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// Accept one connection, hand the request body to `respond`, and write
+    /// back whatever it returns. Yields the port and the received head.
+    fn stub_server(
+        respond: impl FnOnce(&[u8]) -> Vec<u8> + Send + 'static,
+    ) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let reply = respond(&body);
+            reader.get_mut().write_all(&reply).unwrap();
+            head
+        });
+        (port, handle)
+    }
+
+    fn reply(status: &str, body: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nX-Stub: yes\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn forwards_large_bodies_and_returns_status_and_headers() {
+        let (port, server) = stub_server(|body| {
+            reply("201 Created", &format!("{{\"received\":{}}}", body.len()))
+        });
+        let body = format!("\"{}\"", "章".repeat(1024 * 1024));
+        let resp = request(
+            port,
+            "POST",
+            "/api/chapters",
+            &[("Authorization", "Bearer secret")],
+            Some(&body),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+
+        assert_eq!(resp.status, 201);
+        assert_eq!(resp.body, format!("{{\"received\":{}}}", body.len()));
+        assert!(resp.headers.iter().any(|(k, v)| k == "X-Stub" && v == "yes"));
+        let head = server.join().unwrap();
+        assert!(head.starts_with("POST /api/chapters HTTP/1.1\r\n"));
+        assert!(head.contains("Authorization: Bearer secret\r\n"));
+    }
+
+    #[test]
+    fn decodes_chunked_responses() {
+        let (port, server) = stub_server(|_| {
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+                .to_vec()
+        });
+        let resp = request(port, "GET", "/health", &[], None, Duration::from_secs(5)).unwrap();
+        assert_eq!(resp.body, "hello world");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connection_refused_means_agent_not_running() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = request(port, "GET", "/health", &[], None, Duration::from_secs(2)).unwrap_err();
+        assert!(matches!(err, Error::AgentNotRunning(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn slow_agent_times_out() {
+        let (port, server) = stub_server(|_| {
+            std::thread::sleep(Duration::from_millis(600));
+            reply("200 OK", "{}")
+        });
+        let err =
+            request(port, "GET", "/slow", &[], None, Duration::from_millis(200)).unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)), "{:?}", err);
+        let _ = server.join();
+    }
+
+    #[test]
+    fn oversized_response_is_rejected() {
+        let (port, server) = stub_server(|_| reply("200 OK", &"x".repeat(4096)));
+        let err = request_with_limit(port, "GET", "/big", &[], None, Duration::from_secs(5), 1024)
+            .unwrap_err();
+        assert!(matches!(err, Error::TooLarge(_)), "{:?}", err);
+        let _ = server.join();
+    }
+
+    #[test]
+    fn oversized_request_is_rejected_before_connecting() {
+        let body = "x".repeat(MAX_REQUEST_BYTES + 1);
+        let err = request(1, "POST", "/", &[], Some(&body), Duration::from_secs(1)).unwrap_err();
+        assert!(matches!(err, Error::TooLarge(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn streams_chunked_body_piece_by_piece() {
+        let (port, server) = stub_server(|_| {
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n\
+              d\r\ndata: first\n\n\r\ne\r\ndata: second\n\n\r\n0\r\n\r\n"
+                .to_vec()
+        });
+        let (mut stream, _handle) =
+            open_stream(port, "POST", "/agent/stream", &[], Some("{}"), Duration::from_secs(5))
+                .unwrap();
+        assert_eq!(stream.status, 200);
+        assert_eq!(stream.next_chunk().unwrap().unwrap(), b"data: first\n\n");
+        assert_eq!(stream.next_chunk().unwrap().unwrap(), b"data: second\n\n");
+        assert!(stream.next_chunk().unwrap().is_none());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn shutting_down_the_handle_ends_a_pending_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            conn.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .unwrap();
+            // Hold the connection open until the client goes away
+            let mut buf = [0u8; 1024];
+            while conn.read(&mut buf).map(|n| n > 0).unwrap_or(false) {}
+        });
+        let (mut stream, handle) =
+            open_stream(port, "GET", "/agent/stream", &[], None, Duration::from_secs(5)).unwrap();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            handle.shutdown(std::net::Shutdown::Both).unwrap();
+        });
+        assert!(!matches!(stream.next_chunk(), Ok(Some(_))));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn errors_serialize_with_a_kind() {
+        let json = serde_json::to_value(Error::AgentNotRunning("down".into())).unwrap();
+        assert_eq!(json, serde_json::json!({ "kind": "agent_not_running", "message": "down" }));
+    }
+}
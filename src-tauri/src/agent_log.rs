@@ -0,0 +1,83 @@
+//! The agent's stdout/stderr, `agent.log` in the data directory. On Windows the
+//! agent gets its own console window instead, unless the agent_log_to_file
+//! setting sends its output here too.
+//!
+//! Every spawn opens the file afresh in append mode, so a log rotated before the
+//! spawn is the one the new agent writes to. Truncating it while the agent runs is
+//! safe: appends always land at the new end of the file.
+
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "agent.log";
+/// The previous log, kept through one rotation
+pub const ROTATED_FILE_NAME: &str = "agent.log.1";
+/// A log larger than this is rotated when the agent is next spawned
+pub const ROTATE_BYTES: u64 = 10 * 1024 * 1024;
+
+pub fn path(data_dir: &Path) -> PathBuf {
+    data_dir.join(FILE_NAME)
+}
+
+/// Move a log past `max_bytes` to agent.log.1, replacing the one there, so the
+/// next open starts a fresh file. Returns whether it rotated.
+pub fn rotate_if_large(data_dir: &Path, max_bytes: u64) -> Result<bool, String> {
+    let log = path(data_dir);
+    match std::fs::metadata(&log) {
+        Ok(meta) if meta.len() > max_bytes => {}
+        Ok(_) => return Ok(false),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(format!("Cannot read {}: {}", log.display(), e)),
+    }
+    let rotated = data_dir.join(ROTATED_FILE_NAME);
+    std::fs::rename(&log, &rotated)
+        .map_err(|e| format!("Cannot rename {} to {}: {}", log.display(), rotated.display(), e))?;
+    Ok(true)
+}
+
+/// Empty agent.log and delete agent.log.1. A missing log is already clear.
+pub fn clear(data_dir: &Path) -> Result<(), String> {
+    let log = path(data_dir);
+    match OpenOptions::new().write(true).truncate(true).open(&log) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("Cannot clear {}: {}", log.display(), e)),
+    }
+    let rotated = data_dir.join(ROTATED_FILE_NAME);
+    match std::fs::remove_file(&rotated) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Cannot delete {}: {}", rotated.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn large_logs_rotate_and_clear_keeps_the_writer_appending() {
+        let data_dir = std::env::temp_dir().join(format!("agent-log-test-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(path(&data_dir), "x".repeat(100)).unwrap();
+
+        assert!(!rotate_if_large(&data_dir, 100).unwrap());
+        assert!(rotate_if_large(&data_dir, 99).unwrap());
+        assert!(!path(&data_dir).exists());
+        assert_eq!(std::fs::metadata(data_dir.join(ROTATED_FILE_NAME)).unwrap().len(), 100);
+
+        // As the spawn path opens it for the agent
+        let mut writer =
+            OpenOptions::new().create(true).append(true).open(path(&data_dir)).unwrap();
+        writer.write_all(b"started\n").unwrap();
+        clear(&data_dir).unwrap();
+        writer.write_all(b"ready\n").unwrap();
+        assert_eq!(std::fs::read_to_string(path(&data_dir)).unwrap(), "ready\n");
+        assert!(!data_dir.join(ROTATED_FILE_NAME).exists());
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+        clear(&data_dir).unwrap();
+    }
+}
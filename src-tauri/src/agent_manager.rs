@@ -0,0 +1,245 @@
+//! Owns the agent child process. Every spawn and every kill — the start/stop/
+//! restart commands, the auto-start at launch, the watchdog and a data move —
+//! happens while holding the one lifecycle lock, so two of them can never both
+//! decide the agent is down and each start their own.
+//!
+//! The process slot itself is only ever locked briefly, so status queries never
+//! wait on a transition that is blocked in a health check or shutdown.
+
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+
+pub struct AgentManager {
+    process: Mutex<Option<Child>>,
+    lifecycle: Mutex<()>,
+    /// A spawn is underway; further starts return at once instead of queueing
+    starting: AtomicBool,
+    /// A start/stop/restart or data move holds the lifecycle lock
+    transitioning: AtomicBool,
+}
+
+/// What a start did
+#[derive(Debug, PartialEq)]
+pub enum Started {
+    Spawned(u32),
+    /// The spawner found an agent we didn't start serving the port and adopted it
+    Adopted,
+    AlreadyRunning,
+    /// Another caller is starting it right now
+    AlreadyStarting,
+}
+
+impl AgentManager {
+    pub fn new() -> Self {
+        AgentManager {
+            process: Mutex::new(None),
+            lifecycle: Mutex::new(()),
+            starting: AtomicBool::new(false),
+            transitioning: AtomicBool::new(false),
+        }
+    }
+
+    /// Take the lifecycle lock for a user-visible transition, waiting for any
+    /// other to finish. agent_status reports `transitioning` until it's dropped.
+    pub fn lock(&self) -> Lifecycle<'_> {
+        let guard = lock(&self.lifecycle);
+        let transition = Flag::raise(&self.transitioning);
+        Lifecycle { manager: self, _guard: guard, _transition: Some(transition) }
+    }
+
+    /// For the watchdog: None while a transition is underway, to be retried later
+    pub fn try_lock(&self) -> Option<Lifecycle<'_>> {
+        let guard = match self.lifecycle.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(Lifecycle { manager: self, _guard: guard, _transition: None })
+    }
+
+    /// Start the agent with `spawn` unless it's already running or starting.
+    /// `spawn` returns None when it adopted an agent instead of spawning one.
+    pub fn start(
+        &self,
+        spawn: impl FnOnce() -> Result<Option<Child>, String>,
+    ) -> Result<Started, String> {
+        if self.starting.load(Ordering::SeqCst) {
+            return Ok(Started::AlreadyStarting);
+        }
+        self.lock().spawn(spawn)
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.slot().as_ref().map(Child::id)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.slot().is_some()
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transitioning.load(Ordering::SeqCst) || self.starting.load(Ordering::SeqCst)
+    }
+
+    /// Take the child without the lifecycle lock, for app exit, where waiting on
+    /// a transition would hang the window close
+    pub fn take_for_exit(&self) -> Option<Child> {
+        self.slot().take()
+    }
+
+    fn slot(&self) -> MutexGuard<'_, Option<Child>> {
+        lock(&self.process)
+    }
+}
+
+/// Lock `mutex` even if a thread panicked while holding it. What the agent locks
+/// guard (the child, the lifecycle, timestamps, the token) is still whole after
+/// a panic, and one panic must not wedge agent management for the whole session.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Default for AgentManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Proof of holding the lifecycle lock; the only way to spawn or kill the agent
+pub struct Lifecycle<'a> {
+    manager: &'a AgentManager,
+    _guard: MutexGuard<'a, ()>,
+    _transition: Option<Flag<'a>>,
+}
+
+impl Lifecycle<'_> {
+    /// Run `spawn` and keep its child, unless one is already running
+    pub fn spawn(
+        &self,
+        spawn: impl FnOnce() -> Result<Option<Child>, String>,
+    ) -> Result<Started, String> {
+        if self.manager.is_running() {
+            return Ok(Started::AlreadyRunning);
+        }
+        let _starting = Flag::raise(&self.manager.starting);
+        match spawn()? {
+            Some(child) => {
+                let pid = child.id();
+                *self.manager.slot() = Some(child);
+                Ok(Started::Spawned(pid))
+            }
+            None => Ok(Started::Adopted),
+        }
+    }
+
+    /// Remove the child so the caller can shut it down
+    pub fn take(&self) -> Option<Child> {
+        self.manager.slot().take()
+    }
+
+    /// Remove the child if it has exited, with its pid and exit status
+    pub fn reap(&self) -> Option<(u32, ExitStatus)> {
+        let mut slot = self.manager.slot();
+        let child = slot.as_mut()?;
+        let status = child.try_wait().ok().flatten()?;
+        let pid = child.id();
+        slot.take();
+        Some((pid, status))
+    }
+}
+
+/// Holds an AtomicBool up until dropped, so a panic or early return lowers it too
+pub struct Flag<'a>(&'a AtomicBool);
+
+impl<'a> Flag<'a> {
+    fn raise(flag: &'a AtomicBool) -> Self {
+        flag.store(true, Ordering::SeqCst);
+        Flag(flag)
+    }
+
+    /// Raise `flag` unless it's already up
+    pub fn try_raise(flag: &'a AtomicBool) -> Option<Self> {
+        flag.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| Flag(flag))
+    }
+}
+
+impl Drop for Flag<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Barrier};
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_starts_leave_one_child() {
+        let manager = Arc::new(AgentManager::new());
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(2));
+        let starts: Vec<_> = (0..2)
+            .map(|_| {
+                let (manager, spawned, barrier) =
+                    (manager.clone(), spawned.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    manager.start(|| {
+                        spawned.fetch_add(1, Ordering::SeqCst);
+                        // Slow enough for the other start to arrive mid-spawn
+                        std::thread::sleep(Duration::from_millis(200));
+                        let child = Command::new("sleep").arg("30").spawn();
+                        child.map(Some).map_err(|e| e.to_string())
+                    })
+                })
+            })
+            .collect();
+        let mut results: Vec<_> =
+            starts.into_iter().map(|start| start.join().unwrap().unwrap()).collect();
+        results.sort_by_key(|started| matches!(started, Started::Spawned(_)));
+
+        assert_eq!(spawned.load(Ordering::SeqCst), 1);
+        assert!(matches!(results[0], Started::AlreadyRunning | Started::AlreadyStarting));
+        let Started::Spawned(pid) = results[1] else { panic!("nothing was spawned") };
+        assert_eq!(manager.pid(), Some(pid));
+        assert!(!manager.is_transitioning());
+        assert_eq!(manager.start(|| panic!("spawned twice")).unwrap(), Started::AlreadyRunning);
+
+        let mut child = manager.lock().take().unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(!manager.is_running());
+    }
+    #[test]
+    fn a_panic_while_locked_does_not_wedge_the_manager() {
+        let manager = Arc::new(AgentManager::new());
+        let poisoner = manager.clone();
+        let panicked = std::thread::spawn(move || {
+            let _ = poisoner.start(|| {
+                let _slot = poisoner.slot();
+                panic!("spawn blew up")
+            });
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(manager.process.is_poisoned() && manager.lifecycle.is_poisoned());
+
+        assert!(!manager.is_running() && !manager.is_transitioning());
+        assert!(manager.try_lock().is_some());
+        let started = manager.start(|| {
+            Command::new("sleep").arg("30").spawn().map(Some).map_err(|e| e.to_string())
+        });
+        let Ok(Started::Spawned(pid)) = started else { panic!("start failed: {:?}", started) };
+        assert_eq!(manager.pid(), Some(pid));
+        let mut child = manager.lock().take().unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
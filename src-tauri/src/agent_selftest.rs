@@ -0,0 +1,95 @@
+//! agent_selftest: the agent checked one layer at a time, so a report shows
+//! whether the process, the port, HTTP or the agent's own startup is at fault.
+
+use serde::Serialize;
+use std::time::Instant;
+
+/// The layers, in the order they are checked
+pub const PROCESS: &str = "process";
+pub const TCP_CONNECT: &str = "tcp_connect";
+pub const HEALTH: &str = "health";
+pub const ROUND_TRIP: &str = "round_trip";
+
+#[derive(Serialize, Debug)]
+pub struct SelfTestStep {
+    pub name: &'static str,
+    pub ok: bool,
+    /// Not run because an earlier step failed
+    pub skipped: bool,
+    pub latency_ms: Option<u64>,
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SelfTestReport {
+    pub port: u16,
+    pub ok: bool,
+    /// The first step that failed
+    pub failed_step: Option<&'static str>,
+    pub steps: Vec<SelfTestStep>,
+}
+
+/// Runs the steps in order; once one fails the rest are recorded as skipped
+pub struct SelfTest {
+    port: u16,
+    steps: Vec<SelfTestStep>,
+}
+
+impl SelfTest {
+    pub fn new(port: u16) -> Self {
+        SelfTest { port, steps: Vec::new() }
+    }
+
+    /// Time `check`, which returns a detail for the report either way
+    pub fn step(&mut self, name: &'static str, check: impl FnOnce() -> Result<String, String>) {
+        if self.steps.iter().any(|s| !s.ok) {
+            self.steps.push(SelfTestStep {
+                name,
+                ok: false,
+                skipped: true,
+                latency_ms: None,
+                detail: None,
+            });
+            return;
+        }
+        let started = Instant::now();
+        let result = check();
+        let latency_ms = started.elapsed().as_millis() as u64;
+        let ok = result.is_ok();
+        self.steps.push(SelfTestStep {
+            name,
+            ok,
+            skipped: false,
+            latency_ms: Some(latency_ms),
+            detail: Some(result.unwrap_or_else(|e| e)),
+        });
+    }
+
+    pub fn report(self) -> SelfTestReport {
+        let failed_step = self.steps.iter().find(|s| !s.ok && !s.skipped).map(|s| s.name);
+        SelfTestReport {
+            port: self.port,
+            ok: failed_step.is_none(),
+            failed_step,
+            steps: self.steps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_after_a_failure_are_skipped() {
+        let mut test = SelfTest::new(8765);
+        test.step(PROCESS, || Ok("pid 42".into()));
+        test.step(TCP_CONNECT, || Err("connection refused".into()));
+        test.step(HEALTH, || unreachable!());
+        let report = test.report();
+        assert!(!report.ok);
+        assert_eq!(report.failed_step, Some(TCP_CONNECT));
+        assert_eq!(report.steps[1].detail.as_deref(), Some("connection refused"));
+        assert!(report.steps[2].skipped && report.steps[2].latency_ms.is_none());
+    }
+}
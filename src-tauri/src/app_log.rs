@@ -0,0 +1,170 @@
+//! Rust-side logging through `tracing`: events go to stdout, to a daily-rotated
+//! app.log in the data directory (release builds on Windows have no console),
+//! and to an in-memory ring buffer that get_app_log and diagnostics bundles read.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Layer, Registry};
+
+const CAPACITY: usize = 2000;
+const LOG_FILE: &str = "app.log";
+pub const LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+static BUFFER: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+/// Flushes the file writer's background thread; must live as long as the app
+static FILE_GUARD: OnceLock<tracing_appender::non_blocking::WorkerGuard> = OnceLock::new();
+
+#[derive(Serialize, Clone)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    /// The message followed by the event's fields as `key=value`
+    pub message: String,
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:<5} {}: {}", self.timestamp, self.level, self.target, self.message)
+    }
+}
+
+/// Install the global subscriber. Safe to call once; later calls are ignored.
+pub fn init(data_dir: &Path, level: &str) {
+    let (filter, handle) = reload::Layer::new(parse_level(level).unwrap_or(LevelFilter::INFO));
+    let appender = tracing_appender::rolling::daily(data_dir, LOG_FILE);
+    let (file_writer, guard) = tracing_appender::non_blocking(appender);
+    let installed = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(fmt::layer().with_ansi(false).with_writer(file_writer))
+        .with(RingBuffer)
+        .try_init()
+        .is_ok();
+    if installed {
+        let _ = LEVEL.set(handle);
+        let _ = FILE_GUARD.set(guard);
+    }
+}
+
+pub fn parse_level(level: &str) -> Option<LevelFilter> {
+    let level = level.trim().to_lowercase();
+    if !LEVELS.contains(&level.as_str()) {
+        return None;
+    }
+    level.parse().ok()
+}
+
+/// Change the level at runtime (the log_level setting)
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = parse_level(level).ok_or_else(|| format!("Unknown log level: {}", level))?;
+    match LEVEL.get() {
+        Some(handle) => handle.reload(filter).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// The last `lines` buffered entries at `min_level` or more severe, oldest first
+pub fn entries(lines: usize, min_level: Option<LevelFilter>) -> Vec<LogEntry> {
+    let buffer = BUFFER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut matching: Vec<LogEntry> = buffer
+        .iter()
+        .rev()
+        .filter(|entry| match (min_level, entry.level.parse::<Level>()) {
+            (Some(min), Ok(level)) => min >= level,
+            _ => true,
+        })
+        .take(lines)
+        .cloned()
+        .collect();
+    matching.reverse();
+    matching
+}
+
+/// Every buffered entry, formatted one per line
+pub fn snapshot() -> Vec<String> {
+    entries(CAPACITY, None).iter().map(ToString::to_string).collect()
+}
+
+struct RingBuffer;
+
+impl<S: Subscriber> Layer<S> for RingBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        let entry = LogEntry {
+            timestamp: timestamp(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        };
+        let mut buffer = BUFFER.lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.len() == CAPACITY {
+            buffer.pop_front();
+        }
+        buffer.push_back(entry);
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+/// Current time as "YYYY-MM-DD HH:MM:SS" UTC
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
@@ -0,0 +1,260 @@
+//! Reference files kept with a project (images, research PDFs, notes), copied
+//! into `data_dir/attachments/<project_id>/<id>_<filename>` and indexed in the
+//! attachments table. A file already attached to the project, by content, is
+//! reported instead of stored again.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::db::Database;
+
+pub const ATTACHMENTS_DIR: &str = "attachments";
+/// Total size of one project's attachments
+const QUOTA_BYTES: u64 = 1024 * 1024 * 1024;
+const MAX_FILENAME_CHARS: usize = 120;
+
+#[derive(Serialize, Debug)]
+pub struct Attachment {
+    pub id: String,
+    pub project_id: String,
+    pub filename: String,
+    pub mime: String,
+    pub size_bytes: u64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct Added {
+    pub attachment: Attachment,
+    /// The project already had this file; `attachment` is the existing one and
+    /// nothing was copied
+    pub duplicate: bool,
+}
+
+/// Copy `source` in as an attachment of `project_id`
+pub fn add(
+    db: &Database,
+    data_dir: &Path,
+    project_id: &str,
+    source: &Path,
+) -> Result<Added, String> {
+    check_id(project_id)?;
+    if !db.project_exists(project_id).map_err(|e| e.to_string())? {
+        return Err(format!("Project not found: {}", project_id));
+    }
+    let metadata =
+        fs::metadata(source).map_err(|e| format!("Cannot read {}: {}", source.display(), e))?;
+    if !metadata.is_file() {
+        return Err(format!("Not a file: {}", source.display()));
+    }
+    let filename = clean_filename(source)?;
+    let (sha256, size_bytes) = hash_file(source)?;
+    if let Some(attachment) =
+        db.find_attachment(project_id, &sha256).map_err(|e| e.to_string())?
+    {
+        return Ok(Added { attachment, duplicate: true });
+    }
+    let used = db.attachments_size(project_id).map_err(|e| e.to_string())?;
+    if used + size_bytes > QUOTA_BYTES {
+        return Err(format!(
+            "This project's attachments would take {:.1} MB; the limit is {} MB",
+            (used + size_bytes) as f64 / (1024.0 * 1024.0),
+            QUOTA_BYTES / (1024 * 1024)
+        ));
+    }
+
+    let id = new_id();
+    let dir = project_dir(data_dir, project_id);
+    fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    let path = dir.join(stored_name(&id, &filename));
+    // Copied under another name first, so a half-copied file never has a row
+    let tmp = dir.join(format!(".{}.tmp", id));
+    fs::copy(source, &tmp)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Cannot copy {}: {}", source.display(), e)
+        })?;
+    let attachment = Attachment {
+        id,
+        project_id: project_id.to_string(),
+        mime: mime_for(&filename).to_string(),
+        filename,
+        size_bytes,
+        sha256,
+        created_at: String::new(),
+    };
+    match db.insert_attachment(&attachment) {
+        Ok(attachment) => Ok(Added { attachment, duplicate: false }),
+        Err(e) => {
+            let _ = fs::remove_file(&path);
+            Err(e.to_string())
+        }
+    }
+}
+
+/// Where an attachment's file lives, checked to resolve inside the attachments
+/// directory so a tampered row can't point anywhere else
+pub fn resolve(data_dir: &Path, attachment: &Attachment) -> Result<PathBuf, String> {
+    check_id(&attachment.project_id)?;
+    let root = data_dir.join(ATTACHMENTS_DIR);
+    let path = project_dir(data_dir, &attachment.project_id)
+        .join(stored_name(&attachment.id, &attachment.filename));
+    let missing = |e: std::io::Error| format!("Cannot open {}: {}", path.display(), e);
+    let root = root.canonicalize().map_err(missing)?;
+    let target = path.canonicalize().map_err(missing)?;
+    if !target.starts_with(&root) || !target.is_file() {
+        return Err(format!("{} is outside the attachments folder", target.display()));
+    }
+    Ok(target)
+}
+
+/// Delete an attachment's file; a missing file is fine
+pub fn remove_file(data_dir: &Path, attachment: &Attachment) -> Result<(), String> {
+    check_id(&attachment.project_id)?;
+    let path = project_dir(data_dir, &attachment.project_id)
+        .join(stored_name(&attachment.id, &attachment.filename));
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Cannot delete {}: {}", path.display(), e)),
+    }
+}
+
+fn project_dir(data_dir: &Path, project_id: &str) -> PathBuf {
+    data_dir.join(ATTACHMENTS_DIR).join(project_id)
+}
+
+fn stored_name(id: &str, filename: &str) -> String {
+    format!("{}_{}", id, filename)
+}
+
+/// Project ids become directory names, so they mustn't carry path separators
+fn check_id(project_id: &str) -> Result<(), String> {
+    let valid = !project_id.is_empty()
+        && project_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid project id: {}", project_id))
+    }
+}
+
+/// The source's name with anything a file system might choke on replaced
+fn clean_filename(source: &Path) -> Result<String, String> {
+    let name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("Not a file: {}", source.display()))?;
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_control() || r#"/\:*?"<>|"#.contains(c) { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.');
+    // Keep the extension when shortening a long name
+    let (stem, ext) = match cleaned.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && ext.chars().count() <= 10 => (stem, Some(ext)),
+        _ => (cleaned, None),
+    };
+    let budget = MAX_FILENAME_CHARS - ext.map_or(0, |ext| ext.chars().count() + 1);
+    let stem: String = stem.chars().take(budget).collect();
+    let name = match ext {
+        Some(ext) => format!("{}.{}", stem, ext),
+        None => stem,
+    };
+    if name.is_empty() {
+        Ok("attachment".to_string())
+    } else {
+        Ok(name)
+    }
+}
+
+fn hash_file(path: &Path) -> Result<(String, u64), String> {
+    let mut file =
+        fs::File::open(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    let hash = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    Ok((hash, size))
+}
+
+/// Same shape as the database's own ids
+fn new_id() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn mime_for(filename: &str) -> &'static str {
+    let ext = filename.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("pdf") => "application/pdf",
+        Some("txt") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("html" | "htm") => "text/html",
+        Some("doc") => "application/msword",
+        Some("docx") => {
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        }
+        Some("epub") => "application/epub+zip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicates_are_reported_and_files_stay_inside_the_folder() {
+        let root = std::env::temp_dir().join(format!("attachments-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let data_dir = root.join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        let source = root.join("地图 v2.png");
+        fs::write(&source, b"not really a png").unwrap();
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+
+        let added = add(&db, &data_dir, &project.id, &source).unwrap();
+        assert!(!added.duplicate);
+        let attachment = added.attachment;
+        assert_eq!(attachment.filename, "地图 v2.png");
+        assert_eq!(attachment.mime, "image/png");
+        assert_eq!(attachment.size_bytes, 16);
+        let path = resolve(&data_dir, &attachment).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"not really a png");
+
+        let again = add(&db, &data_dir, &project.id, &source).unwrap();
+        assert!(again.duplicate);
+        assert_eq!(again.attachment.id, attachment.id);
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        let tampered = Attachment { filename: "../../escape.png".into(), ..attachment };
+        fs::write(root.join("escape.png"), b"x").unwrap();
+        assert!(resolve(&data_dir, &tampered).is_err());
+        assert!(add(&db, &data_dir, "../elsewhere", &source).is_err());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,134 @@
+//! Database backups into `data_dir/backups`, taken on a schedule or on request.
+//! Each is a standalone `VACUUM INTO` copy that opens as a database by itself;
+//! only the newest few are kept.
+
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::db::Database;
+
+const FILE_PREFIX: &str = "sanhuoai-";
+const FILE_EXTENSION: &str = "db";
+/// Nothing runs this soon after launch, while the app is still starting up
+pub const STARTUP_DELAY: Duration = Duration::from_secs(60);
+/// How often the scheduler wakes to see whether a backup is due
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Clone)]
+pub struct BackupDone {
+    pub path: String,
+    pub size_bytes: u64,
+    /// "YYYY-MM-DD HH:MM:SS" UTC, as recorded in last_backup_at
+    pub created_at: String,
+    /// Older backups deleted to stay within the retention count
+    pub pruned: Vec<String>,
+}
+
+/// Back up `db` into `dir`, then delete all but the newest `keep` backups there.
+/// Nothing is written when the disk doesn't have room for the copy.
+pub fn create(db: &Database, dir: &Path, keep: usize) -> Result<BackupDone, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    // VACUUM INTO leaves out the free pages
+    let size = db.size().map_err(|e| e.to_string())?;
+    crate::data_location::check_free_space(dir, size.allocated_bytes - size.free_bytes)?;
+    let created_at = crate::app_log::timestamp();
+    let stamp = created_at.replace(['-', ':'], "").replace(' ', "-");
+    let mut path = dir.join(format!("{}{}.{}", FILE_PREFIX, stamp, FILE_EXTENSION));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{}{}-{}.{}", FILE_PREFIX, stamp, n, FILE_EXTENSION));
+        n += 1;
+    }
+    // Written under another name first, so a half-written copy never counts as a backup
+    let tmp = path.with_extension("db.tmp");
+    let _ = fs::remove_file(&tmp);
+    db.backup_into(&tmp)
+        .and_then(|_| fs::rename(&tmp, &path).map_err(|e| e.to_string()))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Failed to back up the database: {}", e)
+        })?;
+    let size_bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    let pruned = prune(dir, keep, &path);
+    Ok(BackupDone { path: path.display().to_string(), size_bytes, created_at, pruned })
+}
+
+/// True when `dir` has no backup younger than `interval`
+pub fn is_due(dir: &Path, interval: Duration) -> bool {
+    match list(dir).last() {
+        // A backup dated in the future (the clock moved back) counts as fresh
+        Some((_, modified)) => modified.elapsed().is_ok_and(|age| age >= interval),
+        None => true,
+    }
+}
+
+/// Backups in `dir` with their modification times, oldest first
+fn list(dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<_> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(FILE_PREFIX)
+                && Path::new(name.as_ref()).extension().is_some_and(|ext| ext == FILE_EXTENSION)
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((entry.path(), modified))
+        })
+        .collect();
+    backups.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    backups
+}
+
+/// Delete all but the newest `keep` backups, always counting `just_created` among
+/// those kept whatever its timestamp says. Returns the deleted paths.
+fn prune(dir: &Path, keep: usize, just_created: &Path) -> Vec<String> {
+    let older: Vec<PathBuf> =
+        list(dir).into_iter().map(|(path, _)| path).filter(|p| p != just_created).collect();
+    let excess = older.len().saturating_sub(keep.max(1) - 1);
+    let mut pruned = Vec::new();
+    for path in &older[..excess] {
+        match fs::remove_file(path) {
+            Ok(()) => pruned.push(path.display().to_string()),
+            Err(e) => tracing::warn!(path = %path.display(), error = %e, "failed to prune backup"),
+        }
+    }
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_and_never_the_one_just_made() {
+        let dir = std::env::temp_dir().join(format!("auto-backup-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let db = Database::new_in_memory().unwrap();
+        assert!(is_due(&dir, Duration::from_secs(3600)));
+
+        let first = create(&db, &dir, 2).unwrap();
+        let second = create(&db, &dir, 2).unwrap();
+        assert!(second.pruned.is_empty());
+        assert_ne!(first.path, second.path);
+        assert!(!is_due(&dir, Duration::from_secs(3600)));
+
+        let third = create(&db, &dir, 2).unwrap();
+        assert_eq!(third.pruned, [first.path]);
+
+        // A clock set back makes the newest backup look oldest; it still stays
+        let earlier = SystemTime::now() - Duration::from_secs(86_400);
+        let file = fs::File::options().write(true).open(&third.path).unwrap();
+        file.set_modified(earlier).unwrap();
+        assert_eq!(prune(&dir, 1, Path::new(&third.path)), [second.path]);
+        let left: Vec<_> = list(&dir).into_iter().map(|(p, _)| p.display().to_string()).collect();
+        assert_eq!(left, [third.path]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
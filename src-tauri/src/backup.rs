@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Project;
+
+/// Bump whenever the envelope layout changes; older files must stay importable
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Versioned, re-importable snapshot of a project and its chapters
+#[derive(Serialize, Deserialize)]
+pub struct ProjectBackup {
+    pub schema_version: u32,
+    /// Unix timestamp (seconds)
+    pub exported_at: u64,
+    pub project: Project,
+    pub chapters: Vec<ChapterBackup>,
+    /// Missing from files made before notes existed
+    #[serde(default)]
+    pub notes: Vec<NoteBackup>,
+    #[serde(default)]
+    pub story_events: Vec<StoryEventBackup>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChapterBackup {
+    pub chapter_num: i64,
+    pub title: String,
+    pub phase: String,
+    pub synopsis: String,
+    pub status: String,
+    pub word_count: i64,
+    pub sort_order: i64,
+    pub paragraphs: Vec<ParagraphBackup>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ParagraphBackup {
+    pub para_index: i64,
+    pub content: String,
+    #[serde(default)]
+    pub scene_tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NoteBackup {
+    pub title: String,
+    pub content: String,
+    pub pinned: bool,
+    pub color: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Backups don't carry characters, so an event comes back without its character
+/// links; its chapter is found again by number
+#[derive(Serialize, Deserialize)]
+pub struct StoryEventBackup {
+    pub title: String,
+    pub description: String,
+    pub story_date: String,
+    pub narrative_order: i64,
+    pub chapter_num: Option<i64>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub fn new(
+    project: Project,
+    chapters: Vec<ChapterBackup>,
+    notes: Vec<NoteBackup>,
+    story_events: Vec<StoryEventBackup>,
+) -> ProjectBackup {
+    let exported_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    ProjectBackup {
+        schema_version: SCHEMA_VERSION,
+        exported_at,
+        project,
+        chapters,
+        notes,
+        story_events,
+    }
+}
+
+pub fn to_json(
+    project: Project,
+    chapters: Vec<ChapterBackup>,
+    notes: Vec<NoteBackup>,
+    story_events: Vec<StoryEventBackup>,
+) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&new(project, chapters, notes, story_events))
+}
+
+pub fn from_json(text: &str) -> Result<ProjectBackup, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(text).map_err(|e| format!("Invalid backup file: {}", e))?;
+    let version = value
+        .get("schema_version")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| "Invalid backup file: missing schema_version".to_string())?;
+    if version > SCHEMA_VERSION as u64 {
+        return Err(format!(
+            "Backup was made by a newer version of the app (schema {}, this app supports up to {}). Please update before importing.",
+            version, SCHEMA_VERSION
+        ));
+    }
+    serde_json::from_value(value).map_err(|e| format!("Invalid backup file: {}", e))
+}
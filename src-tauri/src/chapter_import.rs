@@ -0,0 +1,264 @@
+//! Importing chapters into an existing project from plain text or Markdown
+//! files. Files are read a line at a time and each chapter is handed on as soon
+//! as the next heading ends it, so a whole novel in one file never sits in memory.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Markdown headings up to ###, and 第十二章 / 第3回 / 第一卷 style lines
+const DEFAULT_HEADING: &str =
+    r"^\s*(?:#{1,3}\s+\S.*|第\s*[0-9０-９零〇一二三四五六七八九十百千万两]+\s*[章回节卷].*)$";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Start a new chapter at every heading; otherwise each file is one chapter
+    pub split: bool,
+    /// Replaces the built-in heading pattern; matched against each line
+    pub heading_pattern: Option<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions { split: true, heading_pattern: None }
+    }
+}
+
+impl ImportOptions {
+    /// The heading pattern to split on, or None when not splitting
+    pub fn heading(&self) -> Result<Option<Regex>, String> {
+        if !self.split {
+            return Ok(None);
+        }
+        let pattern = self.heading_pattern.as_deref().filter(|p| !p.trim().is_empty());
+        Regex::new(pattern.unwrap_or(DEFAULT_HEADING))
+            .map(Some)
+            .map_err(|e| format!("Invalid heading pattern: {}", e))
+    }
+}
+
+#[derive(Serialize)]
+pub struct ImportedChapter {
+    pub id: String,
+    pub chapter_num: i64,
+    pub title: String,
+    pub word_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct FileImport {
+    pub path: String,
+    pub encoding: &'static str,
+    pub chapters: Vec<ImportedChapter>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Encoding {
+    Utf8,
+    /// Superset of GBK and GB2312, which older Chinese text files use
+    Gb18030,
+}
+
+/// Read `path` and pass each chapter's title and body to `add`, in file order
+pub fn import_file(
+    path: &Path,
+    heading: Option<&Regex>,
+    mut add: impl FnMut(&str, &str) -> Result<ImportedChapter, String>,
+) -> Result<FileImport, String> {
+    let read_error = |e: std::io::Error| format!("Failed to read {}: {}", path.display(), e);
+    let encoding = detect_encoding(path)?;
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let mut report = FileImport {
+        path: path.display().to_string(),
+        encoding: match encoding {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Gb18030 => "GB18030",
+        },
+        chapters: Vec::new(),
+        warnings: Vec::new(),
+    };
+    if encoding == Encoding::Gb18030 {
+        report.warnings.push("Not valid UTF-8; read as GB18030".into());
+    }
+
+    let mut reader = BufReader::new(File::open(path).map_err(read_error)?);
+    let mut splitter = Splitter::new(stem, heading);
+    let mut undecodable = 0;
+    let mut bytes = Vec::new();
+    let mut first = true;
+    loop {
+        bytes.clear();
+        if reader.read_until(b'\n', &mut bytes).map_err(read_error)? == 0 {
+            break;
+        }
+        let mut raw = bytes.as_slice();
+        if first {
+            raw = raw.strip_prefix(b"\xef\xbb\xbf").unwrap_or(raw);
+            first = false;
+        }
+        let (line, had_errors) = decode(raw, encoding);
+        undecodable += usize::from(had_errors);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if let Some(section) = splitter.push(line) {
+            emit(section, &mut report, &mut add)?;
+        }
+    }
+    let saw_heading = splitter.saw_heading;
+    emit(splitter.current, &mut report, &mut add)?;
+
+    if undecodable > 0 {
+        let warning = format!("{} lines had characters that could not be decoded", undecodable);
+        report.warnings.push(warning);
+    }
+    if heading.is_some() && !saw_heading && !report.chapters.is_empty() {
+        report.warnings.push("No headings found; imported as a single chapter".into());
+    }
+    if report.chapters.is_empty() {
+        report.warnings.push("No chapters were created from this file".into());
+    }
+    Ok(report)
+}
+
+fn emit(
+    section: Section,
+    report: &mut FileImport,
+    add: &mut impl FnMut(&str, &str) -> Result<ImportedChapter, String>,
+) -> Result<(), String> {
+    let body = section.lines.join("\n");
+    let body = body.trim_matches('\n').trim_end();
+    if body.trim().is_empty() {
+        if section.from_heading {
+            report.warnings.push(format!("\"{}\" has no text and was skipped", section.title));
+        }
+        return Ok(());
+    }
+    report.chapters.push(add(&section.title, body)?);
+    Ok(())
+}
+
+/// Scans the whole file once so a non-UTF-8 byte near the end can't leave the
+/// first half decoded differently from the rest. UTF-16 is refused outright.
+fn detect_encoding(path: &Path) -> Result<Encoding, String> {
+    let read_error = |e: std::io::Error| format!("Failed to read {}: {}", path.display(), e);
+    let mut reader = BufReader::new(File::open(path).map_err(read_error)?);
+    let mut start = [0u8; 2];
+    let read = reader.read(&mut start).map_err(read_error)?;
+    if read == 2 && (start == [0xff, 0xfe] || start == [0xfe, 0xff]) {
+        return Err(format!("{} is UTF-16; save it as UTF-8 first", path.display()));
+    }
+    // Lines end on '\n', which never falls inside a UTF-8 or GB18030 sequence
+    let mut line = start[..read].to_vec();
+    loop {
+        reader.read_until(b'\n', &mut line).map_err(read_error)?;
+        if line.is_empty() {
+            return Ok(Encoding::Utf8);
+        }
+        if std::str::from_utf8(&line).is_err() {
+            return Ok(Encoding::Gb18030);
+        }
+        line.clear();
+    }
+}
+
+fn decode(bytes: &[u8], encoding: Encoding) -> (std::borrow::Cow<'_, str>, bool) {
+    match encoding {
+        Encoding::Utf8 => (String::from_utf8_lossy(bytes), false),
+        Encoding::Gb18030 => encoding_rs::GB18030.decode_without_bom_handling(bytes),
+    }
+}
+
+struct Section {
+    title: String,
+    lines: Vec<String>,
+    /// False for the text before the first heading (or a whole unsplit file)
+    from_heading: bool,
+}
+
+struct Splitter<'a> {
+    heading: Option<&'a Regex>,
+    current: Section,
+    saw_heading: bool,
+}
+
+impl<'a> Splitter<'a> {
+    /// Text before the first heading is titled `untitled`, normally the file name
+    fn new(untitled: String, heading: Option<&'a Regex>) -> Self {
+        Splitter {
+            heading,
+            current: Section { title: untitled, lines: Vec::new(), from_heading: false },
+            saw_heading: false,
+        }
+    }
+
+    /// Add a line; returns the section it ended, if it was a heading
+    fn push(&mut self, line: &str) -> Option<Section> {
+        if !self.heading.is_some_and(|heading| heading.is_match(line)) {
+            self.current.lines.push(line.to_string());
+            return None;
+        }
+        self.saw_heading = true;
+        let title = line.trim().trim_start_matches('#').trim_end_matches('#').trim().to_string();
+        let next = Section { title, lines: Vec::new(), from_heading: true };
+        Some(std::mem::replace(&mut self.current, next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(name: &str, bytes: &[u8], options: ImportOptions) -> FileImport {
+        let dir = std::env::temp_dir()
+            .join(format!("chapter-import-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        let heading = options.heading().unwrap();
+        let mut num = 0;
+        let report = import_file(&path, heading.as_ref(), |title, body| {
+            num += 1;
+            Ok(ImportedChapter {
+                id: body.to_string(),
+                chapter_num: num,
+                title: title.to_string(),
+                word_count: body.chars().count() as i64,
+            })
+        })
+        .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        report
+    }
+
+    #[test]
+    fn splits_on_markdown_and_chinese_headings() {
+        let text = "\u{feff}楔子\r\n\r\n# 第一卷\n## 第一章 长夜\n夜色深沉。\n\n第二章 黎明\n天亮了。\n";
+        let report = import("长夜.md", text.as_bytes(), ImportOptions::default());
+        assert_eq!(report.encoding, "UTF-8");
+        let chapters: Vec<_> =
+            report.chapters.iter().map(|c| (c.title.as_str(), c.id.as_str())).collect();
+        assert_eq!(
+            chapters,
+            [("长夜", "楔子"), ("第一章 长夜", "夜色深沉。"), ("第二章 黎明", "天亮了。")]
+        );
+        assert_eq!(report.warnings, ["\"第一卷\" has no text and was skipped"]);
+
+        let unsplit = ImportOptions { split: false, ..Default::default() };
+        let whole = import("长夜.txt", text.as_bytes(), unsplit);
+        assert_eq!(whole.chapters.len(), 1);
+        assert!(whole.chapters[0].id.starts_with("楔子\n\n# 第一卷"));
+    }
+
+    #[test]
+    fn falls_back_to_gb18030() {
+        // "第一章\n你好" in GBK
+        let bytes = b"\xb5\xda\xd2\xbb\xd5\xc2\n\xc4\xe3\xba\xc3\n";
+        let report = import("old.txt", bytes, ImportOptions::default());
+        assert_eq!(report.encoding, "GB18030");
+        let chapter = &report.chapters[0];
+        assert_eq!((chapter.title.as_str(), chapter.id.as_str()), ("第一章", "你好"));
+    }
+}
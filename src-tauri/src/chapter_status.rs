@@ -0,0 +1,70 @@
+//! The editorial workflow a chapter moves through: draft → revised → final. A
+//! chapter can always go back to draft, but skipping revision needs `force`.
+//! The database only accepts these three values (see schema.sql's triggers).
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChapterStatus {
+    Draft,
+    Revised,
+    Final,
+}
+
+impl ChapterStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChapterStatus::Draft => "draft",
+            ChapterStatus::Revised => "revised",
+            ChapterStatus::Final => "final",
+        }
+    }
+
+    /// Values older versions wrote are mapped like the agent's migration 027 does;
+    /// anything else is a draft
+    pub fn parse(value: &str) -> ChapterStatus {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "revised" | "reviewing" => ChapterStatus::Revised,
+            "final" | "done" | "written" => ChapterStatus::Final,
+            _ => ChapterStatus::Draft,
+        }
+    }
+
+    /// Whether a chapter in `self` may move to `to`
+    pub fn check_transition(self, to: ChapterStatus, force: bool) -> Result<(), String> {
+        if self == ChapterStatus::Draft && to == ChapterStatus::Final && !force {
+            return Err("A draft can't be marked final before it is revised; \
+                        pass force to skip revision"
+                .into());
+        }
+        Ok(())
+    }
+}
+
+/// Chapters per status, for project_stats
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct StatusCounts {
+    pub draft: i64,
+    pub revised: i64,
+    #[serde(rename = "final")]
+    pub final_: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_skipping_revision_needs_force() {
+        use ChapterStatus::*;
+        assert!(Draft.check_transition(Revised, false).is_ok());
+        assert!(Revised.check_transition(Final, false).is_ok());
+        assert!(Final.check_transition(Draft, false).is_ok());
+        assert!(Revised.check_transition(Draft, false).is_ok());
+        assert!(Draft.check_transition(Final, false).is_err());
+        assert!(Draft.check_transition(Final, true).is_ok());
+        assert_eq!(ChapterStatus::parse("done"), Final);
+        assert_eq!(ChapterStatus::parse("writing"), Draft);
+    }
+}
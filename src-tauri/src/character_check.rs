@@ -0,0 +1,284 @@
+//! Character names across the manuscript: where each character appears, who
+//! never does, and Latin-script words a letter or two off a name, which are
+//! usually stragglers from a rename or plain typos.
+
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::find_replace::ChapterText;
+use crate::tokenize::{self, Token};
+use crate::Character;
+
+/// Shorter words are too often a real word one letter off a name
+const MIN_NEAR_MISS_CHARS: usize = 4;
+/// Names this long or longer tolerate two edits rather than one
+const TWO_EDIT_CHARS: usize = 6;
+
+#[derive(Serialize)]
+pub struct ChapterMentions {
+    pub chapter_id: String,
+    pub chapter_num: i64,
+    pub count: u32,
+}
+
+#[derive(Serialize)]
+pub struct NearMiss {
+    /// As written in the text
+    pub spelling: String,
+    /// The name or alias word it resembles
+    pub resembles: String,
+    pub distance: usize,
+    pub count: u32,
+    pub chapter_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct CharacterMentions {
+    pub character_id: String,
+    pub name: String,
+    pub aliases: Vec<String>,
+    /// Name and alias mentions together
+    pub total: u32,
+    /// Only chapters mentioning the character, in manuscript order
+    pub chapters: Vec<ChapterMentions>,
+    pub near_misses: Vec<NearMiss>,
+}
+
+#[derive(Serialize)]
+pub struct ConsistencyReport {
+    /// In roster order
+    pub characters: Vec<CharacterMentions>,
+    /// Ids of roster characters mentioned nowhere
+    pub never_mentioned: Vec<String>,
+}
+
+struct Names {
+    /// Name and aliases, longest first so "John Smith" wins over "John"
+    regex: Regex,
+    /// Their Latin words, lowercased, for near-miss matching
+    words: Vec<String>,
+}
+
+impl Names {
+    fn new(character: &Character) -> Result<Option<Self>, String> {
+        let mut names: Vec<&str> = std::iter::once(&character.name)
+            .chain(&character.aliases)
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() {
+            return Ok(None);
+        }
+        names.sort_by_key(|name| std::cmp::Reverse(name.chars().count()));
+        // \b only next to Latin letters: Han text has no word boundaries
+        let pattern = names
+            .iter()
+            .map(|name| {
+                let start = name.starts_with(tokenize::is_latin);
+                let end = name.ends_with(tokenize::is_latin);
+                format!(
+                    "{}{}{}",
+                    if start { r"\b" } else { "" },
+                    regex::escape(name),
+                    if end { r"\b" } else { "" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        let regex = Regex::new(&pattern).map_err(|e| format!("Invalid name pattern: {}", e))?;
+        let words = names
+            .iter()
+            .flat_map(|name| name.split(|c: char| !tokenize::is_latin(c)))
+            .filter(|word| word.chars().count() >= MIN_NEAR_MISS_CHARS)
+            .map(str::to_lowercase)
+            .collect();
+        Ok(Some(Names { regex, words }))
+    }
+}
+
+/// Scan `chapters` for everyone on the roster, calling `progress(done, total)`
+/// after each chapter
+pub fn check(
+    roster: &[Character],
+    chapters: &[ChapterText],
+    mut progress: impl FnMut(usize, usize),
+) -> Result<ConsistencyReport, String> {
+    let names = roster.iter().map(Names::new).collect::<Result<Vec<_>, _>>()?;
+    // (character, chapter index) -> mentions
+    let mut mentions: BTreeMap<(usize, usize), u32> = BTreeMap::new();
+    // Capitalized Latin words as written -> mentions per chapter index
+    let mut capitalized: HashMap<String, BTreeMap<usize, u32>> = HashMap::new();
+    // Latin words also written in lowercase are ordinary words, not names
+    let mut lowercase = HashSet::new();
+
+    for (index, chapter) in chapters.iter().enumerate() {
+        for (character, names) in names.iter().enumerate() {
+            let Some(names) = names else { continue };
+            let count: usize = chapter
+                .paragraphs
+                .iter()
+                .map(|p| names.regex.find_iter(&p.content).count())
+                .sum();
+            if count > 0 {
+                mentions.insert((character, index), count as u32);
+            }
+        }
+        for token in tokenize::chapter_segments(chapter).into_iter().flatten() {
+            let Token::Word(word) = token else { continue };
+            let word = word.trim_end_matches("'s").trim_matches('\'');
+            if !word.chars().all(tokenize::is_latin) {
+                continue;
+            }
+            if word.starts_with(char::is_uppercase) {
+                if word.chars().count() >= MIN_NEAR_MISS_CHARS {
+                    let chapters = capitalized.entry(word.to_string()).or_default();
+                    *chapters.entry(index).or_default() += 1;
+                }
+            } else {
+                lowercase.insert(word.to_string());
+            }
+        }
+        progress(index + 1, chapters.len());
+    }
+
+    let known: HashSet<&str> =
+        names.iter().flatten().flat_map(|n| n.words.iter().map(String::as_str)).collect();
+    let mut near_misses: HashMap<usize, Vec<NearMiss>> = HashMap::new();
+    for (spelling, by_chapter) in capitalized {
+        let lower = spelling.to_lowercase();
+        if known.contains(lower.as_str()) || lowercase.contains(&lower) {
+            continue;
+        }
+        let closest = names
+            .iter()
+            .enumerate()
+            .filter_map(|(character, names)| Some((character, names.as_ref()?)))
+            .flat_map(|(character, names)| names.words.iter().map(move |w| (character, w)))
+            .filter_map(|(character, word)| {
+                let limit = if word.chars().count() >= TWO_EDIT_CHARS { 2 } else { 1 };
+                let distance = edit_distance(&lower, word, limit)?;
+                Some((distance, character, word))
+            })
+            .min_by_key(|(distance, character, _)| (*distance, *character));
+        if let Some((distance, character, word)) = closest {
+            near_misses.entry(character).or_default().push(NearMiss {
+                spelling,
+                resembles: word.clone(),
+                distance,
+                count: by_chapter.values().sum(),
+                chapter_ids: by_chapter.keys().map(|&i| chapters[i].chapter_id.clone()).collect(),
+            });
+        }
+    }
+
+    let mut report = ConsistencyReport { characters: Vec::new(), never_mentioned: Vec::new() };
+    for (index, character) in roster.iter().enumerate() {
+        let chapter_mentions: Vec<ChapterMentions> = mentions
+            .range((index, 0)..(index + 1, 0))
+            .map(|(&(_, chapter), &count)| ChapterMentions {
+                chapter_id: chapters[chapter].chapter_id.clone(),
+                chapter_num: chapters[chapter].chapter_num,
+                count,
+            })
+            .collect();
+        let total = chapter_mentions.iter().map(|c| c.count).sum();
+        if total == 0 {
+            report.never_mentioned.push(character.id.clone());
+        }
+        let mut misses = near_misses.remove(&index).unwrap_or_default();
+        misses.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.spelling.cmp(&b.spelling)));
+        report.characters.push(CharacterMentions {
+            character_id: character.id.clone(),
+            name: character.name.clone(),
+            aliases: character.aliases.clone(),
+            total,
+            chapters: chapter_mentions,
+            near_misses: misses,
+        });
+    }
+    Ok(report)
+}
+
+/// Levenshtein distance between `a` and `b` if it's 1..=limit
+fn edit_distance(a: &str, b: &str, limit: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > limit {
+        return None;
+    }
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        if current.iter().min().is_some_and(|&best| best > limit) {
+            return None;
+        }
+        previous = current;
+    }
+    let distance = previous[b.len()];
+    (1..=limit).contains(&distance).then_some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_replace::Paragraph;
+
+    fn character(id: &str, name: &str, aliases: &[&str]) -> Character {
+        Character {
+            id: id.into(),
+            name: name.into(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn chapter(id: &str, text: &str) -> ChapterText {
+        ChapterText {
+            chapter_id: id.into(),
+            chapter_num: 1,
+            title: String::new(),
+            paragraphs: vec![Paragraph { id: "p".into(), para_index: 0, content: text.into() }],
+        }
+    }
+
+    #[test]
+    fn counts_names_and_aliases_and_flags_near_misses() {
+        let roster = [
+            character("a", "林晚", &["晚晚"]),
+            character("b", "Eleanor Vance", &["Eleanor", "Nell"]),
+            character("c", "Theo", &[]),
+        ];
+        let chapters = [
+            chapter("c1", "林晚笑了。晚晚，走吧。Eleanor Vance nodded; Nell smiled."),
+            chapter("c2", "Elenor waved. Eleanor's cup. Then the Eleanorr left, then"),
+        ];
+        let mut done = Vec::new();
+        let report = check(&roster, &chapters, |d, total| done.push((d, total))).unwrap();
+        assert_eq!(done, [(1, 2), (2, 2)]);
+
+        let lin = &report.characters[0];
+        assert_eq!((lin.total, lin.chapters.len()), (2, 1));
+        let eleanor = &report.characters[1];
+        // "Eleanor Vance" counts once, not again as "Eleanor"
+        assert_eq!(eleanor.total, 3);
+        assert_eq!(eleanor.chapters[1].chapter_id, "c2");
+        let misses: Vec<_> =
+            eleanor.near_misses.iter().map(|m| (m.spelling.as_str(), m.distance)).collect();
+        assert_eq!(misses, [("Eleanorr", 1), ("Elenor", 1)]);
+        // "Then" is a Theo lookalike, but it's an ordinary word here
+        assert!(report.characters[2].near_misses.is_empty());
+        assert_eq!(report.never_mentioned, ["c"]);
+    }
+
+    #[test]
+    fn edit_distance_stops_at_the_limit() {
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        assert_eq!(edit_distance("same", "same", 2), None);
+    }
+}
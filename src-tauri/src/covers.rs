@@ -0,0 +1,188 @@
+//! Project cover images, copied into `data_dir/covers` so they survive the
+//! original being moved or deleted. Each cover gets a small PNG thumbnail next
+//! to it for the project cards; the full image is only read when asked for.
+
+use base64::Engine;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+pub const COVERS_DIR: &str = "covers";
+const MAX_BYTES: u64 = 10 * 1024 * 1024;
+const THUMBNAIL_SIZE: u32 = 320;
+const THUMBNAIL_SUFFIX: &str = "thumb.png";
+
+#[derive(Clone, Copy)]
+enum Format {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl Format {
+    /// By content rather than extension, so a renamed file can't slip through
+    fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Format::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Format::Jpeg)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(Format::WebP)
+        } else {
+            None
+        }
+    }
+
+    fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "png" => Some(Format::Png),
+            "jpg" => Some(Format::Jpeg),
+            "webp" => Some(Format::WebP),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::WebP => "webp",
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Jpeg => "image/jpeg",
+            Format::WebP => "image/webp",
+        }
+    }
+
+    fn image_format(self) -> image::ImageFormat {
+        match self {
+            Format::Png => image::ImageFormat::Png,
+            Format::Jpeg => image::ImageFormat::Jpeg,
+            Format::WebP => image::ImageFormat::WebP,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Cover {
+    /// Absolute path of the file the data came from
+    pub path: String,
+    /// `data:` URL, usable directly as an <img> src
+    pub data_url: String,
+}
+
+/// Copy `source` in as the project's cover, replacing any previous one, and
+/// return its path relative to `data_dir` for the project row
+pub fn set(data_dir: &Path, project_id: &str, source: &Path) -> Result<String, String> {
+    check_id(project_id)?;
+    let size = std::fs::metadata(source)
+        .map_err(|e| format!("Cannot read {}: {}", source.display(), e))?
+        .len();
+    if size > MAX_BYTES {
+        return Err(format!(
+            "The image is {:.1} MB; covers can be at most {} MB",
+            size as f64 / (1024.0 * 1024.0),
+            MAX_BYTES / (1024 * 1024)
+        ));
+    }
+    let bytes = std::fs::read(source)
+        .map_err(|e| format!("Cannot read {}: {}", source.display(), e))?;
+    let format = Format::sniff(&bytes).ok_or("Covers must be PNG, JPEG or WebP images")?;
+    let image = image::load_from_memory_with_format(&bytes, format.image_format())
+        .map_err(|e| format!("The image could not be decoded: {}", e))?;
+    let mut thumbnail = Vec::new();
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut std::io::Cursor::new(&mut thumbnail), image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to create the thumbnail: {}", e))?;
+
+    let dir = data_dir.join(COVERS_DIR);
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let file_name = format!("{}.{}", project_id, format.extension());
+    replace(&dir.join(&file_name), &bytes)?;
+    replace(&thumbnail_path(data_dir, project_id), &thumbnail)?;
+    // A previous cover in another format would otherwise linger
+    for other in [Format::Png, Format::Jpeg, Format::WebP] {
+        if other.extension() != format.extension() {
+            let _ = std::fs::remove_file(dir.join(format!("{}.{}", project_id, other.extension())));
+        }
+    }
+    Ok(format!("{}/{}", COVERS_DIR, file_name))
+}
+
+/// The cover at `relative` (as stored on the project row), or its thumbnail
+pub fn read(
+    data_dir: &Path,
+    project_id: &str,
+    relative: &str,
+    thumbnail: bool,
+) -> Result<Cover, String> {
+    let (path, format) = if thumbnail {
+        (thumbnail_path(data_dir, project_id), Format::Png)
+    } else {
+        let path = data_dir.join(relative);
+        let format = path
+            .extension()
+            .and_then(|ext| Format::from_extension(&ext.to_string_lossy()))
+            .ok_or_else(|| format!("Unrecognised cover file: {}", relative))?;
+        (path, format)
+    };
+    let bytes =
+        std::fs::read(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    Ok(Cover {
+        path: path.display().to_string(),
+        data_url: format!(
+            "data:{};base64,{}",
+            format.mime(),
+            base64::engine::general_purpose::STANDARD.encode(bytes)
+        ),
+    })
+}
+
+/// Delete the project's cover and thumbnail; missing files are fine
+pub fn remove(data_dir: &Path, project_id: &str) -> Result<(), String> {
+    check_id(project_id)?;
+    let dir = data_dir.join(COVERS_DIR);
+    let mut files = [Format::Png, Format::Jpeg, Format::WebP]
+        .map(|format| dir.join(format!("{}.{}", project_id, format.extension())))
+        .to_vec();
+    files.push(thumbnail_path(data_dir, project_id));
+    for path in &files {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Cannot delete {}: {}", path.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+/// Project ids become file names, so they mustn't carry path separators
+pub fn check_id(project_id: &str) -> Result<(), String> {
+    let valid = !project_id.is_empty()
+        && project_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid project id: {}", project_id))
+    }
+}
+
+fn thumbnail_path(data_dir: &Path, project_id: &str) -> PathBuf {
+    data_dir.join(COVERS_DIR).join(format!("{}.{}", project_id, THUMBNAIL_SUFFIX))
+}
+
+/// Write next to `path` and rename over it, so a reader never sees half a file
+fn replace(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, bytes).map_err(|e| format!("Cannot write {}: {}", tmp.display(), e))?;
+    std::fs::rename(&tmp, path).map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        format!("Cannot replace {}: {}", path.display(), e)
+    })
+}
@@ -0,0 +1,537 @@
+//! API keys and proxy settings handed to the agent as environment variables.
+//! Secrets go to the OS keychain; when there is none (headless Linux, some
+//! sandboxes) they are XChaCha20-Poly1305 encrypted into api_credentials under a
+//! key derived with Argon2 from the user's passphrase and a per-machine secret
+//! kept outside the data dir, so neither the passphrase nor a copy of the data dir
+//! decrypts them on its own. The passphrase is never stored: unlock_credentials
+//! derives the key once per session and only that key is kept, in memory.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::db::Database;
+
+const KEYRING_SERVICE: &str = "sanhuoai";
+const SALT_LEN: usize = 16;
+const XNONCE_LEN: usize = 24;
+/// Sealed into credential_passphrase.verifier; opening it proves the passphrase
+const VERIFIER: &[u8] = b"sanhuoai credentials";
+/// Keychain entry holding the machine secret, hex encoded
+const MACHINE_SECRET_ENTRY: &str = "credentials-machine-secret";
+/// Without a keychain the machine secret lives here, under the user's config
+/// dir; a folder of its own so it never sits inside the default data dir
+const MACHINE_SECRET_DIR: &str = "sanhuoai-keys";
+const MACHINE_SECRET_FILE: &str = "machine-secret";
+const MACHINE_SECRET_LEN: usize = 32;
+
+/// Credentials the app manages; each is exported to the agent under its own name
+/// (provider keys match PROVIDER_ENV_MAP in agent/agents/llm.py)
+pub const CREDENTIAL_NAMES: &[&str] = &[
+    "OPENAI_API_KEY",
+    "ANTHROPIC_API_KEY",
+    "GEMINI_API_KEY",
+    "DEEPSEEK_API_KEY",
+    "QWEN_API_KEY",
+    "ZHIPU_API_KEY",
+    "MOONSHOT_API_KEY",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+];
+
+pub const STORAGE_KEYCHAIN: &str = "keychain";
+pub const STORAGE_PASSPHRASE: &str = "passphrase";
+
+/// Row of api_credentials; ciphertext/nonce are only set for encrypted storage
+pub struct StoredCredential {
+    pub name: String,
+    pub storage: String,
+    pub ciphertext: Option<Vec<u8>>,
+    pub nonce: Option<Vec<u8>>,
+}
+
+/// The credential_passphrase row
+pub struct PassphraseRecord {
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub verifier: Vec<u8>,
+}
+
+/// Serialized as `{ kind, message }` so the UI can ask again on `wrong_passphrase`
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum PassphraseError {
+    WrongPassphrase(String),
+    /// unlock_credentials before change_credentials_passphrase has set one
+    NoPassphrase(String),
+    InvalidPassphrase(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for PassphraseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PassphraseError::WrongPassphrase(msg)
+            | PassphraseError::NoPassphrase(msg)
+            | PassphraseError::InvalidPassphrase(msg)
+            | PassphraseError::Failed(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<String> for PassphraseError {
+    fn from(msg: String) -> Self {
+        PassphraseError::Failed(msg)
+    }
+}
+
+/// The key derived by unlock_credentials, held for the rest of the session.
+/// Writes under the passphrase hold the lock, so a passphrase change can't
+/// interleave with them.
+#[derive(Default)]
+pub struct Session(Mutex<Option<[u8; 32]>>);
+
+impl Session {
+    fn lock(&self) -> MutexGuard<'_, Option<[u8; 32]>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.lock().is_some()
+    }
+}
+
+/// Whether credentials stored under the passphrase can be read this session
+#[derive(Serialize)]
+pub struct LockStatus {
+    pub passphrase_set: bool,
+    pub unlocked: bool,
+}
+
+/// What the frontend gets to see: never the secret itself
+#[derive(Serialize)]
+pub struct CredentialInfo {
+    pub name: String,
+    pub masked: String,
+    pub storage: String,
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if CREDENTIAL_NAMES.contains(&name) {
+        Ok(())
+    } else {
+        Err(format!("Unknown credential: {}", name))
+    }
+}
+
+/// Below this length only the last four characters are shown
+const MASK_PREFIX_MIN_LEN: usize = 20;
+
+/// `sk-...abcd` for a long key, `...abcd` for a short value and `****` for one
+/// too short to show anything of
+pub fn mask(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    if chars.len() <= 8 {
+        return "****".into();
+    }
+    let suffix: String = chars[chars.len() - 4..].iter().collect();
+    if chars.len() < MASK_PREFIX_MIN_LEN {
+        return format!("...{}", suffix);
+    }
+    let prefix: String = match value.find('-') {
+        Some(i) if i < 4 => value[..=i].to_string(),
+        _ => chars[..2].iter().collect(),
+    };
+    format!("{}...{}", prefix, suffix)
+}
+
+fn keyring_entry(name: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, name).map_err(|e| e.to_string())
+}
+
+/// Store in the keychain and read it back; some backends accept writes they
+/// can't return, which counts as no keychain
+fn keychain_store(name: &str, value: &str) -> bool {
+    let Ok(entry) = keyring_entry(name) else {
+        return false;
+    };
+    entry.set_password(value).is_ok() && entry.get_password().ok().as_deref() == Some(value)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    // An odd length leaves a last slice past the end, which fails
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn machine_secret_path() -> Result<PathBuf, String> {
+    #[cfg(not(test))]
+    let base = dirs_next::config_dir().ok_or("No config directory for the machine secret")?;
+    #[cfg(test)]
+    let base = std::env::temp_dir().join(format!("credentials-test-{}", std::process::id()));
+    Ok(base.join(MACHINE_SECRET_DIR).join(MACHINE_SECRET_FILE))
+}
+
+/// Random bytes made once for this user on this machine, from the keychain or
+/// else the secret file, and created on first use. Losing it loses every
+/// credential stored under the passphrase, as forgetting the passphrase would.
+fn machine_secret() -> Result<Vec<u8>, String> {
+    // Tests never touch the real keychain
+    let keychain = cfg!(not(test));
+    if keychain {
+        let stored = keyring_entry(MACHINE_SECRET_ENTRY)
+            .and_then(|entry| entry.get_password().map_err(|e| e.to_string()));
+        if let Some(secret) = stored.ok().as_deref().and_then(from_hex) {
+            return Ok(secret);
+        }
+    }
+    let path = machine_secret_path()?;
+    if let Some(secret) = read_machine_secret(&path)? {
+        return Ok(secret);
+    }
+    let mut secret = vec![0u8; MACHINE_SECRET_LEN];
+    getrandom::getrandom(&mut secret).map_err(|e| e.to_string())?;
+    if keychain && keychain_store(MACHINE_SECRET_ENTRY, &to_hex(&secret)) {
+        return Ok(secret);
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(mut file) => {
+            #[cfg(not(target_os = "windows"))]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let _ = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600));
+            }
+            file.write_all(to_hex(&secret).as_bytes())
+                .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+            Ok(secret)
+        }
+        // Another caller made it first; theirs is the one to use
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => read_machine_secret(&path)?
+            .ok_or_else(|| format!("Machine secret in {} is unreadable", path.display())),
+        Err(e) => Err(format!("Cannot create {}: {}", path.display(), e)),
+    }
+}
+
+/// Errs rather than starting over when the file is there but damaged, since a
+/// new secret would orphan everything sealed under the old one
+fn read_machine_secret(path: &std::path::Path) -> Result<Option<Vec<u8>>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(hex) => match from_hex(&hex) {
+            Some(secret) if secret.len() == MACHINE_SECRET_LEN => Ok(Some(secret)),
+            _ => Err(format!("Machine secret in {} is damaged", path.display())),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Cannot read {}: {}", path.display(), e)),
+    }
+}
+
+/// Argon2id over the passphrase, keyed with the machine secret
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+    let secret = machine_secret()?;
+    let argon2 =
+        Argon2::new_with_secret(&secret, Algorithm::Argon2id, Version::V0x13, Params::default())
+            .map_err(|e| format!("Failed to derive the credentials key: {}", e))?;
+    let mut key = [0u8; 32];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive the credentials key: {}", e))?;
+    Ok(key)
+}
+
+/// (ciphertext, nonce)
+fn seal(key: &[u8; 32], plain: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut nonce = vec![0u8; XNONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|e| e.to_string())?;
+    let ciphertext = XChaCha20Poly1305::new_from_slice(key)
+        .map_err(|e| e.to_string())?
+        .encrypt(XNonce::from_slice(&nonce), plain)
+        .map_err(|_| "Failed to encrypt credential".to_string())?;
+    Ok((ciphertext, nonce))
+}
+
+/// None when the key is wrong or the data was tampered with
+fn open(key: &[u8; 32], ciphertext: &[u8], nonce: &[u8]) -> Option<Vec<u8>> {
+    if nonce.len() != XNONCE_LEN {
+        return None;
+    }
+    XChaCha20Poly1305::new_from_slice(key).ok()?.decrypt(XNonce::from_slice(nonce), ciphertext).ok()
+}
+
+fn reveal(key: Option<&[u8; 32]>, stored: &StoredCredential) -> Result<String, String> {
+    if stored.storage == STORAGE_KEYCHAIN {
+        return keyring_entry(&stored.name)?
+            .get_password()
+            .map_err(|e| format!("Failed to read {} from the keychain: {}", stored.name, e));
+    }
+    let (Some(ciphertext), Some(nonce)) = (&stored.ciphertext, &stored.nonce) else {
+        return Err(format!("Credential {} has no stored value", stored.name));
+    };
+    let key = key.ok_or_else(|| format!("{} is locked; unlock credentials first", stored.name))?;
+    let plain = open(key, ciphertext, nonce)
+        .ok_or_else(|| format!("Failed to decrypt {}", stored.name))?;
+    String::from_utf8(plain).map_err(|e| e.to_string())
+}
+
+fn info(name: &str, storage: &str, value: &str) -> CredentialInfo {
+    CredentialInfo {
+        name: name.to_string(),
+        masked: mask(value),
+        storage: storage.to_string(),
+    }
+}
+
+/// Without a keychain the value is encrypted under the passphrase, which has to
+/// be unlocked this session
+pub fn set(
+    db: &Database,
+    session: &Session,
+    name: &str,
+    value: &str,
+) -> Result<CredentialInfo, String> {
+    check_name(name)?;
+    if keychain_store(name, value) {
+        let stored = StoredCredential {
+            name: name.to_string(),
+            storage: STORAGE_KEYCHAIN.into(),
+            ciphertext: None,
+            nonce: None,
+        };
+        db.put_credential(&stored).map_err(|e| e.to_string())?;
+        return Ok(info(name, &stored.storage, value));
+    }
+    let key = session.lock();
+    let Some(key) = key.as_ref() else {
+        return Err(
+            "No OS keychain is available, so credentials are encrypted with a passphrase; \
+             set or unlock it first"
+                .into(),
+        );
+    };
+    let (ciphertext, nonce) = seal(key, value.as_bytes())?;
+    let stored = StoredCredential {
+        name: name.to_string(),
+        storage: STORAGE_PASSPHRASE.into(),
+        ciphertext: Some(ciphertext),
+        nonce: Some(nonce),
+    };
+    db.put_credential(&stored).map_err(|e| e.to_string())?;
+    Ok(info(name, &stored.storage, value))
+}
+
+pub fn get(db: &Database, session: &Session, name: &str) -> Result<Option<CredentialInfo>, String> {
+    check_name(name)?;
+    match db.get_credential(name).map_err(|e| e.to_string())? {
+        Some(stored) => {
+            let value = reveal(session.lock().as_ref(), &stored)?;
+            Ok(Some(info(name, &stored.storage, &value)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Credentials that can't be read, locked ones included, are listed with a blank mask
+pub fn list(db: &Database, session: &Session) -> Result<Vec<CredentialInfo>, String> {
+    let stored = db.list_credentials().map_err(|e| e.to_string())?;
+    let key = session.lock();
+    Ok(stored
+        .iter()
+        .map(|c| match reveal(key.as_ref(), c) {
+            Ok(value) => info(&c.name, &c.storage, &value),
+            Err(_) => info(&c.name, &c.storage, ""),
+        })
+        .collect())
+}
+
+/// Returns whether anything was stored under `name`
+pub fn delete(db: &Database, name: &str) -> Result<bool, String> {
+    check_name(name)?;
+    if let Ok(entry) = keyring_entry(name) {
+        let _ = entry.delete_password();
+    }
+    db.delete_credential(name).map_err(|e| e.to_string())
+}
+
+pub fn lock_status(db: &Database, session: &Session) -> Result<LockStatus, String> {
+    Ok(LockStatus {
+        passphrase_set: db.credential_passphrase().map_err(|e| e.to_string())?.is_some(),
+        unlocked: session.is_unlocked(),
+    })
+}
+
+/// Derive the key from `passphrase` and keep it for this session
+pub fn unlock(db: &Database, session: &Session, passphrase: &str) -> Result<(), PassphraseError> {
+    let Some(record) = db.credential_passphrase().map_err(|e| e.to_string())? else {
+        return Err(PassphraseError::NoPassphrase("No credentials passphrase has been set".into()));
+    };
+    let key = verified_key(&record, passphrase)?;
+    *session.lock() = Some(key);
+    Ok(())
+}
+
+fn verified_key(record: &PassphraseRecord, passphrase: &str) -> Result<[u8; 32], PassphraseError> {
+    let key = derive_key(passphrase, &record.salt)?;
+    match open(&key, &record.verifier, &record.nonce) {
+        Some(plain) if plain == VERIFIER => Ok(key),
+        _ => Err(PassphraseError::WrongPassphrase("Wrong credentials passphrase".into())),
+    }
+}
+
+/// Set the passphrase, or replace it given the `current` one, re-encrypting every
+/// credential stored under it in one transaction. Leaves the session unlocked
+/// with the new key.
+pub fn change_passphrase(
+    db: &Database,
+    session: &Session,
+    current: Option<&str>,
+    new: &str,
+) -> Result<(), PassphraseError> {
+    if new.trim().is_empty() {
+        return Err(PassphraseError::InvalidPassphrase("The passphrase cannot be empty".into()));
+    }
+    // Held throughout so no credential is written under the old key meanwhile
+    let mut session_key = session.lock();
+    let old_key = match db.credential_passphrase().map_err(|e| e.to_string())? {
+        Some(record) => {
+            let current = current.ok_or_else(|| {
+                PassphraseError::WrongPassphrase("The current passphrase is required".into())
+            })?;
+            Some(verified_key(&record, current)?)
+        }
+        None => None,
+    };
+
+    let mut salt = vec![0u8; SALT_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| e.to_string())?;
+    let key = derive_key(new, &salt)?;
+    let (verifier, nonce) = seal(&key, VERIFIER)?;
+    let record = PassphraseRecord { salt, nonce, verifier };
+
+    let mut resealed = Vec::new();
+    for stored in db.list_credentials().map_err(|e| e.to_string())? {
+        if stored.storage == STORAGE_KEYCHAIN {
+            continue;
+        }
+        let value = reveal(old_key.as_ref(), &stored)?;
+        let (ciphertext, nonce) = seal(&key, value.as_bytes())?;
+        resealed.push(StoredCredential {
+            name: stored.name,
+            storage: STORAGE_PASSPHRASE.into(),
+            ciphertext: Some(ciphertext),
+            nonce: Some(nonce),
+        });
+    }
+    db.replace_credential_passphrase(&record, &resealed).map_err(|e| e.to_string())?;
+    *session_key = Some(key);
+    Ok(())
+}
+
+/// Every readable credential as (env var, value) for the agent's environment;
+/// those under a passphrase not yet unlocked this session are left out
+pub fn environment(db: &Database, session: &Session) -> Vec<(String, String)> {
+    let stored = match db.list_credentials() {
+        Ok(stored) => stored,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to load credentials");
+            return Vec::new();
+        }
+    };
+    let key = session.lock();
+    stored
+        .iter()
+        .filter(|c| CREDENTIAL_NAMES.contains(&c.name.as_str()))
+        .filter_map(|c| match reveal(key.as_ref(), c) {
+            Ok(value) => Some((c.name.clone(), value)),
+            Err(e) => {
+                tracing::warn!(name = %c.name, error = %e, "skipping credential");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(db: &Database, session: &Session) -> Option<String> {
+        let env = environment(db, session);
+        env.into_iter().find(|(name, _)| name == "OPENAI_API_KEY").map(|(_, value)| value)
+    }
+
+    #[test]
+    fn passphrase_unlocks_and_changes_without_losing_credentials() {
+        let db = Database::new_in_memory().unwrap();
+        let session = Session::default();
+        assert!(matches!(unlock(&db, &session, "old"), Err(PassphraseError::NoPassphrase(_))));
+        change_passphrase(&db, &session, None, "old").unwrap();
+
+        // Written under the passphrase, as set does without a keychain
+        let (ciphertext, nonce) = seal(session.lock().as_ref().unwrap(), b"sk-stored").unwrap();
+        db.put_credential(&StoredCredential {
+            name: "OPENAI_API_KEY".into(),
+            storage: STORAGE_PASSPHRASE.into(),
+            ciphertext: Some(ciphertext),
+            nonce: Some(nonce),
+        })
+        .unwrap();
+
+        // A new session sees nothing until unlocked
+        let session = Session::default();
+        assert_eq!(value(&db, &session), None);
+        assert!(matches!(
+            unlock(&db, &session, "wrong"),
+            Err(PassphraseError::WrongPassphrase(_))
+        ));
+        unlock(&db, &session, "old").unwrap();
+        assert_eq!(value(&db, &session).as_deref(), Some("sk-stored"));
+
+        assert!(matches!(
+            change_passphrase(&db, &session, Some("wrong"), "new"),
+            Err(PassphraseError::WrongPassphrase(_))
+        ));
+        change_passphrase(&db, &session, Some("old"), "new").unwrap();
+        let session = Session::default();
+        assert!(unlock(&db, &session, "old").is_err());
+        unlock(&db, &session, "new").unwrap();
+        assert_eq!(value(&db, &session).as_deref(), Some("sk-stored"));
+    }
+
+    #[test]
+    fn passphrase_alone_does_not_decrypt() {
+        let db = Database::new_in_memory().unwrap();
+        let session = Session::default();
+        change_passphrase(&db, &session, None, "hunter2").unwrap();
+        let (ciphertext, nonce) = seal(session.lock().as_ref().unwrap(), b"sk-stored").unwrap();
+        let record = db.credential_passphrase().unwrap().unwrap();
+
+        // What someone holding the data dir and the passphrase, but not this
+        // machine's secret, would derive
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default());
+        let mut key = [0u8; 32];
+        argon2.hash_password_into(b"hunter2", &record.salt, &mut key).unwrap();
+        assert!(open(&key, &record.verifier, &record.nonce).is_none());
+        assert!(open(&key, &ciphertext, &nonce).is_none());
+
+        let key = derive_key("hunter2", &record.salt).unwrap();
+        assert_eq!(open(&key, &ciphertext, &nonce).as_deref(), Some(&b"sk-stored"[..]));
+    }
+
+    #[test]
+    fn mask_shows_little_of_short_values() {
+        assert_eq!(mask("short"), "****");
+        assert_eq!(mask("abcdefghijkl"), "...ijkl");
+        assert_eq!(mask("sk-proj-0123456789abcdef"), "sk-...cdef");
+    }
+}
@@ -0,0 +1,412 @@
+//! Where the app keeps its data, and moving it somewhere else. The default
+//! location always stays put; when the user relocates, a pointer file there
+//! names the directory actually in use. In portable mode the data lives in
+//! data/ next to the executable instead and the pointer is ignored.
+
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Lives in the default directory and holds the path of the relocated one
+const POINTER_FILE: &str = "data-dir-location";
+/// Either this file or a data/ directory next to the executable enables portable mode
+const PORTABLE_FLAG: &str = "portable.flag";
+const PORTABLE_DATA_DIR: &str = "data";
+/// Left in a portable data/ directory after converting back, so it no longer counts
+const CONVERTED_MARKER: &str = ".converted-to-standard";
+/// Copied separately through SQLite so the snapshot is consistent
+const DB_FILES: &[&str] = &["sanhuoai.db", "sanhuoai.db-journal", "sanhuoai.db-wal", "sanhuoai.db-shm"];
+/// Describe the running agent or this machine's setup, not user data
+const NOT_COPIED: &[&str] = &["agent-runtime.json", POINTER_FILE, CONVERTED_MARKER];
+/// Project agents' runtime files, agent-runtime-<project id>.json
+const NOT_COPIED_PREFIX: &str = "agent-runtime-";
+/// Required headroom on the target beyond the current size, in percent
+const FREE_SPACE_MARGIN: u64 = 10;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+/// Below this much free space the data directory counts as full
+const MIN_FREE_BYTES: u64 = 32 * 1024 * 1024;
+/// Under the system temp directory; used for a session when the data directory is unusable
+const FALLBACK_DIR: &str = "sanhuoai-fallback";
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Portable,
+    Standard,
+}
+
+#[derive(Serialize, Clone)]
+pub struct DataDirInfo {
+    pub path: String,
+    pub mode: Mode,
+    pub default_path: String,
+    /// Whether `path` differs from the default location
+    pub relocated: bool,
+    pub size_bytes: u64,
+    /// None when the volume couldn't be identified
+    pub free_bytes: Option<u64>,
+}
+
+pub fn default_dir() -> PathBuf {
+    let mut p = dirs_next::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    p.push("sanhuoai");
+    p
+}
+
+/// The directory to use this session: data/ next to the executable in portable
+/// mode, otherwise the standard location
+pub fn resolve() -> PathBuf {
+    match portable_dir() {
+        Some(dir) => {
+            fs::create_dir_all(&dir).ok();
+            dir
+        }
+        None => standard_dir(),
+    }
+}
+
+/// The pointer's target if it still exists, otherwise the default location
+pub fn standard_dir() -> PathBuf {
+    let default = default_dir();
+    fs::create_dir_all(&default).ok();
+    let pointer = fs::read_to_string(default.join(POINTER_FILE))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    match pointer {
+        Some(target) if Path::new(&target).is_dir() => PathBuf::from(target),
+        Some(target) => {
+            // Runs before logging is set up, since the log lives in the data directory
+            eprintln!(
+                "[sanhuoai] Data directory {} is missing, falling back to {}",
+                target,
+                default.display()
+            );
+            default
+        }
+        None => default,
+    }
+}
+
+/// Point future launches at `target`; pointing back at the default removes the pointer
+pub fn write_pointer(target: &Path) -> io::Result<()> {
+    let default = default_dir();
+    let pointer = default.join(POINTER_FILE);
+    if same_dir(target, &default) {
+        return match fs::remove_file(&pointer) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    fs::create_dir_all(&default)?;
+    let tmp = pointer.with_extension("tmp");
+    fs::write(&tmp, target.to_string_lossy().as_bytes())?;
+    fs::rename(&tmp, &pointer)
+}
+
+pub fn exe_dir() -> Result<PathBuf, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Cannot locate the executable: {}", e))?;
+    exe.parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| format!("{} has no parent directory", exe.display()))
+}
+
+/// data/ next to the executable, if portable mode is on
+fn portable_dir() -> Option<PathBuf> {
+    let exe_dir = exe_dir().ok()?;
+    let data = exe_dir.join(PORTABLE_DATA_DIR);
+    let enabled = exe_dir.join(PORTABLE_FLAG).is_file()
+        || (data.is_dir() && !data.join(CONVERTED_MARKER).exists());
+    enabled.then_some(data)
+}
+
+pub fn mode(path: &Path) -> Mode {
+    match portable_dir() {
+        Some(dir) if same_dir(path, &dir) => Mode::Portable,
+        _ => Mode::Standard,
+    }
+}
+
+/// Where convert_to_portable copies to; the executable's directory must be
+/// writable, which rules out installs under Program Files
+pub fn portable_target() -> Result<PathBuf, String> {
+    let exe_dir = exe_dir()?;
+    check_writable(&exe_dir).map_err(|e| {
+        format!(
+            "Portable mode needs a writable folder next to the app, but {} is not writable ({}). \
+             Copy the app to a folder you own, such as a USB drive, and try again.",
+            exe_dir.display(),
+            e
+        )
+    })?;
+    Ok(exe_dir.join(PORTABLE_DATA_DIR))
+}
+
+/// Turn portable mode on for the next launch
+pub fn enable_portable() -> Result<(), String> {
+    let flag = exe_dir()?.join(PORTABLE_FLAG);
+    fs::write(&flag, b"").map_err(|e| format!("Failed to create {}: {}", flag.display(), e))
+}
+
+/// Turn portable mode off for the next launch, keeping the old data/ directory
+pub fn disable_portable() -> Result<(), String> {
+    let exe_dir = exe_dir()?;
+    let marker = exe_dir.join(PORTABLE_DATA_DIR).join(CONVERTED_MARKER);
+    fs::write(&marker, b"").map_err(|e| format!("Failed to create {}: {}", marker.display(), e))?;
+    match fs::remove_file(exe_dir.join(PORTABLE_FLAG)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            let _ = fs::remove_file(&marker);
+            Err(format!("Failed to remove {}: {}", PORTABLE_FLAG, e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Write and delete a probe file in `dir`, creating it if needed
+fn check_writable(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".write-test");
+    let written = File::create(&probe).and_then(|mut file| {
+        file.write_all(b"ok")?;
+        file.sync_all()
+    });
+    let removed = fs::remove_file(&probe);
+    written.and(removed)
+}
+
+/// Why `dir` can't hold the app's data this session: missing and uncreatable,
+/// read-only, or on a full volume
+pub fn check_usable(dir: &Path) -> Result<(), String> {
+    check_writable(dir).map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+    match available_space(dir) {
+        Some(free) if free < MIN_FREE_BYTES => Err(format!(
+            "The disk holding {} is full ({} MB free)",
+            dir.display(),
+            free >> 20
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Where the app runs for one session when the real data directory is unusable
+pub fn fallback_dir() -> PathBuf {
+    std::env::temp_dir().join(FALLBACK_DIR)
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+pub fn info(path: &Path) -> DataDirInfo {
+    let default = default_dir();
+    DataDirInfo {
+        path: path.display().to_string(),
+        mode: mode(path),
+        default_path: default.display().to_string(),
+        relocated: !same_dir(path, &default),
+        size_bytes: dir_size(path),
+        free_bytes: available_space(path),
+    }
+}
+
+/// Total size of the regular files under `path`; unreadable entries count as 0
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&entry.path()),
+            Ok(t) if t.is_file() => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Free space on the volume holding `path` (or its nearest existing ancestor)
+pub fn available_space(path: &Path) -> Option<u64> {
+    let path = path.ancestors().find_map(|p| p.canonicalize().ok())?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Fail before an operation that writes about `needed` bytes under `dir` if the
+/// volume can't take them with room to spare, rather than part way through. Passes
+/// when the free space can't be read.
+pub fn check_free_space(dir: &Path, needed: u64) -> Result<(), String> {
+    let required = needed + needed * FREE_SPACE_MARGIN / 100 + MIN_FREE_BYTES;
+    match available_space(dir) {
+        Some(free) if free < required => Err(format!(
+            "insufficient disk space at {}: {} MB needed, {} MB available",
+            dir.display(),
+            required.div_ceil(1 << 20),
+            free >> 20
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// A validated, empty directory to migrate into
+pub struct Target {
+    pub path: PathBuf,
+    /// We created it, so a failed migration removes it entirely
+    created: bool,
+}
+
+/// Check that `target` can take over from `current`: absolute, empty or
+/// missing, writable, outside `current`, and on a volume with room for a copy
+pub fn prepare_target(current: &Path, target: &str) -> Result<Target, String> {
+    let target = target.trim();
+    if target.is_empty() {
+        return Err("No target directory given".into());
+    }
+    let requested = PathBuf::from(target);
+    if !requested.is_absolute() {
+        return Err(format!("{} is not an absolute path", target));
+    }
+    if same_dir(&requested, current) {
+        return Err("That is already the data directory".into());
+    }
+
+    let created = !requested.exists();
+    if created {
+        fs::create_dir_all(&requested)
+            .map_err(|e| format!("Cannot create {}: {}", requested.display(), e))?;
+    } else if !requested.is_dir() {
+        return Err(format!("{} is not a directory", requested.display()));
+    } else if fs::read_dir(&requested).map_err(|e| e.to_string())?.next().is_some() {
+        return Err(format!("{} is not empty; choose an empty folder", requested.display()));
+    }
+    let prepared = Target { path: requested.canonicalize().unwrap_or(requested), created };
+
+    if let Err(e) = check_target(current, &prepared.path) {
+        discard(&prepared);
+        return Err(e);
+    }
+    Ok(prepared)
+}
+
+fn check_target(current: &Path, target: &Path) -> Result<(), String> {
+    if let Ok(current) = current.canonicalize() {
+        if target.starts_with(&current) {
+            return Err("The new location cannot be inside the current data directory".into());
+        }
+    }
+
+    check_writable(target).map_err(|e| format!("{} is not writable: {}", target.display(), e))?;
+
+    let needed = dir_size(current);
+    if let Some(free) = available_space(target) {
+        if free < needed + needed * FREE_SPACE_MARGIN / 100 {
+            return Err(format!(
+                "Not enough free space at {}: {} MB needed, {} MB available",
+                target.display(),
+                needed.div_ceil(1 << 20),
+                free >> 20
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Undo a failed migration; the source is never touched
+pub fn discard(target: &Target) {
+    if target.created {
+        let _ = fs::remove_dir_all(&target.path);
+        return;
+    }
+    // The directory was empty when we started, so everything in it is ours
+    if let Ok(entries) = fs::read_dir(&target.path) {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let _ = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct CopyProgress {
+    pub copied_bytes: u64,
+    pub total_bytes: u64,
+    /// Path relative to the data directory
+    pub file: String,
+}
+
+/// Copy everything under `src` except the database and per-session files into
+/// `dst`, reporting progress at most every PROGRESS_INTERVAL
+pub fn copy_contents(
+    src: &Path,
+    dst: &Path,
+    total_bytes: u64,
+    mut on_progress: impl FnMut(&CopyProgress),
+) -> io::Result<u64> {
+    let mut progress = CopyProgress { copied_bytes: 0, total_bytes, file: String::new() };
+    let mut last_report = Instant::now();
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if DB_FILES.contains(&name.as_ref())
+            || NOT_COPIED.contains(&name.as_ref())
+            || name.starts_with(NOT_COPIED_PREFIX)
+        {
+            continue;
+        }
+        copy_entry(src, &entry.path(), dst, &mut progress, &mut last_report, &mut on_progress)?;
+    }
+    on_progress(&progress);
+    Ok(progress.copied_bytes)
+}
+
+fn copy_entry(
+    root: &Path,
+    path: &Path,
+    dst_root: &Path,
+    progress: &mut CopyProgress,
+    last_report: &mut Instant,
+    on_progress: &mut impl FnMut(&CopyProgress),
+) -> io::Result<()> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let dst = dst_root.join(relative);
+    let file_type = fs::symlink_metadata(path)?.file_type();
+    if file_type.is_dir() {
+        fs::create_dir_all(&dst)?;
+        for entry in fs::read_dir(path)? {
+            copy_entry(root, &entry?.path(), dst_root, progress, last_report, on_progress)?;
+        }
+        return Ok(());
+    }
+    if !file_type.is_file() {
+        // Symlinks and other special files aren't ours to move
+        return Ok(());
+    }
+
+    progress.file = relative.to_string_lossy().into_owned();
+    let mut input = File::open(path)?;
+    let mut output = File::create(&dst)?;
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        output.write_all(&buf[..n])?;
+        progress.copied_bytes += n as u64;
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            on_progress(progress);
+            *last_report = Instant::now();
+        }
+    }
+    output.sync_all()
+}
@@ -1,7 +1,766 @@
-use rusqlite::{params, Connection, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Result};
 use std::sync::Mutex;
 
-use crate::Project;
+use crate::attachments::Attachment;
+use crate::backup::{
+    ChapterBackup, NoteBackup, ParagraphBackup, ProjectBackup, StoryEventBackup,
+};
+use crate::chapter_import::ImportedChapter;
+use crate::chapter_status::{ChapterStatus, StatusCounts};
+use crate::credentials::{PassphraseRecord, StoredCredential};
+use crate::db_health::{
+    problems, CheckMode, Checkpointed, ForeignKeyViolation, IntegrityReport, Optimized, Stage,
+    TableRows, MAX_LISTED_VIOLATIONS, RESTORE_HINT,
+};
+use crate::diagnostics::DatabaseReport;
+use crate::embedding_index::{self, ContentHash, EmbeddingIndexStatus, IndexRecord};
+use crate::find_replace::{ChapterReplacements, ChapterText, Matcher, NoteText, Paragraph};
+use crate::markdown::ManuscriptChapter;
+use crate::onboarding::SampleProject;
+use crate::project_templates::{
+    self, ProjectTemplate, TemplateCharacter, TemplateContent, TemplateOutline, TemplatePrompt,
+    TemplateWorldEntry,
+};
+use crate::relationship_graph::{
+    self, CharacterConnection, Relationship, RelationshipGraph, RelationshipUpdate,
+};
+use crate::text_count;
+use crate::trash::{Purged, TrashedProject};
+use crate::writing_goal;
+use crate::{
+    Character, CharacterUpdate, ChapterLength, DbSize, ModelPreset, ModelPresetUpdate, ModelSlot,
+    Note, NoteUpdate, PresetDeleted, Project, ProjectPage, ProjectQuery, ProjectSort, ProjectStats,
+    Prompt, PromptUpdate, RecentProject, Revision, Scene, SceneUpdate, SortDir, StoryEvent,
+    StoryEventSort, StoryEventUpdate,
+};
+
+/// Revisions kept per chapter; the oldest go as new ones are recorded
+const MAX_REVISIONS: i64 = 50;
+
+const PROJECT_COLUMNS: &str = "id, name, genre, description, status, \
+     model_main, model_secondary, temperature, embedding_dim, word_target, \
+     COALESCE(created_at, ''), COALESCE(updated_at, created_at, ''), \
+     COALESCE(top_p, 1.0), COALESCE(max_tokens, 4096), COALESCE(system_prompt_template, ''), \
+     COALESCE(pinned, 0), manual_order";
+
+/// `%query%` with LIKE's wildcards in `query` matched literally
+fn like_pattern(query: &str) -> String {
+    let mut pattern = String::with_capacity(query.len() + 2);
+    pattern.push('%');
+    for c in query.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Size of the database file plus `suffix` ("-wal"); 0 in memory or when missing
+fn file_len(conn: &Connection, suffix: &str) -> u64 {
+    conn.path()
+        .filter(|path| !path.is_empty())
+        .and_then(|path| std::fs::metadata(format!("{}{}", path, suffix)).ok())
+        .map_or(0, |m| m.len())
+}
+
+/// Another connection (the agent) holds a lock we needed
+pub fn is_busy(error: &rusqlite::Error) -> bool {
+    matches!(
+        error.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+fn project_from_row(row: &rusqlite::Row) -> Result<Project> {
+    Ok(Project {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        genre: row.get(2)?,
+        description: row.get(3)?,
+        status: row.get(4)?,
+        model_main: row.get(5)?,
+        model_secondary: row.get(6)?,
+        temperature: row.get(7)?,
+        embedding_dim: row.get(8)?,
+        word_target: row.get(9)?,
+        created_at: row.get(10)?,
+        updated_at: row.get(11)?,
+        top_p: row.get(12)?,
+        max_tokens: row.get(13)?,
+        system_prompt_template: row.get(14)?,
+        pinned: row.get(15)?,
+        manual_order: row.get(16)?,
+    })
+}
+
+/// PROJECT_COLUMNS of `projects p`, then when it was opened and its last chapter
+/// (only if that chapter still belongs to it)
+const RECENT_PROJECT_COLUMNS: &str = "p.last_opened_at, \
+     (SELECT c.id FROM chapters c WHERE c.project_id = p.id \
+      AND c.id = json_extract(COALESCE(NULLIF(p.settings, ''), '{}'), '$.last_chapter_id'))";
+
+fn recent_project_from_row(row: &rusqlite::Row) -> Result<RecentProject> {
+    Ok(RecentProject {
+        project: project_from_row(row)?,
+        last_opened_at: row.get(17)?,
+        last_chapter_id: row.get(18)?,
+    })
+}
+
+const CHARACTER_COLUMNS: &str = "id, project_id, name, COALESCE(category, ''), COALESCE(gender, ''), \
+     COALESCE(age, ''), COALESCE(identity, ''), COALESCE(appearance, ''), COALESCE(personality, ''), \
+     COALESCE(motivation, ''), COALESCE(backstory, ''), COALESCE(arc, ''), COALESCE(usage_notes, ''), \
+     COALESCE(status, 'active'), COALESCE(sort_order, 0), created_at, COALESCE(updated_at, created_at), \
+     COALESCE(aliases, '[]')";
+
+const PROMPT_COLUMNS: &str = "id, name, COALESCE(category, ''), content, COALESCE(is_builtin, 0), \
+     default_content IS NOT NULL AND content IS NOT default_content, project_id, \
+     COALESCE(updated_at, created_at, '')";
+
+const MODEL_PRESET_COLUMNS: &str = "id, provider, model_id, COALESCE(display_name, ''), \
+     COALESCE(context_window, 0), COALESCE(default_temperature, 0.7), \
+     COALESCE(supports_streaming, 1)";
+
+fn model_preset_from_row(row: &rusqlite::Row) -> Result<ModelPreset> {
+    Ok(ModelPreset {
+        id: row.get(0)?,
+        provider: row.get(1)?,
+        model_id: row.get(2)?,
+        display_name: row.get(3)?,
+        context_window: row.get(4)?,
+        default_temperature: row.get(5)?,
+        supports_streaming: row.get(6)?,
+    })
+}
+
+const SCENE_COLUMNS: &str =
+    "id, chapter_id, COALESCE(summary, ''), COALESCE(order_index, 0), pov_character_id, \
+     COALESCE(created_at, '')";
+
+fn scene_from_row(row: &rusqlite::Row) -> Result<Scene> {
+    Ok(Scene {
+        id: row.get(0)?,
+        chapter_id: row.get(1)?,
+        summary: row.get(2)?,
+        order_index: row.get(3)?,
+        pov_character_id: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+const NOTE_COLUMNS: &str = "id, project_id, COALESCE(title, ''), COALESCE(content, ''), \
+     COALESCE(pinned, 0) != 0, COALESCE(color, ''), created_at, COALESCE(updated_at, created_at)";
+
+fn note_from_row(row: &rusqlite::Row) -> Result<Note> {
+    Ok(Note {
+        id: row.get(0)?,
+        project_id: row.get(1)?,
+        title: row.get(2)?,
+        content: row.get(3)?,
+        pinned: row.get(4)?,
+        color: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+const RELATIONSHIP_COLUMNS: &str = "r.id, r.character_a_id, r.character_b_id, \
+     COALESCE(r.relation_type, ''), COALESCE(r.description, ''), COALESCE(r.strength, 0), \
+     COALESCE(r.created_at, '')";
+
+fn relationship_from_row(row: &rusqlite::Row) -> Result<Relationship> {
+    Ok(Relationship {
+        id: row.get(0)?,
+        from_character: row.get(1)?,
+        to_character: row.get(2)?,
+        kind: row.get(3)?,
+        description: row.get(4)?,
+        strength: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+/// Errs with a Conflict unless no other edge from `from` to `to` has this kind
+fn check_relationship_unique(
+    conn: &Connection,
+    from: &str,
+    to: &str,
+    kind: &str,
+    exclude_id: Option<&str>,
+) -> std::result::Result<(), String> {
+    let taken: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM character_relations \
+             WHERE character_a_id = ?1 AND character_b_id = ?2 \
+             AND COALESCE(relation_type, '') = ?3 AND id != COALESCE(?4, ''))",
+            params![from, to, kind, exclude_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if !taken {
+        return Ok(());
+    }
+    let name = |id: &str| -> String {
+        conn.query_row("SELECT name FROM characters WHERE id = ?1", params![id], |row| row.get(0))
+            .unwrap_or_else(|_| id.to_string())
+    };
+    Err(format!(
+        "Conflict: {} already has a \"{}\" relationship with {}",
+        name(from),
+        kind,
+        name(to)
+    ))
+}
+
+const STORY_EVENT_COLUMNS: &str =
+    "id, project_id, COALESCE(title, ''), COALESCE(description, ''), COALESCE(story_date, ''), \
+     COALESCE(narrative_order, 0), chapter_id, COALESCE(character_ids, '[]'), created_at, \
+     COALESCE(updated_at, created_at)";
+
+fn story_event_from_row(row: &rusqlite::Row) -> Result<StoryEvent> {
+    Ok(StoryEvent {
+        id: row.get(0)?,
+        project_id: row.get(1)?,
+        title: row.get(2)?,
+        description: row.get(3)?,
+        story_date: row.get(4)?,
+        narrative_order: row.get(5)?,
+        chapter_id: row.get(6)?,
+        character_ids: serde_json::from_str(&row.get::<_, String>(7)?).unwrap_or_default(),
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+    })
+}
+
+fn prompt_from_row(row: &rusqlite::Row) -> Result<Prompt> {
+    Ok(Prompt {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        category: row.get(2)?,
+        content: row.get(3)?,
+        is_builtin: row.get(4)?,
+        customized: row.get(5)?,
+        project_id: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+const ATTACHMENT_COLUMNS: &str =
+    "id, project_id, filename, COALESCE(mime, ''), COALESCE(size, 0), sha256, created_at";
+
+fn attachment_from_row(row: &rusqlite::Row) -> Result<Attachment> {
+    Ok(Attachment {
+        id: row.get(0)?,
+        project_id: row.get(1)?,
+        filename: row.get(2)?,
+        mime: row.get(3)?,
+        size_bytes: row.get(4)?,
+        sha256: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+const TEMPLATE_COLUMNS: &str = "id, name, COALESCE(genre, ''), COALESCE(description, ''), \
+     COALESCE(is_builtin, 0), created_at, COALESCE(updated_at, created_at), content";
+
+fn template_from_row(row: &rusqlite::Row) -> Result<ProjectTemplate> {
+    Ok(ProjectTemplate {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        genre: row.get(2)?,
+        description: row.get(3)?,
+        is_builtin: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        content: serde_json::from_str(&row.get::<_, String>(7)?).unwrap_or_default(),
+    })
+}
+
+/// The project row and everything the template seeds it with; returns the project id
+fn insert_template(
+    conn: &Connection,
+    name: &str,
+    genre: &str,
+    description: &str,
+    content: &TemplateContent,
+) -> Result<String> {
+    let structure = Some(content.structure.as_str())
+        .filter(|s| !s.is_empty())
+        .unwrap_or(project_templates::DEFAULT_STRUCTURE);
+    let project_id: String = conn.query_row(
+        "INSERT INTO projects (name, genre, description, structure, custom_structure, \
+         chapter_words, word_target) \
+         VALUES (?1, ?2, ?3, ?4, ?5, COALESCE(?6, 5000), COALESCE(?7, 100000)) RETURNING id",
+        params![
+            name,
+            genre,
+            description,
+            structure,
+            content.custom_structure,
+            content.chapter_words,
+            content.word_target
+        ],
+        |row| row.get(0),
+    )?;
+    for (i, outline) in content.outlines.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO outlines \
+             (project_id, structure, phase, phase_order, title, content, word_range) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                project_id,
+                structure,
+                outline.phase,
+                i as i64 + 1,
+                outline.title,
+                outline.content,
+                outline.word_range
+            ],
+        )?;
+    }
+    for (i, c) in content.characters.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO characters (project_id, name, category, gender, age, identity, \
+             appearance, personality, motivation, backstory, arc, usage_notes, aliases, \
+             sort_order, updated_at) \
+             VALUES (?1, ?2, COALESCE(NULLIF(?3, ''), '配角'), ?4, ?5, ?6, ?7, ?8, ?9, ?10, \
+             ?11, ?12, ?13, ?14, datetime('now'))",
+            params![
+                project_id,
+                c.name,
+                c.category,
+                c.gender,
+                c.age,
+                c.identity,
+                c.appearance,
+                c.personality,
+                c.motivation,
+                c.backstory,
+                c.arc,
+                c.usage_notes,
+                serde_json::to_string(&c.aliases).unwrap_or_default(),
+                i as i64 + 1
+            ],
+        )?;
+    }
+    // Parents are named by title and may come after their children, so link afterwards
+    let mut entry_ids = Vec::with_capacity(content.worldbuilding.len());
+    for (i, entry) in content.worldbuilding.iter().enumerate() {
+        let id: String = conn.query_row(
+            "INSERT INTO worldbuilding (project_id, category, title, content, sort_order) \
+             VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
+            params![project_id, entry.category, entry.title, entry.content, i as i64],
+            |row| row.get(0),
+        )?;
+        entry_ids.push(id);
+    }
+    for (entry, id) in content.worldbuilding.iter().zip(&entry_ids) {
+        let parent = entry.parent.as_deref().and_then(|title| {
+            let index = content.worldbuilding.iter().position(|e| e.title == title)?;
+            Some(&entry_ids[index]).filter(|parent_id| *parent_id != id)
+        });
+        if let Some(parent_id) = parent {
+            conn.execute(
+                "UPDATE worldbuilding SET parent_id = ?1 WHERE id = ?2",
+                params![parent_id, id],
+            )?;
+        }
+    }
+    for prompt in &content.prompts {
+        conn.execute(
+            "INSERT OR REPLACE INTO prompts (name, category, content, project_id) \
+             VALUES (?1, ?2, ?3, ?4)",
+            params![prompt.name, prompt.category, prompt.content, project_id],
+        )?;
+    }
+    Ok(project_id)
+}
+
+fn character_from_row(row: &rusqlite::Row) -> Result<Character> {
+    Ok(Character {
+        id: row.get(0)?,
+        project_id: row.get(1)?,
+        name: row.get(2)?,
+        category: row.get(3)?,
+        gender: row.get(4)?,
+        age: row.get(5)?,
+        identity: row.get(6)?,
+        appearance: row.get(7)?,
+        personality: row.get(8)?,
+        motivation: row.get(9)?,
+        backstory: row.get(10)?,
+        arc: row.get(11)?,
+        usage_notes: row.get(12)?,
+        status: row.get(13)?,
+        sort_order: row.get(14)?,
+        created_at: row.get(15)?,
+        updated_at: row.get(16)?,
+        // Written by update_character; anything else reads as no aliases
+        aliases: serde_json::from_str(&row.get::<_, String>(17)?).unwrap_or_default(),
+    })
+}
+
+fn credential_from_row(row: &rusqlite::Row) -> Result<StoredCredential> {
+    Ok(StoredCredential {
+        name: row.get(0)?,
+        storage: row.get(1)?,
+        ciphertext: row.get(2)?,
+        nonce: row.get(3)?,
+    })
+}
+
+/// Whether `e` is a UNIQUE constraint turning away a duplicate
+pub fn is_unique_violation(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+    )
+}
+
+/// Add a column that newer schema.sql versions declare but older databases lack
+fn ensure_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists = conn
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+    }
+    Ok(())
+}
+
+/// Store a chapter body as one paragraph per line, mirroring the editor's save path.
+/// Returns the chapter's character count.
+fn insert_paragraphs(conn: &Connection, chapter_id: &str, body: &str) -> Result<i64> {
+    let mut total = 0i64;
+    for (index, line) in body.split('\n').enumerate() {
+        let char_count = line.chars().count() as i64;
+        conn.execute(
+            "INSERT INTO chapter_paragraphs (chapter_id, para_index, content, char_count) \
+             VALUES (?1, ?2, ?3, ?4)",
+            params![chapter_id, index as i64, line, char_count],
+        )?;
+        total += text_count::count_words(line);
+    }
+    Ok(total)
+}
+
+/// Pinned projects in their current order, optionally leaving out the trashed ones
+fn pinned_ids(conn: &Connection, outside_trash: bool) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM projects WHERE pinned = 1 AND (NOT ?1 OR trashed_at IS NULL) \
+         ORDER BY manual_order, id",
+    )?;
+    let rows = stmt.query_map(params![outside_trash], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// manual_order 0, 1, 2, ... in the order of `ids`
+fn renumber_pinned(conn: &Connection, ids: &[String]) -> Result<()> {
+    let mut stmt = conn.prepare("UPDATE projects SET manual_order = ?1 WHERE id = ?2")?;
+    for (index, id) in ids.iter().enumerate() {
+        stmt.execute(params![index as i64, id])?;
+    }
+    Ok(())
+}
+
+/// Insert a backup's project and chapters with new ids, returning the project's
+fn insert_backup(conn: &Connection, backup: &ProjectBackup) -> Result<String> {
+    let p = &backup.project;
+    let project_id: String = conn.query_row(
+        // A pinned project goes after those already pinned
+        "INSERT INTO projects (name, genre, description, status, model_main, model_secondary, \
+         temperature, embedding_dim, word_target, top_p, max_tokens, system_prompt_template, \
+         pinned, manual_order) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, CASE WHEN ?13 THEN \
+         (SELECT COALESCE(MAX(manual_order), -1) + 1 FROM projects WHERE pinned = 1) END) \
+         RETURNING id",
+        params![
+            p.name,
+            p.genre,
+            p.description,
+            p.status,
+            p.model_main,
+            p.model_secondary,
+            p.temperature,
+            p.embedding_dim,
+            p.word_target,
+            p.top_p,
+            p.max_tokens,
+            p.system_prompt_template,
+            p.pinned,
+        ],
+        |row| row.get(0),
+    )?;
+    let mut chapter_ids = std::collections::HashMap::new();
+    for chapter in &backup.chapters {
+        let chapter_id: String = conn.query_row(
+            "INSERT INTO chapters (project_id, chapter_num, title, phase, synopsis, status, \
+             word_count, sort_order) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8) RETURNING id",
+            params![
+                project_id,
+                chapter.chapter_num,
+                chapter.title,
+                chapter.phase,
+                chapter.synopsis,
+                ChapterStatus::parse(&chapter.status).as_str(),
+                // Recounted, since older versions counted differently
+                chapter.paragraphs.iter().map(|p| text_count::count_words(&p.content)).sum::<i64>(),
+                chapter.sort_order,
+            ],
+            |row| row.get(0),
+        )?;
+        chapter_ids.insert(chapter.chapter_num, chapter_id.clone());
+        for para in &chapter.paragraphs {
+            conn.execute(
+                "INSERT INTO chapter_paragraphs \
+                 (chapter_id, para_index, content, char_count, scene_tag) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    chapter_id,
+                    para.para_index,
+                    para.content,
+                    para.content.chars().count() as i64,
+                    para.scene_tag,
+                ],
+            )?;
+        }
+    }
+    for note in &backup.notes {
+        conn.execute(
+            "INSERT INTO notes (project_id, title, content, pinned, color, created_at, updated_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                project_id,
+                note.title,
+                note.content,
+                note.pinned,
+                note.color,
+                note.created_at,
+                note.updated_at
+            ],
+        )?;
+    }
+    for event in &backup.story_events {
+        conn.execute(
+            "INSERT INTO story_events (project_id, title, description, story_date, \
+             narrative_order, chapter_id, created_at, updated_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                project_id,
+                event.title,
+                event.description,
+                event.story_date,
+                event.narrative_order,
+                event.chapter_num.and_then(|num| chapter_ids.get(&num)),
+                event.created_at,
+                event.updated_at
+            ],
+        )?;
+    }
+    renumber_story_events(conn, &project_id)?;
+    Ok(project_id)
+}
+
+/// Number the project's events 1..n in their current narrative order
+fn renumber_story_events(conn: &Connection, project_id: &str) -> Result<()> {
+    let ids = conn
+        .prepare(
+            "SELECT id FROM story_events WHERE project_id = ?1 \
+             ORDER BY narrative_order, created_at, id",
+        )?
+        .query_map(params![project_id], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>>>()?;
+    for (index, id) in ids.iter().enumerate() {
+        conn.execute(
+            "UPDATE story_events SET narrative_order = ?1 WHERE id = ?2",
+            params![index as i64 + 1, id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Rewrite a chapter's paragraphs from `body` and update its word count and
+/// updated_at. Returns the new word count.
+fn replace_paragraphs(conn: &Connection, chapter_id: &str, body: &str) -> Result<i64> {
+    conn.execute("DELETE FROM chapter_paragraphs WHERE chapter_id = ?1", params![chapter_id])?;
+    let word_count = insert_paragraphs(conn, chapter_id, body)?;
+    conn.execute(
+        "UPDATE chapters SET word_count = ?1, updated_at = datetime('now') WHERE id = ?2",
+        params![word_count, chapter_id],
+    )?;
+    conn.execute(
+        "UPDATE embedding_index SET stale = 1 \
+         WHERE project_id = (SELECT project_id FROM chapters WHERE id = ?1)",
+        params![chapter_id],
+    )?;
+    Ok(word_count)
+}
+
+/// Flag the project's embeddings as out of date after its chapter text changed.
+/// Returns false if the agent never indexed the project.
+fn mark_index_stale(conn: &Connection, project_id: &str) -> Result<bool> {
+    let marked = conn.execute(
+        "UPDATE embedding_index SET stale = 1 WHERE project_id = ?1",
+        params![project_id],
+    )?;
+    Ok(marked > 0)
+}
+
+/// Add a save's change in word count to the project's writing today, by the
+/// local date at the time of the save
+fn record_words_written(conn: &Connection, project_id: &str, words: i64) -> Result<()> {
+    if words != 0 {
+        conn.execute(
+            "INSERT INTO writing_days (project_id, day, words) VALUES (?1, ?2, ?3) \
+             ON CONFLICT (project_id, day) DO UPDATE SET words = words + excluded.words",
+            params![project_id, writing_goal::today(), words],
+        )?;
+    }
+    Ok(())
+}
+
+/// Every chapter of the project with its paragraphs, in reading order
+fn chapter_texts(conn: &Connection, project_id: &str) -> Result<Vec<ChapterText>> {
+    let mut stmt = conn.prepare(
+        "SELECT c.id, c.chapter_num, COALESCE(c.title, ''), p.id, p.para_index, \
+                COALESCE(p.content, '') \
+         FROM chapters c LEFT JOIN chapter_paragraphs p ON p.chapter_id = c.id \
+         WHERE c.project_id = ?1 \
+         ORDER BY c.sort_order, c.chapter_num, c.id, p.para_index",
+    )?;
+    let mut rows = stmt.query(params![project_id])?;
+    let mut chapters: Vec<ChapterText> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let chapter_id: String = row.get(0)?;
+        if chapters.last().is_none_or(|c| c.chapter_id != chapter_id) {
+            chapters.push(ChapterText {
+                chapter_id,
+                chapter_num: row.get(1)?,
+                title: row.get(2)?,
+                paragraphs: Vec::new(),
+            });
+        }
+        // NULL for a chapter without paragraphs
+        if let Some(id) = row.get::<_, Option<String>>(3)? {
+            let paragraph = Paragraph { id, para_index: row.get(4)?, content: row.get(5)? };
+            chapters.last_mut().unwrap().paragraphs.push(paragraph);
+        }
+    }
+    Ok(chapters)
+}
+
+/// Record a revision of a chapter's text, dropping its oldest beyond MAX_REVISIONS
+fn insert_revision(
+    conn: &Connection,
+    chapter_id: &str,
+    content: &str,
+    word_count: i64,
+    reason: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO chapter_revisions (chapter_id, content, word_count, reason) \
+         VALUES (?1, ?2, ?3, ?4)",
+        params![chapter_id, content, word_count, reason],
+    )?;
+    conn.execute(
+        "DELETE FROM chapter_revisions WHERE chapter_id = ?1 AND id NOT IN \
+         (SELECT id FROM chapter_revisions WHERE chapter_id = ?1 ORDER BY id DESC LIMIT ?2)",
+        params![chapter_id, MAX_REVISIONS],
+    )?;
+    Ok(())
+}
+
+/// The writes for one chapter of replace_in_project: a revision with the old
+/// text, the changed paragraphs, the word count and the chapter's indexed chunks
+fn write_replacements(
+    conn: &Connection,
+    project_id: &str,
+    chapter: &ChapterText,
+    changed: &[(&str, String)],
+    word_count: i64,
+    matcher: &Matcher,
+    replacement: &str,
+) -> Result<()> {
+    let previous: Vec<&str> = chapter.paragraphs.iter().map(|p| p.content.as_str()).collect();
+    let previous_words: i64 = previous.iter().map(|p| text_count::count_words(p)).sum();
+    insert_revision(
+        conn,
+        &chapter.chapter_id,
+        &previous.join("\n"),
+        previous_words,
+        "replace",
+    )?;
+    for (id, content) in changed {
+        conn.execute(
+            "UPDATE chapter_paragraphs SET content = ?1, char_count = ?2 WHERE id = ?3",
+            params![content, content.chars().count() as i64, id],
+        )?;
+    }
+    conn.execute(
+        "UPDATE chapters SET word_count = ?1, updated_at = datetime('now') WHERE id = ?2",
+        params![word_count, chapter.chapter_id],
+    )?;
+
+    let chunks = conn
+        .prepare(
+            "SELECT id, content FROM memory_chunks \
+             WHERE project_id = ?1 AND source_type = 'chapter' AND source_id = ?2",
+        )?
+        .query_map(params![project_id, chapter.chapter_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    for (id, content) in chunks {
+        let (content, count) = matcher.replace(&content, replacement);
+        if count > 0 {
+            // memory_chunks_au keeps chunks_fts in step
+            conn.execute(
+                "UPDATE memory_chunks SET content = ?1 WHERE id = ?2",
+                params![content, id],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends chapters to a project, numbered after its existing ones; see
+/// Database::import_chapters
+pub struct ChapterWriter<'a> {
+    conn: &'a Connection,
+    project_id: &'a str,
+    next_num: i64,
+    next_sort: i64,
+}
+
+impl ChapterWriter<'_> {
+    pub fn add(&mut self, title: &str, body: &str) -> Result<ImportedChapter> {
+        let id: String = self.conn.query_row(
+            "INSERT INTO chapters (project_id, chapter_num, title, sort_order) \
+             VALUES (?1, ?2, ?3, ?4) RETURNING id",
+            params![self.project_id, self.next_num, title, self.next_sort],
+            |row| row.get(0),
+        )?;
+        let word_count = insert_paragraphs(self.conn, &id, body)?;
+        self.conn.execute(
+            "UPDATE chapters SET word_count = ?1 WHERE id = ?2",
+            params![word_count, id],
+        )?;
+        mark_index_stale(self.conn, self.project_id)?;
+        let chapter =
+            ImportedChapter { id, chapter_num: self.next_num, title: title.to_string(), word_count };
+        self.next_num += 1;
+        self.next_sort += 1;
+        Ok(chapter)
+    }
+}
 
 pub struct Database {
     conn: Mutex<Connection>,
@@ -11,42 +770,568 @@ impl Database {
     pub fn new(data_dir: &str) -> Result<Self> {
         let mut db_path = std::path::PathBuf::from(data_dir);
         db_path.push("sanhuoai.db");
-        std::fs::create_dir_all(db_path.parent().unwrap()).ok();
+        if let Err(e) = std::fs::create_dir_all(data_dir) {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+                Some(format!("Cannot create {}: {}", data_dir, e)),
+            ));
+        }
         let conn = Connection::open(&db_path)?;
+        Self::with_connection(conn)
+    }
+
+    /// A fresh schema in memory, for tests that mustn't touch a data directory
+    #[cfg(test)]
+    pub fn new_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        // Off by default in SQLite; the schema's ON DELETE CASCADE clauses rely on it
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
         let db = Self { conn: Mutex::new(conn) };
         db.init_schema()?;
         Ok(db)
     }
 
+    /// Write a consistent copy of the database to `path`, which must not exist yet.
+    /// Writers wait until it's done.
+    pub fn backup_into(&self, path: &std::path::Path) -> std::result::Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("VACUUM INTO ?1", params![path.to_string_lossy()])
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Snapshot the database into `data_dir` and switch this handle to the copy once
+    /// `commit` succeeds. Writers wait throughout, so nothing lands in the old file
+    /// after the snapshot; on any error the old connection stays in use.
+    pub fn relocate(
+        &self,
+        data_dir: &std::path::Path,
+        commit: impl FnOnce() -> std::result::Result<(), String>,
+    ) -> std::result::Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let db_path = data_dir.join("sanhuoai.db");
+        conn.execute("VACUUM INTO ?1", params![db_path.to_string_lossy()])
+            .map_err(|e| format!("Failed to copy the database: {}", e))?;
+        let copy = Connection::open(&db_path).map_err(|e| e.to_string())?;
+        // Per connection, like in with_connection
+        copy.execute_batch("PRAGMA foreign_keys = ON").map_err(|e| e.to_string())?;
+        let check: String = copy
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if check != "ok" {
+            return Err(format!("The copied database failed its integrity check: {}", check));
+        }
+        commit()?;
+        *conn = copy;
+        Ok(())
+    }
+
     fn init_schema(&self) -> Result<()> {
         let conn = self.conn.lock().unwrap();
-        conn.execute_batch(include_str!("../../database/schema.sql"))
+        // schema.sql adds the unique index on character names, which an older
+        // database with duplicates must be renamed into first, as in the agent's
+        // migration 037
+        let has_characters: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master \
+             WHERE type = 'table' AND name = 'characters')",
+            [],
+            |row| row.get(0),
+        )?;
+        if has_characters {
+            conn.execute_batch(include_str!(
+                "../../database/migrations/037_characters_unique_name.sql"
+            ))?;
+        }
+        conn.execute_batch(include_str!("../../database/schema.sql"))?;
+        // SQLite can't add a column with a non-constant default, so existing rows read
+        // updated_at through COALESCE(updated_at, created_at)
+        ensure_column(&conn, "characters", "updated_at", "TEXT")?;
+        // Also added by the agent's migration 017; whichever runs first wins
+        ensure_column(&conn, "projects", "top_p", "REAL DEFAULT 1.0")?;
+        ensure_column(&conn, "projects", "max_tokens", "INTEGER DEFAULT 4096")?;
+        ensure_column(&conn, "projects", "system_prompt_template", "TEXT DEFAULT ''")?;
+        // Also added by the agent's migration 021
+        ensure_column(&conn, "projects", "last_opened_at", "TEXT")?;
+        ensure_column(&conn, "projects", "settings", "TEXT DEFAULT '{}'")?;
+        // Also added by the agent's migration 022
+        ensure_column(&conn, "projects", "cover_path", "TEXT")?;
+        // Also added by the agent's migration 024
+        ensure_column(&conn, "characters", "aliases", "TEXT DEFAULT '[]'")?;
+        // Also added by the agent's migration 032
+        ensure_column(&conn, "character_relations", "strength", "INTEGER DEFAULT 0")?;
+        // Also added by the agent's migration 034
+        ensure_column(&conn, "projects", "trashed_at", "TEXT")?;
+        // Also added by the agent's migration 036
+        ensure_column(&conn, "projects", "pinned", "INTEGER DEFAULT 0")?;
+        ensure_column(&conn, "projects", "manual_order", "INTEGER")?;
+        // Also written by the agent's migration 025
+        for template in project_templates::builtins() {
+            conn.execute(
+                "INSERT OR IGNORE INTO project_templates \
+                 (id, name, genre, description, content, is_builtin) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, 1)",
+                params![
+                    template.id,
+                    template.name,
+                    template.genre,
+                    template.description,
+                    serde_json::to_string(&template.content).unwrap_or_default()
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn list_projects(&self, query: &ProjectQuery) -> Result<ProjectPage> {
+        let key = match query.sort_by {
+            ProjectSort::Name => "name",
+            ProjectSort::UpdatedAt => "updated_at",
+            ProjectSort::CreatedAt => "created_at",
+            ProjectSort::WordProgress => {
+                "(SELECT COALESCE(SUM(word_count), 0) FROM chapters \
+                 WHERE chapters.project_id = projects.id) * 1.0 / NULLIF(word_target, 0)"
+            }
+        };
+        let descending = match (query.sort_dir, query.sort_by) {
+            (Some(dir), _) => matches!(dir, SortDir::Desc),
+            (None, sort_by) => !matches!(sort_by, ProjectSort::Name),
+        };
+        // Unset and blank filters match everything
+        let filter = |value: &Option<String>| value.clone().filter(|v| !v.trim().is_empty());
+        let (status, genre) = (filter(&query.status), filter(&query.genre));
+        let name_pattern = filter(&query.name_contains).map(|name| like_pattern(&name));
+        let condition = "trashed_at IS NULL AND (?1 IS NULL OR status = ?1) \
+             AND (?2 IS NULL OR genre = ?2) AND (?3 IS NULL OR name LIKE ?3 ESCAPE '\\')";
+
+        let conn = self.conn.lock().unwrap();
+        let total = conn.query_row(
+            &format!("SELECT COUNT(*) FROM projects WHERE {}", condition),
+            params![status, genre, name_pattern],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare(&format!(
+            // Pinned first; NULLs (no word_target) last either way; id breaks ties so
+            // pages don't overlap
+            "SELECT {} FROM projects WHERE {} \
+             ORDER BY pinned = 1 DESC, CASE WHEN pinned = 1 THEN manual_order END, \
+             {key} IS NULL, {key} {}, id LIMIT ?4 OFFSET ?5",
+            PROJECT_COLUMNS,
+            condition,
+            if descending { "DESC" } else { "ASC" },
+        ))?;
+        // A negative LIMIT means no limit in SQLite
+        let limit = query.limit.map_or(-1, i64::from);
+        let offset = query.offset.unwrap_or(0);
+        let rows = stmt
+            .query_map(params![status, genre, name_pattern, limit, offset], project_from_row)?;
+        let projects = rows.collect::<Result<_>>()?;
+        Ok(ProjectPage { projects, total })
+    }
+
+    /// Projects whose name or genre contains `query`, ignoring ASCII case
+    pub fn search_projects(&self, query: &str) -> Result<Vec<Project>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM projects WHERE trashed_at IS NULL \
+             AND (name LIKE ?1 ESCAPE '\\' OR genre LIKE ?1 ESCAPE '\\') \
+             ORDER BY updated_at DESC, id",
+            PROJECT_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![like_pattern(query)], project_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_project(&self, id: &str) -> Result<Project> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM projects WHERE id = ?1", PROJECT_COLUMNS),
+            params![id],
+            project_from_row,
+        )
+    }
+
+    /// Stamp last_opened_at, and with `chapter_id` remember it in the project's settings
+    pub fn touch_project(&self, id: &str, chapter_id: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE projects SET last_opened_at = datetime('now'), \
+             settings = CASE WHEN ?2 IS NULL THEN settings \
+             ELSE json_set(COALESCE(NULLIF(settings, ''), '{}'), '$.last_chapter_id', ?2) END \
+             WHERE id = ?1",
+            params![id, chapter_id],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
+
+    /// Pinning puts the project after those already pinned; unpinning closes the
+    /// gap it leaves. updated_at is left alone.
+    pub fn set_project_pinned(&self, id: &str, pinned: bool) -> Result<Project> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let was_pinned: bool = tx.query_row(
+            "SELECT COALESCE(pinned, 0) FROM projects WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        if pinned && !was_pinned {
+            tx.execute(
+                "UPDATE projects SET pinned = 1, manual_order = \
+                 (SELECT COALESCE(MAX(manual_order), -1) + 1 FROM projects WHERE pinned = 1) \
+                 WHERE id = ?1",
+                params![id],
+            )?;
+        } else if !pinned && was_pinned {
+            tx.execute(
+                "UPDATE projects SET pinned = 0, manual_order = NULL WHERE id = ?1",
+                params![id],
+            )?;
+            let order = pinned_ids(&tx, false)?;
+            renumber_pinned(&tx, &order)?;
+        }
+        tx.commit()?;
+        drop(conn);
+        self.get_project(id)
+    }
+
+    /// Put the pinned projects in the order of `ordered_ids`, which must list each
+    /// of them (outside the trash) exactly once. Returns them in their new order.
+    pub fn reorder_pinned_projects(
+        &self,
+        ordered_ids: &[String],
+    ) -> std::result::Result<Vec<Project>, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let pinned = pinned_ids(&tx, true).map_err(|e| e.to_string())?;
+        let mut seen = std::collections::HashSet::new();
+        for id in ordered_ids {
+            if !seen.insert(id.as_str()) {
+                return Err(format!("Project {} is listed more than once", id));
+            }
+            if !pinned.contains(id) {
+                return Err(format!("Project {} is not pinned", id));
+            }
+        }
+        if let Some(missing) = pinned.iter().find(|id| !seen.contains(id.as_str())) {
+            return Err(format!("Every pinned project must be listed; {} is missing", missing));
+        }
+        // Trashed projects that were pinned keep their place after the rest
+        let mut order = ordered_ids.to_vec();
+        let all_pinned = pinned_ids(&tx, false).map_err(|e| e.to_string())?;
+        order.extend(all_pinned.into_iter().filter(|id| !seen.contains(id.as_str())));
+        renumber_pinned(&tx, &order).map_err(|e| e.to_string())?;
+        let mut stmt = tx
+            .prepare(&format!(
+                "SELECT {} FROM projects WHERE pinned = 1 AND trashed_at IS NULL \
+                 ORDER BY manual_order, id",
+                PROJECT_COLUMNS
+            ))
+            .map_err(|e| e.to_string())?;
+        let projects = stmt
+            .query_map([], project_from_row)
+            .and_then(|rows| rows.collect::<Result<Vec<_>>>())
+            .map_err(|e| e.to_string())?;
+        drop(stmt);
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(projects)
+    }
+
+    /// Move the project to the trash; a project already there keeps its date
+    pub fn trash_project(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE projects SET trashed_at = COALESCE(trashed_at, datetime('now')) WHERE id = ?1",
+            params![id],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
+
+    /// Take the project back out of the trash; errs unless it is in there
+    pub fn restore_trashed_project(&self, id: &str) -> Result<Project> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "UPDATE projects SET trashed_at = NULL \
+                 WHERE id = ?1 AND trashed_at IS NOT NULL RETURNING {}",
+                PROJECT_COLUMNS
+            ),
+            params![id],
+            project_from_row,
+        )
+    }
+
+    /// The projects in the trash, longest there first, with the rows each owns.
+    /// file_bytes is left at 0 for the caller, who knows the data directory.
+    pub fn trashed_projects(&self) -> Result<Vec<TrashedProject>> {
+        let conn = self.conn.lock().unwrap();
+        // Every table holding project or chapter rows; virtual tables only mirror others
+        let tables: Vec<(String, bool)> = conn
+            .prepare(
+                "SELECT m.name, MAX(p.name = 'project_id') \
+                 FROM sqlite_master m JOIN pragma_table_info(m.name) p \
+                 WHERE m.type = 'table' AND m.sql NOT LIKE 'CREATE VIRTUAL%' \
+                 AND p.name IN ('project_id', 'chapter_id') GROUP BY m.name",
+            )?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?;
+        let mut projects: Vec<TrashedProject> = conn
+            .prepare(
+                "SELECT id, name, trashed_at FROM projects WHERE trashed_at IS NOT NULL \
+                 ORDER BY trashed_at, id",
+            )?
+            .query_map([], |row| {
+                Ok(TrashedProject {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    trashed_at: row.get(2)?,
+                    rows: 1,
+                    file_bytes: 0,
+                })
+            })?
+            .collect::<Result<_>>()?;
+        for (table, by_project) in &tables {
+            let sql = if *by_project {
+                format!("SELECT COUNT(*) FROM \"{}\" WHERE project_id = ?1", table)
+            } else {
+                format!(
+                    "SELECT COUNT(*) FROM \"{}\" \
+                     WHERE chapter_id IN (SELECT id FROM chapters WHERE project_id = ?1)",
+                    table
+                )
+            };
+            let mut stmt = conn.prepare(&sql)?;
+            for project in &mut projects {
+                project.rows += stmt.query_row(params![project.id], |row| row.get::<_, i64>(0))?;
+            }
+        }
+        Ok(projects)
+    }
+
+    /// Delete the trashed projects, with everything they own, that went in at
+    /// least `older_than_days` days ago, or all of them with None. Projects not in
+    /// the trash are never touched.
+    pub fn purge_trash(&self, older_than_days: Option<u32>) -> Result<Purged> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut purged = Purged::default();
+        {
+            let mut stmt = tx.prepare(
+                "DELETE FROM projects WHERE trashed_at IS NOT NULL \
+                 AND (?1 IS NULL OR trashed_at <= datetime('now', '-' || ?1 || ' days')) \
+                 RETURNING id, name",
+            )?;
+            let mut rows = stmt.query(params![older_than_days])?;
+            while let Some(row) = rows.next()? {
+                purged.project_ids.push(row.get(0)?);
+                purged.names.push(row.get(1)?);
+            }
+        }
+        tx.commit()?;
+        Ok(purged)
+    }
+
+    /// The project's own daily word goal, if it sets one; errs if the project
+    /// doesn't exist
+    pub fn daily_word_goal(&self, project_id: &str) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT json_extract(COALESCE(NULLIF(settings, ''), '{}'), '$.daily_word_goal') \
+             FROM projects WHERE id = ?1",
+            params![project_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Set the project's daily word goal in its settings; None falls back to the
+    /// app-wide one
+    pub fn set_daily_word_goal(&self, project_id: &str, goal: Option<i64>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE projects SET settings = CASE WHEN ?2 IS NULL \
+             THEN json_remove(COALESCE(NULLIF(settings, ''), '{}'), '$.daily_word_goal') \
+             ELSE json_set(COALESCE(NULLIF(settings, ''), '{}'), '$.daily_word_goal', ?2) END \
+             WHERE id = ?1",
+            params![project_id, goal],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
     }
 
-    pub fn list_projects(&self) -> Result<Vec<Project>> {
+    /// Words written on each day the project saw any, as (local date, words)
+    pub fn writing_days(&self, project_id: &str) -> Result<Vec<(String, i64)>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, name, genre, description, status, \
-             model_main, model_secondary, temperature, embedding_dim, word_target \
-             FROM projects ORDER BY updated_at DESC"
+            "SELECT day, COALESCE(words, 0) FROM writing_days WHERE project_id = ?1 \
+             ORDER BY day DESC",
         )?;
-        let rows = stmt.query_map([], |row| {
-            Ok(Project {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                genre: row.get(2)?,
-                description: row.get(3)?,
-                status: row.get(4)?,
-                model_main: row.get(5)?,
-                model_secondary: row.get(6)?,
-                temperature: row.get(7)?,
-                embedding_dim: row.get(8)?,
-                word_target: row.get(9)?,
-            })
-        })?;
+        let rows = stmt.query_map(params![project_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Record that the project's goal for `day` was announced. True only the first
+    /// time for each day, however many windows or reloads ask.
+    pub fn claim_goal_reached(&self, project_id: &str, day: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let claimed = conn.execute(
+            "INSERT INTO writing_days (project_id, day, goal_reached_at) \
+             VALUES (?1, ?2, datetime('now')) \
+             ON CONFLICT (project_id, day) DO UPDATE \
+             SET goal_reached_at = excluded.goal_reached_at WHERE goal_reached_at IS NULL",
+            params![project_id, day],
+        )?;
+        Ok(claimed > 0)
+    }
+
+    /// Most recently opened first. With `include_unopened`, projects never opened
+    /// follow, most recently edited first.
+    pub fn list_recent_projects(
+        &self,
+        limit: u32,
+        include_unopened: bool,
+    ) -> Result<Vec<RecentProject>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, {} FROM projects p \
+             WHERE trashed_at IS NULL AND (?2 OR last_opened_at IS NOT NULL) \
+             ORDER BY last_opened_at DESC NULLS LAST, updated_at DESC, id LIMIT ?1",
+            PROJECT_COLUMNS, RECENT_PROJECT_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![limit, include_unopened], recent_project_from_row)?;
+        rows.collect()
+    }
+
+    /// None when the project no longer exists or is in the trash
+    pub fn recent_project(&self, id: &str) -> Result<Option<RecentProject>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT {}, {} FROM projects p WHERE id = ?1 AND trashed_at IS NULL",
+                PROJECT_COLUMNS, RECENT_PROJECT_COLUMNS
+            ),
+            params![id],
+            recent_project_from_row,
+        )
+        .optional()
+    }
+
+    /// Relative to the data directory; errs if the project doesn't exist
+    pub fn project_cover_path(&self, project_id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT NULLIF(cover_path, '') FROM projects WHERE id = ?1",
+            params![project_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn set_project_cover_path(&self, project_id: &str, path: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE projects SET cover_path = ?2, updated_at = datetime('now') WHERE id = ?1",
+            params![project_id, path],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
+
+    pub fn project_exists(&self, project_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM projects WHERE id = ?1)",
+            params![project_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Oldest first
+    pub fn list_attachments(&self, project_id: &str) -> Result<Vec<Attachment>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM attachments WHERE project_id = ?1 ORDER BY created_at, rowid",
+            ATTACHMENT_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![project_id], attachment_from_row)?;
         rows.collect()
     }
 
+    pub fn get_attachment(&self, id: &str) -> Result<Option<Attachment>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM attachments WHERE id = ?1", ATTACHMENT_COLUMNS),
+            params![id],
+            attachment_from_row,
+        )
+        .optional()
+    }
+
+    /// The project's attachment with these contents, if it has one
+    pub fn find_attachment(&self, project_id: &str, sha256: &str) -> Result<Option<Attachment>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM attachments WHERE project_id = ?1 AND sha256 = ?2 LIMIT 1",
+                ATTACHMENT_COLUMNS
+            ),
+            params![project_id, sha256],
+            attachment_from_row,
+        )
+        .optional()
+    }
+
+    /// Bytes taken by the project's attachments, for the quota
+    pub fn attachments_size(&self, project_id: &str) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM attachments WHERE project_id = ?1",
+            params![project_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Index a file already copied in; the row's created_at comes from the database
+    pub fn insert_attachment(&self, attachment: &Attachment) -> Result<Attachment> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "INSERT INTO attachments (id, project_id, filename, mime, size, sha256) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING {}",
+                ATTACHMENT_COLUMNS
+            ),
+            params![
+                attachment.id,
+                attachment.project_id,
+                attachment.filename,
+                attachment.mime,
+                attachment.size_bytes,
+                attachment.sha256
+            ],
+            attachment_from_row,
+        )
+    }
+
+    /// Remove the row and return it, so the caller can delete the file
+    pub fn delete_attachment(&self, id: &str) -> Result<Option<Attachment>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("DELETE FROM attachments WHERE id = ?1 RETURNING {}", ATTACHMENT_COLUMNS),
+            params![id],
+            attachment_from_row,
+        )
+        .optional()
+    }
+
     pub fn create_project(&self, name: &str, genre: &str) -> Result<Project> {
         let conn = self.conn.lock().unwrap();
         let id: String = conn.query_row(
@@ -55,7 +1340,2968 @@ impl Database {
             |row| row.get(0),
         )?;
         drop(conn);
-        let projects = self.list_projects()?;
-        Ok(projects.into_iter().find(|p| p.id == id).unwrap())
+        // Errs with QueryReturnedNoRows if the row is gone again, e.g. removed by a trigger
+        self.get_project(&id)
+    }
+
+    /// Chapter aggregates for the dashboard, computed in one query
+    pub fn project_stats(&self, project_id: &str) -> Result<ProjectStats> {
+        let conn = self.conn.lock().unwrap();
+        let (project_updated, stats): (Option<String>, ProjectStats) = conn.query_row(
+            "WITH ranked AS ( \
+                 SELECT id, COALESCE(title, '') AS title, COALESCE(word_count, 0) AS words, updated_at, \
+                        COALESCE(status, 'draft') AS status, \
+                        ROW_NUMBER() OVER (ORDER BY COALESCE(word_count, 0) DESC, chapter_num) AS longest_rank, \
+                        ROW_NUMBER() OVER (ORDER BY COALESCE(word_count, 0) ASC, chapter_num) AS shortest_rank \
+                 FROM chapters WHERE project_id = ?1 \
+             ) \
+             SELECT (SELECT updated_at FROM projects WHERE id = ?1), \
+                    COUNT(*), COALESCE(SUM(words), 0), COALESCE(AVG(words), 0.0), MAX(updated_at), \
+                    MAX(CASE WHEN longest_rank = 1 THEN id END), \
+                    MAX(CASE WHEN longest_rank = 1 THEN title END), MAX(words), \
+                    MAX(CASE WHEN shortest_rank = 1 THEN id END), \
+                    MAX(CASE WHEN shortest_rank = 1 THEN title END), MIN(words), \
+                    COUNT(CASE WHEN status = 'revised' THEN 1 END), \
+                    COUNT(CASE WHEN status = 'final' THEN 1 END) \
+             FROM ranked",
+            params![project_id],
+            |row| {
+                let longest = match row.get::<_, Option<String>>(5)? {
+                    Some(chapter_id) => Some(ChapterLength {
+                        chapter_id,
+                        title: row.get(6)?,
+                        word_count: row.get(7)?,
+                    }),
+                    None => None,
+                };
+                let shortest = match row.get::<_, Option<String>>(8)? {
+                    Some(chapter_id) => Some(ChapterLength {
+                        chapter_id,
+                        title: row.get(9)?,
+                        word_count: row.get(10)?,
+                    }),
+                    None => None,
+                };
+                let chapter_count: i64 = row.get(1)?;
+                let revised: i64 = row.get(11)?;
+                let final_: i64 = row.get(12)?;
+                Ok((
+                    row.get(0)?,
+                    ProjectStats {
+                        chapter_count,
+                        total_words: row.get(2)?,
+                        avg_words: row.get(3)?,
+                        longest,
+                        shortest,
+                        // Values the triggers predate count as drafts, as they do everywhere
+                        by_status: StatusCounts {
+                            draft: chapter_count - revised - final_,
+                            revised,
+                            final_,
+                        },
+                        last_updated: row.get(4)?,
+                    },
+                ))
+            },
+        )?;
+        let project_updated = project_updated.ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        Ok(ProjectStats {
+            last_updated: stats.last_updated.max(Some(project_updated)),
+            ..stats
+        })
+    }
+
+    /// Move chapters to `status` in one transaction: every chapter is checked
+    /// before any changes. Only status and updated_at are written, so no revision
+    /// is taken. Returns how many chapters changed.
+    pub fn set_chapters_status(
+        &self,
+        ids: &[String],
+        status: ChapterStatus,
+        force: bool,
+    ) -> std::result::Result<usize, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let mut changed = 0;
+        for id in ids {
+            let current = tx
+                .query_row(
+                    "SELECT chapter_num, COALESCE(status, 'draft') FROM chapters WHERE id = ?1",
+                    params![id],
+                    |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+                )
+                .optional()
+                .map_err(|e| e.to_string())?;
+            let Some((chapter_num, current)) = current else {
+                return Err(format!("Chapter not found: {}", id));
+            };
+            let current = ChapterStatus::parse(&current);
+            if current == status {
+                continue;
+            }
+            current
+                .check_transition(status, force)
+                .map_err(|e| format!("Chapter {}: {}", chapter_num, e))?;
+            tx.execute(
+                "UPDATE chapters SET status = ?1, updated_at = datetime('now') WHERE id = ?2",
+                params![status.as_str(), id],
+            )
+            .map_err(|e| e.to_string())?;
+            changed += 1;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(changed)
+    }
+
+    /// Sum of the chapters' word counts, which every write keeps as
+    /// text_count::count_words of their text; errs if the project doesn't exist
+    pub fn project_word_count(&self, project_id: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT (SELECT COALESCE(SUM(word_count), 0) FROM chapters WHERE project_id = p.id) \
+             FROM projects p WHERE p.id = ?1",
+            params![project_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Set every chapter's word_count to text_count::count_words of its text, for
+    /// rows written before the count understood Chinese. Returns how many chapters
+    /// changed; errs if the project doesn't exist.
+    pub fn recount_project_words(&self, project_id: &str) -> Result<usize> {
+        self.recount_words(Some(project_id))
+    }
+
+    /// recount_project_words for one project, or for every project when None, in a
+    /// single transaction. Only wrong counts are written, so a second run changes
+    /// nothing.
+    pub fn recount_words(&self, project_id: Option<&str>) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let project_ids = match project_id {
+            Some(id) => {
+                tx.query_row("SELECT 1 FROM projects WHERE id = ?1", params![id], |_| Ok(()))?;
+                vec![id.to_string()]
+            }
+            None => tx
+                .prepare("SELECT id FROM projects")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>>>()?,
+        };
+        let mut changed = 0;
+        for project_id in &project_ids {
+            for chapter in chapter_texts(&tx, project_id)? {
+                let words: i64 =
+                    chapter.paragraphs.iter().map(|p| text_count::count_words(&p.content)).sum();
+                // Not an edit, so updated_at stays
+                changed += tx.execute(
+                    "UPDATE chapters SET word_count = ?1 \
+                     WHERE id = ?2 AND word_count IS NOT ?1",
+                    params![words, chapter.chapter_id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// Create a project with its chapters in a single transaction
+    pub fn import_project(
+        &self,
+        name: &str,
+        genre: &str,
+        description: &str,
+        chapters: &[ManuscriptChapter],
+    ) -> Result<Project> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let project_id: String = tx.query_row(
+            "INSERT INTO projects (name, genre, description) VALUES (?1, ?2, ?3) RETURNING id",
+            params![name, genre, description],
+            |row| row.get(0),
+        )?;
+        for (index, chapter) in chapters.iter().enumerate() {
+            let chapter_num = index as i64 + 1;
+            let chapter_id: String = tx.query_row(
+                "INSERT INTO chapters (project_id, chapter_num, title, sort_order) \
+                 VALUES (?1, ?2, ?3, ?2) RETURNING id",
+                params![project_id, chapter_num, chapter.title],
+                |row| row.get(0),
+            )?;
+            let word_count = insert_paragraphs(&tx, &chapter_id, &chapter.body)?;
+            tx.execute(
+                "UPDATE chapters SET word_count = ?1 WHERE id = ?2",
+                params![word_count, chapter_id],
+            )?;
+        }
+        tx.commit()?;
+        drop(conn);
+        self.get_project(&project_id)
+    }
+
+    pub fn export_chapters(&self, project_id: &str) -> Result<Vec<ChapterBackup>> {
+        let conn = self.conn.lock().unwrap();
+        let mut chapter_stmt = conn.prepare(
+            "SELECT id, chapter_num, COALESCE(title, ''), COALESCE(phase, ''), \
+             COALESCE(synopsis, ''), COALESCE(status, 'draft'), COALESCE(word_count, 0), \
+             COALESCE(sort_order, 0) \
+             FROM chapters WHERE project_id = ?1 ORDER BY sort_order, chapter_num"
+        )?;
+        let mut para_stmt = conn.prepare(
+            "SELECT para_index, COALESCE(content, ''), scene_tag \
+             FROM chapter_paragraphs WHERE chapter_id = ?1 ORDER BY para_index"
+        )?;
+
+        let rows = chapter_stmt.query_map(params![project_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                ChapterBackup {
+                    chapter_num: row.get(1)?,
+                    title: row.get(2)?,
+                    phase: row.get(3)?,
+                    synopsis: row.get(4)?,
+                    status: row.get(5)?,
+                    word_count: row.get(6)?,
+                    sort_order: row.get(7)?,
+                    paragraphs: Vec::new(),
+                },
+            ))
+        })?;
+
+        let mut chapters = Vec::new();
+        for row in rows {
+            let (chapter_id, mut chapter) = row?;
+            chapter.paragraphs = para_stmt
+                .query_map(params![chapter_id], |row| {
+                    Ok(ParagraphBackup {
+                        para_index: row.get(0)?,
+                        content: row.get(1)?,
+                        scene_tag: row.get(2)?,
+                    })
+                })?
+                .collect::<Result<_>>()?;
+            chapters.push(chapter);
+        }
+        Ok(chapters)
+    }
+
+    /// Insert a backup as a brand new project; every row gets a fresh id
+    pub fn import_backup(&self, backup: &ProjectBackup) -> Result<Project> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let project_id = insert_backup(&tx, backup)?;
+        tx.commit()?;
+        drop(conn);
+        self.get_project(&project_id)
+    }
+
+    /// import_backup for a snapshot. `place` puts the project's files where they
+    /// belong once its id is known and returns the cover path to record; the
+    /// project is only kept if it succeeds.
+    pub fn restore_backup(
+        &self,
+        backup: &ProjectBackup,
+        place: impl FnOnce(&str) -> std::result::Result<Option<String>, String>,
+    ) -> std::result::Result<Project, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let project_id = insert_backup(&tx, backup).map_err(|e| e.to_string())?;
+        let cover_path = place(&project_id)?;
+        tx.execute(
+            "UPDATE projects SET cover_path = ?2 WHERE id = ?1",
+            params![project_id, cover_path],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        drop(conn);
+        self.get_project(&project_id).map_err(|e| e.to_string())
+    }
+
+    pub fn list_characters(&self, project_id: &str) -> Result<Vec<Character>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM characters WHERE project_id = ?1 ORDER BY sort_order, created_at",
+            CHARACTER_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![project_id], character_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_character(&self, id: &str) -> Result<Character> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM characters WHERE id = ?1", CHARACTER_COLUMNS),
+            params![id],
+            character_from_row,
+        )
+    }
+
+    pub fn create_character(&self, project_id: &str, name: &str) -> Result<Character> {
+        let conn = self.conn.lock().unwrap();
+        let id: String = conn.query_row(
+            "INSERT INTO characters (project_id, name, sort_order, updated_at) \
+             VALUES (?1, ?2, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM characters WHERE project_id = ?1), \
+             datetime('now')) RETURNING id",
+            params![project_id, name],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        self.get_character(&id)
+    }
+
+    pub fn update_character(&self, id: &str, update: &CharacterUpdate) -> Result<Character> {
+        let text_fields = [
+            ("name", &update.name),
+            ("category", &update.category),
+            ("gender", &update.gender),
+            ("age", &update.age),
+            ("identity", &update.identity),
+            ("appearance", &update.appearance),
+            ("personality", &update.personality),
+            ("motivation", &update.motivation),
+            ("backstory", &update.backstory),
+            ("arc", &update.arc),
+            ("usage_notes", &update.usage_notes),
+            ("status", &update.status),
+        ];
+        let mut sets = Vec::new();
+        let mut values = Vec::new();
+        for (column, value) in text_fields {
+            if let Some(value) = value {
+                sets.push(format!("{} = ?", column));
+                values.push(Value::Text(value.clone()));
+            }
+        }
+        if let Some(sort_order) = update.sort_order {
+            sets.push("sort_order = ?".to_string());
+            values.push(Value::Integer(sort_order));
+        }
+        if let Some(aliases) = &update.aliases {
+            let mut kept: Vec<&str> = Vec::new();
+            for alias in aliases.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+                if !kept.contains(&alias) {
+                    kept.push(alias);
+                }
+            }
+            sets.push("aliases = ?".to_string());
+            values.push(Value::Text(serde_json::to_string(&kept).unwrap_or_default()));
+        }
+        values.push(Value::Text(id.to_string()));
+
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            &format!(
+                "UPDATE characters SET {}updated_at = datetime('now') WHERE id = ?",
+                sets.iter().map(|s| format!("{}, ", s)).collect::<String>()
+            ),
+            params_from_iter(values.iter()),
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        drop(conn);
+        self.get_character(id)
+    }
+
+    pub fn delete_character(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM characters WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn get_relationship(&self, id: &str) -> Result<Relationship> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM character_relations r WHERE r.id = ?1", RELATIONSHIP_COLUMNS),
+            params![id],
+            relationship_from_row,
+        )
+    }
+
+    /// An edge from one character to another of the same project; a second edge
+    /// of the same kind between them is a Conflict
+    pub fn create_relationship(
+        &self,
+        from: &str,
+        to: &str,
+        kind: &str,
+        description: &str,
+        strength: i64,
+    ) -> std::result::Result<Relationship, String> {
+        if from == to {
+            return Err("A character can't have a relationship with itself".into());
+        }
+        let conn = self.conn.lock().unwrap();
+        let same_project: Option<bool> = conn
+            .query_row(
+                "SELECT a.project_id = b.project_id FROM characters a, characters b \
+                 WHERE a.id = ?1 AND b.id = ?2",
+                params![from, to],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        match same_project {
+            None => return Err("Character not found".into()),
+            Some(false) => {
+                return Err("Both characters of a relationship must be in the same project".into())
+            }
+            Some(true) => {}
+        }
+        check_relationship_unique(&conn, from, to, kind, None)?;
+        let id: String = conn
+            .query_row(
+                "INSERT INTO character_relations \
+                 (character_a_id, character_b_id, relation_type, description, strength) \
+                 VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
+                params![from, to, kind, description, strength],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        drop(conn);
+        self.get_relationship(&id).map_err(|e| e.to_string())
+    }
+
+    pub fn update_relationship(
+        &self,
+        id: &str,
+        update: &RelationshipUpdate,
+    ) -> std::result::Result<Relationship, String> {
+        let current = self.get_relationship(id).map_err(|e| e.to_string())?;
+        let conn = self.conn.lock().unwrap();
+        if let Some(kind) = &update.kind {
+            check_relationship_unique(
+                &conn,
+                &current.from_character,
+                &current.to_character,
+                kind,
+                Some(id),
+            )?;
+        }
+        conn.execute(
+            "UPDATE character_relations SET relation_type = COALESCE(?2, relation_type), \
+             description = COALESCE(?3, description), strength = COALESCE(?4, strength) \
+             WHERE id = ?1",
+            params![id, update.kind, update.description, update.strength],
+        )
+        .map_err(|e| e.to_string())?;
+        drop(conn);
+        self.get_relationship(id).map_err(|e| e.to_string())
+    }
+
+    pub fn delete_relationship(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM character_relations WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// The project's characters in roster order and every edge between them
+    pub fn relationship_graph(&self, project_id: &str) -> Result<RelationshipGraph> {
+        let conn = self.conn.lock().unwrap();
+        let characters = conn
+            .prepare(
+                "SELECT id, name, COALESCE(category, '') FROM characters WHERE project_id = ?1 \
+                 ORDER BY sort_order, created_at",
+            )?
+            .query_map(params![project_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<_>>>()?;
+        let edges = conn
+            .prepare(&format!(
+                "SELECT {} FROM character_relations r \
+                 JOIN characters a ON a.id = r.character_a_id \
+                 JOIN characters b ON b.id = r.character_b_id \
+                 WHERE a.project_id = ?1 AND b.project_id = ?1 \
+                 ORDER BY r.created_at, r.id",
+                RELATIONSHIP_COLUMNS
+            ))?
+            .query_map(params![project_id], relationship_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(relationship_graph::build(characters, edges))
+    }
+
+    /// The character's relationships in either direction, strongest first
+    pub fn character_connections(&self, character_id: &str) -> Result<Vec<CharacterConnection>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, o.id, o.name FROM character_relations r \
+             JOIN characters o ON o.id = CASE WHEN r.character_a_id = ?1 \
+                                         THEN r.character_b_id ELSE r.character_a_id END \
+             WHERE r.character_a_id = ?1 OR r.character_b_id = ?1 \
+             ORDER BY COALESCE(r.strength, 0) DESC, o.name, r.id",
+            RELATIONSHIP_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![character_id], |row| {
+            let relationship = relationship_from_row(row)?;
+            Ok(CharacterConnection {
+                character_id: row.get(7)?,
+                name: row.get(8)?,
+                outgoing: relationship.from_character == character_id,
+                relationship,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn list_scenes(&self, chapter_id: &str) -> Result<Vec<Scene>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM scenes WHERE chapter_id = ?1 ORDER BY order_index, created_at, id",
+            SCENE_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![chapter_id], scene_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_scene(&self, id: &str) -> Result<Scene> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM scenes WHERE id = ?1", SCENE_COLUMNS),
+            params![id],
+            scene_from_row,
+        )
+    }
+
+    pub fn create_scene(
+        &self,
+        chapter_id: &str,
+        summary: &str,
+        pov_character_id: Option<&str>,
+    ) -> Result<Scene> {
+        let conn = self.conn.lock().unwrap();
+        let id: String = conn.query_row(
+            "INSERT INTO scenes (chapter_id, summary, pov_character_id, order_index) \
+             VALUES (?1, ?2, ?3, \
+             (SELECT COALESCE(MAX(order_index), 0) + 1 FROM scenes WHERE chapter_id = ?1)) \
+             RETURNING id",
+            params![chapter_id, summary, pov_character_id],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        self.get_scene(&id)
+    }
+
+    pub fn update_scene(&self, id: &str, update: &SceneUpdate) -> Result<Scene> {
+        let mut sets = Vec::new();
+        let mut values = Vec::new();
+        if let Some(summary) = &update.summary {
+            sets.push("summary = ?");
+            values.push(Value::Text(summary.clone()));
+        }
+        if let Some(pov) = &update.pov_character_id {
+            sets.push("pov_character_id = ?");
+            values.push(if pov.is_empty() { Value::Null } else { Value::Text(pov.clone()) });
+        }
+        if sets.is_empty() {
+            return self.get_scene(id);
+        }
+        values.push(Value::Text(id.to_string()));
+
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            &format!("UPDATE scenes SET {} WHERE id = ?", sets.join(", ")),
+            params_from_iter(values.iter()),
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        drop(conn);
+        self.get_scene(id)
+    }
+
+    pub fn delete_scene(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM scenes WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Number the chapter's scenes in the order given, in one transaction
+    pub fn reorder_scenes(&self, chapter_id: &str, scene_ids: &[String]) -> Result<Vec<Scene>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (index, id) in scene_ids.iter().enumerate() {
+            tx.execute(
+                "UPDATE scenes SET order_index = ?1 WHERE id = ?2 AND chapter_id = ?3",
+                params![index as i64 + 1, id, chapter_id],
+            )?;
+        }
+        tx.commit()?;
+        drop(conn);
+        self.list_scenes(chapter_id)
+    }
+
+    /// Pinned first, then most recently edited
+    pub fn list_notes(&self, project_id: &str) -> Result<Vec<Note>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM notes WHERE project_id = ?1 \
+             ORDER BY COALESCE(pinned, 0) DESC, COALESCE(updated_at, created_at) DESC, id",
+            NOTE_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![project_id], note_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_note(&self, id: &str) -> Result<Note> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM notes WHERE id = ?1", NOTE_COLUMNS),
+            params![id],
+            note_from_row,
+        )
+    }
+
+    pub fn create_note(
+        &self,
+        project_id: &str,
+        title: &str,
+        content: &str,
+        color: &str,
+    ) -> Result<Note> {
+        let conn = self.conn.lock().unwrap();
+        let id: String = conn.query_row(
+            "INSERT INTO notes (project_id, title, content, color) VALUES (?1, ?2, ?3, ?4) \
+             RETURNING id",
+            params![project_id, title, content, color],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        self.get_note(&id)
+    }
+
+    pub fn update_note(&self, id: &str, update: &NoteUpdate) -> Result<Note> {
+        let mut sets = Vec::new();
+        let mut values = Vec::new();
+        if let Some(title) = &update.title {
+            sets.push("title = ?");
+            values.push(Value::Text(title.trim().to_string()));
+        }
+        if let Some(content) = &update.content {
+            sets.push("content = ?");
+            values.push(Value::Text(content.clone()));
+        }
+        if let Some(color) = &update.color {
+            sets.push("color = ?");
+            values.push(Value::Text(color.clone()));
+        }
+        if !sets.is_empty() {
+            sets.push("updated_at = datetime('now')");
+        }
+        if let Some(pinned) = update.pinned {
+            sets.push("pinned = ?");
+            values.push(Value::Integer(pinned as i64));
+        }
+        if sets.is_empty() {
+            return self.get_note(id);
+        }
+        values.push(Value::Text(id.to_string()));
+
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            &format!("UPDATE notes SET {} WHERE id = ?", sets.join(", ")),
+            params_from_iter(values.iter()),
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        drop(conn);
+        self.get_note(id)
+    }
+
+    pub fn delete_note(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM notes WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// The project's notes for find_replace, in list_notes order
+    pub fn project_note_texts(&self, project_id: &str) -> Result<Vec<NoteText>> {
+        Ok(self
+            .list_notes(project_id)?
+            .into_iter()
+            .map(|note| NoteText { note_id: note.id, title: note.title, content: note.content })
+            .collect())
+    }
+
+    pub fn export_notes(&self, project_id: &str) -> Result<Vec<NoteBackup>> {
+        Ok(self
+            .list_notes(project_id)?
+            .into_iter()
+            .map(|note| NoteBackup {
+                title: note.title,
+                content: note.content,
+                pinned: note.pinned,
+                color: note.color,
+                created_at: note.created_at,
+                updated_at: note.updated_at,
+            })
+            .collect())
+    }
+
+    pub fn list_story_events(
+        &self,
+        project_id: &str,
+        sort: StoryEventSort,
+    ) -> Result<Vec<StoryEvent>> {
+        let order = match sort {
+            StoryEventSort::Story => "story_date = '', story_date, narrative_order, id",
+            StoryEventSort::Narrative => "narrative_order, id",
+        };
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM story_events WHERE project_id = ?1 ORDER BY {}",
+            STORY_EVENT_COLUMNS, order
+        ))?;
+        let rows = stmt.query_map(params![project_id], story_event_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_story_event(&self, id: &str) -> Result<StoryEvent> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM story_events WHERE id = ?1", STORY_EVENT_COLUMNS),
+            params![id],
+            story_event_from_row,
+        )
+    }
+
+    /// Whether the chapter, if any, and every character belong to the project
+    pub fn story_event_links_exist(
+        &self,
+        project_id: &str,
+        chapter_id: Option<&str>,
+        character_ids: &[String],
+    ) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        if let Some(chapter_id) = chapter_id {
+            let found: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM chapters WHERE id = ?1 AND project_id = ?2)",
+                params![chapter_id, project_id],
+                |row| row.get(0),
+            )?;
+            if !found {
+                return Ok(false);
+            }
+        }
+        let ids = serde_json::to_string(character_ids).unwrap_or_default();
+        conn.query_row(
+            "SELECT (SELECT COUNT(DISTINCT value) FROM json_each(?2)) = \
+             (SELECT COUNT(*) FROM characters \
+              WHERE project_id = ?1 AND id IN (SELECT value FROM json_each(?2)))",
+            params![project_id, ids],
+            |row| row.get(0),
+        )
+    }
+
+    /// Appended after the project's last event in narrative order
+    pub fn create_story_event(
+        &self,
+        project_id: &str,
+        title: &str,
+        description: &str,
+        story_date: &str,
+        chapter_id: Option<&str>,
+        character_ids: &[String],
+    ) -> Result<StoryEvent> {
+        let conn = self.conn.lock().unwrap();
+        let id: String = conn.query_row(
+            "INSERT INTO story_events (project_id, title, description, story_date, chapter_id, \
+             character_ids, narrative_order) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, \
+             (SELECT COALESCE(MAX(narrative_order), 0) + 1 FROM story_events \
+              WHERE project_id = ?1)) \
+             RETURNING id",
+            params![
+                project_id,
+                title,
+                description,
+                story_date,
+                chapter_id,
+                serde_json::to_string(character_ids).unwrap_or_default()
+            ],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        self.get_story_event(&id)
+    }
+
+    pub fn update_story_event(&self, id: &str, update: &StoryEventUpdate) -> Result<StoryEvent> {
+        let mut sets = Vec::new();
+        let mut values = Vec::new();
+        if let Some(title) = &update.title {
+            sets.push("title = ?");
+            values.push(Value::Text(title.trim().to_string()));
+        }
+        if let Some(description) = &update.description {
+            sets.push("description = ?");
+            values.push(Value::Text(description.clone()));
+        }
+        if let Some(story_date) = &update.story_date {
+            sets.push("story_date = ?");
+            values.push(Value::Text(story_date.trim().to_string()));
+        }
+        if let Some(chapter_id) = &update.chapter_id {
+            sets.push("chapter_id = ?");
+            values.push(if chapter_id.is_empty() {
+                Value::Null
+            } else {
+                Value::Text(chapter_id.clone())
+            });
+        }
+        if let Some(character_ids) = &update.character_ids {
+            sets.push("character_ids = ?");
+            values.push(Value::Text(serde_json::to_string(character_ids).unwrap_or_default()));
+        }
+        if sets.is_empty() {
+            return self.get_story_event(id);
+        }
+        values.push(Value::Text(id.to_string()));
+
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            &format!(
+                "UPDATE story_events SET {}, updated_at = datetime('now') WHERE id = ?",
+                sets.join(", ")
+            ),
+            params_from_iter(values.iter()),
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        drop(conn);
+        self.get_story_event(id)
+    }
+
+    /// The events after it move up, so narrative_order stays 1..n
+    pub fn delete_story_event(&self, id: &str) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let project_id: Option<String> = tx
+            .query_row(
+                "DELETE FROM story_events WHERE id = ?1 RETURNING project_id",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(project_id) = project_id {
+            renumber_story_events(&tx, &project_id)?;
+        }
+        tx.commit()
+    }
+
+    /// Number the project's events in the order given, in one transaction
+    pub fn reorder_story_events(
+        &self,
+        project_id: &str,
+        event_ids: &[String],
+    ) -> Result<Vec<StoryEvent>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (index, id) in event_ids.iter().enumerate() {
+            tx.execute(
+                "UPDATE story_events SET narrative_order = ?1 WHERE id = ?2 AND project_id = ?3",
+                params![index as i64 + 1, id, project_id],
+            )?;
+        }
+        tx.commit()?;
+        drop(conn);
+        self.list_story_events(project_id, StoryEventSort::Narrative)
+    }
+
+    pub fn export_story_events(&self, project_id: &str) -> Result<Vec<StoryEventBackup>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT COALESCE(e.title, ''), COALESCE(e.description, ''), \
+             COALESCE(e.story_date, ''), COALESCE(e.narrative_order, 0), c.chapter_num, \
+             e.created_at, COALESCE(e.updated_at, e.created_at) \
+             FROM story_events e LEFT JOIN chapters c ON c.id = e.chapter_id \
+             WHERE e.project_id = ?1 ORDER BY e.narrative_order, e.id",
+        )?;
+        let rows = stmt.query_map(params![project_id], |row| {
+            Ok(StoryEventBackup {
+                title: row.get(0)?,
+                description: row.get(1)?,
+                story_date: row.get(2)?,
+                narrative_order: row.get(3)?,
+                chapter_num: row.get(4)?,
+                created_at: row.get(5)?,
+                updated_at: row.get(6)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Replace a chapter's paragraphs with `content`, one per line, update its word
+    /// count and updated_at, and keep a revision unless the text is the latest one.
+    /// The change in word count goes towards today's writing. Returns the chapter's
+    /// project, or None if there is no such chapter.
+    pub fn save_chapter_text(&self, id: &str, content: &str) -> Result<Option<String>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let chapter: Option<(String, i64)> = tx
+            .query_row(
+                "SELECT project_id, COALESCE(word_count, 0) FROM chapters WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((project_id, previous_words)) = chapter else {
+            return Ok(None);
+        };
+        let word_count = replace_paragraphs(&tx, id, content)?;
+        record_words_written(&tx, &project_id, word_count - previous_words)?;
+        let latest: Option<String> = tx
+            .query_row(
+                "SELECT content FROM chapter_revisions WHERE chapter_id = ?1 \
+                 ORDER BY id DESC LIMIT 1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if latest.as_deref() != Some(content) {
+            insert_revision(&tx, id, content, word_count, "save")?;
+        }
+        tx.commit()?;
+        Ok(Some(project_id))
+    }
+
+    /// Make revision `id` the chapter's current text, recorded as a new "restore"
+    /// revision. Errs if the revision belongs to another chapter.
+    pub fn restore_revision(&self, chapter_id: &str, id: i64) -> std::result::Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let revision: Option<(String, String)> = tx
+            .query_row(
+                "SELECT chapter_id, content FROM chapter_revisions WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let content = match revision {
+            Some((owner, content)) if owner == chapter_id => content,
+            Some(_) => {
+                return Err(format!("Revision {} does not belong to chapter {}", id, chapter_id))
+            }
+            None => return Err(format!("Revision not found: {}", id)),
+        };
+        let word_count = replace_paragraphs(&tx, chapter_id, &content).map_err(|e| e.to_string())?;
+        insert_revision(&tx, chapter_id, &content, word_count, "restore")
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// A chapter's stored revisions, newest first, without their text
+    pub fn list_revisions(&self, chapter_id: &str) -> Result<Vec<Revision>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, chapter_id, COALESCE(word_count, 0), COALESCE(reason, ''), \
+             COALESCE(created_at, '') \
+             FROM chapter_revisions WHERE chapter_id = ?1 ORDER BY id DESC",
+        )?;
+        let rows = stmt.query_map(params![chapter_id], |row| {
+            Ok(Revision {
+                id: row.get(0)?,
+                chapter_id: row.get(1)?,
+                word_count: row.get(2)?,
+                reason: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// The chapter's text, one paragraph per line; None if there is no such chapter
+    pub fn chapter_content(&self, id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM chapters WHERE id = ?1)",
+            params![id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(None);
+        }
+        let mut stmt = conn.prepare(
+            "SELECT COALESCE(content, '') FROM chapter_paragraphs WHERE chapter_id = ?1 \
+             ORDER BY para_index",
+        )?;
+        let paragraphs = stmt
+            .query_map(params![id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(paragraphs.join("\n")))
+    }
+
+    /// The text of one of a chapter's revisions, if it's still kept
+    pub fn revision_content(&self, chapter_id: &str, id: i64) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT content FROM chapter_revisions WHERE chapter_id = ?1 AND id = ?2",
+            params![chapter_id, id],
+            |row| row.get(0),
+        )
+        .optional()
+    }
+
+    pub fn project_chapter_texts(&self, project_id: &str) -> Result<Vec<ChapterText>> {
+        let conn = self.conn.lock().unwrap();
+        chapter_texts(&conn, project_id)
+    }
+
+    /// Run `import` with a writer that appends chapters to the project, in one
+    /// transaction: nothing is kept unless `import` succeeds
+    pub fn import_chapters<T>(
+        &self,
+        project_id: &str,
+        import: impl FnOnce(&mut ChapterWriter) -> std::result::Result<T, String>,
+    ) -> std::result::Result<T, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let (next_num, next_sort) = tx
+            .query_row(
+                "SELECT COALESCE(MAX(c.chapter_num), 0) + 1, COALESCE(MAX(c.sort_order), 0) + 1 \
+                 FROM projects p LEFT JOIN chapters c ON c.project_id = p.id \
+                 WHERE p.id = ?1 GROUP BY p.id",
+                params![project_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Project not found: {}", project_id))?;
+        let mut writer = ChapterWriter { conn: &tx, project_id, next_num, next_sort };
+        let imported = import(&mut writer)?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(imported)
+    }
+
+    /// Replace every match in the project's chapters in one transaction. Each
+    /// changed chapter gets a revision holding its previous text, a new word count,
+    /// and its indexed chunks (and so the full-text index) rewritten the same way.
+    pub fn replace_in_project(
+        &self,
+        project_id: &str,
+        matcher: &Matcher,
+        replacement: &str,
+    ) -> std::result::Result<Vec<ChapterReplacements>, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let mut replaced = Vec::new();
+        for chapter in chapter_texts(&tx, project_id).map_err(|e| e.to_string())? {
+            let mut replacements = 0;
+            let mut word_count = 0i64;
+            let mut changed = Vec::new();
+            for paragraph in &chapter.paragraphs {
+                matcher.check_time()?;
+                let (content, count) = matcher.replace(&paragraph.content, replacement);
+                word_count += text_count::count_words(&content);
+                if count > 0 {
+                    replacements += count;
+                    changed.push((paragraph.id.as_str(), content));
+                }
+            }
+            if replacements == 0 {
+                continue;
+            }
+            let written = write_replacements(
+                &tx,
+                project_id,
+                &chapter,
+                &changed,
+                word_count,
+                matcher,
+                replacement,
+            );
+            written.map_err(|e| e.to_string())?;
+            replaced.push(ChapterReplacements {
+                chapter_id: chapter.chapter_id,
+                chapter_num: chapter.chapter_num,
+                title: chapter.title,
+                replacements,
+                word_count,
+            });
+        }
+        if !replaced.is_empty() {
+            mark_index_stale(&tx, project_id).map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(replaced)
+    }
+
+    /// What reindexing the project would write, per embedding_index::estimated_bytes;
+    /// errs if the project doesn't exist
+    pub fn reindex_size_estimate(&self, project_id: &str) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        let (dim, chars, bytes): (i64, i64, i64) = conn.query_row(
+            "SELECT COALESCE(p.embedding_dim, 0), COALESCE(SUM(LENGTH(cp.content)), 0), \
+                    COALESCE(SUM(LENGTH(CAST(cp.content AS BLOB))), 0) \
+             FROM projects p LEFT JOIN chapters c ON c.project_id = p.id \
+             LEFT JOIN chapter_paragraphs cp ON cp.chapter_id = c.id \
+             WHERE p.id = ?1 GROUP BY p.id",
+            params![project_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(embedding_index::estimated_bytes(chars as u64, bytes as u64, dim.max(0) as u64))
+    }
+
+    /// What the agent last recorded about the project's embeddings and whether
+    /// they are out of date, or None if there is no such project. The chapters
+    /// are hashed paragraph by paragraph, and only when nothing else already
+    /// says the index is stale.
+    pub fn embedding_index_status(
+        &self,
+        project_id: &str,
+    ) -> Result<Option<EmbeddingIndexStatus>> {
+        let conn = self.conn.lock().unwrap();
+        let project_dim: Option<i64> = conn
+            .query_row(
+                "SELECT COALESCE(embedding_dim, 0) FROM projects WHERE id = ?1",
+                params![project_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(project_dim) = project_dim else {
+            return Ok(None);
+        };
+        let record = conn
+            .query_row(
+                "SELECT chunk_count, dim, model, content_hash, stale, COALESCE(built_at, '') \
+                 FROM embedding_index WHERE project_id = ?1",
+                params![project_id],
+                |row| {
+                    Ok(IndexRecord {
+                        chunk_count: row.get(0)?,
+                        dim: row.get(1)?,
+                        model: row.get(2)?,
+                        content_hash: row.get(3)?,
+                        stale: row.get(4)?,
+                        built_at: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+        let status = EmbeddingIndexStatus::new(record, project_dim, || -> Result<String> {
+            // The same order agent/rag/search.py indexes chapters in
+            let mut stmt = conn.prepare(
+                "SELECT c.id, COALESCE(p.content, '') \
+                 FROM chapters c JOIN chapter_paragraphs p ON p.chapter_id = c.id \
+                 WHERE c.project_id = ?1 \
+                 ORDER BY c.sort_order, c.chapter_num, c.id, p.para_index",
+            )?;
+            let mut rows = stmt.query(params![project_id])?;
+            let mut hash = ContentHash::default();
+            while let Some(row) = rows.next()? {
+                hash.add(&row.get::<_, String>(0)?, &row.get::<_, String>(1)?);
+            }
+            Ok(hash.finish())
+        })?;
+        Ok(Some(status))
+    }
+
+    /// Flag the project's embeddings as out of date; false if it has none
+    pub fn mark_embedding_index_stale(&self, project_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        mark_index_stale(&conn, project_id)
+    }
+
+    /// Global prompts plus, with `project_id`, that project's own; a project prompt
+    /// replaces the global one of the same name
+    pub fn list_prompts(&self, project_id: Option<&str>) -> Result<Vec<Prompt>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {cols} FROM prompts WHERE project_id = ?1 \
+             UNION ALL \
+             SELECT {cols} FROM prompts g WHERE project_id IS NULL AND NOT EXISTS \
+             (SELECT 1 FROM prompts o WHERE o.project_id = ?1 AND o.name = g.name) \
+             ORDER BY 3, 2",
+            cols = PROMPT_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![project_id], prompt_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_prompt(&self, id: &str) -> Result<Prompt> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM prompts WHERE id = ?1", PROMPT_COLUMNS),
+            params![id],
+            prompt_from_row,
+        )
+    }
+
+    pub fn prompt_name_taken(
+        &self,
+        project_id: Option<&str>,
+        name: &str,
+        exclude_id: Option<&str>,
+    ) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM prompts \
+             WHERE project_id IS ?1 AND name = ?2 AND id IS NOT ?3)",
+            params![project_id, name, exclude_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn create_prompt(
+        &self,
+        name: &str,
+        category: &str,
+        content: &str,
+        project_id: Option<&str>,
+    ) -> Result<Prompt> {
+        let conn = self.conn.lock().unwrap();
+        let id: String = conn.query_row(
+            "INSERT INTO prompts (name, category, content, project_id) \
+             VALUES (?1, ?2, ?3, ?4) RETURNING id",
+            params![name, category, content, project_id],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        self.get_prompt(&id)
+    }
+
+    pub fn update_prompt(&self, id: &str, update: &PromptUpdate) -> Result<Prompt> {
+        let mut sets = Vec::new();
+        let mut values = Vec::new();
+        for (column, value) in [
+            ("name", &update.name),
+            ("category", &update.category),
+            ("content", &update.content),
+        ] {
+            if let Some(value) = value {
+                sets.push(format!("{} = ?, ", column));
+                values.push(Value::Text(value.clone()));
+            }
+        }
+        values.push(Value::Text(id.to_string()));
+
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            &format!(
+                "UPDATE prompts SET {}updated_at = datetime('now') WHERE id = ?",
+                sets.concat()
+            ),
+            params_from_iter(values.iter()),
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        drop(conn);
+        self.get_prompt(id)
+    }
+
+    /// Built-in prompts are never deleted
+    pub fn delete_prompt(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM prompts WHERE id = ?1 AND COALESCE(is_builtin, 0) = 0",
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    /// Put a built-in prompt's shipped content back
+    pub fn reset_prompt(&self, id: &str) -> Result<Prompt> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE prompts SET content = default_content, updated_at = datetime('now') \
+             WHERE id = ?1 AND is_builtin = 1 AND default_content IS NOT NULL",
+            params![id],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        drop(conn);
+        self.get_prompt(id)
+    }
+
+    /// Built-ins first, then custom templates by name
+    pub fn list_project_templates(&self) -> Result<Vec<ProjectTemplate>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM project_templates ORDER BY is_builtin DESC, name, created_at",
+            TEMPLATE_COLUMNS
+        ))?;
+        let rows = stmt.query_map([], template_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_project_template(&self, id: &str) -> Result<Option<ProjectTemplate>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM project_templates WHERE id = ?1", TEMPLATE_COLUMNS),
+            params![id],
+            template_from_row,
+        )
+        .optional()
+    }
+
+    /// A new project with the template's outline, characters, world entries and
+    /// prompt overrides, all or nothing
+    pub fn create_project_from_template(
+        &self,
+        name: &str,
+        template_id: &str,
+    ) -> std::result::Result<Project, String> {
+        let template = self
+            .get_project_template(template_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Template not found: {}", template_id))?;
+        let mut conn = self.conn.lock().unwrap();
+        let id = (|| -> Result<String> {
+            let tx = conn.transaction()?;
+            let id = insert_template(
+                &tx,
+                name,
+                &template.genre,
+                &template.description,
+                &template.content,
+            )?;
+            tx.commit()?;
+            Ok(id)
+        })()
+        .map_err(|e| e.to_string())?;
+        drop(conn);
+        self.get_project(&id).map_err(|e| e.to_string())
+    }
+
+    /// Keep a project's settings, outline, characters, world entries and prompt
+    /// overrides as a custom template; chapters and their text stay out
+    pub fn save_project_as_template(
+        &self,
+        project_id: &str,
+        template_name: &str,
+    ) -> std::result::Result<ProjectTemplate, String> {
+        let conn = self.conn.lock().unwrap();
+        let template = (|| -> Result<Option<String>> {
+            let Some((genre, description, mut content)) = conn
+                .query_row(
+                    "SELECT COALESCE(genre, ''), COALESCE(description, ''), \
+                     COALESCE(structure, ''), COALESCE(custom_structure, ''), \
+                     chapter_words, word_target FROM projects WHERE id = ?1",
+                    params![project_id],
+                    |row| {
+                        let content = TemplateContent {
+                            structure: row.get(2)?,
+                            custom_structure: row.get(3)?,
+                            chapter_words: row.get(4)?,
+                            word_target: row.get(5)?,
+                            ..Default::default()
+                        };
+                        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, content))
+                    },
+                )
+                .optional()?
+            else {
+                return Ok(None);
+            };
+            content.outlines = conn
+                .prepare(
+                    "SELECT COALESCE(phase, ''), COALESCE(title, ''), COALESCE(content, ''), \
+                     COALESCE(word_range, '') FROM outlines WHERE project_id = ?1 \
+                     ORDER BY phase_order, created_at",
+                )?
+                .query_map(params![project_id], |row| {
+                    Ok(TemplateOutline {
+                        phase: row.get(0)?,
+                        title: row.get(1)?,
+                        content: row.get(2)?,
+                        word_range: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<_>>()?;
+            content.characters = conn
+                .prepare(&format!(
+                    "SELECT {} FROM characters WHERE project_id = ?1 ORDER BY sort_order, name",
+                    CHARACTER_COLUMNS
+                ))?
+                .query_map(params![project_id], character_from_row)?
+                .map(|character| {
+                    character.map(|c| TemplateCharacter {
+                        name: c.name,
+                        category: c.category,
+                        gender: c.gender,
+                        age: c.age,
+                        identity: c.identity,
+                        appearance: c.appearance,
+                        personality: c.personality,
+                        motivation: c.motivation,
+                        backstory: c.backstory,
+                        arc: c.arc,
+                        usage_notes: c.usage_notes,
+                        aliases: c.aliases,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            content.worldbuilding = conn
+                .prepare(
+                    "SELECT w.category, w.title, COALESCE(w.content, ''), p.title \
+                     FROM worldbuilding w LEFT JOIN worldbuilding p ON p.id = w.parent_id \
+                     WHERE w.project_id = ?1 ORDER BY w.sort_order, w.created_at",
+                )?
+                .query_map(params![project_id], |row| {
+                    Ok(TemplateWorldEntry {
+                        category: row.get(0)?,
+                        title: row.get(1)?,
+                        content: row.get(2)?,
+                        parent: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<_>>()?;
+            content.prompts = conn
+                .prepare(
+                    "SELECT name, COALESCE(category, ''), content FROM prompts \
+                     WHERE project_id = ?1 ORDER BY name",
+                )?
+                .query_map(params![project_id], |row| {
+                    Ok(TemplatePrompt {
+                        name: row.get(0)?,
+                        category: row.get(1)?,
+                        content: row.get(2)?,
+                    })
+                })?
+                .collect::<Result<_>>()?;
+            conn.query_row(
+                "INSERT INTO project_templates (name, genre, description, content) \
+                 VALUES (?1, ?2, ?3, ?4) RETURNING id",
+                params![
+                    template_name,
+                    genre,
+                    description,
+                    serde_json::to_string(&content).unwrap_or_default()
+                ],
+                |row| row.get(0),
+            )
+            .map(Some)
+        })()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Project not found: {}", project_id))?;
+        drop(conn);
+        self.get_project_template(&template)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Template not found: {}", template))
+    }
+
+    /// The onboarding sample: a template's worth of structure plus a few chapters
+    pub fn create_sample_project(
+        &self,
+        sample: &SampleProject,
+    ) -> std::result::Result<Project, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let id = (|| -> Result<String> {
+            let tx = conn.transaction()?;
+            let id = insert_template(
+                &tx,
+                &sample.name,
+                &sample.genre,
+                &sample.description,
+                &sample.content,
+            )?;
+            let mut writer =
+                ChapterWriter { conn: &tx, project_id: &id, next_num: 1, next_sort: 1 };
+            for chapter in &sample.chapters {
+                let added = writer.add(&chapter.title, &chapter.text)?;
+                tx.execute(
+                    "UPDATE chapters SET synopsis = ?1 WHERE id = ?2",
+                    params![chapter.synopsis, added.id],
+                )?;
+            }
+            tx.commit()?;
+            Ok(id)
+        })()
+        .map_err(|e| e.to_string())?;
+        drop(conn);
+        self.get_project(&id).map_err(|e| e.to_string())
+    }
+
+    pub fn list_model_presets(&self, provider: Option<&str>) -> Result<Vec<ModelPreset>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM model_presets WHERE ?1 IS NULL OR provider = ?1 \
+             ORDER BY provider, display_name, model_id",
+            MODEL_PRESET_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![provider], model_preset_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_model_preset(&self, id: &str) -> Result<ModelPreset> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM model_presets WHERE id = ?1", MODEL_PRESET_COLUMNS),
+            params![id],
+            model_preset_from_row,
+        )
+    }
+
+    pub fn model_preset_exists(
+        &self,
+        provider: &str,
+        model_id: &str,
+        exclude_id: Option<&str>,
+    ) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM model_presets \
+             WHERE provider = ?1 AND model_id = ?2 AND id IS NOT ?3)",
+            params![provider, model_id, exclude_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// `preset` must have provider and model_id; the other fields fall back to the
+    /// column defaults
+    pub fn create_model_preset(&self, preset: &ModelPresetUpdate) -> Result<ModelPreset> {
+        let provider = preset.provider.as_deref().unwrap_or("").trim();
+        let model_id = preset.model_id.as_deref().unwrap_or("").trim();
+        let display_name = preset.display_name.as_deref().map(str::trim).unwrap_or(model_id);
+        let conn = self.conn.lock().unwrap();
+        let id: String = conn.query_row(
+            "INSERT INTO model_presets \
+             (provider, model_id, display_name, context_window, default_temperature, \
+              supports_streaming) \
+             VALUES (?1, ?2, ?3, COALESCE(?4, 0), COALESCE(?5, 0.7), COALESCE(?6, 1)) \
+             RETURNING id",
+            params![
+                provider,
+                model_id,
+                display_name,
+                preset.context_window,
+                preset.default_temperature,
+                preset.supports_streaming,
+            ],
+            |row| row.get(0),
+        )?;
+        drop(conn);
+        self.get_model_preset(&id)
+    }
+
+    pub fn update_model_preset(&self, id: &str, update: &ModelPresetUpdate) -> Result<ModelPreset> {
+        let mut sets = Vec::new();
+        let mut values = Vec::new();
+        for (column, value) in [
+            ("provider", &update.provider),
+            ("model_id", &update.model_id),
+            ("display_name", &update.display_name),
+        ] {
+            if let Some(value) = value {
+                sets.push(format!("{} = ?, ", column));
+                values.push(Value::Text(value.trim().to_string()));
+            }
+        }
+        if let Some(context_window) = update.context_window {
+            sets.push("context_window = ?, ".to_string());
+            values.push(Value::Integer(context_window));
+        }
+        if let Some(temperature) = update.default_temperature {
+            sets.push("default_temperature = ?, ".to_string());
+            values.push(Value::Real(temperature));
+        }
+        if let Some(streaming) = update.supports_streaming {
+            sets.push("supports_streaming = ?, ".to_string());
+            values.push(Value::Integer(streaming.into()));
+        }
+        values.push(Value::Text(id.to_string()));
+
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            &format!(
+                "UPDATE model_presets SET {}updated_at = datetime('now') WHERE id = ?",
+                sets.concat()
+            ),
+            params_from_iter(values.iter()),
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        drop(conn);
+        self.get_model_preset(id)
+    }
+
+    /// Projects copied the preset's values rather than referencing it, so they're left
+    /// alone; the count is of projects whose main or secondary model is its model id
+    pub fn delete_model_preset(&self, id: &str) -> Result<PresetDeleted> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let model_id: String = tx.query_row(
+            "SELECT model_id FROM model_presets WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        let referencing_projects = tx.query_row(
+            "SELECT COUNT(*) FROM projects WHERE model_main = ?1 OR model_secondary = ?1",
+            params![model_id],
+            |row| row.get(0),
+        )?;
+        tx.execute("DELETE FROM model_presets WHERE id = ?1", params![id])?;
+        tx.commit()?;
+        Ok(PresetDeleted { referencing_projects })
+    }
+
+    pub fn apply_preset_to_project(
+        &self,
+        project_id: &str,
+        preset_id: &str,
+        slot: ModelSlot,
+    ) -> Result<Project> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let (model_id, temperature): (String, f64) = tx.query_row(
+            "SELECT model_id, COALESCE(default_temperature, 0.7) FROM model_presets WHERE id = ?1",
+            params![preset_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let changed = tx.execute(
+            &format!(
+                "UPDATE projects SET {} = ?1, temperature = ?2, updated_at = datetime('now') \
+                 WHERE id = ?3",
+                slot.column()
+            ),
+            params![model_id, temperature, project_id],
+        )?;
+        if changed == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        tx.commit()?;
+        drop(conn);
+        self.get_project(project_id)
+    }
+
+    /// Raw value from global_settings, the string store shared with the agent
+    pub fn get_global_setting(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT value FROM global_settings WHERE key = ?1",
+            params![key],
+            |row| row.get::<_, Option<String>>(0),
+        )
+        .optional()
+        .map(Option::flatten)
+    }
+
+    pub fn delete_global_setting(&self, key: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM global_settings WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
+    /// True until the first project or setting is written, by the app or the agent
+    pub fn is_pristine(&self) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT NOT EXISTS(SELECT 1 FROM projects) AND NOT EXISTS(SELECT 1 FROM app_settings) \
+             AND NOT EXISTS(SELECT 1 FROM global_settings)",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    /// All app_settings rows; values that aren't valid JSON are skipped
+    pub fn list_settings(&self) -> Result<Vec<(String, serde_json::Value)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT key, value FROM app_settings")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut settings = Vec::new();
+        for row in rows {
+            let (key, value) = row?;
+            if let Ok(value) = serde_json::from_str(&value) {
+                settings.push((key, value));
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn set_setting(&self, key: &str, value: &serde_json::Value) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO app_settings (key, value, updated_at) VALUES (?1, ?2, datetime('now')) \
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            params![key, value.to_string()],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_setting(&self, key: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM app_settings WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
+    pub fn list_global_settings(&self) -> Result<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT key, COALESCE(value, '') FROM global_settings ORDER BY key")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// On-disk size next to what the pages account for; free pages are what VACUUM reclaims
+    pub fn size(&self) -> Result<DbSize> {
+        let conn = self.conn.lock().unwrap();
+        let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get(0));
+        let (page_count, page_size, freelist_count): (u64, u64, u64) =
+            (pragma("page_count")?, pragma("page_size")?, pragma("freelist_count")?);
+        Ok(DbSize {
+            file_bytes: file_len(&conn, ""),
+            wal_bytes: file_len(&conn, "-wal"),
+            page_count,
+            page_size,
+            allocated_bytes: page_count * page_size,
+            free_bytes: freelist_count * page_size,
+        })
+    }
+
+    /// Rebuild the file without free pages. Needs every other connection idle.
+    pub fn vacuum(&self) -> Result<()> {
+        self.conn.lock().unwrap().execute_batch("VACUUM")
+    }
+
+    /// quick_check (integrity_check when `full`), foreign_key_check and the rows
+    /// in each table. Holds the connection throughout, so app writes wait.
+    pub fn integrity_report(
+        &self,
+        mode: CheckMode,
+        mut progress: impl FnMut(Stage),
+    ) -> Result<IntegrityReport> {
+        // A full check can take minutes; on a connection of its own it leaves the
+        // app's free for writes, and in WAL mode the agent's aren't blocked either
+        let reader = self.open_reader()?;
+        let shared;
+        let conn = match &reader {
+            Some(reader) => reader,
+            None => {
+                shared = self.conn.lock().unwrap();
+                &*shared
+            }
+        };
+        progress(Stage::IntegrityCheck);
+        let pragma = match mode {
+            CheckMode::Quick => "PRAGMA quick_check",
+            CheckMode::Full => "PRAGMA integrity_check",
+        };
+        let mut check_result: Vec<String> =
+            conn.prepare(pragma)?.query_map([], |row| row.get(0))?.collect::<Result<_>>()?;
+        if reader.is_some() {
+            // FTS5 checks its index inside a write transaction, which the read-only
+            // connection can't open; chunks_fts only mirrors memory_chunks anyway
+            check_result.retain(|line| !line.starts_with("unable to validate the inverted index"));
+            if check_result.is_empty() {
+                check_result.push("ok".into());
+            }
+        }
+
+        progress(Stage::ForeignKeyCheck);
+        let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+        let mut rows = stmt.query([])?;
+        let (mut foreign_key_violations, mut foreign_key_violation_count) = (Vec::new(), 0);
+        while let Some(row) = rows.next()? {
+            foreign_key_violation_count += 1;
+            if foreign_key_violations.len() < MAX_LISTED_VIOLATIONS {
+                foreign_key_violations.push(ForeignKeyViolation {
+                    table: row.get(0)?,
+                    rowid: row.get(1)?,
+                    parent: row.get(2)?,
+                });
+            }
+        }
+        drop(rows);
+
+        progress(Stage::CountRows);
+        // Virtual tables are skipped: chunks_fts only mirrors memory_chunks
+        let names: Vec<String> = conn
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+                 AND sql NOT LIKE 'CREATE VIRTUAL%' ORDER BY name",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_>>()?;
+        let tables = names
+            .into_iter()
+            .map(|name| -> Result<TableRows> {
+                let sql = format!("SELECT COUNT(*) FROM \"{}\"", name.replace('"', "\"\""));
+                let rows = conn.query_row(&sql, [], |row| row.get(0))?;
+                Ok(TableRows { name, rows })
+            })
+            .collect::<Result<_>>()?;
+        let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get(0));
+        let (page_size, freelist_pages): (u64, u64) =
+            (pragma("page_size")?, pragma("freelist_count")?);
+        progress(Stage::Done);
+
+        let problems =
+            problems(&check_result, &foreign_key_violations, foreign_key_violation_count);
+        Ok(IntegrityReport {
+            mode,
+            ok: problems.is_empty(),
+            hint: (!problems.is_empty()).then(|| RESTORE_HINT.to_string()),
+            problems,
+            check_result,
+            foreign_key_violation_count,
+            foreign_key_violations,
+            tables,
+            file_bytes: file_len(conn, ""),
+            page_size,
+            freelist_pages,
+            checked_at: crate::app_log::timestamp(),
+        })
+    }
+
+    /// A read-only connection to the same file, for long reads; None when the
+    /// database is in memory and only the shared connection can see it
+    fn open_reader(&self) -> Result<Option<Connection>> {
+        let path = self.conn.lock().unwrap().path().filter(|p| !p.is_empty()).map(String::from);
+        path.map(|path| {
+            let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+            Connection::open_with_flags(path, flags)
+        })
+        .transpose()
+    }
+
+    /// PRAGMA optimize, VACUUM, then ANALYZE, all under one hold on the
+    /// connection so no app write lands between them
+    pub fn optimize(&self, mut progress: impl FnMut(Stage)) -> Result<Optimized> {
+        let started = std::time::Instant::now();
+        let conn = self.conn.lock().unwrap();
+        let file_bytes_before = file_len(&conn, "");
+        let steps = [
+            (Stage::Optimize, "PRAGMA optimize"),
+            (Stage::Vacuum, "VACUUM"),
+            (Stage::Analyze, "ANALYZE"),
+        ];
+        for (stage, sql) in steps {
+            progress(stage);
+            conn.execute_batch(sql)?;
+        }
+        progress(Stage::Done);
+        Ok(Optimized {
+            file_bytes_before,
+            file_bytes_after: file_len(&conn, ""),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Copy the write-ahead log into the database file and truncate it to zero
+    /// bytes. Only the part no other connection still needs is copied, so this
+    /// reclaims everything when nothing else has the database open, as at startup
+    /// before the agent runs. A database not in WAL mode has nothing to do.
+    pub fn checkpoint(&self) -> Result<Checkpointed> {
+        let conn = self.conn.lock().unwrap();
+        let wal_bytes_before = file_len(&conn, "-wal");
+        let busy: bool = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+        Ok(Checkpointed { busy, wal_bytes_before, wal_bytes_after: file_len(&conn, "-wal") })
+    }
+
+    /// Schema version, migrations and integrity check for a diagnostics bundle
+    pub fn diagnostics(&self) -> Result<DatabaseReport> {
+        let conn = self.conn.lock().unwrap();
+        let user_version = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        // schema_migrations belongs to the agent and is missing until it first starts
+        let migrations = conn
+            .prepare("SELECT version FROM schema_migrations ORDER BY version")
+            .and_then(|mut stmt| {
+                let rows = stmt.query_map([], |row| row.get(0))?;
+                rows.collect()
+            })
+            .unwrap_or_default();
+        let integrity_check = conn
+            .prepare("PRAGMA integrity_check")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_>>()?;
+        let count = |table: &str| {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get::<_, i64>(0)
+            })
+        };
+        Ok(DatabaseReport {
+            user_version,
+            migrations,
+            integrity_check,
+            project_count: count("projects")?,
+            chapter_count: count("chapters")?,
+        })
+    }
+
+    pub fn list_credentials(&self) -> Result<Vec<StoredCredential>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT name, storage, ciphertext, nonce FROM api_credentials ORDER BY name",
+        )?;
+        let rows = stmt.query_map([], credential_from_row)?;
+        rows.collect()
+    }
+
+    pub fn get_credential(&self, name: &str) -> Result<Option<StoredCredential>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT name, storage, ciphertext, nonce FROM api_credentials WHERE name = ?1",
+            params![name],
+            credential_from_row,
+        )
+        .optional()
+    }
+
+    pub fn put_credential(&self, credential: &StoredCredential) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO api_credentials (name, storage, ciphertext, nonce, updated_at) \
+             VALUES (?1, ?2, ?3, ?4, datetime('now')) \
+             ON CONFLICT(name) DO UPDATE SET storage = excluded.storage, \
+             ciphertext = excluded.ciphertext, nonce = excluded.nonce, updated_at = excluded.updated_at",
+            params![credential.name, credential.storage, credential.ciphertext, credential.nonce],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_credential(&self, name: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute("DELETE FROM api_credentials WHERE name = ?1", params![name])?;
+        Ok(changed > 0)
+    }
+
+    pub fn credential_passphrase(&self) -> Result<Option<PassphraseRecord>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT salt, nonce, verifier FROM credential_passphrase WHERE id = 1",
+            [],
+            |row| {
+                Ok(PassphraseRecord {
+                    salt: row.get(0)?,
+                    nonce: row.get(1)?,
+                    verifier: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Store a new passphrase record along with the credentials re-encrypted under
+    /// it, all or nothing
+    pub fn replace_credential_passphrase(
+        &self,
+        record: &PassphraseRecord,
+        credentials: &[StoredCredential],
+    ) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO credential_passphrase (id, salt, nonce, verifier, updated_at) \
+             VALUES (1, ?1, ?2, ?3, datetime('now')) \
+             ON CONFLICT(id) DO UPDATE SET salt = excluded.salt, nonce = excluded.nonce, \
+             verifier = excluded.verifier, updated_at = excluded.updated_at",
+            params![record.salt, record.nonce, record.verifier],
+        )?;
+        for credential in credentials {
+            tx.execute(
+                "UPDATE api_credentials SET storage = ?2, ciphertext = ?3, nonce = ?4, \
+                 updated_at = datetime('now') WHERE name = ?1",
+                params![
+                    credential.name,
+                    credential.storage,
+                    credential.ciphertext,
+                    credential.nonce
+                ],
+            )?;
+        }
+        tx.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_project_returns_the_inserted_row() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        assert_eq!(project.name, "长夜");
+        assert_eq!(project.genre, "玄幻");
+        assert!(!project.id.is_empty());
+        assert!(!project.created_at.is_empty());
+        assert_eq!((project.top_p, project.max_tokens), (1.0, 4096));
+        assert_eq!(db.get_project(&project.id).unwrap().name, "长夜");
+    }
+
+    #[test]
+    fn create_project_errors_when_the_row_disappears() {
+        let db = Database::new_in_memory().unwrap();
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "CREATE TRIGGER drop_new_projects AFTER INSERT ON projects \
+                 BEGIN DELETE FROM projects WHERE id = NEW.id; END;",
+            )
+            .unwrap();
+        let err = db.create_project("长夜", "玄幻").unwrap_err();
+        assert!(matches!(err, rusqlite::Error::QueryReturnedNoRows));
+    }
+
+    #[test]
+    fn list_projects_pages_and_counts_everything() {
+        let db = Database::new_in_memory().unwrap();
+        for name in ["一", "二", "三"] {
+            db.create_project(name, "玄幻").unwrap();
+        }
+        let all = db.list_projects(&ProjectQuery::default()).unwrap();
+        assert_eq!((all.projects.len(), all.total), (3, 3));
+        let query = ProjectQuery { limit: Some(2), offset: Some(1), ..Default::default() };
+        let page = db.list_projects(&query).unwrap();
+        assert_eq!((page.projects.len(), page.total), (2, 3));
+        assert_eq!(page.projects[0].id, all.projects[1].id);
+        let query = ProjectQuery { offset: Some(2), ..Default::default() };
+        assert_eq!(db.list_projects(&query).unwrap().projects.len(), 1);
+    }
+
+    #[test]
+    fn list_projects_filters_and_sorts() {
+        let db = Database::new_in_memory().unwrap();
+        let short = db.create_project("短篇 50%", "都市").unwrap();
+        let long = db.create_project("长夜", "玄幻").unwrap();
+        db.create_project("Abc", "玄幻").unwrap();
+        let conn = db.conn.lock().unwrap();
+        conn.execute("UPDATE projects SET word_target = 0 WHERE name = 'Abc'", []).unwrap();
+        conn.execute(
+            "UPDATE projects SET word_target = 1000, status = 'done' WHERE id = ?1",
+            params![short.id],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO chapters (project_id, chapter_num, word_count) \
+             VALUES (?1, 1, 900), (?2, 1, 5000)",
+            params![short.id, long.id],
+        )
+        .unwrap();
+        drop(conn);
+        let names = |query: ProjectQuery| -> (Vec<String>, i64) {
+            let page = db.list_projects(&query).unwrap();
+            (page.projects.into_iter().map(|p| p.name).collect(), page.total)
+        };
+
+        let progress = ProjectQuery { sort_by: ProjectSort::WordProgress, ..Default::default() };
+        let (by_progress, _) = names(progress);
+        assert_eq!(by_progress, ["短篇 50%", "长夜", "Abc"]);
+        let (by_name, _) = names(ProjectQuery {
+            sort_by: ProjectSort::Name,
+            sort_dir: Some(SortDir::Desc),
+            ..Default::default()
+        });
+        assert_eq!(by_name, ["长夜", "短篇 50%", "Abc"]);
+        let genre = ProjectQuery { genre: Some("玄幻".into()), limit: Some(1), ..Default::default() };
+        assert_eq!(names(genre).1, 2);
+        let status = ProjectQuery { status: Some("done".into()), ..Default::default() };
+        assert_eq!(names(status).0, ["短篇 50%"]);
+        let name = ProjectQuery { name_contains: Some("50%".into()), ..Default::default() };
+        assert_eq!(names(name).0, ["短篇 50%"]);
+        let blank = ProjectQuery { name_contains: Some(" ".into()), ..Default::default() };
+        assert_eq!(names(blank).1, 3);
+    }
+
+    #[test]
+    fn pinned_projects_come_first_in_their_manual_order() {
+        let db = Database::new_in_memory().unwrap();
+        let ids: Vec<String> =
+            ["一", "二", "三", "四"].iter().map(|n| db.create_project(n, "").unwrap().id).collect();
+        let names = || -> Vec<String> {
+            let page = db.list_projects(&ProjectQuery::default()).unwrap();
+            page.projects.into_iter().map(|p| p.name).collect()
+        };
+        for id in [&ids[2], &ids[0], &ids[1]] {
+            db.set_project_pinned(id, true).unwrap();
+        }
+        assert_eq!(names()[..3], ["三", "一", "二"]);
+
+        assert!(db.reorder_pinned_projects(&[ids[1].clone(), ids[0].clone()]).is_err());
+        let reordered = [ids[1].clone(), ids[0].clone(), ids[3].clone()];
+        assert!(db.reorder_pinned_projects(&reordered).is_err());
+        let reordered = [ids[1].clone(), ids[1].clone(), ids[2].clone()];
+        assert!(db.reorder_pinned_projects(&reordered).is_err());
+        let reordered = [ids[1].clone(), ids[2].clone(), ids[0].clone()];
+        let pinned = db.reorder_pinned_projects(&reordered).unwrap();
+        let order: Vec<_> = pinned.iter().map(|p| (p.name.as_str(), p.manual_order)).collect();
+        assert_eq!(order, [("二", Some(0)), ("三", Some(1)), ("一", Some(2))]);
+
+        let unpinned = db.set_project_pinned(&ids[2], false).unwrap();
+        assert_eq!((unpinned.pinned, unpinned.manual_order), (false, None));
+        assert_eq!(db.get_project(&ids[0]).unwrap().manual_order, Some(1));
+        assert_eq!(names()[..2], ["二", "一"]);
+    }
+
+    #[test]
+    fn search_projects_matches_wildcards_literally() {
+        let db = Database::new_in_memory().unwrap();
+        db.create_project("50% Off", "Comedy").unwrap();
+        db.create_project("500 Days", "Romance").unwrap();
+        db.create_project("长夜", "玄幻").unwrap();
+        let names = |query: &str| -> Vec<String> {
+            db.search_projects(query).unwrap().into_iter().map(|p| p.name).collect()
+        };
+        assert_eq!(names("50%"), ["50% Off"]);
+        assert_eq!(names("comedy"), ["50% Off"]);
+        assert_eq!(names("玄幻"), ["长夜"]);
+        assert!(names("5_0").is_empty());
+    }
+
+    #[test]
+    fn project_word_count_sums_chapters() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        assert_eq!(db.project_word_count(&project.id).unwrap(), 0);
+        db.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO chapters (project_id, chapter_num, word_count) \
+                 VALUES (?1, 1, 1200), (?1, 2, 800)",
+                params![project.id],
+            )
+            .unwrap();
+        assert_eq!(db.project_word_count(&project.id).unwrap(), 2000);
+        let chapter_id: String = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "INSERT INTO chapters (project_id, chapter_num) VALUES (?1, 3) RETURNING id",
+                params![project.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        db.save_chapter_text(&chapter_id, "林风说：“Let's go.”\n夜色 deep").unwrap();
+        assert_eq!(db.project_word_count(&project.id).unwrap(), 2000 + 3 + 2 + 2 + 1);
+        // The first two chapters' counts have no text behind them
+        assert_eq!(db.recount_project_words(&project.id).unwrap(), 2);
+        assert_eq!(db.recount_project_words(&project.id).unwrap(), 0);
+        assert_eq!(db.project_word_count(&project.id).unwrap(), 3 + 2 + 2 + 1);
+        assert!(db.recount_project_words("missing").is_err());
+        let other = db.create_project("旧作", "都市").unwrap();
+        db.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO chapters (project_id, chapter_num, word_count) VALUES (?1, 1, 500)",
+                params![other.id],
+            )
+            .unwrap();
+        assert_eq!(db.recount_words(None).unwrap(), 1);
+        assert_eq!(db.recount_words(None).unwrap(), 0);
+        let err = db.project_word_count("missing").unwrap_err();
+        assert!(matches!(err, rusqlite::Error::QueryReturnedNoRows));
+    }
+
+    #[test]
+    fn model_presets_apply_and_report_references_on_delete() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let preset = db
+            .create_model_preset(&ModelPresetUpdate {
+                provider: Some("deepseek".into()),
+                model_id: Some("deepseek-chat".into()),
+                default_temperature: Some(1.0),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(preset.display_name, "deepseek-chat");
+        assert!(preset.supports_streaming);
+        assert_eq!(db.list_model_presets(Some("deepseek")).unwrap().len(), 1);
+        assert!(db.list_model_presets(Some("openai")).unwrap().is_empty());
+
+        let applied =
+            db.apply_preset_to_project(&project.id, &preset.id, ModelSlot::Secondary).unwrap();
+        assert_eq!((applied.model_secondary.as_str(), applied.temperature), ("deepseek-chat", 1.0));
+        assert_eq!(applied.model_main, project.model_main);
+
+        assert_eq!(db.delete_model_preset(&preset.id).unwrap().referencing_projects, 1);
+        assert_eq!(db.get_project(&project.id).unwrap().model_secondary, "deepseek-chat");
+        let err = db.apply_preset_to_project(&project.id, &preset.id, ModelSlot::Main);
+        assert!(matches!(err.unwrap_err(), rusqlite::Error::QueryReturnedNoRows));
+    }
+
+    #[test]
+    fn character_aliases_are_trimmed_and_deduplicated() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let character = db.create_character(&project.id, "林晚").unwrap();
+        assert!(character.aliases.is_empty());
+        let aliases = vec![" 晚晚 ".to_string(), "".to_string(), "晚晚".to_string()];
+        let update = CharacterUpdate { aliases: Some(aliases), ..Default::default() };
+        let updated = db.update_character(&character.id, &update).unwrap();
+        assert_eq!(updated.aliases, ["晚晚"]);
+    }
+
+    #[test]
+    fn character_names_are_unique_per_project() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let other = db.create_project("短昼", "都市").unwrap();
+        let first = db.create_character(&project.id, "林风").unwrap();
+        let err = db.create_character(&project.id, "林风").err().unwrap();
+        assert!(is_unique_violation(&err));
+        db.create_character(&other.id, "林风").unwrap();
+        let second = db.create_character(&project.id, "林晚").unwrap();
+        let update = CharacterUpdate { name: Some("林风".into()), ..Default::default() };
+        assert!(is_unique_violation(&db.update_character(&second.id, &update).err().unwrap()));
+
+        // A database from before the index: duplicates are renamed, the oldest kept
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute_batch("DROP INDEX idx_characters_project_name").unwrap();
+            conn.execute(
+                "INSERT INTO characters (project_id, name, created_at) \
+                 VALUES (?1, '林风', datetime('now', '+1 day'))",
+                params![project.id],
+            )
+            .unwrap();
+        }
+        db.init_schema().unwrap();
+        let names: Vec<String> =
+            db.list_characters(&project.id).unwrap().into_iter().map(|c| c.name).collect();
+        assert_eq!(names.iter().filter(|name| *name == "林风").count(), 1);
+        assert_eq!(db.get_character(&first.id).unwrap().name, "林风");
+        assert!(db.create_character(&project.id, "林风").is_err());
+    }
+
+    #[test]
+    fn scenes_reorder_and_cascade_with_their_chapter() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let chapter_id: String = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "INSERT INTO chapters (project_id, chapter_num) VALUES (?1, 1) RETURNING id",
+                params![project.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let first = db.create_scene(&chapter_id, "开场", None).unwrap();
+        let second = db.create_scene(&chapter_id, "冲突", None).unwrap();
+        assert_eq!((first.order_index, second.order_index), (1, 2));
+
+        let reordered = db.reorder_scenes(&chapter_id, &[second.id.clone(), first.id]).unwrap();
+        assert_eq!(reordered[0].id, second.id);
+        assert!(db.create_scene("missing", "", None).is_err());
+
+        db.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM chapters WHERE id = ?1", params![chapter_id])
+            .unwrap();
+        assert!(db.list_scenes(&chapter_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn touch_project_orders_recents_and_remembers_the_chapter() {
+        let db = Database::new_in_memory().unwrap();
+        let first = db.create_project("一", "玄幻").unwrap();
+        let second = db.create_project("二", "玄幻").unwrap();
+        let unopened = db.create_project("三", "玄幻").unwrap();
+        let chapter_id: String = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "INSERT INTO chapters (project_id, chapter_num) VALUES (?1, 1) RETURNING id",
+                params![first.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        db.touch_project(&second.id, None).unwrap();
+        db.touch_project(&first.id, Some(&chapter_id)).unwrap();
+        db.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE projects SET last_opened_at = datetime('now', '-1 hour') WHERE id = ?1",
+                params![second.id],
+            )
+            .unwrap();
+
+        let recent = db.list_recent_projects(10, false).unwrap();
+        let ids: Vec<_> = recent.iter().map(|r| r.project.id.as_str()).collect();
+        assert_eq!(ids, [first.id.as_str(), second.id.as_str()]);
+        assert_eq!(recent[0].last_chapter_id.as_deref(), Some(chapter_id.as_str()));
+        assert_eq!(recent[1].last_chapter_id, None);
+        let all = db.list_recent_projects(10, true).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[2].project.id, unopened.id);
+        assert_eq!(all[2].last_opened_at, None);
+        assert!(db.recent_project("missing").unwrap().is_none());
+        assert!(db.touch_project("missing", None).is_err());
+    }
+
+    #[test]
+    fn foreign_keys_are_enforced() {
+        let db = Database::new_in_memory().unwrap();
+        let conn = db.conn.lock().unwrap();
+        let enabled: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0)).unwrap();
+        assert!(enabled);
+        let err = conn
+            .execute("INSERT INTO chapters (project_id, chapter_num) VALUES ('missing', 1)", [])
+            .unwrap_err();
+        assert_eq!(err.sqlite_error_code(), Some(rusqlite::ErrorCode::ConstraintViolation));
+    }
+
+    #[test]
+    fn import_chapters_appends_and_rolls_back_on_failure() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let imported = db
+            .import_chapters(&project.id, |writer| {
+                let first = writer.add("第一章", "夜色\n深沉").map_err(|e| e.to_string())?;
+                let second = writer.add("第二章", "天亮").map_err(|e| e.to_string())?;
+                Ok(vec![first, second])
+            })
+            .unwrap();
+        assert_eq!((imported[0].chapter_num, imported[0].word_count), (1, 4));
+        assert_eq!(imported[1].chapter_num, 2);
+
+        let failed = db.import_chapters(&project.id, |writer| {
+            let third = writer.add("第三章", "黄昏").map_err(|e| e.to_string())?;
+            assert_eq!(third.chapter_num, 3);
+            Err::<(), _>("unreadable file".to_string())
+        });
+        assert!(failed.is_err());
+        assert_eq!(db.project_chapter_texts(&project.id).unwrap().len(), 2);
+        assert!(db.import_chapters("missing", |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn restore_backup_keeps_nothing_when_placing_fails() {
+        let db = Database::new_in_memory().unwrap();
+        let original = db.create_project("长夜", "玄幻").unwrap();
+        let project = db.get_project(&original.id).unwrap();
+        let backup = crate::backup::new(project, Vec::new(), Vec::new(), Vec::new());
+
+        let failed = db.restore_backup(&backup, |_| Err("disk full".to_string()));
+        assert_eq!(failed.unwrap_err(), "disk full");
+        assert_eq!(db.list_projects(&ProjectQuery::default()).unwrap().total, 1);
+
+        let restored = db
+            .restore_backup(&backup, |id| Ok(Some(format!("covers/{}.png", id))))
+            .unwrap();
+        assert_ne!(restored.id, original.id);
+        assert_eq!(restored.name, "长夜");
+        let cover = db.project_cover_path(&restored.id).unwrap();
+        assert_eq!(cover, Some(format!("covers/{}.png", restored.id)));
+    }
+
+    #[test]
+    fn save_chapter_text_replaces_paragraphs_and_word_count() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db
+            .import_project(
+                "长夜",
+                "玄幻",
+                "",
+                &[ManuscriptChapter { title: "一".into(), body: "夜色\n深沉\n无边".into() }],
+            )
+            .unwrap();
+        let chapter_id = db.project_chapter_texts(&project.id).unwrap()[0].chapter_id.clone();
+        assert!(db.save_chapter_text(&chapter_id, "天亮了。\n").unwrap().is_some());
+
+        let chapter = &db.project_chapter_texts(&project.id).unwrap()[0];
+        let paragraphs: Vec<_> = chapter.paragraphs.iter().map(|p| p.content.as_str()).collect();
+        assert_eq!(paragraphs, ["天亮了。", ""]);
+        // autosave_chapter compares against this to skip unchanged text
+        assert_eq!(db.chapter_content(&chapter_id).unwrap().as_deref(), Some("天亮了。\n"));
+        let conn = db.conn.lock().unwrap();
+        let words: i64 = conn
+            .query_row("SELECT word_count FROM chapters WHERE id = ?1", params![chapter_id], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        // The full stop isn't a word
+        assert_eq!(words, 3);
+        drop(conn);
+        assert!(db.save_chapter_text("missing", "text").unwrap().is_none());
+    }
+
+    #[test]
+    fn trashed_projects_leave_the_lists_until_restored_or_purged() {
+        let db = Database::new_in_memory().unwrap();
+        let kept = db.create_project("长夜", "玄幻").unwrap();
+        let project = db
+            .import_project(
+                "旧稿",
+                "",
+                "",
+                &[ManuscriptChapter { title: "一".into(), body: "夜色\n深沉".into() }],
+            )
+            .unwrap();
+        db.trash_project(&project.id).unwrap();
+        assert_eq!(db.list_projects(&ProjectQuery::default()).unwrap().total, 1);
+        assert!(db.search_projects("旧").unwrap().is_empty());
+        assert!(db.restore_trashed_project(&kept.id).is_err());
+
+        let trashed = db.trashed_projects().unwrap();
+        assert_eq!(trashed.len(), 1);
+        // The project, its chapter and two paragraphs
+        assert_eq!(trashed[0].rows, 1 + 1 + 2);
+        assert!(db.purge_trash(Some(30)).unwrap().names.is_empty());
+        db.restore_trashed_project(&project.id).unwrap();
+        assert_eq!(db.list_projects(&ProjectQuery::default()).unwrap().total, 2);
+
+        db.trash_project(&project.id).unwrap();
+        assert_eq!(db.purge_trash(None).unwrap().names, ["旧稿"]);
+        assert!(db.trashed_projects().unwrap().is_empty());
+        let chapters: i64 = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM chapters", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(chapters, 0);
+        assert!(db.trash_project(&project.id).is_err());
+    }
+
+    #[test]
+    fn saves_count_towards_today_and_the_goal_is_announced_once() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db
+            .import_project(
+                "长夜",
+                "玄幻",
+                "",
+                &[ManuscriptChapter { title: "一".into(), body: "夜色".into() }],
+            )
+            .unwrap();
+        let chapter_id = db.project_chapter_texts(&project.id).unwrap()[0].chapter_id.clone();
+        // Imported text isn't today's writing; edits are, less what they delete
+        assert!(db.writing_days(&project.id).unwrap().is_empty());
+        db.save_chapter_text(&chapter_id, "夜色深沉，林风拔剑").unwrap();
+        db.save_chapter_text(&chapter_id, "夜色深沉，林风").unwrap();
+        let today = writing_goal::today();
+        assert_eq!(db.writing_days(&project.id).unwrap(), [(today.clone(), 6 - 2)]);
+
+        assert_eq!(db.daily_word_goal(&project.id).unwrap(), None);
+        db.set_daily_word_goal(&project.id, Some(1000)).unwrap();
+        assert_eq!(db.daily_word_goal(&project.id).unwrap(), Some(1000));
+        db.set_daily_word_goal(&project.id, None).unwrap();
+        assert_eq!(db.daily_word_goal(&project.id).unwrap(), None);
+        assert!(db.daily_word_goal("missing").is_err());
+
+        assert!(db.claim_goal_reached(&project.id, &today).unwrap());
+        assert!(!db.claim_goal_reached(&project.id, &today).unwrap());
+        assert_eq!(db.writing_days(&project.id).unwrap()[0].1, 4);
+    }
+
+    #[test]
+    fn saves_keep_revisions_up_to_the_cap() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db
+            .import_project(
+                "长夜",
+                "玄幻",
+                "",
+                &[ManuscriptChapter { title: "一".into(), body: String::new() }],
+            )
+            .unwrap();
+        let chapter_id = db.project_chapter_texts(&project.id).unwrap()[0].chapter_id.clone();
+        for n in 0..MAX_REVISIONS + 2 {
+            db.save_chapter_text(&chapter_id, &format!("第{}稿", n)).unwrap();
+        }
+        // The same text again adds nothing
+        db.save_chapter_text(&chapter_id, &format!("第{}稿", MAX_REVISIONS + 1)).unwrap();
+
+        let revisions = db.list_revisions(&chapter_id).unwrap();
+        assert_eq!(revisions.len() as i64, MAX_REVISIONS);
+        assert_eq!(revisions[0].reason, "save");
+        let newest = db.revision_content(&chapter_id, revisions[0].id).unwrap();
+        assert_eq!(newest.as_deref(), Some("第51稿"));
+        let oldest = db.revision_content(&chapter_id, revisions.last().unwrap().id).unwrap();
+        assert_eq!(oldest.as_deref(), Some("第2稿"));
+        assert_eq!(db.revision_content("missing", revisions[0].id).unwrap(), None);
+    }
+
+    #[test]
+    fn restore_revision_only_takes_the_chapters_own_revisions() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db
+            .import_project(
+                "长夜",
+                "玄幻",
+                "",
+                &[
+                    ManuscriptChapter { title: "一".into(), body: "初稿".into() },
+                    ManuscriptChapter { title: "二".into(), body: "别章".into() },
+                ],
+            )
+            .unwrap();
+        let chapters = db.project_chapter_texts(&project.id).unwrap();
+        let (first, other) = (&chapters[0].chapter_id, &chapters[1].chapter_id);
+        db.save_chapter_text(first, "夜色\n深沉").unwrap();
+        db.save_chapter_text(first, "改坏了").unwrap();
+        db.save_chapter_text(other, "别章二稿").unwrap();
+        let revisions = db.list_revisions(first).unwrap();
+        let good = revisions[1].id;
+
+        let foreign = db.list_revisions(other).unwrap()[0].id;
+        assert!(db.restore_revision(first, foreign).is_err());
+        assert!(db.restore_revision(first, 9999).is_err());
+        db.restore_revision(first, good).unwrap();
+
+        let chapter = &db.project_chapter_texts(&project.id).unwrap()[0];
+        let paragraphs: Vec<_> = chapter.paragraphs.iter().map(|p| p.content.as_str()).collect();
+        assert_eq!(paragraphs, ["夜色", "深沉"]);
+        let history = db.list_revisions(first).unwrap();
+        assert_eq!((history.len(), history[0].reason.as_str()), (3, "restore"));
+        assert_eq!(history[0].word_count, 4);
+    }
+
+    #[test]
+    fn replace_in_project_rewrites_paragraphs_and_keeps_a_revision() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db
+            .import_project(
+                "长夜",
+                "玄幻",
+                "",
+                &[
+                    ManuscriptChapter { title: "一".into(), body: "林风拔剑。\n林风走了。".into() },
+                    ManuscriptChapter { title: "二".into(), body: "无人".into() },
+                ],
+            )
+            .unwrap();
+        let matcher = Matcher::new("林风", Default::default()).unwrap();
+        let replaced = db.replace_in_project(&project.id, &matcher, "萧然").unwrap();
+        assert_eq!(replaced.len(), 1);
+        // Punctuation isn't counted
+        assert_eq!((replaced[0].replacements, replaced[0].word_count), (2, 8));
+
+        let chapters = db.project_chapter_texts(&project.id).unwrap();
+        assert_eq!(chapters[0].paragraphs[1].content, "萧然走了。");
+        let conn = db.conn.lock().unwrap();
+        let (revision, words): (String, i64) = conn
+            .query_row(
+                "SELECT content, word_count FROM chapter_revisions WHERE chapter_id = ?1",
+                params![chapters[0].chapter_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((revision.as_str(), words), ("林风拔剑。\n林风走了。", 8));
+    }
+
+    #[test]
+    fn size_counts_pages_and_vacuum_succeeds() {
+        let db = Database::new_in_memory().unwrap();
+        let size = db.size().unwrap();
+        assert!(size.page_count > 0);
+        assert_eq!(size.allocated_bytes, size.page_count * size.page_size);
+        assert_eq!(size.file_bytes, 0);
+        db.vacuum().unwrap();
+    }
+
+    #[test]
+    fn integrity_report_lists_orphaned_rows() {
+        let db = Database::new_in_memory().unwrap();
+        let report = db.integrity_report(CheckMode::Quick, |_| {}).unwrap();
+        assert!(report.ok);
+        assert_eq!(report.check_result, ["ok"]);
+        assert!(report.problems.is_empty() && report.hint.is_none());
+        assert!(report.tables.iter().all(|t| t.name != "chunks_fts"));
+
+        // What an unclean shutdown with foreign keys off could leave behind
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                 INSERT INTO chapters (project_id, chapter_num) VALUES ('gone', 1);
+                 PRAGMA foreign_keys = ON;",
+            )
+            .unwrap();
+        let mut stages = Vec::new();
+        let report = db.integrity_report(CheckMode::Full, |stage| stages.push(stage)).unwrap();
+        assert!(!report.ok);
+        assert_eq!(report.foreign_key_violation_count, 1);
+        let violation = &report.foreign_key_violations[0];
+        assert_eq!((violation.table.as_str(), violation.parent.as_str()), ("chapters", "projects"));
+        assert_eq!(report.problems, ["chapters row 1 refers to a missing projects row"]);
+        assert!(report.hint.is_some());
+        let chapters = report.tables.iter().find(|t| t.name == "chapters").unwrap();
+        assert_eq!(chapters.rows, 1);
+        assert_eq!(stages.last(), Some(&Stage::Done));
+        db.optimize(|_| {}).unwrap();
+    }
+
+    #[test]
+    fn list_prompts_prefers_project_overrides() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO prompts (name, category, content, is_builtin, default_content) \
+                 VALUES ('editor', 'agent', 'shipped', 1, 'shipped')",
+            )
+            .unwrap();
+        db.create_prompt("style", "guide", "global style", None).unwrap();
+        db.create_prompt("editor", "agent", "project editor", Some(&project.id)).unwrap();
+
+        let global = db.list_prompts(None).unwrap();
+        assert_eq!(global.len(), 2);
+        let merged = db.list_prompts(Some(&project.id)).unwrap();
+        let editor: Vec<_> = merged.iter().filter(|p| p.name == "editor").collect();
+        assert_eq!(editor.len(), 1);
+        assert_eq!(editor[0].content, "project editor");
+        assert_eq!(merged.len(), 2);
+
+        let builtin = global.iter().find(|p| p.is_builtin).unwrap();
+        db.delete_prompt(&builtin.id).unwrap();
+        let edited = PromptUpdate { content: Some("mine".into()), ..Default::default() };
+        assert!(db.update_prompt(&builtin.id, &edited).unwrap().customized);
+        let reset = db.reset_prompt(&builtin.id).unwrap();
+        assert_eq!((reset.content.as_str(), reset.customized), ("shipped", false));
+    }
+
+    #[test]
+    fn templates_round_trip_structure_without_prose() {
+        let db = Database::new_in_memory().unwrap();
+        let templates = db.list_project_templates().unwrap();
+        assert!(templates.iter().all(|t| t.is_builtin));
+        let project = db.create_project_from_template("青云志", "builtin-urban-cultivation").unwrap();
+        assert_eq!(project.genre, "都市修真");
+        let characters = db.list_characters(&project.id).unwrap();
+        assert_eq!(characters[0].category, "主角");
+
+        let conn = db.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO worldbuilding (project_id, category, title, parent_id) \
+             SELECT project_id, '力量体系', '筑基', id FROM worldbuilding \
+             WHERE project_id = ?1 AND title = '修炼境界'",
+            params![project.id],
+        )
+        .unwrap();
+        let chapter_id: String = conn
+            .query_row(
+                "INSERT INTO chapters (project_id, chapter_num) VALUES (?1, 1) RETURNING id",
+                params![project.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        drop(conn);
+        db.save_chapter_text(&chapter_id, "不该进模板的正文").unwrap();
+        let saved = db.save_project_as_template(&project.id, "我的模板").unwrap();
+        assert!(!saved.is_builtin);
+        assert_eq!(saved.content.structure, "三幕式");
+        assert_eq!(saved.content.outlines.len(), 3);
+        assert_eq!(saved.content.prompts[0].name, "chapter_writer");
+        let child = saved.content.worldbuilding.iter().find(|e| e.title == "筑基").unwrap();
+        assert_eq!(child.parent.as_deref(), Some("修炼境界"));
+        assert!(!serde_json::to_string(&saved.content).unwrap().contains("不该进模板"));
+
+        let copy = db.create_project_from_template("副本", &saved.id).unwrap();
+        let conn = db.conn.lock().unwrap();
+        let linked: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM worldbuilding w JOIN worldbuilding p ON p.id = w.parent_id \
+                 WHERE w.project_id = ?1 AND w.title = '筑基' AND p.title = '修炼境界'",
+                params![copy.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(linked, 1);
+        drop(conn);
+        assert!(db.create_project_from_template("x", "missing").is_err());
+        assert!(db.save_project_as_template("missing", "x").is_err());
+    }
+
+    #[test]
+    fn chapter_status_changes_are_all_or_nothing_and_leave_text_alone() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let conn = db.conn.lock().unwrap();
+        let mut ids = Vec::new();
+        for num in 1..=3 {
+            let id: String = conn
+                .query_row(
+                    "INSERT INTO chapters (project_id, chapter_num) VALUES (?1, ?2) RETURNING id",
+                    params![project.id, num],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            ids.push(id);
+        }
+        drop(conn);
+        db.save_chapter_text(&ids[0], "正文").unwrap();
+        let revisions = db.list_revisions(&ids[0]).unwrap().len();
+
+        assert_eq!(db.set_chapters_status(&ids[..2], ChapterStatus::Revised, false), Ok(2));
+        // Chapter 3 is still a draft, so nothing becomes final
+        assert!(db.set_chapters_status(&ids, ChapterStatus::Final, false).is_err());
+        assert_eq!(db.project_stats(&project.id).unwrap().by_status.final_, 0);
+        assert_eq!(db.set_chapters_status(&ids, ChapterStatus::Final, true), Ok(3));
+        assert_eq!(db.set_chapters_status(&ids[..1], ChapterStatus::Draft, false), Ok(1));
+
+        let stats = db.project_stats(&project.id).unwrap();
+        assert_eq!(stats.by_status, StatusCounts { draft: 1, revised: 0, final_: 2 });
+        assert_eq!(db.list_revisions(&ids[0]).unwrap().len(), revisions);
+        assert!(db.set_chapters_status(&["missing".into()], ChapterStatus::Draft, false).is_err());
+        let conn = db.conn.lock().unwrap();
+        let text: String = conn
+            .query_row(
+                "SELECT content FROM chapter_paragraphs WHERE chapter_id = ?1",
+                params![ids[0]],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(text, "正文");
+        assert!(conn.execute("UPDATE chapters SET status = 'done'", []).is_err());
+    }
+
+    #[test]
+    fn notes_list_pinned_first_and_travel_with_backups() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let older = db.create_note(&project.id, "伏笔", "第三章的信", "").unwrap();
+        let newer = db.create_note(&project.id, "杂记", "灯塔", "#ffcc00").unwrap();
+        let conn = db.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE notes SET updated_at = '2000-01-01 00:00:00' WHERE id = ?1",
+            params![older.id],
+        )
+        .unwrap();
+        drop(conn);
+        let order = |db: &Database, id: &str| -> Vec<String> {
+            db.list_notes(id).unwrap().into_iter().map(|n| n.title).collect()
+        };
+        assert_eq!(order(&db, &project.id), ["杂记", "伏笔"]);
+
+        let pin = NoteUpdate { pinned: Some(true), ..Default::default() };
+        let pinned = db.update_note(&older.id, &pin).unwrap();
+        assert_eq!(pinned.updated_at, "2000-01-01 00:00:00");
+        assert_eq!(order(&db, &project.id), ["伏笔", "杂记"]);
+        assert!(db.update_note("missing", &pin).is_err());
+
+        let backup = crate::backup::new(
+            db.get_project(&project.id).unwrap(),
+            Vec::new(),
+            db.export_notes(&project.id).unwrap(),
+            Vec::new(),
+        );
+        let copy = db.import_backup(&backup).unwrap();
+        let notes = db.list_notes(&copy.id).unwrap();
+        assert_eq!(notes.len(), 2);
+        assert!(notes[0].pinned);
+        assert_eq!(notes[1].color, newer.color);
+        db.delete_note(&newer.id).unwrap();
+        assert_eq!(db.list_notes(&project.id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn embedding_index_goes_stale_when_chapters_change() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let chapter_id: String = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "INSERT INTO chapters (project_id, chapter_num) VALUES (?1, 1) RETURNING id",
+                params![project.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        db.save_chapter_text(&chapter_id, "林风拔剑。\nend").unwrap();
+        let reason = |db: &Database| {
+            db.embedding_index_status(&project.id).unwrap().unwrap().stale_reason
+        };
+        assert_eq!(reason(&db).as_deref(), Some("not_built"));
+        assert!(db.embedding_index_status("missing").unwrap().is_none());
+        assert!(!db.mark_embedding_index_stale(&project.id).unwrap());
+
+        // What the agent writes after indexing the chapter
+        let mut hash = ContentHash::default();
+        hash.add(&chapter_id, "林风拔剑。");
+        hash.add(&chapter_id, "end");
+        let hash = hash.finish();
+        let built = |db: &Database| {
+            db.conn
+                .lock()
+                .unwrap()
+                .execute(
+                    "INSERT OR REPLACE INTO embedding_index \
+                     (project_id, chunk_count, dim, model, content_hash) \
+                     VALUES (?1, 1, 3072, 'm', ?2)",
+                    params![project.id, hash],
+                )
+                .unwrap();
+        };
+        built(&db);
+        let status = db.embedding_index_status(&project.id).unwrap().unwrap();
+        assert!(status.built && !status.stale);
+        assert_eq!((status.chunk_count, status.dim), (1, 3072));
+
+        // Edits made behind the desktop app's back are caught by the hash
+        let set_last = |db: &Database, content: &str| {
+            db.conn
+                .lock()
+                .unwrap()
+                .execute(
+                    "UPDATE chapter_paragraphs SET content = ?1 WHERE para_index = 1",
+                    params![content],
+                )
+                .unwrap();
+        };
+        set_last(&db, "END");
+        assert_eq!(reason(&db).as_deref(), Some("content_changed"));
+        set_last(&db, "end");
+        assert_eq!(reason(&db), None);
+        // Its own saves flag the index without hashing
+        db.save_chapter_text(&chapter_id, "林风拔剑。\nend").unwrap();
+        assert_eq!(reason(&db).as_deref(), Some("content_changed"));
+
+        built(&db);
+        db.conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE projects SET embedding_dim = 1536 WHERE id = ?1", params![project.id])
+            .unwrap();
+        assert_eq!(reason(&db).as_deref(), Some("dim_changed"));
+    }
+
+    #[test]
+    fn story_events_keep_a_dense_order_and_outlive_their_links() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let other = db.create_project("别处", "玄幻").unwrap();
+        let hero = db.create_character(&project.id, "林风").unwrap();
+        let chapter_id: String = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "INSERT INTO chapters (project_id, chapter_num) VALUES (?1, 7) RETURNING id",
+                params![project.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let heroes = [hero.id.clone()];
+        assert!(db.story_event_links_exist(&project.id, Some(&chapter_id), &heroes).unwrap());
+        assert!(!db.story_event_links_exist(&other.id, None, &heroes).unwrap());
+        assert!(!db.story_event_links_exist(&other.id, Some(&chapter_id), &[]).unwrap());
+
+        let add = |title: &str, date: &str, chapter: Option<&str>| {
+            db.create_story_event(&project.id, title, "", date, chapter, &heroes).unwrap()
+        };
+        let duel = add("决斗", "0003-04", Some(&chapter_id));
+        let birth = add("出生", "0001-01", None);
+        let rumor = add("传闻", "", None);
+        let titles = |sort| -> Vec<String> {
+            db.list_story_events(&project.id, sort).unwrap().into_iter().map(|e| e.title).collect()
+        };
+        assert_eq!(titles(StoryEventSort::Narrative), ["决斗", "出生", "传闻"]);
+        assert_eq!(titles(StoryEventSort::Story), ["出生", "决斗", "传闻"]);
+
+        let order = [rumor.id.clone(), duel.id.clone(), birth.id.clone()];
+        let reordered = db.reorder_story_events(&project.id, &order).unwrap();
+        let numbers: Vec<i64> = reordered.iter().map(|e| e.narrative_order).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        db.delete_story_event(&rumor.id).unwrap();
+        assert_eq!(titles(StoryEventSort::Narrative), ["决斗", "出生"]);
+        assert_eq!(db.get_story_event(&duel.id).unwrap().narrative_order, 1);
+
+        let backup = crate::backup::new(
+            db.get_project(&project.id).unwrap(),
+            db.export_chapters(&project.id).unwrap(),
+            Vec::new(),
+            db.export_story_events(&project.id).unwrap(),
+        );
+        let copy = db.import_backup(&backup).unwrap();
+        let copied = db.list_story_events(&copy.id, StoryEventSort::Narrative).unwrap();
+        assert_eq!(copied.len(), 2);
+        assert!(copied[0].chapter_id.is_some() && copied[0].chapter_id != duel.chapter_id);
+        assert!(copied[0].character_ids.is_empty());
+
+        db.delete_character(&hero.id).unwrap();
+        db.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM chapters WHERE id = ?1", params![chapter_id])
+            .unwrap();
+        let duel = db.get_story_event(&duel.id).unwrap();
+        assert_eq!((duel.chapter_id, duel.character_ids.len()), (None, 0));
+        assert_eq!(db.get_story_event(&birth.id).unwrap().title, "出生");
+    }
+
+    #[test]
+    fn checkpoint_truncates_the_write_ahead_log() {
+        let dir = std::env::temp_dir().join(format!("checkpoint-test-{}", std::process::id()));
+        let db = Database::new(dir.to_str().unwrap()).unwrap();
+        // The agent puts the database in WAL mode; it sticks to the file
+        db.conn.lock().unwrap().execute_batch("PRAGMA journal_mode = WAL").unwrap();
+        db.create_project("长夜", "玄幻").unwrap();
+        let checkpointed = db.checkpoint().unwrap();
+        assert!(!checkpointed.busy);
+        assert!(checkpointed.wal_bytes_before > 0);
+        assert_eq!(checkpointed.wal_bytes_after, 0);
+        assert_eq!(db.list_projects(&ProjectQuery::default()).unwrap().total, 1);
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn integrity_check_runs_beside_an_open_write() {
+        let dir = std::env::temp_dir().join(format!("integrity-test-{}", std::process::id()));
+        let db = Database::new(dir.to_str().unwrap()).unwrap();
+        db.conn.lock().unwrap().execute_batch("PRAGMA journal_mode = WAL").unwrap();
+        db.create_project("长夜", "玄幻").unwrap();
+        // Like the agent mid-write: the file's write lock is taken and not let go
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch("BEGIN IMMEDIATE; UPDATE projects SET name = '长夜行';")
+            .unwrap();
+        let report = db.integrity_report(CheckMode::Full, |_| {}).unwrap();
+        assert!(report.ok);
+        assert_eq!(report.tables.iter().find(|t| t.name == "projects").unwrap().rows, 1);
+        db.conn.lock().unwrap().execute_batch("ROLLBACK").unwrap();
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn relationships_stay_within_a_project_and_go_with_their_characters() {
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_project("长夜", "玄幻").unwrap();
+        let master = db.create_character(&project.id, "萧然").unwrap();
+        let pupil = db.create_character(&project.id, "林风").unwrap();
+        let rival = db.create_character(&project.id, "沈川").unwrap();
+        let stranger =
+            db.create_character(&db.create_project("别处", "").unwrap().id, "路人").unwrap();
+
+        let taught = db.create_relationship(&master.id, &pupil.id, "师徒", "", 8).unwrap();
+        db.create_relationship(&pupil.id, &rival.id, "宿敌", "", 3).unwrap();
+        let conflict = db.create_relationship(&master.id, &pupil.id, "师徒", "", 1).unwrap_err();
+        assert!(conflict.starts_with("Conflict: 萧然"), "{}", conflict);
+        // The other direction, or another kind, is a different edge
+        let back = db.create_relationship(&pupil.id, &master.id, "师徒", "", 0).unwrap();
+        assert!(db.create_relationship(&master.id, &stranger.id, "旧识", "", 0).is_err());
+        assert!(db.create_relationship(&master.id, &master.id, "自省", "", 0).is_err());
+
+        let rename = RelationshipUpdate { kind: Some("师徒".into()), ..Default::default() };
+        assert!(db.update_relationship(&back.id, &RelationshipUpdate::default()).is_ok());
+        let changed = RelationshipUpdate { strength: Some(5), ..Default::default() };
+        assert_eq!(db.update_relationship(&back.id, &changed).unwrap().kind, "师徒");
+        assert!(db.update_relationship(&taught.id, &rename).is_ok());
+
+        let connections = db.character_connections(&pupil.id).unwrap();
+        let names: Vec<(&str, bool)> =
+            connections.iter().map(|c| (c.name.as_str(), c.outgoing)).collect();
+        assert_eq!(names, [("萧然", false), ("萧然", true), ("沈川", true)]);
+
+        let graph = db.relationship_graph(&project.id).unwrap();
+        assert_eq!(graph.edges.len(), 3);
+        let degrees: Vec<usize> = graph.nodes.iter().map(|n| n.degree).collect();
+        assert_eq!(degrees, [2, 3, 1]);
+
+        db.delete_character(&pupil.id).unwrap();
+        let graph = db.relationship_graph(&project.id).unwrap();
+        assert_eq!((graph.nodes.len(), graph.edges.len()), (2, 0));
     }
 }
@@ -0,0 +1,114 @@
+//! Health check and optimization of sanhuoai.db, for after an unclean shutdown
+//! or when the file has grown. The SQL lives in db.rs; these are its results
+//! and the stages reported while it runs.
+
+use serde::Serialize;
+
+/// Foreign key violations listed in a report; the count covers the rest
+pub const MAX_LISTED_VIOLATIONS: usize = 100;
+
+/// What a report that found problems suggests doing about them
+pub const RESTORE_HINT: &str = "The database has problems. Restore it from a recent backup \
+     before making new ones, which would copy the damage.";
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Check,
+    Optimize,
+}
+
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// PRAGMA quick_check, or integrity_check when the full check was asked for
+    IntegrityCheck,
+    ForeignKeyCheck,
+    CountRows,
+    Optimize,
+    Vacuum,
+    Analyze,
+    Done,
+}
+
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckMode {
+    /// Skips matching indexes against their tables; seconds rather than minutes
+    Quick,
+    Full,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    /// None for a WITHOUT ROWID table
+    pub rowid: Option<i64>,
+    /// The table the missing row should be in
+    pub parent: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TableRows {
+    pub name: String,
+    pub rows: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IntegrityReport {
+    pub mode: CheckMode,
+    /// Nothing reported by the check and no foreign key violations
+    pub ok: bool,
+    /// One line per problem found, for showing as is; empty when ok
+    pub problems: Vec<String>,
+    /// RESTORE_HINT when there are problems
+    pub hint: Option<String>,
+    /// Lines from quick_check / integrity_check; just "ok" when the file is sound
+    pub check_result: Vec<String>,
+    pub foreign_key_violation_count: usize,
+    /// The first MAX_LISTED_VIOLATIONS of them
+    pub foreign_key_violations: Vec<ForeignKeyViolation>,
+    pub tables: Vec<TableRows>,
+    pub file_bytes: u64,
+    pub page_size: u64,
+    /// Unused pages that optimize_database would give back
+    pub freelist_pages: u64,
+    pub checked_at: String,
+}
+
+/// The check's own lines other than its "ok", then a line per listed foreign key
+/// violation and one for any left unlisted
+pub fn problems(
+    check_result: &[String],
+    violations: &[ForeignKeyViolation],
+    violation_count: usize,
+) -> Vec<String> {
+    let mut problems: Vec<String> =
+        check_result.iter().filter(|line| *line != "ok").cloned().collect();
+    problems.extend(violations.iter().map(|v| match v.rowid {
+        Some(rowid) => format!("{} row {} refers to a missing {} row", v.table, rowid, v.parent),
+        None => format!("A {} row refers to a missing {} row", v.table, v.parent),
+    }));
+    if violation_count > violations.len() {
+        let more = violation_count - violations.len();
+        problems.push(format!("{} more foreign key violations", more));
+    }
+    problems
+}
+
+#[derive(Serialize)]
+pub struct Optimized {
+    pub file_bytes_before: u64,
+    pub file_bytes_after: u64,
+    pub elapsed_ms: u64,
+}
+
+/// Result of folding the write-ahead log back into the database file
+#[derive(Serialize)]
+pub struct Checkpointed {
+    /// Another connection (usually the agent) was reading or writing, so part of
+    /// the log stayed behind; it goes at a later checkpoint
+    pub busy: bool,
+    pub wal_bytes_before: u64,
+    pub wal_bytes_after: u64,
+}
@@ -0,0 +1,164 @@
+//! Zip bundle for attaching to bug reports. Only metadata goes in: never
+//! chapter text, never credential values.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const AGENT_LOG_LINES: usize = 500;
+const FORMAT_VERSION: u32 = 1;
+/// global_settings keys containing any of these have their values replaced
+const SECRET_HINTS: &[&str] = &["key", "token", "secret", "password", "passwd", "auth"];
+const REDACTED: &str = "[redacted]";
+/// Shorter "secrets" would blank out ordinary words
+const MIN_SCRUBBED_LEN: usize = 8;
+
+#[derive(Serialize)]
+pub struct DatabaseReport {
+    pub user_version: i64,
+    /// Applied agent migrations from schema_migrations, oldest first
+    pub migrations: Vec<String>,
+    /// PRAGMA integrity_check output; a single "ok" when healthy
+    pub integrity_check: Vec<String>,
+    pub project_count: i64,
+    pub chapter_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct Bundle {
+    pub path: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    format_version: u32,
+    created_at: String,
+    app_version: &'a str,
+    files: Vec<ManifestEntry<'a>>,
+    note: &'static str,
+}
+
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    name: &'a str,
+    size_bytes: usize,
+}
+
+#[derive(Serialize)]
+pub struct SystemInfo {
+    pub app_version: String,
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub debug_build: bool,
+}
+
+pub fn system_info(app_version: String) -> SystemInfo {
+    SystemInfo {
+        app_version,
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        os_version: sysinfo::System::long_os_version(),
+        kernel_version: sysinfo::System::kernel_version(),
+        debug_build: cfg!(debug_assertions),
+    }
+}
+
+/// Replace the values of anything that looks like a secret
+pub fn redact(settings: Vec<(String, String)>) -> BTreeMap<String, String> {
+    settings
+        .into_iter()
+        .map(|(key, value)| {
+            let lower = key.to_lowercase();
+            if SECRET_HINTS.iter().any(|hint| lower.contains(hint)) && !value.is_empty() {
+                (key, REDACTED.to_string())
+            } else {
+                (key, value)
+            }
+        })
+        .collect()
+}
+
+/// Replace every occurrence of each secret in a text file's contents
+pub fn scrub(data: Vec<u8>, secrets: &[&str]) -> Vec<u8> {
+    let secrets: Vec<&str> =
+        secrets.iter().copied().filter(|s| s.len() >= MIN_SCRUBBED_LEN).collect();
+    let text = String::from_utf8_lossy(&data);
+    if !secrets.iter().any(|secret| text.contains(secret)) {
+        return data;
+    }
+    secrets
+        .iter()
+        .fold(text.into_owned(), |text, secret| text.replace(secret, REDACTED))
+        .into_bytes()
+}
+
+/// Last `lines` lines of a text file, or None if it can't be read
+pub fn tail_file(path: &Path, lines: usize) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    let text = String::from_utf8_lossy(&bytes);
+    let all: Vec<&str> = text.lines().collect();
+    Some(all[all.len().saturating_sub(lines)..].join("\n"))
+}
+
+/// Where to write: `dest` itself, or a timestamped file inside it if it's a directory
+pub fn bundle_path(dest: &str) -> Result<PathBuf, String> {
+    let dest = dest.trim();
+    if dest.is_empty() {
+        return Err("No destination given".into());
+    }
+    let dest = PathBuf::from(dest);
+    if dest.is_dir() {
+        let stamp = crate::app_log::timestamp().replace([' ', ':'], "-");
+        return Ok(dest.join(format!("sanhuoai-diagnostics-{}.zip", stamp)));
+    }
+    match dest.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !parent.is_dir() => {
+            Err(format!("{} does not exist", parent.display()))
+        }
+        _ => Ok(dest),
+    }
+}
+
+/// Write `files` plus a manifest.json describing them into a zip at `path`
+pub fn write_zip(path: &Path, app_version: &str, files: &[(&str, Vec<u8>)]) -> Result<Bundle, String> {
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        created_at: crate::app_log::timestamp(),
+        app_version,
+        files: files
+            .iter()
+            .map(|(name, data)| ManifestEntry { name, size_bytes: data.len() })
+            .collect(),
+        note: "Chapter content, credential values and the agent token are never included",
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+
+    let tmp = path.with_extension("zip.tmp");
+    let result = (|| -> zip::result::ZipResult<()> {
+        let mut zip = zip::ZipWriter::new(File::create(&tmp)?);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("manifest.json", options)?;
+        zip.write_all(&manifest)?;
+        for (name, data) in files {
+            zip.start_file(*name, options)?;
+            zip.write_all(data)?;
+        }
+        zip.finish()?.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = result.map_err(|e| e.to_string()).and_then(|_| {
+        std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+    }) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(format!("Failed to write {}: {}", path.display(), e));
+    }
+
+    let size_bytes = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    Ok(Bundle { path: path.display().to_string(), size_bytes })
+}
@@ -0,0 +1,127 @@
+//! What the agent recorded about a project's chapter embeddings (the
+//! embedding_index table), and whether they still match the chapters. The agent
+//! hashes the chapters it indexes the same way ContentHash does; see
+//! agent/rag/search.py.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 over every paragraph in reading order, fed one paragraph at a time so
+/// a project's text never has to be held in memory at once
+#[derive(Default)]
+pub struct ContentHash(Sha256);
+
+impl ContentHash {
+    pub fn add(&mut self, chapter_id: &str, content: &str) {
+        self.0.update(chapter_id.as_bytes());
+        self.0.update([0x1f]);
+        self.0.update(content.as_bytes());
+        self.0.update([0x1e]);
+    }
+
+    pub fn finish(self) -> String {
+        self.0.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// The agent's reindex chunk length in characters (_REINDEX_CHUNK_CHARS)
+const CHUNK_CHARS: u64 = 1500;
+
+/// Roughly what rebuilding an index over this much text writes: the text three
+/// times (memory_chunks, its full-text index and the vector store's copy) and
+/// each chunk's float32 vector twice (the vectors and their HNSW graph)
+pub fn estimated_bytes(text_chars: u64, text_bytes: u64, dim: u64) -> u64 {
+    let chunks = text_chars.div_ceil(CHUNK_CHARS);
+    text_bytes * 3 + chunks * dim * 4 * 2
+}
+
+/// A row of embedding_index as the agent wrote it
+pub struct IndexRecord {
+    pub chunk_count: i64,
+    pub dim: i64,
+    pub model: String,
+    pub content_hash: String,
+    pub stale: bool,
+    pub built_at: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EmbeddingIndexStatus {
+    pub built: bool,
+    pub chunk_count: i64,
+    /// The dimension the index was built with, and the one the project asks for now
+    pub dim: i64,
+    pub project_dim: i64,
+    pub model: String,
+    pub built_at: Option<String>,
+    pub stale: bool,
+    /// "not_built", "dim_changed" or "content_changed" when stale
+    pub stale_reason: Option<String>,
+}
+
+impl EmbeddingIndexStatus {
+    /// `content_hash` is only called if nothing cheaper already says the index is
+    /// stale
+    pub fn new<E>(
+        record: Option<IndexRecord>,
+        project_dim: i64,
+        content_hash: impl FnOnce() -> Result<String, E>,
+    ) -> Result<Self, E> {
+        let record = match record {
+            Some(record) => record,
+            None => {
+                return Ok(EmbeddingIndexStatus {
+                    built: false,
+                    chunk_count: 0,
+                    dim: 0,
+                    project_dim,
+                    model: String::new(),
+                    built_at: None,
+                    stale: true,
+                    stale_reason: Some("not_built".into()),
+                })
+            }
+        };
+        let stale_reason = if record.dim != project_dim {
+            Some("dim_changed")
+        } else if record.stale || content_hash()? != record.content_hash {
+            Some("content_changed")
+        } else {
+            None
+        };
+        Ok(EmbeddingIndexStatus {
+            built: true,
+            chunk_count: record.chunk_count,
+            dim: record.dim,
+            project_dim,
+            model: record.model,
+            built_at: Some(record.built_at),
+            stale: stale_reason.is_some(),
+            stale_reason: stale_reason.map(String::from),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_hash_matches_the_agents() {
+        // hashlib.sha256 over the same bytes, as agent/rag/search.py feeds them
+        let mut hash = ContentHash::default();
+        hash.add("c1", "林风拔剑。");
+        hash.add("c1", "");
+        hash.add("c2", "end");
+        assert_eq!(
+            hash.finish(),
+            "0c93cd748507bc2682507dcec3c8b749dc5fdc1c2410129fe0e6f6f4c7c4e66f"
+        );
+        assert_eq!(estimated_bytes(0, 0, 384), 0);
+        assert_eq!(estimated_bytes(1501, 4503, 384), 4503 * 3 + 2 * 384 * 8);
+        assert_eq!(
+            ContentHash::default().finish(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
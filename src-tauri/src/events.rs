@@ -0,0 +1,215 @@
+//! Names and payloads of the events emitted to the webview. Every emit goes
+//! through these constants so the frontend's listeners have one place to match.
+
+use serde::Serialize;
+
+use crate::Project;
+
+/// AgentSpawned: a new agent process was started (by a command, the watchdog or at launch)
+pub const AGENT_SPAWNED: &str = "agent://spawned";
+/// AgentReady: first successful health check after a spawn
+pub const AGENT_READY: &str = "agent://ready";
+/// AgentStopped: the agent was stopped on request (stop, restart, data move, window close)
+pub const AGENT_STOPPED: &str = "agent://stopped";
+/// AgentCrashed: the agent exited without being asked to
+pub const AGENT_CRASHED: &str = "agent://crashed";
+/// AgentRestarting: the watchdog is respawning a crashed agent, or
+/// force_reset_agent a wedged one
+pub const AGENT_RESTARTING: &str = "agent://restarting";
+/// AgentStartFailed: launching the agent without a caller to report to failed
+/// (at startup or a watchdog restart)
+pub const AGENT_START_FAILED: &str = "agent://start-failed";
+pub const AGENT_INCOMPATIBLE: &str = "agent://incompatible";
+pub const AGENT_TOKEN_ROTATED: &str = "agent://token-rotated";
+pub const AGENT_INSTALL_PROGRESS: &str = "agent://install-progress";
+/// Followed by the request id, e.g. `agent://stream/42`
+pub const AGENT_STREAM_PREFIX: &str = "agent://stream/";
+/// ProjectChanged: a Rust command created, changed, trashed, restored or purged a
+/// project, so other windows can refresh
+pub const PROJECT_CHANGED: &str = "db://project-changed";
+pub const SETTINGS_CHANGED: &str = "settings://changed";
+pub const DATA_DIR_PROGRESS: &str = "data-dir://progress";
+pub const REINDEX_PROGRESS: &str = "reindex-progress";
+/// SnapshotProgress: bytes copied by snapshot_project or restore_snapshot
+pub const SNAPSHOT_PROGRESS: &str = "snapshot://progress";
+/// WordFrequencyProgress: one chapter of analyze_word_frequency done
+pub const WORD_FREQUENCY_PROGRESS: &str = "analysis://word-frequency";
+/// CharacterCheckProgress: one chapter of check_character_consistency done
+pub const CHARACTER_CHECK_PROGRESS: &str = "analysis://character-consistency";
+/// BackupCompleted: a scheduled or run_backup_now database backup was written
+pub const BACKUP_COMPLETED: &str = "backup://completed";
+/// BackupFailed: a scheduled or run_backup_now database backup failed
+pub const BACKUP_FAILED: &str = "backup://failed";
+/// DbMaintenanceProgress: check_database_integrity or optimize_database began a stage
+pub const DB_MAINTENANCE_PROGRESS: &str = "db://maintenance";
+/// GoalReached: a project's daily word goal was met; once per project per local day
+pub const GOAL_REACHED: &str = "goal://reached";
+/// TrashPurged: projects were deleted from the trash for good
+pub const TRASH_PURGED: &str = "trash://purged";
+/// AppReady: setup finished; sent once at launch
+pub const APP_READY: &str = "app://ready";
+/// DataDirFallback: the data directory was unusable at launch; sent once, after APP_READY
+pub const DATA_DIR_FALLBACK: &str = "app://data-dir-fallback";
+/// single_instance::Launch: the app was launched again; its arguments are forwarded here
+pub const SECOND_INSTANCE: &str = "app://second-instance";
+
+/// Whether an agent event's payload is about a project's agent rather than the
+/// shared one
+pub fn from_project_agent(payload: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(payload)
+        .is_ok_and(|payload| !payload["project_id"].is_null())
+}
+
+#[derive(Serialize, Clone)]
+pub struct AgentSpawned {
+    /// The project whose agent this is; None for the shared agent
+    pub project_id: Option<String>,
+    pub pid: u32,
+    pub port: u16,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AgentReady {
+    /// The project whose agent this is; None for the shared agent
+    pub project_id: Option<String>,
+    pub pid: u32,
+    pub port: u16,
+    /// Time from spawn to the first healthy response
+    pub elapsed_ms: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AgentStopped {
+    /// The project whose agent this is; None for the shared agent
+    pub project_id: Option<String>,
+    /// None for an adopted agent we didn't spawn
+    pub pid: Option<u32>,
+    /// Killed after ignoring the shutdown request for the grace period
+    pub forced: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AgentCrashed {
+    /// The project whose agent this is; None for the shared agent
+    pub project_id: Option<String>,
+    pub pid: u32,
+    /// None when the process was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Whether the watchdog is about to restart it
+    pub will_restart: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AgentRestarting {
+    /// The project whose agent this is; None for the shared agent
+    pub project_id: Option<String>,
+    pub previous_pid: u32,
+    /// Watchdog restarts and force resets this session, including this one
+    pub restart_count: u32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AppReady {
+    pub data_dir: String,
+    pub agent_port: u16,
+    /// The interpreter the agent will be launched with exists
+    pub python_found: bool,
+    pub debug: bool,
+    /// Set when this session runs from a temporary directory
+    pub data_dir_fallback: Option<DataDirFallback>,
+}
+
+/// The app couldn't write to its data directory and is running from a temporary
+/// one; anything saved this session is lost when the system clears it
+#[derive(Serialize, Clone)]
+pub struct DataDirFallback {
+    pub requested: String,
+    pub fallback: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AgentStartFailed {
+    /// The project whose agent this is; None for the shared agent
+    pub project_id: Option<String>,
+    pub error: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct WordFrequencyProgress<'a> {
+    pub project_id: &'a str,
+    pub stage: crate::word_frequency::Stage,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CharacterCheckProgress<'a> {
+    pub project_id: &'a str,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Serialize, Clone)]
+pub struct BackupCompleted<'a> {
+    /// False for run_backup_now
+    pub scheduled: bool,
+    #[serde(flatten)]
+    pub backup: &'a crate::auto_backup::BackupDone,
+}
+
+#[derive(Serialize, Clone)]
+pub struct BackupFailed<'a> {
+    pub scheduled: bool,
+    pub error: &'a str,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SnapshotProgress<'a> {
+    pub operation: crate::snapshot::Operation,
+    #[serde(flatten)]
+    pub progress: &'a crate::snapshot::Progress,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectChange {
+    /// Also covers imports
+    Created,
+    Updated,
+    /// Moved to the trash, or purged from it
+    Deleted,
+    /// Taken back out of the trash
+    Restored,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ProjectChanged<'a> {
+    pub action: ProjectChange,
+    pub project_id: &'a str,
+    /// None for Deleted
+    pub project: Option<&'a Project>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct DbMaintenanceProgress {
+    pub operation: crate::db_health::Operation,
+    pub stage: crate::db_health::Stage,
+}
+
+#[derive(Serialize, Clone)]
+pub struct GoalReached<'a> {
+    pub project_id: &'a str,
+    /// Local date, YYYY-MM-DD
+    pub day: &'a str,
+    pub goal: i64,
+    pub written_today: i64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct TrashPurged<'a> {
+    /// By the retention setting rather than empty_trash
+    pub automatic: bool,
+    #[serde(flatten)]
+    pub purged: &'a crate::trash::Purged,
+}
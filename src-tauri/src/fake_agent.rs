@@ -0,0 +1,188 @@
+//! Stand-in for the Python agent, for exercising the process lifecycle in CI
+//! without Python or uvicorn. With SANHUOAI_FAKE_AGENT set, spawn_agent re-runs
+//! this executable with `--fake-agent <port>`, and run() serves a minimal HTTP
+//! endpoint instead of opening a window. Being a real child process, it is
+//! stopped, killed and watched exactly like the real agent.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+pub const ENABLE_ENV_KEY: &str = "SANHUOAI_FAKE_AGENT";
+const ARG: &str = "--fake-agent";
+const VERSION: &str = "0.1.0";
+#[cfg(test)]
+const TEST_PORT_ENV_KEY: &str = "SANHUOAI_FAKE_AGENT_TEST_PORT";
+
+pub fn enabled() -> bool {
+    std::env::var(ENABLE_ENV_KEY).is_ok_and(|v| crate::is_truthy(&v))
+}
+
+/// Command that launches the stub on `port`
+pub fn command(port: u16) -> Result<(PathBuf, Command), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Cannot locate the executable: {}", e))?;
+    let mut cmd = Command::new(&exe);
+    // The test harness rejects --fake-agent, so have it run the test that serves
+    #[cfg(test)]
+    cmd.args(["fake_agent::tests::serve_stub", "--exact", "--nocapture"])
+        .env(TEST_PORT_ENV_KEY, port.to_string());
+    #[cfg(not(test))]
+    cmd.arg(ARG).arg(port.to_string());
+    Ok((exe, cmd))
+}
+
+/// Whether a process command line is this executable launched as the stub
+pub fn is_stub_command(cmd: &[String]) -> bool {
+    let file_name = |path: PathBuf| path.file_name().map(|n| n.to_os_string());
+    let this_exe = std::env::current_exe().ok().and_then(file_name);
+    cmd.get(1).map(String::as_str) == Some(ARG)
+        && this_exe.is_some()
+        && cmd.first().and_then(|arg0| file_name(PathBuf::from(arg0))) == this_exe
+}
+
+/// Serve the stub if this process was launched as one; returns once it shuts down
+pub fn serve_if_requested() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) != Some(ARG) {
+        return false;
+    }
+    let Some(port) = args.get(2).and_then(|p| p.parse::<u16>().ok()) else {
+        eprintln!("[fake-agent] usage: {} <port>", ARG);
+        std::process::exit(2);
+    };
+    let token = std::env::var(crate::AGENT_TOKEN_ENV_KEY).unwrap_or_default();
+    if let Err(e) = serve(port, &token) {
+        eprintln!("[fake-agent] {}", e);
+        std::process::exit(1);
+    }
+    true
+}
+
+fn serve(port: u16, token: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("[fake-agent] listening on port {}", port);
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+        if let Ok(Route::Shutdown) = handle(stream, token) {
+            println!("[fake-agent] shutting down");
+            break;
+        }
+    }
+    Ok(())
+}
+
+enum Route {
+    Continue,
+    Shutdown,
+}
+
+fn handle(mut stream: TcpStream, token: &str) -> io::Result<Route> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut content_length = 0;
+    let mut bearer = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().unwrap_or(0);
+        } else if name.eq_ignore_ascii_case("authorization") {
+            bearer = value.strip_prefix("Bearer ").map(str::to_string);
+        }
+    }
+    if content_length > crate::agent_http::MAX_REQUEST_BYTES {
+        let payload = serde_json::json!({ "detail": "Request body too large" });
+        respond(&mut stream, 413, &payload)?;
+        return Ok(Route::Continue);
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+
+    let authorized = token.is_empty() || bearer.as_deref() == Some(token);
+    let (status, payload, route) = if !authorized {
+        (401, serde_json::json!({ "detail": "Unauthorized" }), Route::Continue)
+    } else {
+        match (method.as_str(), path.as_str()) {
+            ("GET", "/health") => (
+                200,
+                serde_json::json!({ "status": "ok", "version": VERSION, "agent": "sanhuoai" }),
+                Route::Continue,
+            ),
+            ("GET", "/info") => (
+                200,
+                serde_json::json!({
+                    "version": VERSION,
+                    "python_version": "fake",
+                    "models": [],
+                    "features": ["fake"],
+                }),
+                Route::Continue,
+            ),
+            ("POST", "/shutdown") => {
+                (200, serde_json::json!({ "status": "shutting_down" }), Route::Shutdown)
+            }
+            _ => (404, serde_json::json!({ "detail": "Not Found" }), Route::Continue),
+        }
+    };
+
+    respond(&mut stream, status, &payload)?;
+    Ok(route)
+}
+
+fn respond(stream: &mut TcpStream, status: u16, payload: &serde_json::Value) -> io::Result<()> {
+    let payload = payload.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        if status == 200 { "OK" } else { "Error" },
+        payload.len(),
+        payload
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only serves when command() launched the test binary as the stub
+    #[test]
+    fn serve_stub() {
+        let Some(port) = std::env::var(TEST_PORT_ENV_KEY).ok().and_then(|p| p.parse().ok()) else {
+            return;
+        };
+        let token = std::env::var(crate::AGENT_TOKEN_ENV_KEY).unwrap_or_default();
+        serve(port, &token).unwrap();
+    }
+
+    #[test]
+    fn oversized_bodies_are_refused_unread() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let length = crate::agent_http::MAX_REQUEST_BYTES + 1;
+            write!(stream, "POST /shutdown HTTP/1.1\r\nContent-Length: {}\r\n\r\n", length)
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        assert!(matches!(handle(stream, ""), Ok(Route::Continue)));
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 413 "));
+    }
+}
@@ -0,0 +1,140 @@
+//! Show app files in the system file manager. The frontend names a location
+//! rather than passing a path, and whatever it resolves to must stay inside the
+//! data directory, so this can't be used to open arbitrary paths.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const BACKUPS_DIR: &str = "backups";
+pub const EXPORTS_DIR: &str = "exports";
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PathKind {
+    DataDir,
+    AgentLog,
+    BackupsDir,
+    ExportsDir,
+}
+
+impl PathKind {
+    fn resolve(self, data_dir: &Path) -> PathBuf {
+        match self {
+            PathKind::DataDir => data_dir.to_path_buf(),
+            PathKind::AgentLog => crate::agent_log::path(data_dir),
+            PathKind::BackupsDir => data_dir.join(BACKUPS_DIR),
+            PathKind::ExportsDir => data_dir.join(EXPORTS_DIR),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            PathKind::DataDir => "The data directory",
+            PathKind::AgentLog => "The agent log (the agent hasn't run yet)",
+            PathKind::BackupsDir => "The backups folder (no backups yet)",
+            PathKind::ExportsDir => "The exports folder (nothing exported yet)",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Revealed {
+    pub path: String,
+    /// False when no file manager could be launched; `message` then carries the path
+    pub opened: bool,
+    pub message: String,
+}
+
+/// Open the location `kind` names under `data_dir`, selecting it when it's a file
+pub fn reveal(kind: PathKind, data_dir: &Path) -> Result<Revealed, String> {
+    let target = kind.resolve(data_dir);
+    if !target.exists() {
+        return Err(format!("{} does not exist: {}", kind.describe(), target.display()));
+    }
+    // Canonical forms so a symlink can't point the check somewhere else
+    let root = data_dir.canonicalize().map_err(|e| e.to_string())?;
+    let target = target.canonicalize().map_err(|e| e.to_string())?;
+    if !target.starts_with(&root) {
+        return Err(format!("{} is outside the data directory", target.display()));
+    }
+
+    let path = target.display().to_string();
+    match launch(&target) {
+        Ok(()) => Ok(Revealed { message: format!("Opened {}", path), path, opened: true }),
+        Err(e) if cfg!(target_os = "linux") => Ok(Revealed {
+            message: format!("Could not open a file manager ({}). The location is: {}", e, path),
+            path,
+            opened: false,
+        }),
+        Err(e) => Err(format!("Could not open {}: {}", path, e)),
+    }
+}
+
+/// Open a file with its default application. Callers check the path first;
+/// attachments::resolve does for attachments.
+pub fn open(target: &Path) -> Result<(), String> {
+    open_with_default(target).map_err(|e| format!("Could not open {}: {}", target.display(), e))
+}
+
+#[cfg(target_os = "windows")]
+fn open_with_default(target: &Path) -> Result<(), String> {
+    // Through `start` rather than explorer, which would browse a folder instead
+    Command::new("cmd")
+        .args(["/C", "start", ""])
+        .arg(target)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn open_with_default(target: &Path) -> Result<(), String> {
+    run(Command::new("open").arg(target))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn open_with_default(target: &Path) -> Result<(), String> {
+    run(Command::new("xdg-open").arg(target))
+}
+
+#[cfg(target_os = "windows")]
+fn launch(target: &Path) -> Result<(), String> {
+    let mut cmd = Command::new("explorer");
+    if target.is_file() {
+        cmd.arg(format!("/select,{}", target.display()));
+    } else {
+        cmd.arg(target);
+    }
+    // explorer exits with 1 even when it worked, so only a failed spawn counts
+    cmd.spawn().map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn launch(target: &Path) -> Result<(), String> {
+    let mut cmd = Command::new("open");
+    if target.is_file() {
+        cmd.arg("-R");
+    }
+    run(cmd.arg(target))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn launch(target: &Path) -> Result<(), String> {
+    // xdg-open has no "select": show the folder containing a file
+    let dir = match target.parent() {
+        Some(parent) if target.is_file() => parent,
+        _ => target,
+    };
+    run(Command::new("xdg-open").arg(dir))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn run(cmd: &mut Command) -> Result<(), String> {
+    let status = cmd.status().map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", cmd.get_program().to_string_lossy(), status))
+    }
+}
@@ -0,0 +1,367 @@
+//! Find and replace across a project's chapters, and find in its notes. Plain text, whole-word and
+//! regex searches all compile to one `Regex`; the regex crate runs in linear
+//! time, so a hostile pattern can cost compile size but never backtrack forever.
+
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Compiled program size; keeps pathological counted repetitions from compiling
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const MAX_QUERY_CHARS: usize = 1000;
+/// Spent matching a whole project before giving up
+const TIME_LIMIT: Duration = Duration::from_secs(10);
+const SNIPPETS_PER_CHAPTER: usize = 50;
+const CONTEXT_CHARS: usize = 30;
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    /// `query` is a regular expression and `replacement` may use `$1` / `${name}`
+    pub regex: bool,
+}
+
+pub struct Matcher {
+    regex: Regex,
+    expand: bool,
+    whole_word: bool,
+    deadline: Instant,
+}
+
+impl Matcher {
+    pub fn new(query: &str, options: SearchOptions) -> Result<Self, String> {
+        if query.is_empty() {
+            return Err("Search text cannot be empty".into());
+        }
+        if query.chars().count() > MAX_QUERY_CHARS {
+            return Err(format!("Search text is limited to {} characters", MAX_QUERY_CHARS));
+        }
+        let pattern = if options.regex { query.to_string() } else { regex::escape(query) };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!options.case_sensitive)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| match e {
+                regex::Error::CompiledTooBig(_) => "The pattern is too complex".to_string(),
+                e => format!("Invalid pattern: {}", e),
+            })?;
+        if regex.is_match("") {
+            return Err("The pattern must not match empty text".into());
+        }
+        Ok(Matcher {
+            regex,
+            expand: options.regex,
+            whole_word: options.whole_word,
+            deadline: Instant::now() + TIME_LIMIT,
+        })
+    }
+
+    /// Called between paragraphs so a huge project can't hold the database forever
+    pub fn check_time(&self) -> Result<(), String> {
+        if Instant::now() > self.deadline {
+            Err(format!("Search took longer than {} seconds", TIME_LIMIT.as_secs()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `text` with every match replaced, and how many there were
+    pub fn replace(&self, text: &str, replacement: &str) -> (String, usize) {
+        let mut replaced = String::with_capacity(text.len());
+        let mut last = 0;
+        let mut count = 0;
+        for caps in self.captures(text) {
+            let m = caps.get(0).expect("group 0 is the whole match");
+            replaced.push_str(&text[last..m.start()]);
+            if self.expand {
+                caps.expand(replacement, &mut replaced);
+            } else {
+                replaced.push_str(replacement);
+            }
+            last = m.end();
+            count += 1;
+        }
+        if count == 0 {
+            return (text.to_string(), 0);
+        }
+        replaced.push_str(&text[last..]);
+        (replaced, count)
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        self.captures(text).next().is_some()
+    }
+
+    /// Non-overlapping matches, leftmost first. A whole-word match must not run on
+    /// into a letter or digit, but only where its own edge is Latin or a digit:
+    /// Chinese has no spaces, so 林风 is a whole word in 林风拔剑.
+    fn captures<'t>(&'t self, text: &'t str) -> impl Iterator<Item = Captures<'t>> + 't {
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            while pos <= text.len() {
+                let caps = self.regex.captures_at(text, pos)?;
+                let m = caps.get(0).expect("group 0 is the whole match");
+                let step = m.start() + text[m.start()..].chars().next().map_or(1, char::len_utf8);
+                if !self.whole_word || is_whole_word(text, m.start(), m.end()) {
+                    pos = if m.is_empty() { step } else { m.end() };
+                    return Some(caps);
+                }
+                // Retry from the next character, which may start a whole word
+                pos = step;
+            }
+            None
+        })
+    }
+}
+
+/// Letters and digits of scripts that separate words with spaces
+fn is_latin_or_digit(c: char) -> bool {
+    c.is_numeric()
+        || c == '_'
+        || (c.is_alphabetic()
+            && matches!(c, 'A'..='Z' | 'a'..='z' | '\u{C0}'..='\u{24F}' | '\u{1E00}'..='\u{1EFF}'))
+}
+
+/// Whether `text[start..end]` is bounded on both sides as a word
+fn is_whole_word(text: &str, start: usize, end: usize) -> bool {
+    let matched = &text[start..end];
+    let joins = |edge: Option<char>, neighbour: Option<char>| {
+        edge.is_some_and(is_latin_or_digit) && neighbour.is_some_and(is_latin_or_digit)
+    };
+    !joins(matched.chars().next(), text[..start].chars().next_back())
+        && !joins(matched.chars().next_back(), text[end..].chars().next())
+}
+
+/// One paragraph of a chapter, as stored
+pub struct Paragraph {
+    pub id: String,
+    pub para_index: i64,
+    pub content: String,
+}
+
+pub struct ChapterText {
+    pub chapter_id: String,
+    pub chapter_num: i64,
+    pub title: String,
+    pub paragraphs: Vec<Paragraph>,
+}
+
+#[derive(Serialize)]
+pub struct Snippet {
+    pub para_index: i64,
+    /// Byte offsets of the match within the paragraph
+    pub start: usize,
+    pub end: usize,
+    pub before: String,
+    pub matched: String,
+    pub after: String,
+}
+
+#[derive(Serialize)]
+pub struct ChapterMatches {
+    pub chapter_id: String,
+    pub chapter_num: i64,
+    pub title: String,
+    pub match_count: usize,
+    /// The first few matches; `match_count` counts them all
+    pub snippets: Vec<Snippet>,
+}
+
+pub struct NoteText {
+    pub note_id: String,
+    pub title: String,
+    pub content: String,
+}
+
+#[derive(Serialize)]
+pub struct NoteMatches {
+    pub note_id: String,
+    pub title: String,
+    pub title_matches: bool,
+    /// Matches in the content; snippets' para_index is the line within it
+    pub match_count: usize,
+    pub snippets: Vec<Snippet>,
+}
+
+#[derive(Serialize)]
+pub struct ProjectMatches {
+    pub chapters: Vec<ChapterMatches>,
+    pub notes: Vec<NoteMatches>,
+}
+
+#[derive(Serialize)]
+pub struct ChapterReplacements {
+    pub chapter_id: String,
+    pub chapter_num: i64,
+    pub title: String,
+    pub replacements: usize,
+    /// Chapter length afterwards
+    pub word_count: i64,
+}
+
+/// Chapters with at least one match, in the order given
+pub fn find(matcher: &Matcher, chapters: &[ChapterText]) -> Result<Vec<ChapterMatches>, String> {
+    let mut found = Vec::new();
+    for chapter in chapters {
+        let mut match_count = 0;
+        let mut snippets = Vec::new();
+        for paragraph in &chapter.paragraphs {
+            matcher.check_time()?;
+            let (index, text) = (paragraph.para_index, &paragraph.content);
+            collect(matcher, index, text, &mut match_count, &mut snippets);
+        }
+        if match_count > 0 {
+            found.push(ChapterMatches {
+                chapter_id: chapter.chapter_id.clone(),
+                chapter_num: chapter.chapter_num,
+                title: chapter.title.clone(),
+                match_count,
+                snippets,
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// Notes whose title or content match, in the order given
+pub fn find_in_notes(matcher: &Matcher, notes: &[NoteText]) -> Result<Vec<NoteMatches>, String> {
+    let mut found = Vec::new();
+    for note in notes {
+        matcher.check_time()?;
+        let title_matches = matcher.is_match(&note.title);
+        let mut match_count = 0;
+        let mut snippets = Vec::new();
+        for (line, text) in note.content.split('\n').enumerate() {
+            collect(matcher, line as i64, text, &mut match_count, &mut snippets);
+        }
+        if title_matches || match_count > 0 {
+            found.push(NoteMatches {
+                note_id: note.note_id.clone(),
+                title: note.title.clone(),
+                title_matches,
+                match_count,
+                snippets,
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// Count the matches in one paragraph and keep snippets of the first few
+fn collect(
+    matcher: &Matcher,
+    para_index: i64,
+    text: &str,
+    match_count: &mut usize,
+    snippets: &mut Vec<Snippet>,
+) {
+    for caps in matcher.captures(text) {
+        let m = caps.get(0).expect("group 0 is the whole match");
+        *match_count += 1;
+        if snippets.len() < SNIPPETS_PER_CHAPTER {
+            snippets.push(Snippet {
+                para_index,
+                start: m.start(),
+                end: m.end(),
+                before: tail(&text[..m.start()], CONTEXT_CHARS).to_string(),
+                matched: m.as_str().to_string(),
+                after: head(&text[m.end()..], CONTEXT_CHARS).to_string(),
+            });
+        }
+    }
+}
+
+fn head(text: &str, chars: usize) -> &str {
+    match text.char_indices().nth(chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+fn tail(text: &str, chars: usize) -> &str {
+    let start = text.char_indices().rev().take(chars).last().map_or(text.len(), |(i, _)| i);
+    &text[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(paragraphs: &[&str]) -> ChapterText {
+        ChapterText {
+            chapter_id: "c1".into(),
+            chapter_num: 1,
+            title: "第一章".into(),
+            paragraphs: paragraphs
+                .iter()
+                .enumerate()
+                .map(|(i, p)| Paragraph {
+                    id: format!("p{}", i),
+                    para_index: i as i64,
+                    content: p.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn options_control_matching() {
+        let plain = SearchOptions::default();
+        let text = "Cat, cat and concat. 猫";
+        assert_eq!(Matcher::new("cat", plain).unwrap().replace(text, "x").1, 3);
+        let case = SearchOptions { case_sensitive: true, ..plain };
+        assert_eq!(Matcher::new("cat", case).unwrap().replace(text, "x").1, 2);
+        let word = SearchOptions { whole_word: true, ..plain };
+        assert_eq!(Matcher::new("cat", word).unwrap().replace(text, "x").1, 2);
+        // Han text has no spaces to bound a word with
+        let name = Matcher::new("林风", word).unwrap();
+        let (renamed, count) = name.replace("林风拔剑，看向林风。", "林峰");
+        assert_eq!((renamed.as_str(), count), ("林峰拔剑，看向林峰。", 2));
+        let cat = Matcher::new("cat", word).unwrap();
+        assert_eq!(cat.replace("用cat命令", "ls").0, "用ls命令");
+        // Skipping a match inside a longer word still finds the next one
+        assert_eq!(Matcher::new("ab", word).unwrap().replace("aab ab", "x").0, "aab x");
+        // Literal mode takes $ and . as written
+        let literal = Matcher::new("a.", plain).unwrap();
+        assert_eq!(literal.replace("a. ab", "$0").0, "$0 ab");
+        let regex = SearchOptions { regex: true, ..plain };
+        let swap = Matcher::new(r"(\w+)@(\w+)", regex).unwrap();
+        assert_eq!(swap.replace("a@b", "$2@$1").0, "b@a");
+        assert!(Matcher::new("a*", regex).is_err());
+        assert!(Matcher::new("(", regex).is_err());
+        assert!(Matcher::new(r"\w{1000}{1000}", regex).is_err());
+    }
+
+    #[test]
+    fn find_reports_byte_offsets_and_context() {
+        let matcher = Matcher::new("长夜", SearchOptions::default()).unwrap();
+        let found = find(&matcher, &[chapter(&["无关", "漫漫长夜将尽"])]).unwrap();
+        assert_eq!(found.len(), 1);
+        let snippet = &found[0].snippets[0];
+        assert_eq!((snippet.para_index, snippet.start, snippet.end), (1, 6, 12));
+        assert_eq!((snippet.before.as_str(), snippet.after.as_str()), ("漫漫", "将尽"));
+        assert!(find(&matcher, &[chapter(&["白昼"])]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn notes_match_by_title_or_by_line() {
+        let matcher = Matcher::new("伏笔", SearchOptions::default()).unwrap();
+        let note = |id: &str, title: &str, content: &str| NoteText {
+            note_id: id.into(),
+            title: title.into(),
+            content: content.into(),
+        };
+        let notes = [
+            note("n1", "伏笔清单", "第三章的信"),
+            note("n2", "杂记", "灯塔\n埋下伏笔"),
+            note("n3", "杂记", "无关"),
+        ];
+        let found = find_in_notes(&matcher, &notes).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found[0].title_matches && found[0].match_count == 0);
+        assert_eq!((found[1].match_count, found[1].snippets[0].para_index), (1, 1));
+    }
+}
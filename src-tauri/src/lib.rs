@@ -1,24 +1,251 @@
+mod agent_env;
+mod agent_http;
+mod agent_log;
+mod agent_manager;
+mod agent_selftest;
+mod app_log;
+mod attachments;
+mod auto_backup;
+mod backup;
+mod chapter_import;
+mod chapter_status;
+mod character_check;
+mod covers;
+mod credentials;
+mod data_location;
 mod db;
+mod db_health;
+mod diagnostics;
+mod embedding_index;
+mod events;
+mod fake_agent;
+mod file_manager;
+mod find_replace;
+mod markdown;
+mod notify;
+mod onboarding;
+mod project_export;
+mod project_templates;
+mod reader;
+mod relationship_graph;
+mod settings;
+mod single_instance;
+mod snapshot;
+mod text_count;
+mod text_diff;
+mod tokenize;
+mod trash;
+mod tray;
+mod word_frequency;
+mod writing_goal;
 
+use agent_manager::{AgentManager, Lifecycle, Started};
 use db::Database;
 use serde::{Deserialize, Serialize};
 #[cfg(not(target_os = "windows"))]
 use std::fs::OpenOptions;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::process::{Child, Command};
-use std::sync::Mutex;
-use std::time::Duration;
-use tauri::{Manager, State};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{Emitter, Manager, State};
+use tracing::{error, info, warn};
 
-const AGENT_PORT: u16 = 8765;
+/// Oldest agent (agent/main.py AGENT_VERSION) whose endpoints this build relies on
+const MIN_AGENT_VERSION: &str = "0.1.0";
+const AGENT_READY_TIMEOUT: Duration = Duration::from_secs(60);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// This many watchdog restarts within CRASH_LOOP_WINDOW means the agent can't stay up
+const CRASH_LOOP_RESTARTS: usize = 3;
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(60);
+
+/// `{ port, token, pid, agent_dir }` of the live shared agent; see AgentRuntime
+const AGENT_RUNTIME_FILE: &str = "agent-runtime.json";
+/// Followed by `<project id>.json`, the same for a project's agent
+const PROJECT_RUNTIME_FILE_PREFIX: &str = "agent-runtime-";
+const SPAWN_ATTEMPTS: u32 = 3;
+const SPAWN_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// How long force_reset_agent waits for the old agent to let go of the port
+const PORT_RELEASE_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_PROXY_TIMEOUT_SECS: u64 = 120;
+const DEPENDENCY_INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Longer notes belong in a chapter or the world entries
+const MAX_NOTE_CHARS: usize = 100_000;
+const MAX_NOTE_TITLE_CHARS: usize = 200;
+
+// Shared with the agent's local API auth (see agent/main.py)
+const AGENT_TOKEN_ENV_KEY: &str = "SANHUOAI_AGENT_TOKEN";
 
 pub struct AppState {
     pub db: Database,
-    pub agent_process: Mutex<Option<Child>>,
-    pub data_dir: String,
+    /// Every agent by project id. The shared one, on the agent_port setting and
+    /// the one the webview talks to, is under SHARED_AGENT and always present.
+    pub agents: Mutex<HashMap<String, AgentHandle>>,
+    /// Only changes when set_data_dir moves everything elsewhere
+    pub data_dir: Mutex<String>,
+    pub watchdog: WatchdogConfig,
+    /// Kept across calls so per-process CPU usage has a previous sample to diff against
+    pub sysinfo: Mutex<sysinfo::System>,
+    /// Held while install_agent_dependencies runs pip
+    pub installing_dependencies: AtomicBool,
+    /// In-flight agent_stream_request calls by request id
+    pub streams: Mutex<HashMap<String, ActiveStream>>,
+    /// The close_to_tray setting, read when the main window is closed
+    pub close_to_tray: AtomicBool,
+    /// The notifications_enabled setting
+    pub notifications_enabled: AtomicBool,
+    /// Recent analyze_word_frequency results by content hash
+    pub word_frequency: word_frequency::ReportCache,
+    /// Recent chapter_html results by content hash
+    pub chapter_html: reader::HtmlCache,
+    /// Held shared by exports, restores and data moves, and exclusively by a
+    /// database backup, so a backup never runs in the middle of one
+    pub maintenance: RwLock<()>,
+    /// Set when the data directory was unusable at launch and a temporary one is in use
+    pub data_dir_fallback: Option<events::DataDirFallback>,
+    /// Key for credentials stored under the passphrase, once unlocked this session
+    pub credentials: credentials::Session,
 }
 
-#[derive(Serialize, Deserialize)]
+impl AppState {
+    pub fn data_dir(&self) -> String {
+        self.data_dir.lock().unwrap().clone()
+    }
+
+    /// The shared agent's port
+    pub fn agent_port(&self) -> u16 {
+        self.shared_agent().port()
+    }
+
+    pub fn shared_agent(&self) -> AgentHandle {
+        agent_manager::lock(&self.agents)[SHARED_AGENT].clone()
+    }
+
+    /// The project's own agent, if one was ever started this session
+    pub fn project_agent(&self, project_id: &str) -> Option<AgentHandle> {
+        agent_manager::lock(&self.agents).get(project_id).cloned()
+    }
+
+    /// Every agent, the shared one first
+    pub fn all_agents(&self) -> Vec<AgentHandle> {
+        let agents = agent_manager::lock(&self.agents);
+        let mut all: Vec<_> = agents.values().cloned().collect();
+        all.sort_by_key(|agent| agent.project_id.clone());
+        all
+    }
+}
+
+/// Map key of the shared agent; project ids are never empty
+pub const SHARED_AGENT: &str = "";
+
+/// Cloned out of AppState.agents, so no transition holds the map's lock
+pub type AgentHandle = Arc<Agent>;
+
+/// One agent process with the port, token and bookkeeping that go with it
+pub struct Agent {
+    /// None for the shared agent
+    pub project_id: Option<String>,
+    /// The child process and the lock every spawn and kill goes through
+    pub manager: AgentManager,
+    /// The shared agent's only changes through set_agent_port; a project's is
+    /// picked when its agent is first started
+    pub port: AtomicU16,
+    /// Secret handed to the agent on every (re)start; every request must carry it
+    pub token: Mutex<String>,
+    /// An agent we didn't spawn (e.g. left over from a previous session) is serving the port
+    pub external: AtomicBool,
+    /// Set by an explicit stop_agent so the agent stays stopped until start_agent
+    pub suspended: AtomicBool,
+    /// When the current agent process was spawned
+    pub started_at: Mutex<Option<SystemTime>>,
+    /// Interpreter and agent directory the current agent process was spawned with
+    pub paths: Mutex<Option<AgentPaths>>,
+    /// Crash restarts performed by the watchdog this session, and force resets
+    pub restart_count: AtomicU32,
+    /// When those restarts happened, for crash-loop detection
+    pub crash_times: Mutex<VecDeque<Instant>>,
+}
+
+impl Agent {
+    fn new(project_id: Option<String>, port: u16) -> Self {
+        Agent {
+            project_id,
+            manager: AgentManager::new(),
+            port: AtomicU16::new(port),
+            token: Mutex::new(generate_agent_token()),
+            external: AtomicBool::new(false),
+            suspended: AtomicBool::new(false),
+            started_at: Mutex::new(None),
+            paths: Mutex::new(None),
+            restart_count: AtomicU32::new(0),
+            crash_times: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.load(Ordering::SeqCst)
+    }
+
+    fn is_running(&self) -> bool {
+        self.manager.is_running() || self.external.load(Ordering::SeqCst)
+    }
+
+    /// For messages: "Agent" or "Agent for project <id>"
+    fn name(&self) -> String {
+        match &self.project_id {
+            Some(id) => format!("Agent for project {}", id),
+            None => "Agent".into(),
+        }
+    }
+}
+
+pub struct ActiveStream {
+    /// Label of the webview listening for the events
+    window: String,
+    /// Project whose agent serves it; None for the shared agent
+    agent: Option<String>,
+    /// Second handle on the connection; None until it's open
+    socket: Option<std::net::TcpStream>,
+}
+
+/// Runtime switches read by the watchdog thread on every iteration
+pub struct WatchdogConfig {
+    /// User preference, persisted in app_settings
+    pub enabled: AtomicBool,
+    pub interval_secs: AtomicU64,
+    /// The external_agent_mode setting: attach to an agent, never spawn one
+    pub external_agent_mode: AtomicBool,
+}
+
+impl WatchdogConfig {
+    fn new(settings: &settings::AppSettings) -> Self {
+        Self {
+            enabled: AtomicBool::new(settings.watchdog_enabled),
+            interval_secs: AtomicU64::new(settings.watchdog_interval_secs.max(1)),
+            external_agent_mode: AtomicBool::new(settings.external_agent_mode),
+        }
+    }
+
+    fn apply(&self, settings: &settings::AppSettings) {
+        self.enabled.store(settings.watchdog_enabled, Ordering::SeqCst);
+        self.interval_secs
+            .store(settings.watchdog_interval_secs.max(1), Ordering::SeqCst);
+        self.external_agent_mode.store(settings.external_agent_mode, Ordering::SeqCst);
+    }
+
+    fn should_restart(&self, agent: &Agent) -> bool {
+        self.enabled.load(Ordering::SeqCst) && !agent.suspended.load(Ordering::SeqCst)
+    }
+}
+
+fn is_truthy(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Project {
     pub id: String,
     pub name: String,
@@ -30,90 +257,3667 @@ pub struct Project {
     pub temperature: f64,
     pub embedding_dim: i32,
     pub word_target: i32,
+    /// SQLite datetime, UTC; absent from backups made before these were exported
+    #[serde(default)]
+    pub created_at: String,
+    #[serde(default)]
+    pub updated_at: String,
+    /// Nucleus sampling, 0–1; 1 leaves it to the provider
+    #[serde(default = "default_top_p")]
+    pub top_p: f64,
+    /// Upper bound on a single generation's output
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: i64,
+    /// Project-wide instructions for every agent; `{prompt}` stands for the
+    /// agent's own system prompt, otherwise the template is appended to it
+    #[serde(default)]
+    pub system_prompt_template: String,
+    /// Listed first on the home screen, in manual_order
+    #[serde(default)]
+    pub pinned: bool,
+    /// Position among the pinned projects, from 0; None when not pinned
+    #[serde(default)]
+    pub manual_order: Option<i64>,
+}
+
+fn default_top_p() -> f64 {
+    1.0
+}
+
+fn default_max_tokens() -> i64 {
+    4096
+}
+
+impl Project {
+    pub fn check_generation_settings(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.top_p) {
+            return Err(format!("top_p must be between 0 and 1, got {}", self.top_p));
+        }
+        if self.max_tokens <= 0 {
+            return Err(format!("max_tokens must be positive, got {}", self.max_tokens));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct ProjectPage {
+    pub projects: Vec<Project>,
+    /// Every project outside the trash that matches the filters, regardless of
+    /// limit/offset
+    pub total: i64,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectSort {
+    Name,
+    #[default]
+    UpdatedAt,
+    CreatedAt,
+    /// Words written as a share of word_target; projects without a target last
+    WordProgress,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDir {
+    Asc,
+    Desc,
+}
+
+/// What list_projects returns; every field is optional. Pinned projects always
+/// come first, in their manual order; the sort applies to the rest.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ProjectQuery {
+    pub offset: Option<u32>,
+    pub limit: Option<u32>,
+    pub sort_by: ProjectSort,
+    /// Ascending for names, otherwise descending, when not given
+    pub sort_dir: Option<SortDir>,
+    pub status: Option<String>,
+    pub genre: Option<String>,
+    /// Case-insensitive for ASCII; wildcards match literally
+    pub name_contains: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RecentProject {
+    #[serde(flatten)]
+    pub project: Project,
+    /// SQLite datetime, UTC; None for a project never opened
+    pub last_opened_at: Option<String>,
+    /// The chapter open when the project was last touched, if it still exists
+    pub last_chapter_id: Option<String>,
+}
+
+/// What the frontend should reopen at launch
+#[derive(Serialize)]
+pub struct StartupState {
+    /// The project open when the app was last used
+    pub last_project_id: Option<String>,
+    /// False when that project has been deleted since
+    pub project_exists: bool,
+    pub project: Option<RecentProject>,
+}
+
+#[derive(Serialize)]
+pub struct DbSize {
+    /// sanhuoai.db on disk
+    pub file_bytes: u64,
+    /// Write-ahead log not yet checkpointed into the main file
+    pub wal_bytes: u64,
+    pub page_count: u64,
+    pub page_size: u64,
+    /// page_count * page_size
+    pub allocated_bytes: u64,
+    /// Unused pages that vacuum_database would give back
+    pub free_bytes: u64,
+}
+
+#[derive(Serialize, Default)]
+pub struct Character {
+    pub id: String,
+    pub project_id: String,
+    pub name: String,
+    /// The character's role in the story (主角 / 配角 / ...)
+    pub category: String,
+    pub gender: String,
+    pub age: String,
+    pub identity: String,
+    pub appearance: String,
+    pub personality: String,
+    pub motivation: String,
+    pub backstory: String,
+    pub arc: String,
+    pub usage_notes: String,
+    pub status: String,
+    pub sort_order: i64,
+    pub created_at: String,
+    pub updated_at: String,
+    /// Other names the text uses for the character (nicknames, titles)
+    pub aliases: Vec<String>,
+}
+
+/// Partial update: only fields that are present get written
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct CharacterUpdate {
+    pub name: Option<String>,
+    pub category: Option<String>,
+    pub gender: Option<String>,
+    pub age: Option<String>,
+    pub identity: Option<String>,
+    pub appearance: Option<String>,
+    pub personality: Option<String>,
+    pub motivation: Option<String>,
+    pub backstory: Option<String>,
+    pub arc: Option<String>,
+    pub usage_notes: Option<String>,
+    pub status: Option<String>,
+    pub sort_order: Option<i64>,
+    pub aliases: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct Prompt {
+    pub id: String,
+    pub name: String,
+    pub category: String,
+    pub content: String,
+    /// Seeded by the agent's migrations; can be reset but not deleted
+    pub is_builtin: bool,
+    /// A built-in whose content no longer matches what shipped
+    pub customized: bool,
+    /// None for global prompts
+    pub project_id: Option<String>,
+    pub updated_at: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PromptUpdate {
+    pub name: Option<String>,
+    pub category: Option<String>,
+    pub content: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Scene {
+    pub id: String,
+    pub chapter_id: String,
+    pub summary: String,
+    pub order_index: i64,
+    pub pov_character_id: Option<String>,
+    pub created_at: String,
+}
+
+/// A stored version of a chapter's text, listed without the text itself
+#[derive(Serialize)]
+pub struct Revision {
+    pub id: i64,
+    pub chapter_id: String,
+    pub word_count: i64,
+    /// What recorded it: "save" keeps the text as saved, "restore" the text a
+    /// revision was rolled back to, "replace" the text from before a project-wide
+    /// replace
+    pub reason: String,
+    pub created_at: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SceneUpdate {
+    pub summary: Option<String>,
+    /// An empty string clears the POV character
+    pub pov_character_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Note {
+    pub id: String,
+    pub project_id: String,
+    pub title: String,
+    pub content: String,
+    pub pinned: bool,
+    /// A CSS color for the note card; empty for the default
+    pub color: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct NoteUpdate {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    /// Doesn't count as an edit, so updated_at stays
+    pub pinned: Option<bool>,
+    pub color: Option<String>,
+}
+
+/// An event on the project's timeline. story_date is free text sorted as text,
+/// so "0003-04" style dates sort in story order; narrative_order numbers the
+/// events 1..n in the order the book tells them.
+#[derive(Serialize)]
+pub struct StoryEvent {
+    pub id: String,
+    pub project_id: String,
+    pub title: String,
+    pub description: String,
+    pub story_date: String,
+    pub narrative_order: i64,
+    pub chapter_id: Option<String>,
+    pub character_ids: Vec<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct StoryEventUpdate {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub story_date: Option<String>,
+    /// An empty string unlinks the chapter
+    pub chapter_id: Option<String>,
+    pub character_ids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StoryEventSort {
+    /// By story_date, undated events last
+    Story,
+    #[default]
+    Narrative,
+}
+
+#[derive(Serialize)]
+pub struct ModelPreset {
+    pub id: String,
+    pub provider: String,
+    pub model_id: String,
+    pub display_name: String,
+    /// Tokens; 0 when unknown
+    pub context_window: i64,
+    pub default_temperature: f64,
+    pub supports_streaming: bool,
+}
+
+/// Fields for create_model_preset (all but provider and model_id optional) and
+/// update_model_preset (only what is set changes)
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ModelPresetUpdate {
+    pub provider: Option<String>,
+    pub model_id: Option<String>,
+    pub display_name: Option<String>,
+    pub context_window: Option<i64>,
+    pub default_temperature: Option<f64>,
+    pub supports_streaming: Option<bool>,
+}
+
+impl ModelPresetUpdate {
+    fn check(&self) -> Result<(), String> {
+        for (field, value) in [("provider", &self.provider), ("model_id", &self.model_id)] {
+            if value.as_deref().is_some_and(|v| v.trim().is_empty()) {
+                return Err(format!("{} cannot be empty", field));
+            }
+        }
+        if let Some(t) = self.default_temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+            return Err(format!("default_temperature must be between 0 and 2, got {}", t));
+        }
+        if let Some(n) = self.context_window.filter(|n| *n < 0) {
+            return Err(format!("context_window cannot be negative, got {}", n));
+        }
+        Ok(())
+    }
+}
+
+/// Which of a project's two model settings a preset fills
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelSlot {
+    Main,
+    Secondary,
+}
+
+impl ModelSlot {
+    fn column(self) -> &'static str {
+        match self {
+            ModelSlot::Main => "model_main",
+            ModelSlot::Secondary => "model_secondary",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PresetDeleted {
+    /// Projects still using the preset's model id; they keep their copied values
+    pub referencing_projects: i64,
+}
+
+#[derive(Serialize)]
+pub struct ChapterLength {
+    pub chapter_id: String,
+    pub title: String,
+    pub word_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct ProjectStats {
+    pub chapter_count: i64,
+    pub total_words: i64,
+    pub avg_words: f64,
+    pub longest: Option<ChapterLength>,
+    pub shortest: Option<ChapterLength>,
+    pub by_status: chapter_status::StatusCounts,
+    /// Most recent chapter or project update (SQLite datetime, UTC)
+    pub last_updated: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TargetStatus {
+    pub current: i64,
+    /// 0 when the project has no word goal
+    pub target: i64,
+    /// None when there is no goal to meet
+    pub met: Option<bool>,
+    /// Words still to write; 0 once the goal is passed
+    pub remaining: i64,
+}
+
+impl TargetStatus {
+    fn new(current: i64, target: i64) -> Self {
+        TargetStatus {
+            current,
+            target,
+            met: (target > 0).then_some(current >= target),
+            remaining: (target - current).max(0),
+        }
+    }
+}
+
+// ---- Project Commands ----
+
+/// Newest first by default; without a query, or limit and offset, every project.
+/// `limit` and `offset` outside the query are still accepted.
+#[tauri::command]
+fn list_projects(
+    state: State<AppState>,
+    query: Option<ProjectQuery>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<ProjectPage, String> {
+    let mut query = query.unwrap_or_default();
+    query.limit = query.limit.or(limit);
+    query.offset = query.offset.or(offset);
+    state.db.list_projects(&query).map_err(|e| e.to_string())
+}
+
+/// Pin a project to the top of the list, after those already pinned, or unpin it
+#[tauri::command]
+fn set_project_pinned(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    id: String,
+    pinned: bool,
+) -> Result<Project, String> {
+    let project = state.db.set_project_pinned(&id, pinned).map_err(|e| e.to_string())?;
+    Ok(project_changed(&app, events::ProjectChange::Updated, project))
+}
+
+/// `ordered_ids` lists every pinned project, in the order to show them
+#[tauri::command]
+fn reorder_pinned_projects(
+    state: State<AppState>,
+    ordered_ids: Vec<String>,
+) -> Result<Vec<Project>, String> {
+    state.db.reorder_pinned_projects(&ordered_ids)
+}
+
+/// Projects whose name or genre contains `query` (case-insensitive), newest first
+#[tauri::command]
+fn search_projects(state: State<AppState>, query: String) -> Result<Vec<Project>, String> {
+    state.db.search_projects(query.trim()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_project(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    name: String,
+    genre: String,
+) -> Result<Project, String> {
+    let project = state.db.create_project(&name, &genre).map_err(|e| e.to_string())?;
+    Ok(project_created(&app, project))
+}
+
+#[tauri::command]
+fn list_project_templates(
+    state: State<AppState>,
+) -> Result<Vec<project_templates::ProjectTemplate>, String> {
+    state.db.list_project_templates().map_err(|e| e.to_string())
+}
+
+/// A new project seeded with the template's outline, characters, world entries
+/// and prompt overrides
+#[tauri::command]
+fn create_project_from_template(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    name: String,
+    template_id: String,
+) -> Result<Project, String> {
+    let project = state.db.create_project_from_template(&name, &template_id)?;
+    Ok(project_created(&app, project))
+}
+
+/// Save a project's structure, not its chapters, as a custom template
+#[tauri::command]
+fn save_project_as_template(
+    state: State<AppState>,
+    project_id: String,
+    template_name: String,
+) -> Result<project_templates::ProjectTemplate, String> {
+    let template_name = template_name.trim();
+    if template_name.is_empty() {
+        return Err("Template name cannot be empty".to_string());
+    }
+    state.db.save_project_as_template(&project_id, template_name)
+}
+
+#[tauri::command]
+fn get_onboarding_state(state: State<AppState>) -> onboarding::Onboarding {
+    settings::load(&state.db).onboarding
+}
+
+#[tauri::command]
+fn set_onboarding_step(
+    state: State<AppState>,
+    step: onboarding::Step,
+    done: bool,
+) -> Result<onboarding::Onboarding, String> {
+    onboarding::update(&state.db, step, done)
+}
+
+/// Seed the demo project. Once it has been created, even if since deleted, this
+/// returns None unless `force` is set.
+#[tauri::command]
+fn create_sample_project(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    force: Option<bool>,
+) -> Result<Option<Project>, String> {
+    if settings::load(&state.db).onboarding.sample_created && !force.unwrap_or(false) {
+        return Ok(None);
+    }
+    let project = state.db.create_sample_project(&onboarding::sample())?;
+    onboarding::update(&state.db, onboarding::Step::SampleCreated, true)?;
+    Ok(Some(project_created(&app, project)))
+}
+
+/// Tell every window about a new project so their project lists can refresh
+fn project_created(app: &tauri::AppHandle, project: Project) -> Project {
+    project_changed(app, events::ProjectChange::Created, project)
+}
+
+fn project_changed(
+    app: &tauri::AppHandle,
+    action: events::ProjectChange,
+    project: Project,
+) -> Project {
+    let _ = app.emit(
+        events::PROJECT_CHANGED,
+        events::ProjectChanged { action, project_id: &project.id, project: Some(&project) },
+    );
+    project
+}
+
+/// For commands that don't return the project they changed
+fn project_updated(app: &tauri::AppHandle, state: &AppState, project_id: &str) {
+    match state.db.get_project(project_id) {
+        Ok(project) => {
+            project_changed(app, events::ProjectChange::Updated, project);
+        }
+        Err(e) => warn!(project_id, error = %e, "could not reload a changed project"),
+    }
+}
+
+fn project_deleted(app: &tauri::AppHandle, project_id: &str) {
+    let _ = app.emit(
+        events::PROJECT_CHANGED,
+        events::ProjectChanged {
+            action: events::ProjectChange::Deleted,
+            project_id,
+            project: None,
+        },
+    );
+}
+
+/// Create a project from a Markdown manuscript: H1 is the project name, each H2 a chapter
+#[tauri::command]
+fn import_project_markdown(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    file_path: String,
+    genre: String,
+) -> Result<Project, String> {
+    let path = PathBuf::from(&file_path);
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut manuscript = markdown::parse(&text);
+
+    let name = manuscript
+        .title
+        .take()
+        .or_else(|| path.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "Imported project".into());
+
+    let (description, chapters) = if manuscript.chapters.is_empty() {
+        let single = markdown::ManuscriptChapter {
+            title: "第1章".into(),
+            body: manuscript.preface,
+        };
+        (String::new(), vec![single])
+    } else {
+        (manuscript.preface, manuscript.chapters)
+    };
+
+    let project = state
+        .db
+        .import_project(&name, &genre, &description, &chapters)
+        .map_err(|e| e.to_string())?;
+    Ok(project_created(&app, project))
+}
+
+/// Append chapters read from text or Markdown files to a project, in the order
+/// given, splitting each file on headings unless `options.split` is false. All
+/// files go in one transaction, so a failure part way imports nothing.
+#[tauri::command]
+async fn import_chapters_from_files(
+    app: tauri::AppHandle,
+    project_id: String,
+    paths: Vec<String>,
+    options: Option<chapter_import::ImportOptions>,
+) -> Result<Vec<chapter_import::FileImport>, String> {
+    if paths.is_empty() {
+        return Err("No files to import".into());
+    }
+    let heading = options.unwrap_or_default().heading()?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = &app.state::<AppState>().db;
+        db.import_chapters(&project_id, |writer| {
+            paths
+                .iter()
+                .map(|path| {
+                    chapter_import::import_file(
+                        std::path::Path::new(path),
+                        heading.as_ref(),
+                        |title, body| writer.add(title, body).map_err(|e| e.to_string()),
+                    )
+                })
+                .collect()
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn export_project_json(
+    state: State<AppState>,
+    project_id: String,
+    dest_path: String,
+) -> Result<(), String> {
+    let _maintenance = state.maintenance.read().unwrap();
+    let project = state.db.get_project(&project_id).map_err(|e| e.to_string())?;
+    let chapters = state.db.export_chapters(&project_id).map_err(|e| e.to_string())?;
+    let notes = state.db.export_notes(&project_id).map_err(|e| e.to_string())?;
+    let events = state.db.export_story_events(&project_id).map_err(|e| e.to_string())?;
+    let json = backup::to_json(project, chapters, notes, events).map_err(|e| e.to_string())?;
+    std::fs::write(&dest_path, json).map_err(|e| format!("Failed to write {}: {}", dest_path, e))
+}
+
+/// Every project into `dest_dir`, one markdown, text or json file each. A project
+/// that fails is listed in the summary and the rest are still written.
+#[tauri::command]
+async fn export_all_projects(
+    app: tauri::AppHandle,
+    dest_dir: String,
+    format: String,
+) -> Result<project_export::ExportSummary, String> {
+    let format = project_export::ExportFormat::parse(&format)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let _maintenance = state.maintenance.read().unwrap();
+        let dir = PathBuf::from(dest_dir.trim());
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+        let projects = state
+            .db
+            .list_projects(&ProjectQuery::default())
+            .map_err(|e| e.to_string())?
+            .projects;
+        let mut names = project_export::FileNames::default();
+        let mut summary = project_export::ExportSummary::default();
+        for project in projects {
+            let (id, name) = (project.id.clone(), project.name.clone());
+            let result = (|| -> Result<PathBuf, String> {
+                let chapters = state.db.export_chapters(&id).map_err(|e| e.to_string())?;
+                let notes = state.db.export_notes(&id).map_err(|e| e.to_string())?;
+                let events = state.db.export_story_events(&id).map_err(|e| e.to_string())?;
+                let backup = backup::new(project, chapters, notes, events);
+                project_export::write(&dir, format, &backup, &mut names)
+            })();
+            if let Err(e) = &result {
+                warn!(project_id = %id, error = %e, "project export failed");
+            }
+            summary.record(&id, &name, result);
+        }
+        Ok(summary)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn import_project_json(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    file_path: String,
+) -> Result<Project, String> {
+    let _maintenance = state.maintenance.read().unwrap();
+    let text = std::fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read {}: {}", file_path, e))?;
+    let backup = backup::from_json(&text)?;
+    backup.project.check_generation_settings()?;
+    let project = state.db.import_backup(&backup).map_err(|e| e.to_string())?;
+    Ok(project_created(&app, project))
+}
+
+/// Zip the project's JSON export, cover and files under data_dir/projects into one
+/// archive at `dest_path`, emitting `snapshot://progress` as file contents are copied
+#[tauri::command]
+async fn snapshot_project(
+    app: tauri::AppHandle,
+    project_id: String,
+    dest_path: String,
+) -> Result<snapshot::SnapshotInfo, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let _maintenance = state.maintenance.read().unwrap();
+        let project = state.db.get_project(&project_id).map_err(|e| e.to_string())?;
+        let cover = state.db.project_cover_path(&project_id).map_err(|e| e.to_string())?;
+        let chapters = state.db.export_chapters(&project_id).map_err(|e| e.to_string())?;
+        let notes = state.db.export_notes(&project_id).map_err(|e| e.to_string())?;
+        let events = state.db.export_story_events(&project_id).map_err(|e| e.to_string())?;
+        let backup = backup::new(project, chapters, notes, events);
+        let app_version = app.package_info().version.to_string();
+        let data_dir = PathBuf::from(state.data_dir());
+        let dest = std::path::Path::new(dest_path.trim());
+        snapshot::write(dest, &app_version, &data_dir, &backup, cover.as_deref(), |progress| {
+            let progress =
+                events::SnapshotProgress { operation: snapshot::Operation::Snapshot, progress };
+            let _ = app.emit(events::SNAPSHOT_PROGRESS, progress);
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Recreate a snapshot as a new project with new ids. The snapshot is checked in
+/// full before anything is written, and a failure part way leaves nothing behind.
+#[tauri::command]
+async fn restore_snapshot(app: tauri::AppHandle, path: String) -> Result<Project, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let _maintenance = state.maintenance.read().unwrap();
+        let mut snapshot = snapshot::open(std::path::Path::new(path.trim()))?;
+        snapshot.backup.project.check_generation_settings()?;
+        let data_dir = PathBuf::from(state.data_dir());
+        let staged = snapshot.extract(&data_dir, |progress| {
+            let progress =
+                events::SnapshotProgress { operation: snapshot::Operation::Restore, progress };
+            let _ = app.emit(events::SNAPSHOT_PROGRESS, progress);
+        })?;
+        let project = state
+            .db
+            .restore_backup(&snapshot.backup, |project_id| staged.place(&data_dir, project_id))?;
+        Ok(project_created(&app, project))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn project_stats(state: State<AppState>, project_id: String) -> Result<ProjectStats, String> {
+    state.db.project_stats(&project_id).map_err(|e| e.to_string())
+}
+
+/// Matches in every chapter of the project, grouped by chapter in reading order
+#[tauri::command]
+async fn find_in_project(
+    app: tauri::AppHandle,
+    project_id: String,
+    query: String,
+    options: Option<find_replace::SearchOptions>,
+) -> Result<Vec<find_replace::ChapterMatches>, String> {
+    let matcher = find_replace::Matcher::new(&query, options.unwrap_or_default())?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = &app.state::<AppState>().db;
+        let chapters = db.project_chapter_texts(&project_id).map_err(|e| e.to_string())?;
+        find_replace::find(&matcher, &chapters)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// find_in_project plus the project's notes, as separate sections
+#[tauri::command]
+async fn search_project(
+    app: tauri::AppHandle,
+    project_id: String,
+    query: String,
+    options: Option<find_replace::SearchOptions>,
+) -> Result<find_replace::ProjectMatches, String> {
+    let matcher = find_replace::Matcher::new(&query, options.unwrap_or_default())?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = &app.state::<AppState>().db;
+        let chapters = db.project_chapter_texts(&project_id).map_err(|e| e.to_string())?;
+        let notes = db.project_note_texts(&project_id).map_err(|e| e.to_string())?;
+        Ok(find_replace::ProjectMatches {
+            chapters: find_replace::find(&matcher, &chapters)?,
+            notes: find_replace::find_in_notes(&matcher, &notes)?,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Replace every match in the project at once; all or nothing. Returns the
+/// chapters that changed, each of which also gets a revision of its old text.
+#[tauri::command]
+async fn replace_in_project(
+    app: tauri::AppHandle,
+    project_id: String,
+    query: String,
+    replacement: String,
+    options: Option<find_replace::SearchOptions>,
+) -> Result<Vec<find_replace::ChapterReplacements>, String> {
+    let matcher = find_replace::Matcher::new(&query, options.unwrap_or_default())?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        state.db.replace_in_project(&project_id, &matcher, &replacement)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Most used words and repeated phrases in the project. Runs off the main thread,
+/// emitting `analysis://word-frequency` per chapter; a repeat request on unchanged
+/// text is answered from the cache.
+#[tauri::command]
+async fn analyze_word_frequency(
+    app: tauri::AppHandle,
+    project_id: String,
+    options: Option<word_frequency::FrequencyOptions>,
+) -> Result<word_frequency::FrequencyReport, String> {
+    let options = options.unwrap_or_default();
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let chapters = state.db.project_chapter_texts(&project_id).map_err(|e| e.to_string())?;
+        let hash = word_frequency::content_hash(&chapters, &options);
+        if let Some(report) = state.word_frequency.get(hash) {
+            return Ok(report);
+        }
+        let report = word_frequency::analyze(&chapters, &options, |stage, done, total| {
+            let progress =
+                events::WordFrequencyProgress { project_id: &project_id, stage, done, total };
+            let _ = app.emit(events::WORD_FREQUENCY_PROGRESS, progress);
+        });
+        state.word_frequency.insert(hash, report.clone());
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Record that the project (and optionally one of its chapters) was just opened
+#[tauri::command]
+fn touch_project(
+    app: tauri::AppHandle,
+    id: String,
+    chapter_id: Option<String>,
+) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    state
+        .db
+        .touch_project(&id, chapter_id.as_deref())
+        .map_err(|e| e.to_string())?;
+    let current = settings::load(&state.db);
+    if current.last_open_project_id.as_deref() != Some(id.as_str()) {
+        let updated = settings::AppSettings { last_open_project_id: Some(id), ..current.clone() };
+        settings::save(&state.db, &current, &updated)?;
+        // Saved without change_settings, which would re-validate unrelated settings
+        let _ = app.emit(events::SETTINGS_CHANGED, updated);
+    }
+    Ok(())
+}
+
+/// Most recently opened first; projects never opened are left out
+#[tauri::command]
+fn list_recent_projects(
+    state: State<AppState>,
+    limit: Option<u32>,
+) -> Result<Vec<RecentProject>, String> {
+    state
+        .db
+        .list_recent_projects(limit.unwrap_or(10), false)
+        .map_err(|e| e.to_string())
+}
+
+/// touch_project without a chapter. Only records the open: updated_at keeps
+/// tracking edits, so "recently opened" and "recently edited" can differ.
+#[tauri::command]
+fn touch_project_opened(app: tauri::AppHandle, id: String) -> Result<(), String> {
+    touch_project(app, id, None)
+}
+
+/// Every project, most recently opened first and never-opened ones last
+#[tauri::command]
+fn recent_projects(state: State<AppState>, limit: u32) -> Result<Vec<RecentProject>, String> {
+    state.db.list_recent_projects(limit, true).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_startup_state(state: State<AppState>) -> Result<StartupState, String> {
+    let last_project_id = settings::load(&state.db).last_open_project_id;
+    let project = match &last_project_id {
+        Some(id) => state.db.recent_project(id).map_err(|e| e.to_string())?,
+        None => None,
+    };
+    Ok(StartupState { last_project_id, project_exists: project.is_some(), project })
+}
+
+/// Copy a PNG, JPEG or WebP image in as the project's cover, replacing any previous one
+#[tauri::command]
+async fn set_project_cover(
+    app: tauri::AppHandle,
+    project_id: String,
+    source_path: String,
+) -> Result<covers::Cover, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        // Errs for an unknown project before anything is copied
+        state.db.project_cover_path(&project_id).map_err(|e| e.to_string())?;
+        let data_dir = std::path::PathBuf::from(state.data_dir());
+        let relative =
+            covers::set(&data_dir, &project_id, std::path::Path::new(source_path.trim()))?;
+        state
+            .db
+            .set_project_cover_path(&project_id, Some(&relative))
+            .map_err(|e| e.to_string())?;
+        project_updated(&app, &state, &project_id);
+        covers::read(&data_dir, &project_id, &relative, true)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// None when the project has no cover. `thumbnail` (the default) returns the
+/// small version made for project cards.
+#[tauri::command]
+async fn get_project_cover(
+    app: tauri::AppHandle,
+    project_id: String,
+    thumbnail: Option<bool>,
+) -> Result<Option<covers::Cover>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let Some(relative) =
+            state.db.project_cover_path(&project_id).map_err(|e| e.to_string())?
+        else {
+            return Ok(None);
+        };
+        let data_dir = std::path::PathBuf::from(state.data_dir());
+        covers::read(&data_dir, &project_id, &relative, thumbnail.unwrap_or(true)).map(Some)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn remove_project_cover(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    project_id: String,
+) -> Result<(), String> {
+    state.db.project_cover_path(&project_id).map_err(|e| e.to_string())?;
+    covers::remove(std::path::Path::new(&state.data_dir()), &project_id)?;
+    state
+        .db
+        .set_project_cover_path(&project_id, None)
+        .map_err(|e| e.to_string())?;
+    project_updated(&app, &state, &project_id);
+    Ok(())
+}
+
+/// Copy a file in as a project attachment. A file the project already has (same
+/// contents) comes back with `duplicate` set instead of being copied again.
+#[tauri::command]
+async fn add_attachment(
+    app: tauri::AppHandle,
+    project_id: String,
+    source_path: String,
+) -> Result<attachments::Added, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let data_dir = std::path::PathBuf::from(state.data_dir());
+        let source = std::path::Path::new(source_path.trim());
+        attachments::add(&state.db, &data_dir, &project_id, source)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn list_attachments(
+    state: State<AppState>,
+    project_id: String,
+) -> Result<Vec<attachments::Attachment>, String> {
+    state.db.list_attachments(&project_id).map_err(|e| e.to_string())
+}
+
+/// Drop the row first, so a file that can't be deleted is at worst left orphaned
+#[tauri::command]
+fn remove_attachment(state: State<AppState>, id: String) -> Result<(), String> {
+    let attachment = state
+        .db
+        .delete_attachment(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Attachment not found: {}", id))?;
+    attachments::remove_file(std::path::Path::new(&state.data_dir()), &attachment)
+}
+
+/// Open an attachment with the system's default application for its type
+#[tauri::command]
+fn open_attachment(state: State<AppState>, id: String) -> Result<(), String> {
+    let attachment = state
+        .db
+        .get_attachment(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Attachment not found: {}", id))?;
+    let path = attachments::resolve(std::path::Path::new(&state.data_dir()), &attachment)?;
+    file_manager::open(&path)
+}
+
+/// Where each roster character is mentioned (by name or alias), who never is,
+/// and likely misspellings of Latin-script names. Runs off the main thread,
+/// emitting `analysis://character-consistency` per chapter.
+#[tauri::command]
+async fn check_character_consistency(
+    app: tauri::AppHandle,
+    project_id: String,
+) -> Result<character_check::ConsistencyReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = &app.state::<AppState>().db;
+        let roster = db.list_characters(&project_id).map_err(|e| e.to_string())?;
+        let chapters = db.project_chapter_texts(&project_id).map_err(|e| e.to_string())?;
+        character_check::check(&roster, &chapters, |done, total| {
+            let progress = events::CharacterCheckProgress { project_id: &project_id, done, total };
+            let _ = app.emit(events::CHARACTER_CHECK_PROGRESS, progress);
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Progress towards the project's word_target
+#[tauri::command]
+fn word_target_status(state: State<AppState>, project_id: String) -> Result<TargetStatus, String> {
+    let project = state.db.get_project(&project_id).map_err(|e| e.to_string())?;
+    let current = state.db.project_word_count(&project_id).map_err(|e| e.to_string())?;
+    Ok(TargetStatus::new(current, project.word_target.into()))
+}
+
+/// Today's writing towards the project's daily word goal, or the app-wide one
+#[tauri::command]
+fn get_today_progress(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    project_id: String,
+) -> Result<writing_goal::TodayProgress, String> {
+    today_progress(&app, &state, &project_id)
+}
+
+/// The project's own daily word goal; None falls back to the app-wide
+/// daily_word_goal setting, and 0 means none for this project
+#[tauri::command]
+fn set_daily_word_goal(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    project_id: String,
+    goal: Option<i64>,
+) -> Result<(), String> {
+    if goal.is_some_and(|goal| goal < 0) {
+        return Err("Daily word goal cannot be negative".into());
+    }
+    state.db.set_daily_word_goal(&project_id, goal).map_err(|e| e.to_string())?;
+    project_updated(&app, &state, &project_id);
+    Ok(())
+}
+
+/// Progress by the clock as it reads now, never a cached date. The first call
+/// that finds today's goal met sends goal://reached; later ones, from any window,
+/// don't.
+fn today_progress(
+    app: &tauri::AppHandle,
+    state: &AppState,
+    project_id: &str,
+) -> Result<writing_goal::TodayProgress, String> {
+    let goal = match state.db.daily_word_goal(project_id).map_err(|e| e.to_string())? {
+        Some(goal) => goal,
+        None => settings::load(&state.db).daily_word_goal.into(),
+    };
+    let days = state.db.writing_days(project_id).map_err(|e| e.to_string())?;
+    let progress = writing_goal::TodayProgress::new(&writing_goal::today(), goal, &days);
+    if progress.met == Some(true)
+        && state.db.claim_goal_reached(project_id, &progress.day).map_err(|e| e.to_string())?
+    {
+        let reached = events::GoalReached {
+            project_id,
+            day: &progress.day,
+            goal: progress.goal,
+            written_today: progress.written_today,
+        };
+        let _ = app.emit(events::GOAL_REACHED, reached);
+    }
+    Ok(progress)
+}
+
+// ---- Trash Commands ----
+
+/// Move a project to the trash. It stays whole, and out of the project lists,
+/// until restored or purged.
+#[tauri::command]
+fn trash_project(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    project_id: String,
+) -> Result<(), String> {
+    state.db.trash_project(&project_id).map_err(|e| e.to_string())?;
+    project_deleted(&app, &project_id);
+    Ok(())
+}
+
+/// Take a project back out of the trash. Under the maintenance lock, so a purge
+/// can't take it meanwhile.
+#[tauri::command]
+fn restore_trashed_project(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    project_id: String,
+) -> Result<Project, String> {
+    let _maintenance = state.maintenance.read().unwrap();
+    let project = state.db.restore_trashed_project(&project_id).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => format!("Project not in the trash: {}", project_id),
+        e => e.to_string(),
+    })?;
+    Ok(project_changed(&app, events::ProjectChange::Restored, project))
+}
+
+/// What is in the trash and roughly how much purging it would free
+#[tauri::command]
+async fn get_trash_info(app: tauri::AppHandle) -> Result<trash::TrashInfo, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let data_dir = PathBuf::from(state.data_dir());
+        let mut projects = state.db.trashed_projects().map_err(|e| e.to_string())?;
+        for project in &mut projects {
+            project.file_bytes = trash::file_bytes(&data_dir, &project.id);
+        }
+        let retention_days = settings::load(&state.db).trash_retention_days;
+        Ok(trash::TrashInfo::new(projects, retention_days))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Purge everything in the trash now, whatever its age
+#[tauri::command]
+async fn empty_trash(app: tauri::AppHandle) -> Result<trash::Purged, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let maintenance = state.maintenance.try_write().map_err(|_| {
+            "A backup, export, restore or data move is in progress; try again once it finishes"
+                .to_string()
+        })?;
+        purge_trash(&app, &maintenance, false)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// ---- Character Commands ----
+
+fn check_character_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Character name cannot be empty".into());
+    }
+    Ok(())
+}
+
+/// Character names are unique within a project, enforced by an index so that the
+/// agent's writes are held to it too
+fn character_error(e: rusqlite::Error, name: &str) -> String {
+    if db::is_unique_violation(&e) {
+        format!("A character named \"{}\" already exists in this project", name)
+    } else {
+        e.to_string()
+    }
+}
+
+#[tauri::command]
+fn list_characters(state: State<AppState>, project_id: String) -> Result<Vec<Character>, String> {
+    state.db.list_characters(&project_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_character(
+    state: State<AppState>,
+    project_id: String,
+    name: String,
+) -> Result<Character, String> {
+    let name = name.trim();
+    check_character_name(name)?;
+    state.db.create_character(&project_id, name).map_err(|e| character_error(e, name))
+}
+
+#[tauri::command]
+fn update_character(
+    state: State<AppState>,
+    id: String,
+    mut update: CharacterUpdate,
+) -> Result<Character, String> {
+    if let Some(name) = update.name.as_mut() {
+        *name = name.trim().to_string();
+        check_character_name(name)?;
+    }
+    state.db.update_character(&id, &update).map_err(|e| match &update.name {
+        Some(name) => character_error(e, name),
+        None => e.to_string(),
+    })
+}
+
+#[tauri::command]
+fn delete_character(state: State<AppState>, id: String) -> Result<(), String> {
+    state.db.delete_character(&id).map_err(|e| e.to_string())
+}
+
+// ---- Relationship Commands ----
+
+/// An edge from `from_character` to `to_character`, both of the same project.
+/// Errs with "Conflict: ..." if they already have a relationship of this kind.
+#[tauri::command]
+fn create_relationship(
+    state: State<AppState>,
+    from_character: String,
+    to_character: String,
+    kind: String,
+    description: Option<String>,
+    strength: Option<i64>,
+) -> Result<relationship_graph::Relationship, String> {
+    let kind = check_relationship_kind(&kind)?;
+    let strength = strength.unwrap_or(0);
+    relationship_graph::check_strength(strength)?;
+    state.db.create_relationship(
+        &from_character,
+        &to_character,
+        kind,
+        description.as_deref().unwrap_or("").trim(),
+        strength,
+    )
+}
+
+#[tauri::command]
+fn update_relationship(
+    state: State<AppState>,
+    id: String,
+    mut update: relationship_graph::RelationshipUpdate,
+) -> Result<relationship_graph::Relationship, String> {
+    if let Some(kind) = update.kind.as_mut() {
+        *kind = check_relationship_kind(kind)?.to_string();
+    }
+    if let Some(strength) = update.strength {
+        relationship_graph::check_strength(strength)?;
+    }
+    state.db.update_relationship(&id, &update)
+}
+
+#[tauri::command]
+fn delete_relationship(state: State<AppState>, id: String) -> Result<(), String> {
+    state.db.delete_relationship(&id).map_err(|e| e.to_string())
+}
+
+/// Nodes and edges for the relationship graph view
+#[tauri::command]
+fn get_relationship_graph(
+    state: State<AppState>,
+    project_id: String,
+) -> Result<relationship_graph::RelationshipGraph, String> {
+    state.db.relationship_graph(&project_id).map_err(|e| e.to_string())
+}
+
+/// The character's direct relationships, strongest first, for the editor's sidebar
+#[tauri::command]
+fn get_character_connections(
+    state: State<AppState>,
+    character_id: String,
+) -> Result<Vec<relationship_graph::CharacterConnection>, String> {
+    state.db.character_connections(&character_id).map_err(|e| e.to_string())
+}
+
+fn check_relationship_kind(kind: &str) -> Result<&str, String> {
+    let kind = kind.trim();
+    if kind.is_empty() {
+        return Err("Relationship kind cannot be empty".into());
+    }
+    Ok(kind)
+}
+
+// ---- Chapter Commands ----
+
+/// Save the editor's text for a chapter, skipping the write when the chapter
+/// already holds that text. Returns whether anything was written.
+#[tauri::command]
+fn autosave_chapter(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    id: String,
+    content: String,
+) -> Result<bool, String> {
+    // Against the stored text, so edits by replace, restore or the agent count
+    let stored = state.db.chapter_content(&id).map_err(|e| e.to_string())?;
+    if stored.as_deref() == Some(content.as_str()) {
+        return Ok(false);
+    }
+    let Some(project_id) = state.db.save_chapter_text(&id, &content).map_err(|e| e.to_string())?
+    else {
+        return Err(format!("Chapter not found: {}", id));
+    };
+    // Only for goal://reached; the save itself went through
+    if let Err(e) = today_progress(&app, &state, &project_id) {
+        warn!(project_id = %project_id, error = %e, "could not update today's writing progress");
+    }
+    Ok(true)
+}
+
+/// The chapter rendered for the reader view: each line a paragraph, Markdown
+/// formatting applied and any raw HTML shown as text
+#[tauri::command]
+fn chapter_html(state: State<AppState>, id: String) -> Result<String, String> {
+    let content = state
+        .db
+        .chapter_content(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Chapter not found: {}", id))?;
+    let hash = reader::content_hash(&content);
+    if let Some(html) = state.chapter_html.get(hash) {
+        return Ok(html);
+    }
+    let html = reader::render(&content);
+    state.chapter_html.insert(hash, html.clone());
+    Ok(html)
+}
+
+/// Counts for the editor's status bar, as the chapter's word_count will be once saved
+#[tauri::command]
+fn count_text(text: String) -> text_count::TextCounts {
+    text_count::count(&text)
+}
+
+/// Recount every chapter of the project from its text, returning how many counts
+/// were wrong
+#[tauri::command]
+fn recount_project_words(state: State<AppState>, project_id: String) -> Result<usize, String> {
+    state.db.recount_project_words(&project_id).map_err(|e| e.to_string())
+}
+
+/// recount_project_words for every project at once, or just `project_id`'s, after
+/// a bulk import or a change to the counter
+#[tauri::command]
+fn recompute_word_counts(
+    state: State<AppState>,
+    project_id: Option<String>,
+) -> Result<usize, String> {
+    state.db.recount_words(project_id.as_deref()).map_err(|e| e.to_string())
+}
+
+/// The chapter's kept revisions, newest first
+#[tauri::command]
+fn list_revisions(state: State<AppState>, chapter_id: String) -> Result<Vec<Revision>, String> {
+    state.db.list_revisions(&chapter_id).map_err(|e| e.to_string())
+}
+
+/// Line-by-line changes from revision `from_rev` to `to_rev` of the chapter
+#[tauri::command]
+fn diff_revisions(
+    state: State<AppState>,
+    chapter_id: String,
+    from_rev: i64,
+    to_rev: i64,
+) -> Result<Vec<text_diff::DiffLine>, String> {
+    let content = |rev: i64| {
+        state
+            .db
+            .revision_content(&chapter_id, rev)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Revision not found: {}", rev))
+    };
+    Ok(text_diff::diff_lines(&content(from_rev)?, &content(to_rev)?))
+}
+
+/// Roll the chapter back to revision `rev`, itself kept as a new revision
+#[tauri::command]
+fn restore_revision(state: State<AppState>, chapter_id: String, rev: i64) -> Result<(), String> {
+    state.db.restore_revision(&chapter_id, rev)
+}
+
+/// Move a chapter through draft → revised → final. Going straight from draft to
+/// final needs `force`; content and revisions are left alone.
+#[tauri::command]
+fn set_chapter_status(
+    state: State<AppState>,
+    id: String,
+    status: chapter_status::ChapterStatus,
+    force: Option<bool>,
+) -> Result<bool, String> {
+    let changed = state.db.set_chapters_status(&[id], status, force.unwrap_or(false))?;
+    Ok(changed > 0)
+}
+
+/// set_chapter_status for many chapters at once, e.g. a whole arc; all or none
+/// change. Returns how many weren't already in `status`.
+#[tauri::command]
+fn set_chapters_status(
+    state: State<AppState>,
+    ids: Vec<String>,
+    status: chapter_status::ChapterStatus,
+    force: Option<bool>,
+) -> Result<usize, String> {
+    state.db.set_chapters_status(&ids, status, force.unwrap_or(false))
+}
+
+// ---- Scene Commands ----
+
+/// A chapter's scenes in outline order
+#[tauri::command]
+fn list_scenes(state: State<AppState>, chapter_id: String) -> Result<Vec<Scene>, String> {
+    state.db.list_scenes(&chapter_id).map_err(|e| e.to_string())
+}
+
+/// Appended after the chapter's last scene
+#[tauri::command]
+fn create_scene(
+    state: State<AppState>,
+    chapter_id: String,
+    summary: Option<String>,
+    pov_character_id: Option<String>,
+) -> Result<Scene, String> {
+    let pov = pov_character_id.as_deref().filter(|id| !id.is_empty());
+    state
+        .db
+        .create_scene(&chapter_id, summary.as_deref().unwrap_or(""), pov)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_scene(state: State<AppState>, id: String, update: SceneUpdate) -> Result<Scene, String> {
+    state.db.update_scene(&id, &update).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_scene(state: State<AppState>, id: String) -> Result<(), String> {
+    state.db.delete_scene(&id).map_err(|e| e.to_string())
+}
+
+/// `scene_ids` must list every scene of the chapter exactly once, in the new order
+#[tauri::command]
+fn reorder_scenes(
+    state: State<AppState>,
+    chapter_id: String,
+    scene_ids: Vec<String>,
+) -> Result<Vec<Scene>, String> {
+    let current = state.db.list_scenes(&chapter_id).map_err(|e| e.to_string())?;
+    let mut expected: Vec<&str> = current.iter().map(|scene| scene.id.as_str()).collect();
+    let mut given: Vec<&str> = scene_ids.iter().map(String::as_str).collect();
+    expected.sort_unstable();
+    given.sort_unstable();
+    if expected != given {
+        return Err("The new order must list each of the chapter's scenes exactly once".into());
+    }
+    state.db.reorder_scenes(&chapter_id, &scene_ids).map_err(|e| e.to_string())
+}
+
+// ---- Note Commands ----
+
+/// Pinned notes first, then the most recently edited
+#[tauri::command]
+fn list_notes(state: State<AppState>, project_id: String) -> Result<Vec<Note>, String> {
+    state.db.list_notes(&project_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_note(
+    state: State<AppState>,
+    project_id: String,
+    title: Option<String>,
+    content: Option<String>,
+    color: Option<String>,
+) -> Result<Note, String> {
+    let (title, content) = (title.unwrap_or_default(), content.unwrap_or_default());
+    check_note(&title, &content)?;
+    state
+        .db
+        .create_note(&project_id, title.trim(), &content, color.as_deref().unwrap_or(""))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_note(state: State<AppState>, id: String, update: NoteUpdate) -> Result<Note, String> {
+    check_note(update.title.as_deref().unwrap_or(""), update.content.as_deref().unwrap_or(""))?;
+    state.db.update_note(&id, &update).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_note(state: State<AppState>, id: String) -> Result<(), String> {
+    state.db.delete_note(&id).map_err(|e| e.to_string())
+}
+
+fn check_note(title: &str, content: &str) -> Result<(), String> {
+    if title.chars().count() > MAX_NOTE_TITLE_CHARS {
+        return Err(format!("Note titles are limited to {} characters", MAX_NOTE_TITLE_CHARS));
+    }
+    if content.chars().count() > MAX_NOTE_CHARS {
+        return Err(format!(
+            "Notes are limited to {} characters; split this one up",
+            MAX_NOTE_CHARS
+        ));
+    }
+    Ok(())
+}
+
+// ---- Story Event Commands ----
+
+/// `sort` is "narrative" (the default) or "story", by in-world date
+#[tauri::command]
+fn list_story_events(
+    state: State<AppState>,
+    project_id: String,
+    sort: Option<StoryEventSort>,
+) -> Result<Vec<StoryEvent>, String> {
+    state.db.list_story_events(&project_id, sort.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+/// Appended last in narrative order
+#[tauri::command]
+fn create_story_event(
+    state: State<AppState>,
+    project_id: String,
+    title: String,
+    description: Option<String>,
+    story_date: Option<String>,
+    chapter_id: Option<String>,
+    character_ids: Option<Vec<String>>,
+) -> Result<StoryEvent, String> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err("Event title cannot be empty".into());
+    }
+    let chapter_id = chapter_id.as_deref().filter(|id| !id.is_empty());
+    let character_ids = character_ids.unwrap_or_default();
+    check_story_event_links(&state.db, &project_id, chapter_id, &character_ids)?;
+    state
+        .db
+        .create_story_event(
+            &project_id,
+            title,
+            description.as_deref().unwrap_or(""),
+            story_date.as_deref().unwrap_or("").trim(),
+            chapter_id,
+            &character_ids,
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_story_event(
+    state: State<AppState>,
+    id: String,
+    update: StoryEventUpdate,
+) -> Result<StoryEvent, String> {
+    if update.title.as_deref().is_some_and(|title| title.trim().is_empty()) {
+        return Err("Event title cannot be empty".into());
+    }
+    if update.chapter_id.is_some() || update.character_ids.is_some() {
+        let current = state.db.get_story_event(&id).map_err(|e| e.to_string())?;
+        check_story_event_links(
+            &state.db,
+            &current.project_id,
+            update.chapter_id.as_deref().filter(|id| !id.is_empty()),
+            update.character_ids.as_deref().unwrap_or_default(),
+        )?;
+    }
+    state.db.update_story_event(&id, &update).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_story_event(state: State<AppState>, id: String) -> Result<(), String> {
+    state.db.delete_story_event(&id).map_err(|e| e.to_string())
+}
+
+/// `event_ids` must list every event of the project exactly once, in the new
+/// narrative order
+#[tauri::command]
+fn reorder_story_events(
+    state: State<AppState>,
+    project_id: String,
+    event_ids: Vec<String>,
+) -> Result<Vec<StoryEvent>, String> {
+    let current = state
+        .db
+        .list_story_events(&project_id, StoryEventSort::Narrative)
+        .map_err(|e| e.to_string())?;
+    let mut expected: Vec<&str> = current.iter().map(|event| event.id.as_str()).collect();
+    let mut given: Vec<&str> = event_ids.iter().map(String::as_str).collect();
+    expected.sort_unstable();
+    given.sort_unstable();
+    if expected != given {
+        return Err("The new order must list each of the project's events exactly once".into());
+    }
+    state.db.reorder_story_events(&project_id, &event_ids).map_err(|e| e.to_string())
+}
+
+/// An event may only link the chapters and characters of its own project
+fn check_story_event_links(
+    db: &Database,
+    project_id: &str,
+    chapter_id: Option<&str>,
+    character_ids: &[String],
+) -> Result<(), String> {
+    if !db
+        .story_event_links_exist(project_id, chapter_id, character_ids)
+        .map_err(|e| e.to_string())?
+    {
+        return Err("An event can only link chapters and characters of its own project".into());
+    }
+    Ok(())
+}
+
+// ---- Prompt Library Commands ----
+
+fn check_prompt_name(
+    db: &Database,
+    project_id: Option<&str>,
+    name: &str,
+    exclude_id: Option<&str>,
+) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Prompt name cannot be empty".into());
+    }
+    if db
+        .prompt_name_taken(project_id, name, exclude_id)
+        .map_err(|e| e.to_string())?
+    {
+        return Err(format!("A prompt named \"{}\" already exists here", name));
+    }
+    Ok(())
+}
+
+/// Global prompts merged with `project_id`'s overrides (project wins on name)
+#[tauri::command]
+fn list_prompts(state: State<AppState>, project_id: Option<String>) -> Result<Vec<Prompt>, String> {
+    state.db.list_prompts(project_id.as_deref()).map_err(|e| e.to_string())
+}
+
+/// With `project_id`, a project-specific prompt, which overrides a global one of the same name
+#[tauri::command]
+fn create_prompt(
+    state: State<AppState>,
+    name: String,
+    category: Option<String>,
+    content: String,
+    project_id: Option<String>,
+) -> Result<Prompt, String> {
+    let name = name.trim();
+    check_prompt_name(&state.db, project_id.as_deref(), name, None)?;
+    let category = category.as_deref().unwrap_or("").trim();
+    state
+        .db
+        .create_prompt(name, category, &content, project_id.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_prompt(
+    state: State<AppState>,
+    id: String,
+    mut update: PromptUpdate,
+) -> Result<Prompt, String> {
+    if let Some(name) = update.name.as_mut() {
+        *name = name.trim().to_string();
+        let current = state.db.get_prompt(&id).map_err(|e| e.to_string())?;
+        // The agent looks built-ins up by name
+        if current.is_builtin && *name != current.name {
+            return Err("Built-in prompts cannot be renamed".into());
+        }
+        check_prompt_name(&state.db, current.project_id.as_deref(), name, Some(&id))?;
+    }
+    state.db.update_prompt(&id, &update).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_prompt(state: State<AppState>, id: String) -> Result<(), String> {
+    let prompt = state.db.get_prompt(&id).map_err(|e| e.to_string())?;
+    if prompt.is_builtin {
+        return Err(format!("\"{}\" is built in; reset it to the default instead", prompt.name));
+    }
+    state.db.delete_prompt(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn reset_prompt(state: State<AppState>, id: String) -> Result<Prompt, String> {
+    let prompt = state.db.get_prompt(&id).map_err(|e| e.to_string())?;
+    if !prompt.is_builtin {
+        return Err(format!("\"{}\" is not a built-in prompt", prompt.name));
+    }
+    state.db.reset_prompt(&id).map_err(|e| e.to_string())
+}
+
+// ---- Model Preset Commands ----
+
+/// With `provider`, only that provider's presets
+#[tauri::command]
+fn list_model_presets(
+    state: State<AppState>,
+    provider: Option<String>,
+) -> Result<Vec<ModelPreset>, String> {
+    let provider = provider.as_deref().map(str::trim).filter(|p| !p.is_empty());
+    state.db.list_model_presets(provider).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_model_preset(
+    state: State<AppState>,
+    preset: ModelPresetUpdate,
+) -> Result<ModelPreset, String> {
+    preset.check()?;
+    let (Some(provider), Some(model_id)) = (&preset.provider, &preset.model_id) else {
+        return Err("provider and model_id are required".into());
+    };
+    if state
+        .db
+        .model_preset_exists(provider.trim(), model_id.trim(), None)
+        .map_err(|e| e.to_string())?
+    {
+        return Err(format!("A preset for {}/{} already exists", provider.trim(), model_id.trim()));
+    }
+    state.db.create_model_preset(&preset).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_model_preset(
+    state: State<AppState>,
+    id: String,
+    update: ModelPresetUpdate,
+) -> Result<ModelPreset, String> {
+    update.check()?;
+    if update.provider.is_some() || update.model_id.is_some() {
+        let current = state.db.get_model_preset(&id).map_err(|e| e.to_string())?;
+        let provider = update.provider.as_deref().unwrap_or(&current.provider).trim();
+        let model_id = update.model_id.as_deref().unwrap_or(&current.model_id).trim();
+        if state
+            .db
+            .model_preset_exists(provider, model_id, Some(&id))
+            .map_err(|e| e.to_string())?
+        {
+            return Err(format!("A preset for {}/{} already exists", provider, model_id));
+        }
+    }
+    state.db.update_model_preset(&id, &update).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_model_preset(state: State<AppState>, id: String) -> Result<PresetDeleted, String> {
+    state.db.delete_model_preset(&id).map_err(|e| e.to_string())
+}
+
+/// Copy the preset's model id into `slot` and its temperature into the project
+#[tauri::command]
+fn apply_preset_to_project(
+    app: tauri::AppHandle,
+    state: State<AppState>,
+    project_id: String,
+    preset_id: String,
+    slot: ModelSlot,
+) -> Result<Project, String> {
+    let project = state
+        .db
+        .apply_preset_to_project(&project_id, &preset_id, slot)
+        .map_err(|e| e.to_string())?;
+    Ok(project_changed(&app, events::ProjectChange::Updated, project))
+}
+
+// ---- Credential Commands ----
+
+#[tauri::command]
+fn list_api_credentials(state: State<AppState>) -> Result<Vec<credentials::CredentialInfo>, String> {
+    credentials::list(&state.db, &state.credentials)
+}
+
+#[tauri::command]
+fn get_api_credential(
+    state: State<AppState>,
+    name: String,
+) -> Result<Option<credentials::CredentialInfo>, String> {
+    credentials::get(&state.db, &state.credentials, &name)
+}
+
+#[derive(Serialize)]
+struct CredentialChange {
+    credential: Option<credentials::CredentialInfo>,
+    /// The running agent still has the old environment; offer restart_agent
+    restart_required: bool,
+}
+
+fn agent_running(state: &AppState) -> bool {
+    state.all_agents().iter().any(|agent| agent.is_running())
+}
+
+#[tauri::command]
+fn set_api_credential(
+    state: State<AppState>,
+    name: String,
+    value: String,
+) -> Result<CredentialChange, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("Credential value cannot be empty".into());
+    }
+    let info = credentials::set(&state.db, &state.credentials, &name, value)?;
+    Ok(CredentialChange {
+        credential: Some(info),
+        restart_required: agent_running(&state),
+    })
+}
+
+#[tauri::command]
+fn delete_api_credential(state: State<AppState>, name: String) -> Result<CredentialChange, String> {
+    let removed = credentials::delete(&state.db, &name)?;
+    Ok(CredentialChange {
+        credential: None,
+        restart_required: removed && agent_running(&state),
+    })
+}
+
+#[tauri::command]
+fn credential_lock_status(state: State<AppState>) -> Result<credentials::LockStatus, String> {
+    credentials::lock_status(&state.db, &state.credentials)
+}
+
+/// Needed once per session, when there is no OS keychain, before credentials can
+/// be read, set or handed to the agent
+#[tauri::command]
+async fn unlock_credentials(
+    app: tauri::AppHandle,
+    passphrase: String,
+) -> Result<CredentialChange, credentials::PassphraseError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        credentials::unlock(&state.db, &state.credentials, &passphrase)?;
+        Ok(CredentialChange {
+            credential: None,
+            restart_required: agent_running(&state),
+        })
+    })
+    .await
+    .map_err(|e| credentials::PassphraseError::Failed(e.to_string()))?
+}
+
+/// Set the credentials passphrase, or change it given the current one
+#[tauri::command]
+async fn change_credentials_passphrase(
+    app: tauri::AppHandle,
+    current: Option<String>,
+    new_passphrase: String,
+) -> Result<(), credentials::PassphraseError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        credentials::change_passphrase(
+            &state.db,
+            &state.credentials,
+            current.as_deref(),
+            &new_passphrase,
+        )
+    })
+    .await
+    .map_err(|e| credentials::PassphraseError::Failed(e.to_string()))?
+}
+
+#[derive(Serialize)]
+struct DataDirLocation {
+    path: String,
+    mode: data_location::Mode,
+}
+
+#[tauri::command]
+fn get_data_dir(state: State<AppState>) -> DataDirLocation {
+    let path = state.data_dir();
+    DataDirLocation { mode: data_location::mode(std::path::Path::new(&path)), path }
+}
+
+/// Path, size on disk and free space of the data directory
+#[tauri::command]
+async fn get_data_dir_info(app: tauri::AppHandle) -> Result<data_location::DataDirInfo, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let data_dir = app.state::<AppState>().data_dir();
+        data_location::info(std::path::Path::new(&data_dir))
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Free bytes on the volume holding the data directory
+#[tauri::command]
+fn available_disk_space(state: State<AppState>) -> Result<u64, String> {
+    let data_dir = state.data_dir();
+    data_location::available_space(std::path::Path::new(&data_dir))
+        .ok_or_else(|| format!("Cannot read the free space of the disk holding {}", data_dir))
+}
+
+/// Show the data directory, agent log, backups or exports in the file manager
+#[tauri::command]
+async fn reveal_in_file_manager(
+    app: tauri::AppHandle,
+    path_kind: file_manager::PathKind,
+) -> Result<file_manager::Revealed, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let data_dir = app.state::<AppState>().data_dir();
+        file_manager::reveal(path_kind, std::path::Path::new(&data_dir))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+enum DataDirProgress {
+    Copying(data_location::CopyProgress),
+    Database,
+    Done { path: String },
+    Failed { error: String },
+}
+
+/// Move all app data to `new_path` and keep using it from then on, streaming
+/// `data-dir://progress` events. The agent is stopped for the duration and
+/// restarted against the new directory. The old directory is left in place; until
+/// the very last step it stays the one in use, so any failure changes nothing.
+#[tauri::command]
+async fn set_data_dir(
+    app: tauri::AppHandle,
+    new_path: String,
+) -> Result<data_location::DataDirInfo, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let current = app.state::<AppState>().data_dir();
+        if data_location::mode(std::path::Path::new(&current)) == data_location::Mode::Portable {
+            return Err(
+                "Portable mode keeps data next to the app; convert to standard mode first".into(),
+            );
+        }
+        move_data_dir(&app, &new_path, |target| {
+            data_location::write_pointer(target)
+                .map_err(|e| format!("Failed to record the new data directory: {}", e))
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// set_data_dir for callers that only care whether the move worked
+#[tauri::command]
+async fn migrate_data_dir(app: tauri::AppHandle, new_dir: String) -> Result<(), String> {
+    set_data_dir(app, new_dir).await.map(|_| ())
+}
+
+/// Move all app data into data/ next to the executable and switch to portable mode
+#[tauri::command]
+async fn convert_to_portable(app: tauri::AppHandle) -> Result<data_location::DataDirInfo, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let current = app.state::<AppState>().data_dir();
+        if data_location::mode(std::path::Path::new(&current)) == data_location::Mode::Portable {
+            return Err("Already in portable mode".into());
+        }
+        let target = data_location::portable_target()?;
+        move_data_dir(&app, &target.to_string_lossy(), |_| data_location::enable_portable())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Move portable data back to the standard location; data/ next to the
+/// executable is kept but no longer used
+#[tauri::command]
+async fn convert_to_standard(app: tauri::AppHandle) -> Result<data_location::DataDirInfo, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let current = app.state::<AppState>().data_dir();
+        if data_location::mode(std::path::Path::new(&current)) == data_location::Mode::Standard {
+            return Err("Not in portable mode".into());
+        }
+        let target = data_location::standard_dir();
+        move_data_dir(&app, &target.to_string_lossy(), |_| data_location::disable_portable())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Copy the data directory to `new_path` with the agents stopped, then switch over.
+/// `commit` records the new location for future launches; it runs last, and if
+/// it fails the copy is discarded and the current directory stays in use.
+fn move_data_dir(
+    app: &tauri::AppHandle,
+    new_path: &str,
+    commit: impl FnOnce(&std::path::Path) -> Result<(), String>,
+) -> Result<data_location::DataDirInfo, String> {
+    let state = app.state::<AppState>();
+    let agents = state.all_agents();
+    let lifecycles: Vec<_> = agents.iter().map(|agent| agent.manager.lock()).collect();
+    let _maintenance = state.maintenance.read().unwrap();
+    let current = PathBuf::from(state.data_dir());
+    let target = data_location::prepare_target(&current, new_path)?;
+
+    let stopped: Vec<_> = agents
+        .iter()
+        .zip(&lifecycles)
+        .map(|(agent, lifecycle)| {
+            let was_suspended = agent.suspended.swap(true, Ordering::SeqCst);
+            (was_suspended, take_down_agent(app, &state, agent, lifecycle))
+        })
+        .collect();
+
+    let result = copy_data_dir(app, &state, &current, &target.path, commit);
+    if let Err(error) = &result {
+        data_location::discard(&target);
+        let _ = app.emit(events::DATA_DIR_PROGRESS, DataDirProgress::Failed { error: error.clone() });
+    }
+
+    for ((agent, lifecycle), (was_suspended, was_running)) in
+        agents.iter().zip(&lifecycles).zip(stopped)
+    {
+        agent.suspended.store(was_suspended, Ordering::SeqCst);
+        if was_running {
+            let data_dir = state.data_dir();
+            if let Err(e) = lifecycle.spawn(|| spawn_agent(app, agent, &data_dir).map(Some)) {
+                error!(error = %e, agent = agent.name(), "failed to restart agent after data move");
+            }
+        }
+    }
+    result?;
+    Ok(data_location::info(&target.path))
+}
+
+fn copy_data_dir(
+    app: &tauri::AppHandle,
+    state: &AppState,
+    current: &std::path::Path,
+    target: &std::path::Path,
+    commit: impl FnOnce(&std::path::Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let total = data_location::dir_size(current);
+    data_location::copy_contents(current, target, total, |progress| {
+        let _ = app.emit(events::DATA_DIR_PROGRESS, DataDirProgress::Copying(progress.clone()));
+    })
+    .map_err(|e| format!("Failed to copy data to {}: {}", target.display(), e))?;
+
+    let _ = app.emit(events::DATA_DIR_PROGRESS, DataDirProgress::Database);
+    let new_dir = target.to_string_lossy().into_owned();
+    state.db.relocate(target, || {
+        commit(target)?;
+        *state.data_dir.lock().unwrap() = new_dir.clone();
+        Ok(())
+    })?;
+    info!(from = %current.display(), to = %new_dir, "data directory moved");
+    notify::finished(app, "Data directory moved", &new_dir);
+    let _ = app.emit(events::DATA_DIR_PROGRESS, DataDirProgress::Done { path: new_dir });
+    Ok(())
+}
+
+// ---- Agent Process Management ----
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+enum AgentMode {
+    /// Spawned and stopped by the app
+    Managed,
+    /// Started outside the app; stop_agent leaves it alone
+    Attached,
+}
+
+#[derive(Serialize)]
+struct AgentStatus {
+    /// None for the shared agent
+    project_id: Option<String>,
+    /// Where the agent listens, while it runs
+    port: Option<u16>,
+    running: bool,
+    ready: bool,
+    /// A start, stop or restart is in progress
+    transitioning: bool,
+    pid: Option<u32>,
+    external: bool,
+    mode: AgentMode,
+    /// Unix timestamp (seconds)
+    started_at: Option<u64>,
+    uptime_secs: Option<u64>,
+    restart_count: u32,
+    memory_bytes: Option<u64>,
+    cpu_percent: Option<f32>,
+    paths: Option<AgentPaths>,
+    /// False when the agent answers without a token, e.g. a dev agent started by hand
+    auth_active: bool,
+}
+
+/// `project_id`'s own agent, or the shared one without it. Probes /health twice
+/// and refreshes sysinfo, so it stays off the main thread.
+#[tauri::command]
+async fn agent_status(
+    app: tauri::AppHandle,
+    project_id: Option<String>,
+) -> Result<AgentStatus, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let agent = match project_id {
+            // Never started: reported as stopped, without setting one up
+            Some(id) => state
+                .project_agent(&id)
+                .unwrap_or_else(|| Arc::new(Agent::new(Some(id), 0))),
+            None => state.shared_agent(),
+        };
+        status_of(&state, &agent)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+fn status_of(state: &AppState, agent: &Agent) -> AgentStatus {
+    let pid = agent.manager.pid();
+    // Mid start/stop the agent may be hanging in shutdown; don't wait on it
+    let transitioning = agent.manager.is_transitioning();
+    let healthy = !transitioning && check_health(agent);
+    if !healthy && !transitioning {
+        // An adopted agent that stopped answering is gone for good
+        agent.external.store(false, Ordering::SeqCst);
+    }
+    let external = pid.is_none() && agent.external.load(Ordering::SeqCst);
+    let running = pid.is_some() || external;
+
+    let (started, paths) = match pid {
+        Some(_) => (
+            *agent_manager::lock(&agent.started_at),
+            agent_manager::lock(&agent.paths).clone(),
+        ),
+        None => (None, None),
+    };
+    let (memory_bytes, cpu_percent) = match pid {
+        Some(pid) => process_usage(state, pid),
+        None => (None, None),
+    };
+
+    AgentStatus {
+        project_id: agent.project_id.clone(),
+        port: running.then(|| agent.port()),
+        running,
+        ready: running && healthy,
+        transitioning,
+        pid,
+        external,
+        mode: if external { AgentMode::Attached } else { AgentMode::Managed },
+        started_at: started
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+        uptime_secs: started
+            .and_then(|t| t.elapsed().ok())
+            .map(|d| d.as_secs()),
+        restart_count: agent.restart_count.load(Ordering::SeqCst),
+        memory_bytes,
+        cpu_percent,
+        paths,
+        auth_active: running && healthy && agent_requires_auth(agent),
+    }
+}
+
+/// Resident memory and CPU usage of a single process; only that pid is refreshed
+fn process_usage(state: &AppState, pid: u32) -> (Option<u64>, Option<f32>) {
+    let mut sys = agent_manager::lock(&state.sysinfo);
+    let pid = sysinfo::Pid::from_u32(pid);
+    if !sys.refresh_process(pid) {
+        return (None, None);
+    }
+    match sys.process(pid) {
+        Some(process) => (Some(process.memory()), Some(process.cpu_usage())),
+        None => (None, None),
+    }
+}
+
+/// Start `project_id`'s own agent, on a port of its own, or without it the
+/// shared one. Several can run at once, so switching projects keeps each warm.
+#[tauri::command]
+async fn start_agent(app: tauri::AppHandle, project_id: Option<String>) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let agent = agent_for(&state, project_id.as_deref())?;
+        let started = agent.manager.start(|| {
+            agent.suspended.store(false, Ordering::SeqCst);
+            // agent_for made sure external agent mode only ever has the shared agent
+            let claim = if state.watchdog.external_agent_mode.load(Ordering::SeqCst) {
+                attach_agent(&agent).map(|()| PortClaim::Adopted)
+            } else {
+                claim_agent_port(&state, &agent)
+            };
+            if let PortClaim::Adopted = claim? {
+                if agent.project_id.is_none() {
+                    tray::set_indicator(&app, tray::Indicator::Ready);
+                }
+                return Ok(None);
+            }
+            check_python(&app)?;
+            spawn_agent(&app, &agent, &state.data_dir()).map(Some)
+        })?;
+        Ok(match started {
+            Started::Spawned(_) => format!("{} started on port {}", agent.name(), agent.port()),
+            Started::Adopted => {
+                format!("Adopted the agent already running on port {}", agent.port())
+            }
+            Started::AlreadyRunning => format!("{} already running", agent.name()),
+            Started::AlreadyStarting => format!("{} is already starting", agent.name()),
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// `project_id`'s own agent, made on first use with a free port of its own;
+/// the shared agent without one. Project agents are always spawned by the app,
+/// so external agent mode has none.
+fn agent_for(state: &AppState, project_id: Option<&str>) -> Result<AgentHandle, String> {
+    let Some(project_id) = project_id else {
+        return Ok(state.shared_agent());
+    };
+    if state.watchdog.external_agent_mode.load(Ordering::SeqCst) {
+        return Err("External agent mode is on; only the shared agent can run".into());
+    }
+    if let Some(agent) = state.project_agent(project_id) {
+        return Ok(agent);
+    }
+    // The id goes into the agent's runtime file name
+    let valid = !project_id.is_empty()
+        && project_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("Invalid project id: {}", project_id));
+    }
+    state
+        .db
+        .get_project(project_id)
+        .map_err(|_| format!("Project not found: {}", project_id))?;
+    let port = free_port()?;
+    let mut agents = agent_manager::lock(&state.agents);
+    let agent = agents
+        .entry(project_id.to_string())
+        .or_insert_with(|| Arc::new(Agent::new(Some(project_id.to_string()), port)));
+    Ok(agent.clone())
+}
+
+/// The project's own agent while it runs, otherwise the shared one
+fn agent_serving(state: &AppState, project_id: Option<&str>) -> AgentHandle {
+    project_id
+        .and_then(|id| state.project_agent(id))
+        .filter(|agent| agent.is_running())
+        .unwrap_or_else(|| state.shared_agent())
+}
+
+/// A port nothing listens on right now, for a project's agent
+fn free_port() -> Result<u16, String> {
+    std::net::TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| format!("No free port for the agent: {}", e))
+}
+
+/// Resolve once the agent answers /health: Ok(true) when ready, Ok(false) on
+/// timeout or when the watchdog sees it crash-looping
+#[tauri::command]
+async fn wait_for_agent_ready(
+    app: tauri::AppHandle,
+    timeout_ms: u64,
+    project_id: Option<String>,
+) -> Result<bool, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let agent = match &project_id {
+            Some(id) => match state.project_agent(id) {
+                Some(agent) => agent,
+                None => return false,
+            },
+            None => state.shared_agent(),
+        };
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+        loop {
+            if check_health(&agent) {
+                return true;
+            }
+            if in_crash_loop(&agent) || Instant::now() >= deadline {
+                return false;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            std::thread::sleep(READY_POLL_INTERVAL.min(remaining));
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// What the app resolved at launch, for a status bar. Also sent as `app://ready`
+/// from setup; this is for a frontend that started listening too late.
+#[tauri::command]
+fn get_startup_info(app: tauri::AppHandle) -> events::AppReady {
+    startup_info(&app)
+}
+
+fn startup_info(app: &tauri::AppHandle) -> events::AppReady {
+    let state = app.state::<AppState>();
+    events::AppReady {
+        data_dir: state.data_dir(),
+        agent_port: state.agent_port(),
+        python_found: fake_agent::enabled() || AgentPaths::resolve(app).python.is_file(),
+        debug: cfg!(debug_assertions),
+        data_dir_fallback: state.data_dir_fallback.clone(),
+    }
+}
+
+/// Fail early with the offending path instead of an OS error from spawning uvicorn
+fn check_python(app: &tauri::AppHandle) -> Result<(), String> {
+    if fake_agent::enabled() {
+        return Ok(());
+    }
+    let python = AgentPaths::resolve(app).python;
+    let version = agent_env::verify_python(&python).map_err(|e| {
+        format!("bundled Python not found or not runnable: {}: {}", python.display(), e)
+    })?;
+    info!(version = %version, python = %python.display(), "using python");
+    Ok(())
+}
+
+#[tauri::command]
+async fn stop_agent(app: tauri::AppHandle, project_id: Option<String>) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let agent = match &project_id {
+            Some(id) => match state.project_agent(id) {
+                Some(agent) => agent,
+                None => return Ok(format!("No agent is running for project {}", id)),
+            },
+            None => state.shared_agent(),
+        };
+        let lifecycle = agent.manager.lock();
+        if !agent.manager.is_running() && agent.external.load(Ordering::SeqCst) {
+            return Ok(format!(
+                "The agent on port {} was started outside the app; stop it where it runs",
+                agent.port()
+            ));
+        }
+        agent.suspended.store(true, Ordering::SeqCst);
+        cancel_agent_streams(&state, &agent);
+
+        match lifecycle.take() {
+            Some(child) => match stop_child(&app, &state, &agent, child) {
+                Shutdown::Graceful => Ok(format!("{} stopped gracefully", agent.name())),
+                Shutdown::Forced => Ok(format!(
+                    "{} did not exit in time and was force-stopped",
+                    agent.name()
+                )),
+            },
+            None => Ok(format!("{} not running", agent.name())),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn restart_agent(
+    app: tauri::AppHandle,
+    project_id: Option<String>,
+) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        if project_id.is_none() && state.watchdog.external_agent_mode.load(Ordering::SeqCst) {
+            return Err("External agent mode is on; restart the agent where it runs".into());
+        }
+        let agent = agent_for(&state, project_id.as_deref())?;
+        let lifecycle = agent.manager.lock();
+        take_down_agent(&app, &state, &agent, &lifecycle);
+        agent.suspended.store(false, Ordering::SeqCst);
+        lifecycle
+            .spawn(|| spawn_agent(&app, &agent, &state.data_dir()).map(Some))
+            .map_err(|e| format!("Failed to restart agent: {}", e))?;
+        Ok(format!("{} restarted", agent.name()))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[derive(Serialize)]
+struct ResetStep {
+    step: &'static str,
+    duration_ms: u64,
+    detail: String,
+}
+
+#[derive(Serialize)]
+struct ResetReport {
+    /// What was done, in order
+    steps: Vec<ResetStep>,
+    /// The new agent's pid
+    pid: Option<u32>,
+    restart_count: u32,
+}
+
+/// For a wedged shared agent that still answers /health: cancel its in-flight streams,
+/// stop the agent (gracefully, then by force), remove a stale runtime file, wait
+/// until the port can be bound again, then spawn a fresh one. Holds the lifecycle
+/// lock throughout, so the watchdog can't spawn one of its own meanwhile.
+#[tauri::command]
+async fn force_reset_agent(app: tauri::AppHandle) -> Result<ResetReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        if state.watchdog.external_agent_mode.load(Ordering::SeqCst) {
+            return Err("External agent mode is on; restart the agent where it runs".into());
+        }
+        let agent = state.shared_agent();
+        let lifecycle = agent.manager.lock();
+        let mut steps = Vec::new();
+        let mut timed = |step: &'static str, action: &mut dyn FnMut() -> String| {
+            let started = Instant::now();
+            let detail = action();
+            let duration_ms = started.elapsed().as_millis() as u64;
+            info!(step, duration_ms, detail = %detail, "force reset");
+            steps.push(ResetStep { step, duration_ms, detail });
+        };
+
+        timed("cancel_streams", &mut || {
+            let count = cancel_agent_streams(&state, &agent);
+            format!("Cancelled {} streaming requests", count)
+        });
+        let mut previous_pid = None;
+        timed("stop", &mut || match lifecycle.take() {
+            Some(child) => {
+                let pid = child.id();
+                previous_pid = Some(pid);
+                match stop_child(&app, &state, &agent, child) {
+                    Shutdown::Graceful => format!("Agent {} exited gracefully", pid),
+                    Shutdown::Forced => format!("Agent {} was killed after the grace period", pid),
+                }
+            }
+            None if agent.external.swap(false, Ordering::SeqCst) => {
+                let _ = call_agent(&agent, "POST", "/shutdown", None, Duration::from_secs(2));
+                let stopped = events::AgentStopped { project_id: None, pid: None, forced: false };
+                let _ = app.emit(events::AGENT_STOPPED, stopped);
+                "Asked the adopted agent to shut down".into()
+            }
+            None => "No agent was running".into(),
+        });
+        timed("clear_runtime_file", &mut || {
+            let runtime_file = runtime_file_path(&state.data_dir(), &agent);
+            let killed = kill_orphaned_agent(&state, &runtime_file, &[]);
+            let _ = std::fs::remove_file(&runtime_file);
+            if killed {
+                "Killed the agent recorded in the runtime file and removed it".into()
+            } else {
+                // kill_orphaned_agent leaves any pid that isn't verifiably our agent alone
+                "Removed the runtime file; killed nothing".into()
+            }
+        });
+        let port = agent.port();
+        let mut released = false;
+        timed("wait_for_port", &mut || {
+            released = wait_until_bindable(port, PORT_RELEASE_TIMEOUT);
+            if released {
+                format!("Port {} is free", port)
+            } else {
+                format!("Port {} is still taken", port)
+            }
+        });
+        if !released {
+            let done: Vec<String> =
+                steps.iter().map(|s| format!("{}: {}", s.step, s.detail)).collect();
+            return Err(format!(
+                "Port {} was not released within {} seconds; try again or restart the app ({})",
+                port,
+                PORT_RELEASE_TIMEOUT.as_secs(),
+                done.join("; ")
+            ));
+        }
+
+        let restart_count = agent.restart_count.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(previous_pid) = previous_pid {
+            let restarting =
+                events::AgentRestarting { project_id: None, previous_pid, restart_count };
+            let _ = app.emit(events::AGENT_RESTARTING, restarting);
+        }
+        agent.suspended.store(false, Ordering::SeqCst);
+        let mut spawned = Err(String::new());
+        timed("spawn", &mut || {
+            spawned = lifecycle.spawn(|| spawn_agent(&app, &agent, &state.data_dir()).map(Some));
+            match &spawned {
+                Ok(_) => format!("Spawned a new agent on port {}", port),
+                Err(e) => format!("Failed: {}", e),
+            }
+        });
+        spawned.map_err(|e| format!("Failed to restart agent: {}", e))?;
+        Ok(ResetReport { steps, pid: agent.manager.pid(), restart_count })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Move the shared agent to another port without relaunching the app. The port must be
+/// free before the running agent is touched; the agent is then stopped, restarted
+/// on the new port (which rewrites agent-runtime.json) and the port is saved for
+/// the next launch. An agent that wasn't running stays stopped.
+#[tauri::command]
+async fn set_agent_port(app: tauri::AppHandle, port: u16) -> Result<(), String> {
+    if port < settings::MIN_AGENT_PORT {
+        return Err(format!(
+            "Agent port must be between {} and 65535",
+            settings::MIN_AGENT_PORT
+        ));
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let agent = state.shared_agent();
+        // Held throughout so the watchdog can't restart the agent on the old port
+        let lifecycle = agent.manager.lock();
+        let old_port = agent.port();
+        if port == old_port {
+            return Ok(());
+        }
+        check_port_available(port)?;
+
+        let was_running = take_down_agent(&app, &state, &agent, &lifecycle);
+        info!(from = old_port, to = port, "moving agent to another port");
+        agent.port.store(port, Ordering::SeqCst);
+        let respawn = || spawn_agent(&app, &agent, &state.data_dir()).map(Some);
+        if was_running {
+            agent.suspended.store(false, Ordering::SeqCst);
+            if let Err(e) = lifecycle.spawn(respawn) {
+                // Back to where it was, so the saved setting still names a working port
+                agent.port.store(old_port, Ordering::SeqCst);
+                if let Err(e) = lifecycle.spawn(respawn) {
+                    error!(port = old_port, error = %e, "failed to restart the agent");
+                }
+                return Err(format!("Failed to start the agent on port {}: {}", port, e));
+            }
+        }
+        // Saved only now that the agent runs there
+        change_settings(&app, serde_json::json!({ "agent_port": port }))?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Stop whichever agent is serving `agent`'s port, ours or adopted, and wait for
+/// it to go away. Returns whether one was running.
+fn take_down_agent(
+    app: &tauri::AppHandle,
+    state: &AppState,
+    agent: &Agent,
+    lifecycle: &Lifecycle,
+) -> bool {
+    cancel_agent_streams(state, agent);
+    let child = lifecycle.take();
+    if let Some(child) = child {
+        stop_child(app, state, agent, child);
+        true
+    } else if agent.external.swap(false, Ordering::SeqCst) {
+        let _ = call_agent(agent, "POST", "/shutdown", None, Duration::from_secs(2));
+        wait_for_port_release(agent.port(), shutdown_grace(state));
+        let project_id = agent.project_id.clone();
+        let stopped = events::AgentStopped { project_id, pid: None, forced: false };
+        let _ = app.emit(events::AGENT_STOPPED, stopped);
+        true
+    } else {
+        false
+    }
+}
+
+/// Kill agents left behind by a previous session, as recorded in their runtime
+/// files. Returns whether anything was killed.
+#[tauri::command]
+async fn kill_orphaned_agents(app: tauri::AppHandle) -> Result<bool, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let current: Vec<u32> =
+            state.all_agents().iter().filter_map(|agent| agent.manager.pid()).collect();
+        let data_dir = state.data_dir();
+        let mut files = project_runtime_files(&data_dir);
+        files.push(runtime_file_path(&data_dir, &state.shared_agent()));
+        let mut killed = false;
+        for file in files {
+            killed |= kill_orphaned_agent(&state, &file, &current);
+        }
+        killed
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct AgentInfo {
+    version: String,
+    python_version: String,
+    #[serde(default)]
+    models: Vec<String>,
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+#[tauri::command]
+async fn agent_info(app: tauri::AppHandle) -> Result<AgentInfo, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        fetch_agent_info(&app.state::<AppState>().shared_agent())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn fetch_agent_info(agent: &Agent) -> Result<AgentInfo, String> {
+    let resp = call_agent(agent, "GET", "/info", None, Duration::from_secs(5))?;
+    if !resp.is_success() {
+        return Err(format!("Agent returned {} for /info", resp.status));
+    }
+    serde_json::from_str(&resp.body).map_err(|e| format!("Invalid /info response: {}", e))
+}
+
+/// Check the agent layer by layer, timing each: the process, a TCP connect to
+/// its port, /health, then /info, which reads the database. The report names
+/// the first layer that failed.
+#[tauri::command]
+async fn agent_selftest(app: tauri::AppHandle) -> Result<agent_selftest::SelfTestReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let agent = app.state::<AppState>().shared_agent();
+        let port = agent.port();
+        let mut test = agent_selftest::SelfTest::new(port);
+        test.step(agent_selftest::PROCESS, || match agent.manager.pid() {
+            Some(pid) => Ok(format!("Running as pid {}", pid)),
+            None if agent.external.load(Ordering::SeqCst) => {
+                Ok("Adopted from another session".into())
+            }
+            None if agent.manager.is_transitioning() => {
+                Err("The agent is starting or stopping".into())
+            }
+            None => Err("The agent is not running".into()),
+        });
+        test.step(agent_selftest::TCP_CONNECT, || {
+            let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+            std::net::TcpStream::connect_timeout(&addr, Duration::from_secs(2))
+                .map(|_| format!("Port {} accepts connections", port))
+                .map_err(|e| format!("Cannot connect to port {}: {}", port, e))
+        });
+        test.step(agent_selftest::HEALTH, || {
+            let resp = call_agent(&agent, "GET", "/health", None, Duration::from_secs(5))
+                .map_err(|e| e.to_string())?;
+            let body: serde_json::Value = serde_json::from_str(&resp.body).unwrap_or_default();
+            let version = body["version"].as_str().unwrap_or("?");
+            match resp.status {
+                200..=299 => Ok(format!("Agent {} is ready", version)),
+                401 => Err("Another session's agent holds the port; it rejected our token".into()),
+                503 => Err(format!(
+                    "The agent is still starting up: {}",
+                    body["message"].as_str().unwrap_or("not ready")
+                )),
+                status => Err(format!("/health returned {}", status)),
+            }
+        });
+        test.step(agent_selftest::ROUND_TRIP, || {
+            fetch_agent_info(&agent).map(|info| format!("{} models configured", info.models.len()))
+        });
+        test.report()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Payload of `agent://incompatible`
+#[derive(Clone, Serialize)]
+struct AgentIncompatible {
+    version: String,
+    min_version: String,
+}
+
+/// Compare dotted numeric versions; missing components count as 0
+fn version_at_least(version: &str, min: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| {
+                part.chars()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect::<String>()
+                    .parse()
+                    .unwrap_or(0)
+            })
+            .collect()
+    };
+    let (version, min) = (parse(version), parse(min));
+    for i in 0..version.len().max(min.len()) {
+        let (a, b) = (version.get(i).unwrap_or(&0), min.get(i).unwrap_or(&0));
+        if a != b {
+            return a > b;
+        }
+    }
+    true
+}
+
+/// Once the agent answers, warn if it's older than this build expects. Agents
+/// predating /info 404 there and are reported as incompatible too.
+fn check_agent_compatibility(app: &tauri::AppHandle) {
+    let agent = app.state::<AppState>().shared_agent();
+    let deadline = Instant::now() + AGENT_READY_TIMEOUT;
+    while !check_health(&agent) {
+        if Instant::now() >= deadline {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    let version = match fetch_agent_info(&agent) {
+        Ok(info) if version_at_least(&info.version, MIN_AGENT_VERSION) => return,
+        Ok(info) => info.version,
+        Err(e) => {
+            warn!(error = %e, "could not read agent info");
+            "unknown".to_string()
+        }
+    };
+    warn!(version = %version, min_version = MIN_AGENT_VERSION, "agent is older than required");
+    let _ = app.emit(
+        events::AGENT_INCOMPATIBLE,
+        AgentIncompatible { version, min_version: MIN_AGENT_VERSION.to_string() },
+    );
+}
+
+/// Check the resolved Python interpreter, its packages and the agent directory
+/// before the first start; the probes can take a while, so run them off the main thread
+#[tauri::command]
+async fn validate_agent_environment(
+    app: tauri::AppHandle,
+) -> Result<agent_env::EnvironmentReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let paths = AgentPaths::resolve(&app);
+        let mut report = agent_env::validate(&paths.python, &paths.agent_dir);
+        report.interpreter_from_override = paths.python_from_override;
+        report.agent_dir_from_override = paths.agent_dir_from_override;
+        if let Err(e) = paths.check_overrides() {
+            report.ok = false;
+            report.error = Some(e);
+        }
+        report
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+struct ProxyResponse {
+    status: u16,
+    /// Lower-cased names; repeated headers are joined with ", "
+    headers: std::collections::BTreeMap<String, String>,
+    body: String,
+}
+
+/// Forward a request to the agent so the webview never talks to the agent port
+/// itself: the session token is attached here and the port stays an internal detail.
+/// `body` is sent verbatim as JSON; non-2xx statuses are returned, not raised.
+/// With `project_id` it goes to that project's agent if one runs.
+#[tauri::command]
+async fn agent_request(
+    app: tauri::AppHandle,
+    method: String,
+    path: String,
+    body: Option<String>,
+    timeout_ms: Option<u64>,
+    project_id: Option<String>,
+) -> Result<ProxyResponse, agent_http::Error> {
+    let method = method.to_uppercase();
+    if !matches!(method.as_str(), "GET" | "POST" | "PUT" | "PATCH" | "DELETE") {
+        return Err(agent_http::Error::InvalidRequest(format!("Unsupported method: {}", method)));
+    }
+    if !path.starts_with('/') || path.chars().any(char::is_control) {
+        return Err(agent_http::Error::InvalidRequest(format!("Invalid agent path: {}", path)));
+    }
+    let timeout = timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_secs(DEFAULT_PROXY_TIMEOUT_SECS));
+
+    let resp = tauri::async_runtime::spawn_blocking(move || {
+        let agent = agent_serving(&app.state::<AppState>(), project_id.as_deref());
+        call_agent(&agent, &method, &path, body.as_deref(), timeout)
+    })
+    .await
+    .map_err(|e| agent_http::Error::Transport(e.to_string()))??;
+
+    let mut headers = std::collections::BTreeMap::<String, String>::new();
+    for (name, value) in resp.headers {
+        headers
+            .entry(name.to_lowercase())
+            .and_modify(|v| {
+                v.push_str(", ");
+                v.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    Ok(ProxyResponse {
+        status: resp.status,
+        headers,
+        body: resp.body,
+    })
+}
+
+/// Payload of `agent://stream/{request_id}`
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    Start { status: u16 },
+    Chunk { data: String },
+    Done,
+    Cancelled,
+    Error { error: agent_http::Error },
+}
+
+/// Stream a long-running agent response (SSE or chunked) to the calling window
+/// as `agent://stream/{request_id}` events: one `start`, a `chunk` per piece of
+/// body, then `done`, `cancelled` or `error`. Returns once the request is queued.
+/// With `project_id` it goes to that project's agent if one runs.
+#[tauri::command]
+fn agent_stream_request(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    request_id: String,
+    method: String,
+    path: String,
+    body: Option<String>,
+    project_id: Option<String>,
+) -> Result<(), agent_http::Error> {
+    let invalid = agent_http::Error::InvalidRequest;
+    if request_id.is_empty()
+        || !request_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(invalid(format!("Invalid request id: {}", request_id)));
+    }
+    let method = method.to_uppercase();
+    if !matches!(method.as_str(), "GET" | "POST") {
+        return Err(invalid(format!("Unsupported method for streaming: {}", method)));
+    }
+    if !path.starts_with('/') || path.chars().any(char::is_control) {
+        return Err(invalid(format!("Invalid agent path: {}", path)));
+    }
+
+    let state = app.state::<AppState>();
+    let agent = agent_serving(&state, project_id.as_deref());
+    {
+        let mut streams = agent_manager::lock(&state.streams);
+        if streams.contains_key(&request_id) {
+            return Err(invalid(format!("Request {} is already running", request_id)));
+        }
+        let active = ActiveStream {
+            window: window.label().to_string(),
+            agent: agent.project_id.clone(),
+            socket: None,
+        };
+        streams.insert(request_id.clone(), active);
+    }
+    std::thread::spawn(move || run_stream(app, agent, request_id, method, path, body));
+    Ok(())
+}
+
+fn run_stream(
+    app: tauri::AppHandle,
+    agent: AgentHandle,
+    request_id: String,
+    method: String,
+    path: String,
+    body: Option<String>,
+) {
+    let state = app.state::<AppState>();
+    let event = format!("{}{}", events::AGENT_STREAM_PREFIX, request_id);
+    let emit = |payload: StreamEvent| {
+        let _ = app.emit(&event, payload);
+    };
+    // Cancellation removes the entry, possibly before we got to connect
+    let cancelled = || !agent_manager::lock(&state.streams).contains_key(&request_id);
+
+    let bearer = agent_bearer(&agent);
+    let opened = agent_http::open_stream(
+        agent.port(),
+        &method,
+        &path,
+        &[("Authorization", bearer.as_str()), ("Accept", "text/event-stream")],
+        body.as_deref(),
+        Duration::from_secs(10),
+    );
+    let (mut stream, socket) = match opened {
+        Ok(opened) => opened,
+        Err(error) => {
+            agent_manager::lock(&state.streams).remove(&request_id);
+            emit(StreamEvent::Error { error });
+            return;
+        }
+    };
+    match agent_manager::lock(&state.streams).get_mut(&request_id) {
+        Some(active) => active.socket = Some(socket),
+        None => {
+            let _ = socket.shutdown(std::net::Shutdown::Both);
+            emit(StreamEvent::Cancelled);
+            return;
+        }
+    }
+
+    emit(StreamEvent::Start { status: stream.status });
+    // Chunks can end mid-character; hold the partial bytes for the next one
+    let mut pending = Vec::new();
+    let outcome = loop {
+        match stream.next_chunk() {
+            Ok(Some(bytes)) => {
+                pending.extend_from_slice(&bytes);
+                let valid = match std::str::from_utf8(&pending) {
+                    Ok(text) => text.len(),
+                    Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                    Err(_) => pending.len(),
+                };
+                let rest = pending.split_off(valid);
+                let data = String::from_utf8_lossy(&pending).into_owned();
+                pending = rest;
+                if !data.is_empty() {
+                    emit(StreamEvent::Chunk { data });
+                }
+            }
+            Ok(None) => break StreamEvent::Done,
+            Err(error) => break StreamEvent::Error { error },
+        }
+    };
+
+    if cancelled() {
+        emit(StreamEvent::Cancelled);
+        return;
+    }
+    agent_manager::lock(&state.streams).remove(&request_id);
+    emit(outcome);
+}
+
+/// Drop the connection so uvicorn sees the client go away and stops generating
+#[tauri::command]
+fn agent_cancel_request(state: State<AppState>, request_id: String) -> bool {
+    let removed = agent_manager::lock(&state.streams).remove(&request_id);
+    match removed {
+        Some(active) => {
+            if let Some(socket) = active.socket {
+                let _ = socket.shutdown(std::net::Shutdown::Both);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Ask the agent to stop the generation job `job_id` (POST /jobs/{job_id}/cancel)
+/// and wait for its acknowledgement. Fails with "agent not reachable" when the
+/// agent is down and "job not found" when it has no such job, usually because
+/// it already finished. Jobs started through `project_id`'s agent are cancelled
+/// there.
+#[tauri::command]
+async fn cancel_generation(
+    app: tauri::AppHandle,
+    job_id: String,
+    project_id: Option<String>,
+) -> Result<(), String> {
+    let valid = !job_id.is_empty()
+        && job_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("Invalid job id: {}", job_id));
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let agent = agent_serving(&app.state::<AppState>(), project_id.as_deref());
+        let path = format!("/jobs/{}/cancel", job_id);
+        let resp = call_agent(&agent, "POST", &path, None, Duration::from_secs(10)).map_err(|e| {
+            warn!(job_id = %job_id, error = %e, "cancel_generation: agent unreachable");
+            "agent not reachable".to_string()
+        })?;
+        match resp.status {
+            status if (200..300).contains(&status) => Ok(()),
+            404 => Err("job not found".to_string()),
+            status => Err(format!("Agent rejected the cancel ({}): {}", status, resp.body)),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Cancel the in-flight streams feeding `window`
+fn cancel_streams(state: &AppState, window: &str) -> usize {
+    cancel_streams_where(state, |active| active.window == window)
+}
+
+/// Cancel the in-flight streams served by `agent`, before it goes away
+fn cancel_agent_streams(state: &AppState, agent: &Agent) -> usize {
+    cancel_streams_where(state, |active| active.agent == agent.project_id)
+}
+
+fn cancel_streams_where(state: &AppState, cancel: impl Fn(&ActiveStream) -> bool) -> usize {
+    let mut streams = agent_manager::lock(&state.streams);
+    let before = streams.len();
+    streams.retain(|_, active| {
+        if !cancel(active) {
+            return true;
+        }
+        if let Some(socket) = &active.socket {
+            let _ = socket.shutdown(std::net::Shutdown::Both);
+        }
+        false
+    });
+    before - streams.len()
+}
+
+#[derive(Clone, Serialize)]
+struct InstallProgress {
+    line: String,
+}
+
+#[derive(Serialize)]
+struct InstallResult {
+    success: bool,
+    /// Last lines of pip output when the install failed
+    error: Option<String>,
+    report: agent_env::EnvironmentReport,
 }
 
-// ---- Project Commands ----
+/// pip-install the agent's requirements, streaming output as `agent://install-progress`
+#[tauri::command]
+async fn install_agent_dependencies(app: tauri::AppHandle) -> Result<InstallResult, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let Some(_installing) = agent_manager::Flag::try_raise(&state.installing_dependencies)
+        else {
+            return Err("Dependency installation is already running".to_string());
+        };
+
+        let AgentPaths { python, agent_dir, .. } = AgentPaths::resolve(&app);
+        let outcome = agent_env::install_dependencies(
+            &python,
+            &agent_dir,
+            DEPENDENCY_INSTALL_TIMEOUT,
+            |line| {
+                let _ = app.emit(events::AGENT_INSTALL_PROGRESS, InstallProgress { line: line.to_string() });
+            },
+        );
+        let report = agent_env::validate(&python, &agent_dir);
+
+        Ok(InstallResult {
+            success: outcome.is_ok() && report.ok,
+            error: outcome.err(),
+            report,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// ---- Indexing ----
+
+/// Payload of the `reindex-progress` event, mirrored from GET /rag/reindex/{project_id}
+#[derive(Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+struct ReindexProgress {
+    project_id: String,
+    status: String,
+    done: u64,
+    total: u64,
+    error: String,
+}
 
+/// Ask the agent to rebuild the project's chapter embeddings. Returns once the
+/// agent has accepted the job; progress arrives as `reindex-progress` events.
+/// Refused up front when the data directory's disk looks too full for the index.
 #[tauri::command]
-fn list_projects(state: State<AppState>) -> Result<Vec<Project>, String> {
-    state.db.list_projects().map_err(|e| e.to_string())
+async fn reindex_project(app: tauri::AppHandle, project_id: String) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        // The project's own agent keeps the index it builds warm
+        let agent = agent_serving(&state, Some(&project_id));
+        if !check_health(&agent) {
+            return Err("agent not ready".into());
+        }
+        // The agent writes the index under the data directory
+        let needed = state.db.reindex_size_estimate(&project_id).map_err(|e| e.to_string())?;
+        data_location::check_free_space(std::path::Path::new(&state.data_dir()), needed)?;
+        let body = serde_json::json!({ "project_id": project_id }).to_string();
+        let resp =
+            call_agent(&agent, "POST", "/rag/reindex", Some(&body), Duration::from_secs(10))?;
+        if !resp.is_success() {
+            return Err(format!("Agent rejected reindex ({}): {}", resp.status, resp.body));
+        }
+        let app = app.clone();
+        std::thread::spawn(move || watch_reindex(app, agent, project_id));
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn watch_reindex(app: tauri::AppHandle, agent: AgentHandle, project_id: String) {
+    let path = format!("/rag/reindex/{}", project_id);
+    loop {
+        std::thread::sleep(Duration::from_millis(500));
+        let mut progress = match call_agent(&agent, "GET", &path, None, Duration::from_secs(5)) {
+            Ok(resp) if resp.is_success() => {
+                serde_json::from_str::<ReindexProgress>(&resp.body).unwrap_or_default()
+            }
+            Ok(resp) => ReindexProgress {
+                status: "failed".into(),
+                error: format!("Agent returned {}", resp.status),
+                ..Default::default()
+            },
+            Err(e) => ReindexProgress {
+                status: "failed".into(),
+                error: e.to_string(),
+                ..Default::default()
+            },
+        };
+        progress.project_id = project_id.clone();
+        let finished = progress.status != "running";
+        let _ = app.emit(events::REINDEX_PROGRESS, progress);
+        if finished {
+            return;
+        }
+    }
 }
 
+/// Whether the agent's embeddings still match the project: built at all, with the
+/// project's current embedding_dim, and from the chapters as they are now
 #[tauri::command]
-fn create_project(state: State<AppState>, name: String, genre: String) -> Result<Project, String> {
-    state.db.create_project(&name, &genre).map_err(|e| e.to_string())
+async fn get_embedding_index_status(
+    app: tauri::AppHandle,
+    project_id: String,
+) -> Result<embedding_index::EmbeddingIndexStatus, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        state
+            .db
+            .embedding_index_status(&project_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Project not found: {}", project_id))
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
+/// Flag the project's embeddings as out of date. Chapter edits, imports and
+/// replacements already do; returns false if the project was never indexed.
 #[tauri::command]
-fn get_data_dir(state: State<AppState>) -> String {
-    state.data_dir.clone()
+fn mark_embedding_index_stale(state: State<AppState>, project_id: String) -> Result<bool, String> {
+    state.db.mark_embedding_index_stale(&project_id).map_err(|e| e.to_string())
 }
 
-// ---- Agent Process Management ----
+// ---- Watchdog Configuration ----
 
 #[derive(Serialize)]
-struct AgentStatus {
-    running: bool,
-    ready: bool,
-    pid: Option<u32>,
+struct WatchdogStatus {
+    enabled: bool,
+    suspended: bool,
+    interval_secs: u64,
 }
 
 #[tauri::command]
-fn agent_status(state: State<AppState>) -> AgentStatus {
-    let proc = state.agent_process.lock().unwrap();
-    let (running, pid) = match proc.as_ref() {
-        Some(child) => (true, Some(child.id())),
-        None => (false, None),
-    };
-    let ready = running && check_health();
-    AgentStatus { running, ready, pid }
+fn get_watchdog_config(state: State<AppState>) -> WatchdogStatus {
+    WatchdogStatus {
+        enabled: state.watchdog.enabled.load(Ordering::SeqCst),
+        suspended: state.shared_agent().suspended.load(Ordering::SeqCst),
+        interval_secs: state.watchdog.interval_secs.load(Ordering::SeqCst),
+    }
 }
 
 #[tauri::command]
-fn start_agent(state: State<AppState>, app: tauri::AppHandle) -> Result<String, String> {
-    let mut proc = state.agent_process.lock().map_err(|e| e.to_string())?;
-    if proc.is_some() {
-        return Ok("Agent already running".into());
-    }
+fn set_watchdog_enabled(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    change_settings(&app, serde_json::json!({ "watchdog_enabled": enabled })).map(|_| ())
+}
+
+#[tauri::command]
+fn set_watchdog_interval(app: tauri::AppHandle, interval_secs: u64) -> Result<(), String> {
+    change_settings(&app, serde_json::json!({ "watchdog_interval_secs": interval_secs }))
+        .map(|_| ())
+}
+
+// ---- App Settings ----
 
-    let child = spawn_agent(&app, &state.data_dir)
-        .ok_or_else(|| "Failed to start agent process".to_string())?;
-    *proc = Some(child);
-    Ok("Agent started on port 8765".into())
+#[tauri::command]
+fn get_app_settings(state: State<AppState>) -> settings::AppSettings {
+    settings::load(&state.db)
 }
 
+/// Partial update: only the fields present in `patch` change
 #[tauri::command]
-fn stop_agent(state: State<AppState>) -> Result<String, String> {
-    let mut proc = state.agent_process.lock().map_err(|e| e.to_string())?;
-    if let Some(child) = proc.take() {
-        kill_process_tree(child);
-        Ok("Agent stopped".into())
-    } else {
-        Ok("Agent not running".into())
-    }
+fn update_app_settings(
+    app: tauri::AppHandle,
+    patch: serde_json::Value,
+) -> Result<settings::AppSettings, String> {
+    change_settings(&app, patch)
 }
 
+/// One setting by name, e.g. "theme" or "last_open_project_id"; None for names
+/// that aren't settings
 #[tauri::command]
-fn restart_agent(state: State<AppState>, app: tauri::AppHandle) -> Result<String, String> {
-    let mut proc = state.agent_process.lock().map_err(|e| e.to_string())?;
-    if let Some(child) = proc.take() {
-        kill_process_tree(child);
+fn get_setting(state: State<AppState>, key: String) -> Option<serde_json::Value> {
+    settings::get(&settings::load(&state.db), &key)
+}
+
+/// Change one setting, validated like update_app_settings; returns the value stored
+#[tauri::command]
+fn set_setting(
+    app: tauri::AppHandle,
+    key: String,
+    value: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let mut patch = serde_json::Map::new();
+    patch.insert(key.clone(), value);
+    let updated = change_settings(&app, serde_json::Value::Object(patch))?;
+    Ok(settings::get(&updated, &key).unwrap_or_default())
+}
+
+/// Validate, persist and apply a settings patch, then tell every window via
+/// `settings://changed`
+fn change_settings(
+    app: &tauri::AppHandle,
+    patch: serde_json::Value,
+) -> Result<settings::AppSettings, String> {
+    let serde_json::Value::Object(patch) = patch else {
+        return Err("Settings update must be an object".into());
+    };
+    let state = app.state::<AppState>();
+    let current = settings::load(&state.db);
+    let updated = settings::merge(&current, patch)?;
+    if updated.python_path_override != current.python_path_override {
+        if let Some(path) = &updated.python_path_override {
+            check_python_override(std::path::Path::new(path))?;
+        }
+    }
+    if updated.agent_dir_override != current.agent_dir_override {
+        if let Some(path) = &updated.agent_dir_override {
+            check_agent_dir_override(std::path::Path::new(path))?;
+        }
     }
-    let child = spawn_agent(&app, &state.data_dir)
-        .ok_or_else(|| "Failed to restart agent".to_string())?;
-    *proc = Some(child);
-    Ok("Agent restarted".into())
+
+    // Taken by the next launch; the port the agent runs on now is in use by it
+    if updated.agent_port != current.agent_port && updated.agent_port != state.agent_port() {
+        check_port_available(updated.agent_port)?;
+    }
+
+    settings::save(&state.db, &current, &updated)?;
+    state.watchdog.apply(&updated);
+    state.close_to_tray.store(updated.close_to_tray, Ordering::SeqCst);
+    state.notifications_enabled.store(updated.notifications_enabled, Ordering::SeqCst);
+    app_log::set_level(&updated.log_level)?;
+    let _ = app.emit(events::SETTINGS_CHANGED, updated.clone());
+    Ok(updated)
+}
+
+fn agent_bearer(agent: &Agent) -> String {
+    format!("Bearer {}", agent_manager::lock(&agent.token))
+}
+
+/// Send a request to the agent with its session token attached
+fn call_agent(
+    agent: &Agent,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<agent_http::Response, agent_http::Error> {
+    let bearer = agent_bearer(agent);
+    let headers = [("Authorization", bearer.as_str())];
+    agent_http::request(agent.port(), method, path, &headers, body, timeout)
+}
+
+/// Whether the agent turns away requests that carry no token
+fn agent_requires_auth(agent: &Agent) -> bool {
+    agent_http::request(agent.port(), "GET", "/health", &[], None, Duration::from_millis(500))
+        .map(|resp| resp.status == 401)
+        .unwrap_or(false)
+}
+
+/// Check if the agent HTTP service is responding to us. The agent requires the
+/// session token even on /health, so another app probing the port gets a 401.
+fn check_health(agent: &Agent) -> bool {
+    call_agent(agent, "GET", "/health", None, Duration::from_millis(500))
+        .map(|resp| resp.is_success())
+        .unwrap_or(false)
+}
+
+fn check_port_available(port: u16) -> Result<(), String> {
+    std::net::TcpListener::bind(("127.0.0.1", port))
+        .map(drop)
+        .map_err(|e| format!("Port {} is not available: {}", port, e))
 }
 
-/// Check if the agent HTTP service is responding
-fn check_health() -> bool {
+/// Whether anything at all is listening on the agent port
+fn port_open(port: u16) -> bool {
     std::net::TcpStream::connect_timeout(
-        &format!("127.0.0.1:{}", AGENT_PORT).parse().unwrap(),
+        &format!("127.0.0.1:{}", port).parse().unwrap(),
         Duration::from_millis(500),
     )
     .is_ok()
 }
 
+/// Who is listening on the agent port
+enum PortOwner {
+    Nobody,
+    /// An agent that accepts our session token
+    Ours,
+    /// An agent holding another token, i.e. another session's
+    OtherSession,
+    Foreign,
+}
+
+/// Identify whoever is listening on the agent port. Only a 2xx /health counts
+/// as ours: an agent from another session rejects our token with a 401, though
+/// it still names itself in the body.
+fn probe_agent_port(agent: &Agent) -> PortOwner {
+    if !port_open(agent.port()) {
+        return PortOwner::Nobody;
+    }
+    let Ok(resp) = call_agent(agent, "GET", "/health", None, Duration::from_secs(2)) else {
+        return PortOwner::Foreign;
+    };
+    let body = serde_json::from_str::<serde_json::Value>(&resp.body).unwrap_or_default();
+    let names_agent = body.get("agent").and_then(|a| a.as_str()) == Some("sanhuoai");
+    if resp.is_success()
+        && (names_agent || (body.get("status").is_some() && body.get("version").is_some()))
+    {
+        PortOwner::Ours
+    } else if resp.status == 401 && names_agent {
+        PortOwner::OtherSession
+    } else {
+        PortOwner::Foreign
+    }
+}
+
+/// Random hex token for authenticating to the agent
+fn generate_agent_token() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Issue a fresh token for the agent about to be spawned. For the shared agent
+/// the frontend is told via `agent://token-rotated` and re-reads it with
+/// get_agent_token; project agents are only reached through agent_request.
+fn rotate_agent_token(app: &tauri::AppHandle, agent: &Agent) -> String {
+    let token = generate_agent_token();
+    *agent_manager::lock(&agent.token) = token.clone();
+    if agent.project_id.is_none() {
+        let _ = app.emit(events::AGENT_TOKEN_ROTATED, ());
+    }
+    token
+}
+
+/// Hand the shared agent's session token to the frontend, but only to a focused window
+#[tauri::command]
+fn get_agent_token(window: tauri::WebviewWindow, state: State<AppState>) -> Result<String, String> {
+    if !window.is_focused().unwrap_or(false) {
+        return Err("Window must be focused to read the agent token".into());
+    }
+    Ok(agent_manager::lock(&state.shared_agent().token).clone())
+}
+
+enum PortClaim {
+    Free,
+    Adopted,
+}
+
+/// Make sure spawning a fresh agent won't collide with a stale one on the port:
+/// one that takes our token is adopted, orphans from a previous session (known
+/// via the runtime file) are killed, and anything else is reported
+fn claim_agent_port(state: &AppState, agent: &Agent) -> Result<PortClaim, String> {
+    let runtime_file = runtime_file_path(&state.data_dir(), agent);
+    match probe_agent_port(agent) {
+        PortOwner::Nobody => {
+            let _ = std::fs::remove_file(&runtime_file);
+            Ok(PortClaim::Free)
+        }
+        PortOwner::Ours => {
+            info!(port = agent.port(), "adopting agent already running");
+            agent.external.store(true, Ordering::SeqCst);
+            Ok(PortClaim::Adopted)
+        }
+        PortOwner::OtherSession if kill_orphaned_agent(state, &runtime_file, &[]) => {
+            Ok(PortClaim::Free)
+        }
+        PortOwner::OtherSession => Err(format!(
+            "Port {} is held by an agent from another session; close that window and try again",
+            agent.port()
+        )),
+        PortOwner::Foreign => Err(format!(
+            "Port {} is in use by another program; close it and try again",
+            agent.port()
+        )),
+    }
+}
+
+/// External agent mode: take on the agent someone started on the port, which
+/// must answer /health like ours does
+fn attach_agent(agent: &Agent) -> Result<(), String> {
+    let port = agent.port();
+    match probe_agent_port(agent) {
+        PortOwner::Ours => {
+            info!(port, "attached to an agent started outside the app");
+            agent.external.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+        PortOwner::OtherSession => {
+            Err(format!("Port {} is held by an agent from another session", port))
+        }
+        PortOwner::Foreign => {
+            Err(format!("Port {} is in use by a program that is not the agent", port))
+        }
+        PortOwner::Nobody => Err(format!(
+            "External agent mode is on, but no agent is listening on port {}; start it there",
+            port
+        )),
+    }
+}
+
+/// With no child to reap, the watchdog follows an attached agent through /health:
+/// one that stops answering is let go, and in external agent mode one that shows
+/// up on the shared port (again, after a --reload say) is attached
+fn watch_attached_agent(app: &tauri::AppHandle, state: &AppState, agent: &Agent) {
+    if agent.external.load(Ordering::SeqCst) {
+        if !check_health(agent) {
+            warn!(port = agent.port(), "attached agent stopped answering");
+            agent.external.store(false, Ordering::SeqCst);
+            let project_id = agent.project_id.clone();
+            let stopped = events::AgentStopped { project_id, pid: None, forced: false };
+            let _ = app.emit(events::AGENT_STOPPED, stopped);
+        }
+    } else if agent.project_id.is_none()
+        && state.watchdog.external_agent_mode.load(Ordering::SeqCst)
+        && !agent.manager.is_running()
+        && attach_agent(agent).is_ok()
+    {
+        tray::set_indicator(app, tray::Indicator::Ready);
+    }
+}
+
+/// Where and how to reach an agent we spawned, so the agent can rediscover its
+/// token after a reload and other local tools can cooperate with it. One file per
+/// agent, rewritten on every spawn (watchdog restarts included) and removed on a
+/// clean stop; one left behind at startup points at an orphan.
+#[derive(Serialize, Deserialize)]
+struct AgentRuntime {
+    port: u16,
+    token: String,
+    pid: u32,
+    /// Working directory of the agent, to recognise it again by; absent in files
+    /// from older versions
+    #[serde(default)]
+    agent_dir: Option<PathBuf>,
+}
+
+/// agent-runtime.json for the shared agent, agent-runtime-<project id>.json for
+/// a project's
+fn runtime_file_path(data_dir: &str, agent: &Agent) -> PathBuf {
+    let name = match &agent.project_id {
+        Some(id) => format!("{}{}.json", PROJECT_RUNTIME_FILE_PREFIX, id),
+        None => AGENT_RUNTIME_FILE.to_string(),
+    };
+    PathBuf::from(data_dir).join(name)
+}
+
+/// Runtime files of project agents, this session's and any left behind
+fn project_runtime_files(data_dir: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(data_dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+                name.starts_with(PROJECT_RUNTIME_FILE_PREFIX) && name.ends_with(".json")
+            })
+        })
+        .collect()
+}
+
+fn write_runtime_file(path: &std::path::Path, runtime: &AgentRuntime) {
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_string_pretty(runtime).unwrap_or_default();
+    if let Err(e) = std::fs::write(&tmp, json) {
+        error!(path = %tmp.display(), error = %e, "failed to write runtime file");
+        return;
+    }
+    // The token grants full access to the agent
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600));
+    }
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        error!(path = %path.display(), error = %e, "failed to write runtime file");
+    }
+}
+
+fn read_runtime_file(path: &std::path::Path) -> Option<AgentRuntime> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+}
+
+/// Stop the process recorded in the runtime file at `path` unless it's one we
+/// currently manage (`current`). Returns whether anything was killed.
+fn kill_orphaned_agent(state: &AppState, path: &std::path::Path, current: &[u32]) -> bool {
+    let runtime = match read_runtime_file(path) {
+        Some(runtime) if !current.contains(&runtime.pid) => runtime,
+        _ => return false,
+    };
+    let pid = runtime.pid;
+    if !is_agent_process(state, &runtime) {
+        // After a reboot or pid reuse the recorded pid is some other program's
+        warn!(pid, "recorded agent pid is not an agent; leaving it alone");
+        return false;
+    }
+    info!(pid, port = runtime.port, "stopping orphaned agent from a previous session");
+    if !runtime.token.is_empty() {
+        // The orphan still accepts the token it was started with
+        let bearer = format!("Bearer {}", runtime.token);
+        let _ = agent_http::request(
+            runtime.port,
+            "POST",
+            "/shutdown",
+            &[("Authorization", bearer.as_str())],
+            None,
+            Duration::from_millis(500),
+        );
+    }
+    request_agent_exit(None, pid);
+    if !wait_for_port_release(runtime.port, shutdown_grace(state)) {
+        // Checked again: the /shutdown above may have let the pid go already
+        if is_agent_process(state, &runtime) {
+            kill_pid_tree(pid);
+        }
+        wait_for_port_release(runtime.port, Duration::from_secs(2));
+    }
+    let _ = std::fs::remove_file(path);
+    true
+}
+
+/// Whether the runtime file's pid is still an agent: uvicorn serving main:app
+/// from the recorded agent directory, or this executable running as the stub
+fn is_agent_process(state: &AppState, runtime: &AgentRuntime) -> bool {
+    use sysinfo::{ProcessRefreshKind, UpdateKind};
+    let mut sys = agent_manager::lock(&state.sysinfo);
+    let pid = sysinfo::Pid::from_u32(runtime.pid);
+    let refresh = ProcessRefreshKind::new()
+        .with_cmd(UpdateKind::Always)
+        .with_cwd(UpdateKind::Always);
+    if !sys.refresh_process_specifics(pid, refresh) {
+        return false;
+    }
+    let Some(process) = sys.process(pid) else { return false };
+    let cmd = process.cmd();
+    if fake_agent::is_stub_command(cmd) {
+        return true;
+    }
+    let serves_agent =
+        cmd.iter().any(|arg| arg == "uvicorn") && cmd.iter().any(|arg| arg == "main:app");
+    let in_agent_dir = match (process.cwd(), &runtime.agent_dir) {
+        (Some(cwd), Some(agent_dir)) => same_dir(cwd, agent_dir),
+        // Not every platform lets us read another process's working directory
+        _ => true,
+    };
+    serves_agent && in_agent_dir
+}
+
+fn same_dir(a: &std::path::Path, b: &std::path::Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn wait_for_port_release(port: u16, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if !port_open(port) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    !port_open(port)
+}
+
+/// Poll until a listener could bind `port`, which is what the new agent needs:
+/// a closed port can still be held by a process that is exiting
+fn wait_until_bindable(port: u16, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if std::net::TcpListener::bind(("127.0.0.1", port)).is_ok() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Interpreter and agent directory to launch with, honouring the user's overrides
+#[derive(Serialize, Clone)]
+pub struct AgentPaths {
+    python: PathBuf,
+    python_from_override: bool,
+    agent_dir: PathBuf,
+    agent_dir_from_override: bool,
+}
+
+impl AgentPaths {
+    fn resolve(app: &tauri::AppHandle) -> Self {
+        let settings = settings::load(&app.state::<AppState>().db);
+        let python_override = settings.python_path_override.map(PathBuf::from);
+        let agent_dir_override = settings.agent_dir_override.map(PathBuf::from);
+        Self {
+            python_from_override: python_override.is_some(),
+            python: python_override.unwrap_or_else(|| resolve_python(app)),
+            agent_dir_from_override: agent_dir_override.is_some(),
+            agent_dir: agent_dir_override.unwrap_or_else(|| resolve_agent_dir(app)),
+        }
+    }
+
+    /// An override that no longer exists is reported rather than silently ignored
+    fn check_overrides(&self) -> Result<(), String> {
+        if self.python_from_override {
+            check_python_override(&self.python)?;
+        }
+        if self.agent_dir_from_override {
+            check_agent_dir_override(&self.agent_dir)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_python_override(path: &std::path::Path) -> Result<(), String> {
+    if !path.is_file() {
+        return Err(format!(
+            "Python interpreter override {} does not exist; fix or clear it in settings",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+fn check_agent_dir_override(path: &std::path::Path) -> Result<(), String> {
+    if !path.is_dir() {
+        return Err(format!(
+            "Agent directory override {} does not exist; fix or clear it in settings",
+            path.display()
+        ));
+    }
+    if !path.join("main.py").is_file() {
+        return Err(format!(
+            "Agent directory override {} does not contain main.py",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PathOverrides {
+    python_path: Option<String>,
+    agent_dir: Option<String>,
+}
+
+#[tauri::command]
+fn get_path_overrides(state: State<AppState>) -> PathOverrides {
+    let settings = settings::load(&state.db);
+    PathOverrides {
+        python_path: settings.python_path_override,
+        agent_dir: settings.agent_dir_override,
+    }
+}
+
+/// Takes effect the next time the agent is started; None or an empty path clears it
+#[tauri::command]
+fn set_python_path_override(app: tauri::AppHandle, path: Option<String>) -> Result<(), String> {
+    change_settings(&app, serde_json::json!({ "python_path_override": path })).map(|_| ())
+}
+
+/// Takes effect the next time the agent is started; None or an empty path clears it
+#[tauri::command]
+fn set_agent_dir_override(app: tauri::AppHandle, path: Option<String>) -> Result<(), String> {
+    change_settings(&app, serde_json::json!({ "agent_dir_override": path })).map(|_| ())
+}
+
 /// Resolve the agent directory: dev uses project root, production uses bundled resources
 fn resolve_agent_dir(app: &tauri::AppHandle) -> std::path::PathBuf {
     if cfg!(debug_assertions) {
@@ -152,90 +3956,474 @@ fn resolve_python(app: &tauri::AppHandle) -> std::path::PathBuf {
             base.join("bin").join("python3.12"),
         ];
 
-        candidates
-            .into_iter()
-            .find(|p| p.exists())
-            .unwrap_or_else(|| {
-                if cfg!(target_os = "windows") {
-                    base.join("python.exe")
+        candidates
+            .into_iter()
+            .find(|p| p.exists())
+            .unwrap_or_else(|| {
+                if cfg!(target_os = "windows") {
+                    base.join("python.exe")
+                } else {
+                    base.join("bin").join("python3")
+                }
+            })
+    }
+}
+
+fn exe_dir() -> Option<PathBuf> {
+    std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|d| d.to_path_buf()))
+}
+
+fn candidate_resource_roots(app: &tauri::AppHandle) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+
+    if let Ok(rd) = app.path().resource_dir() {
+        roots.push(rd);
+    }
+
+    if let Some(dir) = exe_dir() {
+        roots.push(dir.join("resources"));
+        roots.push(dir);
+    }
+
+    roots
+}
+
+fn resolve_bundled_resource(app: &tauri::AppHandle, name: &str) -> Option<PathBuf> {
+    let clean_name = name.trim_matches('/');
+    candidate_resource_roots(app).into_iter().find_map(|root| {
+        let direct = root.join(clean_name);
+        if direct.exists() {
+            return Some(direct);
+        }
+
+        // Some bundle layouts place user resources under ".../Resources/resources/".
+        let nested = root.join("resources").join(clean_name);
+        if nested.exists() {
+            return Some(nested);
+        }
+
+        None
+    })
+}
+
+#[derive(Serialize)]
+struct PathCheck {
+    path: String,
+    exists: bool,
+}
+
+impl PathCheck {
+    fn new(path: &std::path::Path) -> Self {
+        Self {
+            path: path.display().to_string(),
+            exists: path.exists(),
+        }
+    }
+}
+
+/// Everything the path resolution looked at, for bug reports from packaged builds
+#[derive(Serialize)]
+struct ResolveReport {
+    debug_build: bool,
+    data_dir: PathCheck,
+    exe_dir: Option<PathCheck>,
+    resource_roots: Vec<PathCheck>,
+    agent_dir: PathCheck,
+    agent_dir_from_override: bool,
+    python: PathCheck,
+    python_from_override: bool,
+}
+
+#[tauri::command]
+fn resolve_diagnostics(state: State<AppState>, app: tauri::AppHandle) -> ResolveReport {
+    let paths = AgentPaths::resolve(&app);
+    ResolveReport {
+        debug_build: cfg!(debug_assertions),
+        data_dir: PathCheck::new(std::path::Path::new(&state.data_dir())),
+        exe_dir: exe_dir().as_deref().map(PathCheck::new),
+        resource_roots: candidate_resource_roots(&app)
+            .iter()
+            .map(|root| PathCheck::new(root))
+            .collect(),
+        agent_dir: PathCheck::new(&paths.agent_dir),
+        agent_dir_from_override: paths.agent_dir_from_override,
+        python: PathCheck::new(&paths.python),
+        python_from_override: paths.python_from_override,
+    }
+}
+
+/// Platform, build and bundle layout in one blob users can paste into an issue.
+/// Gathered locally; nothing is sent anywhere.
+#[derive(Serialize)]
+struct SystemReport {
+    #[serde(flatten)]
+    system: diagnostics::SystemInfo,
+    tauri_version: &'static str,
+    webview_version: Option<String>,
+    data_dir: PathCheck,
+    resource_roots: Vec<PathCheck>,
+    /// Where the bundled agent was found, if in any of the resource roots
+    bundled_agent: Option<String>,
+}
+
+#[tauri::command]
+fn system_report(state: State<AppState>, app: tauri::AppHandle) -> SystemReport {
+    SystemReport {
+        system: diagnostics::system_info(app.package_info().version.to_string()),
+        tauri_version: tauri::VERSION,
+        webview_version: tauri::webview_version().ok(),
+        data_dir: PathCheck::new(std::path::Path::new(&state.data_dir())),
+        resource_roots: candidate_resource_roots(&app)
+            .iter()
+            .map(|root| PathCheck::new(root))
+            .collect(),
+        bundled_agent: resolve_bundled_resource(&app, "agent").map(|p| p.display().to_string()),
+    }
+}
+
+#[tauri::command]
+fn database_size(state: State<AppState>) -> Result<DbSize, String> {
+    state.db.size().map_err(|e| e.to_string())
+}
+
+/// Reclaim the space left by deleted rows
+#[tauri::command]
+async fn vacuum_database(app: tauri::AppHandle) -> Result<(), String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        app.state::<AppState>().db.vacuum().map_err(|e| {
+            if db::is_busy(&e) {
+                "The AI agent is writing to the database; stop the agent and try again".to_string()
+            } else {
+                e.to_string()
+            }
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// quick_check (or the full integrity_check), foreign key check, row counts and
+/// free pages; for after an unclean shutdown
+#[tauri::command]
+async fn check_database_integrity(
+    app: tauri::AppHandle,
+    full: Option<bool>,
+) -> Result<db_health::IntegrityReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let mode = if full.unwrap_or(false) {
+            db_health::CheckMode::Full
+        } else {
+            db_health::CheckMode::Quick
+        };
+        let operation = db_health::Operation::Check;
+        let report = app
+            .state::<AppState>()
+            .db
+            .integrity_report(mode, |stage| {
+                let progress = events::DbMaintenanceProgress { operation, stage };
+                let _ = app.emit(events::DB_MAINTENANCE_PROGRESS, progress);
+            })
+            .map_err(|e| e.to_string())?;
+        if !report.ok {
+            warn!(
+                check_result = ?report.check_result,
+                foreign_key_violations = report.foreign_key_violation_count,
+                "database integrity check found problems"
+            );
+        }
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// The full integrity_check and foreign key check, without progress events; an
+/// empty `problems` means the database is sound. Runs on its own read-only
+/// connection, so the agent can keep writing meanwhile
+#[tauri::command]
+async fn check_integrity(app: tauri::AppHandle) -> Result<db_health::IntegrityReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let report = app
+            .state::<AppState>()
+            .db
+            .integrity_report(db_health::CheckMode::Full, |_| {})
+            .map_err(|e| e.to_string())?;
+        if !report.ok {
+            warn!(problems = ?report.problems, "database integrity check found problems");
+        }
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// PRAGMA optimize, VACUUM and ANALYZE. Backups, exports, restores and data
+/// moves are held off, and app writes wait, until it finishes.
+#[tauri::command]
+async fn optimize_database(app: tauri::AppHandle) -> Result<db_health::Optimized, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let _maintenance = state.maintenance.try_write().map_err(|_| {
+            "A backup, export, restore or data move is in progress; try again once it finishes"
+                .to_string()
+        })?;
+        let operation = db_health::Operation::Optimize;
+        let optimized = state
+            .db
+            .optimize(|stage| {
+                let progress = events::DbMaintenanceProgress { operation, stage };
+                let _ = app.emit(events::DB_MAINTENANCE_PROGRESS, progress);
+            })
+            .map_err(|e| {
+                if db::is_busy(&e) {
+                    "The AI agent is writing to the database; stop the agent and try again"
+                        .to_string()
                 } else {
-                    base.join("bin").join("python3")
+                    e.to_string()
                 }
-            })
-    }
+            })?;
+        info!(
+            before = optimized.file_bytes_before,
+            after = optimized.file_bytes_after,
+            elapsed_ms = optimized.elapsed_ms,
+            "database optimized"
+        );
+        Ok(optimized)
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-fn exe_dir() -> Option<PathBuf> {
-    std::env::current_exe()
-        .ok()
-        .and_then(|p| p.parent().map(|d| d.to_path_buf()))
+/// Fold the write-ahead log back into the database file so it stops growing over
+/// a long session. While the agent is reading or writing, part of it stays behind
+/// and `busy` is set.
+#[tauri::command]
+fn checkpoint_database(state: State<AppState>) -> Result<db_health::Checkpointed, String> {
+    let checkpointed = state.db.checkpoint().map_err(|e| {
+        if db::is_busy(&e) {
+            "The AI agent is writing to the database; try again in a moment".to_string()
+        } else {
+            e.to_string()
+        }
+    })?;
+    info!(
+        before = checkpointed.wal_bytes_before,
+        after = checkpointed.wal_bytes_after,
+        busy = checkpointed.busy,
+        "database checkpointed"
+    );
+    Ok(checkpointed)
 }
 
-fn candidate_resource_roots(app: &tauri::AppHandle) -> Vec<PathBuf> {
-    let mut roots = Vec::new();
+/// Most recent Rust-side log entries, oldest first. `level_filter` ("error",
+/// "warn", ...) keeps that level and anything more severe.
+#[tauri::command]
+fn get_app_log(
+    lines: Option<usize>,
+    level_filter: Option<String>,
+) -> Result<Vec<app_log::LogEntry>, String> {
+    let min_level = level_filter
+        .as_deref()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|l| app_log::parse_level(l).ok_or_else(|| format!("Unknown log level: {}", l)))
+        .transpose()?;
+    Ok(app_log::entries(lines.unwrap_or(500), min_level))
+}
 
-    if let Ok(rd) = app.path().resource_dir() {
-        roots.push(rd);
-    }
+/// The last `lines` lines of the agent log (500 by default); empty until the
+/// agent has written one
+#[tauri::command]
+fn get_agent_log(state: State<AppState>, lines: Option<usize>) -> Result<String, String> {
+    let data_dir = state.data_dir();
+    let lines = lines.unwrap_or(diagnostics::AGENT_LOG_LINES);
+    let path = agent_log::path(std::path::Path::new(&data_dir));
+    Ok(diagnostics::tail_file(&path, lines).unwrap_or_default())
+}
 
-    if let Some(dir) = exe_dir() {
-        roots.push(dir.join("resources"));
-        roots.push(dir);
-    }
+/// Truncate the agent log and delete the rotated one. A running agent keeps
+/// writing to the emptied file.
+#[tauri::command]
+fn clear_agent_log(state: State<AppState>) -> Result<(), String> {
+    agent_log::clear(std::path::Path::new(&state.data_dir()))
+}
 
-    roots
+/// Zip up what's needed to debug a report: versions, resolved paths, agent
+/// status, logs, the runtime file, database health and redacted settings.
+/// `dest_path` may be a file or a directory to create a timestamped bundle in.
+#[tauri::command]
+async fn export_diagnostics(
+    app: tauri::AppHandle,
+    dest_path: String,
+) -> Result<diagnostics::Bundle, String> {
+    tauri::async_runtime::spawn_blocking(move || write_diagnostics_bundle(&app, &dest_path))
+        .await
+        .map_err(|e| e.to_string())?
 }
 
-fn resolve_bundled_resource(app: &tauri::AppHandle, name: &str) -> Option<PathBuf> {
-    let clean_name = name.trim_matches('/');
-    candidate_resource_roots(app).into_iter().find_map(|root| {
-        let direct = root.join(clean_name);
-        if direct.exists() {
-            return Some(direct);
-        }
+/// export_diagnostics, answering with just the path of the written zip
+#[tauri::command]
+async fn export_diagnostics_bundle(
+    app: tauri::AppHandle,
+    dest_path: String,
+) -> Result<String, String> {
+    export_diagnostics(app, dest_path).await.map(|bundle| bundle.path)
+}
 
-        // Some bundle layouts place user resources under ".../Resources/resources/".
-        let nested = root.join("resources").join(clean_name);
-        if nested.exists() {
-            return Some(nested);
-        }
+/// Every file is scrubbed of the agent token before it's zipped, since bundles
+/// end up attached to public bug reports
+fn write_diagnostics_bundle(
+    app: &tauri::AppHandle,
+    dest_path: &str,
+) -> Result<diagnostics::Bundle, String> {
+    let path = diagnostics::bundle_path(dest_path)?;
+    let state = app.state::<AppState>();
+    let data_dir = state.data_dir();
+    let app_version = app.package_info().version.to_string();
+    fn json(value: &impl Serialize) -> Vec<u8> {
+        serde_json::to_vec_pretty(value).unwrap_or_default()
+    }
 
-        None
-    })
+    let database = match state.db.diagnostics() {
+        Ok(report) => json(&report),
+        Err(e) => json(&serde_json::json!({ "error": e.to_string() })),
+    };
+    let integrity = match state.db.integrity_report(db_health::CheckMode::Quick, |_| {}) {
+        Ok(report) => json(&report),
+        Err(e) => json(&serde_json::json!({ "error": e.to_string() })),
+    };
+    let credentials: Vec<_> = state
+        .db
+        .list_credentials()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| serde_json::json!({ "name": c.name, "storage": c.storage }))
+        .collect();
+    let global_settings = state.db.list_global_settings().unwrap_or_default();
+    let redacted_settings = serde_json::json!({
+        "app_settings": settings::load(&state.db),
+        "global_settings": diagnostics::redact(global_settings),
+        "credentials": credentials,
+    });
+    let agent_log = diagnostics::tail_file(
+        &agent_log::path(std::path::Path::new(&data_dir)),
+        diagnostics::AGENT_LOG_LINES,
+    )
+    .unwrap_or_default();
+
+    let agent = state.shared_agent();
+    let runtime_path = runtime_file_path(&data_dir, &agent);
+    let runtime_file = std::fs::read(&runtime_path).unwrap_or_default();
+    let versions = serde_json::json!({
+        "app": app_version,
+        "agent": match fetch_agent_info(&agent) {
+            Ok(info) => serde_json::json!(info),
+            Err(e) => serde_json::json!({ "error": e }),
+        },
+    });
+
+    let files = [
+        ("system.json", json(&diagnostics::system_info(app_version.clone()))),
+        ("versions.json", json(&versions)),
+        ("data_dir.json", json(&data_location::info(std::path::Path::new(&data_dir)))),
+        ("paths.json", json(&resolve_diagnostics(app.state(), app.clone()))),
+        ("agent_status.json", json(&status_of(&state, &agent))),
+        ("database.json", database),
+        ("integrity.json", integrity),
+        ("settings.json", json(&redacted_settings)),
+        ("agent-runtime.json", runtime_file),
+        ("agent.log", agent_log.into_bytes()),
+        ("app.log", app_log::snapshot().join("\n").into_bytes()),
+    ];
+    // The runtime file's token may be a previous session's; scrub it and every
+    // agent's current one
+    let mut tokens: Vec<String> =
+        state.all_agents().iter().map(|agent| agent_manager::lock(&agent.token).clone()).collect();
+    tokens.extend(read_runtime_file(&runtime_path).map(|r| r.token));
+    let secrets: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    let files: Vec<_> = files
+        .into_iter()
+        .map(|(name, data)| (name, diagnostics::scrub(data, &secrets)))
+        .collect();
+    let bundle = diagnostics::write_zip(&path, &app_version, &files)?;
+    info!(path = %bundle.path, size_bytes = bundle.size_bytes, "wrote diagnostics bundle");
+    notify::finished(app, "Diagnostics bundle ready", &bundle.path);
+    Ok(bundle)
 }
 
-fn spawn_agent(app: &tauri::AppHandle, data_dir: &str) -> Option<Child> {
-    let agent_dir = resolve_agent_dir(app);
-    let python = resolve_python(app);
-    println!("[sanhuoai] resolved agent_dir={}", agent_dir.display());
-    println!("[sanhuoai] resolved python={}", python.display());
+fn spawn_agent(
+    app: &tauri::AppHandle,
+    agent: &AgentHandle,
+    data_dir: &str,
+) -> Result<Child, String> {
+    let paths = AgentPaths::resolve(app);
+    paths.check_overrides()?;
+    let (python, agent_dir) = (&paths.python, &paths.agent_dir);
+    info!(
+        agent_dir = %agent_dir.display(),
+        from_override = paths.agent_dir_from_override,
+        "resolved agent_dir"
+    );
+    info!(
+        python = %python.display(),
+        from_override = paths.python_from_override,
+        "resolved python"
+    );
     if !agent_dir.exists() {
-        eprintln!("[sanhuoai] agent_dir missing: {}", agent_dir.display());
+        error!(agent_dir = %agent_dir.display(), "agent_dir missing");
     }
     if !python.exists() {
-        eprintln!("[sanhuoai] python missing: {}", python.display());
-    }
-    let mut cmd = Command::new(&python);
-    cmd.args(["-m", "uvicorn", "main:app", "--host", "127.0.0.1", "--port", &AGENT_PORT.to_string()]);
-    if cfg!(debug_assertions) {
-        cmd.arg("--reload");
+        error!(python = %python.display(), "python missing");
     }
-    cmd.current_dir(&agent_dir)
-        .env("SANHUOAI_DATA_DIR", data_dir);
+    let token = rotate_agent_token(app, agent);
+    let port = agent.port();
+    let project_id = agent.project_id.clone();
+    let _span = tracing::info_span!("spawn_agent", port, project_id).entered();
+    let (program, mut cmd) = if fake_agent::enabled() {
+        info!(env = fake_agent::ENABLE_ENV_KEY, "spawning the stub agent");
+        fake_agent::command(port)?
+    } else {
+        let mut cmd = Command::new(python);
+        cmd.args(["-m", "uvicorn", "main:app", "--host", "127.0.0.1", "--port", &port.to_string()]);
+        if cfg!(debug_assertions) {
+            cmd.arg("--reload");
+        }
+        cmd.current_dir(agent_dir);
+        (python.clone(), cmd)
+    };
+    let state = app.state::<AppState>();
+    // Credentials under a passphrase not yet unlocked this session are left out
+    cmd.env("SANHUOAI_DATA_DIR", data_dir)
+        .env(AGENT_TOKEN_ENV_KEY, &token)
+        .envs(credentials::environment(&state.db, &state.credentials));
+
+    // Windows shows the agent in its own console unless agent_log_to_file is set;
+    // everywhere else its output always goes to agent.log
+    let log_to_file =
+        cfg!(not(target_os = "windows")) || settings::load(&state.db).agent_log_to_file;
 
     // 在 Windows 上创建独立的控制台窗口，让后端 CMD 常驻显示
     #[cfg(target_os = "windows")]
     {
         use std::os::windows::process::CommandExt;
         const CREATE_NEW_CONSOLE: u32 = 0x00000010;
-        cmd.creation_flags(CREATE_NEW_CONSOLE);
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(if log_to_file { CREATE_NO_WINDOW } else { CREATE_NEW_CONSOLE });
     }
 
-    // 非 Windows 平台仍然重定向到日志文件
     #[cfg(not(target_os = "windows"))]
-    {
-        let mut log_path = std::path::PathBuf::from(data_dir);
-        log_path.push("agent.log");
+    isolate_process_group(&mut cmd);
+
+    if log_to_file {
+        let log_dir = std::path::Path::new(data_dir);
+        match agent_log::rotate_if_large(log_dir, agent_log::ROTATE_BYTES) {
+            Ok(true) => info!(file = agent_log::ROTATED_FILE_NAME, "rotated the agent log"),
+            Ok(false) => {}
+            Err(e) => warn!(error = %e, "failed to rotate the agent log"),
+        }
+        let log_path = agent_log::path(log_dir);
         if let Ok(file) = OpenOptions::new().create(true).append(true).open(&log_path) {
             if let Ok(err_file) = file.try_clone() {
                 cmd.stdout(std::process::Stdio::from(file));
@@ -244,22 +4432,161 @@ fn spawn_agent(app: &tauri::AppHandle, data_dir: &str) -> Option<Child> {
         }
     }
 
-    match cmd.spawn() {
-        Ok(child) => {
-            println!("[sanhuoai] Agent spawned (pid={})", child.id());
-            Some(child)
+    // On first launch Windows may still be unpacking python_embed's DLLs, so
+    // a failed spawn gets a couple more tries before we give up
+    let mut attempt = 1;
+    let child = loop {
+        match cmd.spawn() {
+            Ok(child) => break child,
+            Err(e) => {
+                error!(attempt, max_attempts = SPAWN_ATTEMPTS, error = %e, "failed to start agent");
+                if attempt == SPAWN_ATTEMPTS {
+                    return Err(format!(
+                        "Failed to start agent with {} after {} attempts: {}",
+                        program.display(),
+                        SPAWN_ATTEMPTS,
+                        e
+                    ));
+                }
+                std::thread::sleep(SPAWN_RETRY_DELAY * attempt);
+                attempt += 1;
+            }
+        }
+    };
+
+    info!(pid = child.id(), port, "agent spawned");
+    let spawned = events::AgentSpawned { project_id, pid: child.id(), port };
+    let _ = app.emit(events::AGENT_SPAWNED, spawned);
+    watch_for_ready(app.clone(), agent.clone(), child.id());
+    write_runtime_file(
+        &runtime_file_path(data_dir, agent),
+        &AgentRuntime { port, token, pid: child.id(), agent_dir: Some(agent_dir.clone()) },
+    );
+    *agent_manager::lock(&agent.started_at) = Some(SystemTime::now());
+    *agent_manager::lock(&agent.paths) = Some(paths.clone());
+    Ok(child)
+}
+
+enum Shutdown {
+    Graceful,
+    Forced,
+}
+
+/// Stop the agents we spawned as the app goes away: on window close and tray Quit.
+/// An adopted agent is left running.
+fn stop_agent_for_exit(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    for agent in state.all_agents() {
+        if let Some(child) = agent.manager.take_for_exit() {
+            stop_child(app, &state, &agent, child);
         }
-        Err(e) => {
-            eprintln!("[sanhuoai] Failed to start agent: {}", e);
-            None
+    }
+}
+
+/// shutdown_agent for a requested stop, announced as `agent://stopped`
+fn stop_child(app: &tauri::AppHandle, state: &AppState, agent: &Agent, child: Child) -> Shutdown {
+    let pid = child.id();
+    let outcome = shutdown_agent(state, agent, child);
+    let forced = matches!(outcome, Shutdown::Forced);
+    let project_id = agent.project_id.clone();
+    let stopped = events::AgentStopped { project_id, pid: Some(pid), forced };
+    let _ = app.emit(events::AGENT_STOPPED, stopped);
+    outcome
+}
+
+/// Emit `agent://ready` once the freshly spawned agent `pid` first answers /health.
+/// Gives up after AGENT_READY_TIMEOUT or once another process has replaced it.
+fn watch_for_ready(app: tauri::AppHandle, agent: AgentHandle, pid: u32) {
+    std::thread::spawn(move || {
+        let port = agent.port();
+        let started = Instant::now();
+        while started.elapsed() < AGENT_READY_TIMEOUT {
+            std::thread::sleep(READY_POLL_INTERVAL);
+            if !check_health(&agent) {
+                continue;
+            }
+            // Stored by the caller right after spawn_agent returns
+            if agent.manager.pid() == Some(pid) {
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+                info!(pid, port, elapsed_ms, "agent ready");
+                let project_id = agent.project_id.clone();
+                let ready = events::AgentReady { project_id, pid, port, elapsed_ms };
+                let _ = app.emit(events::AGENT_READY, ready);
+            }
+            return;
+        }
+    });
+}
+
+/// Ask the agent to exit on its own so uvicorn runs its shutdown handlers,
+/// escalating to kill_process_tree once the grace period runs out
+fn shutdown_agent(state: &AppState, agent: &Agent, mut child: Child) -> Shutdown {
+    let grace = shutdown_grace(state);
+    let pid = child.id();
+    let started = Instant::now();
+    let runtime_file = runtime_file_path(&state.data_dir(), agent);
+
+    request_agent_exit(Some(agent), pid);
+
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        match child.try_wait() {
+            Ok(Some(_)) => {
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+                info!(pid, elapsed_ms, "agent exited gracefully");
+                let _ = std::fs::remove_file(&runtime_file);
+                return Shutdown::Graceful;
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+            Err(_) => break,
         }
     }
+
+    warn!(pid, grace_secs = grace.as_secs(), "agent still running after grace period, killing it");
+    kill_process_tree(child);
+    let _ = std::fs::remove_file(&runtime_file);
+    Shutdown::Forced
+}
+
+fn shutdown_grace(state: &AppState) -> Duration {
+    Duration::from_secs(settings::load(&state.db).agent_shutdown_grace_secs)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn request_agent_exit(_agent: Option<&Agent>, pid: u32) {
+    // The agent leads its own process group, so this reaches uvicorn's reload workers too
+    unsafe { libc::kill(-(pid as i32), libc::SIGTERM); }
+}
+
+/// Make the child lead a new process group so kill(-pid) targets the agent's
+/// whole tree instead of our own group
+#[cfg(not(target_os = "windows"))]
+fn isolate_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+/// The agent runs in its own console on Windows, so it can't receive our
+/// CTRL_BREAK_EVENT; ask it over HTTP instead. An orphan (no `agent`) has
+/// already been asked with its own token.
+#[cfg(target_os = "windows")]
+fn request_agent_exit(agent: Option<&Agent>, _pid: u32) {
+    if let Some(agent) = agent {
+        let _ = call_agent(agent, "POST", "/shutdown", None, Duration::from_millis(500));
+    }
 }
 
 /// Kill a process and its entire process tree (important on Windows where
 /// child.kill() only kills the parent, leaving uvicorn workers orphaned)
 fn kill_process_tree(mut child: Child) {
     let pid = child.id();
+    kill_pid_tree(pid);
+    let _ = child.kill();
+    let _ = child.wait();
+    info!(pid, "agent stopped");
+}
+
+fn kill_pid_tree(pid: u32) {
     #[cfg(target_os = "windows")]
     {
         // taskkill /F /T /PID kills the entire process tree
@@ -271,107 +4598,836 @@ fn kill_process_tree(mut child: Child) {
     }
     #[cfg(not(target_os = "windows"))]
     {
-        // Send SIGTERM to process group
-        unsafe { libc::kill(-(pid as i32), libc::SIGTERM); }
+        // SIGKILL the whole process group (see isolate_process_group)
+        unsafe { libc::kill(-(pid as i32), libc::SIGKILL); }
+    }
+}
+
+fn record_crash(agent: &Agent) {
+    let mut crashes = agent_manager::lock(&agent.crash_times);
+    crashes.push_back(Instant::now());
+    while crashes.len() > CRASH_LOOP_RESTARTS {
+        crashes.pop_front();
     }
-    let _ = child.kill();
-    let _ = child.wait();
-    println!("[sanhuoai] Agent stopped (pid={})", pid);
 }
 
-/// Background watchdog: restarts agent if it crashes
+fn in_crash_loop(agent: &Agent) -> bool {
+    let crashes = agent_manager::lock(&agent.crash_times);
+    crashes.len() >= CRASH_LOOP_RESTARTS
+        && crashes.front().is_some_and(|first| first.elapsed() < CRASH_LOOP_WINDOW)
+}
+
+/// Back up the database into the backups folder, prune old backups and record
+/// last_backup_at, emitting `backup://completed` or `backup://failed`. The
+/// maintenance guard proves no export, restore or data move is underway.
+fn run_backup(
+    app: &tauri::AppHandle,
+    _maintenance: &std::sync::RwLockWriteGuard<()>,
+    scheduled: bool,
+) -> Result<auto_backup::BackupDone, String> {
+    let state = app.state::<AppState>();
+    let result = (|| -> Result<auto_backup::BackupDone, String> {
+        let current = settings::load(&state.db);
+        let dir = PathBuf::from(state.data_dir()).join(file_manager::BACKUPS_DIR);
+        let done = auto_backup::create(&state.db, &dir, current.auto_backup_keep as usize)?;
+        let updated = settings::AppSettings {
+            last_backup_at: Some(done.created_at.clone()),
+            ..current.clone()
+        };
+        settings::save(&state.db, &current, &updated)?;
+        Ok(done)
+    })();
+    match &result {
+        Ok(backup) => {
+            let pruned = backup.pruned.len();
+            info!(path = %backup.path, pruned, scheduled, "database backed up");
+            let completed = events::BackupCompleted { scheduled, backup };
+            let _ = app.emit(events::BACKUP_COMPLETED, completed);
+        }
+        Err(error) => {
+            error!(error = %error, scheduled, "database backup failed");
+            let _ = app.emit(events::BACKUP_FAILED, events::BackupFailed { scheduled, error });
+        }
+    }
+    result
+}
+
+/// Back up the database now, whatever the schedule says
+#[tauri::command]
+async fn run_backup_now(app: tauri::AppHandle) -> Result<auto_backup::BackupDone, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        let maintenance = state.maintenance.try_write().map_err(|_| {
+            "A backup, export, restore or data move is in progress; try again once it finishes"
+                .to_string()
+        })?;
+        run_backup(&app, &maintenance, false)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Background backups: once the interval since the newest backup has passed,
+/// back up unless an export, restore or data move is running (then next round)
+fn start_backup_scheduler(handle: tauri::AppHandle) {
+    std::thread::spawn(move || {
+        std::thread::sleep(auto_backup::STARTUP_DELAY);
+        loop {
+            let state = handle.state::<AppState>();
+            let current = settings::load(&state.db);
+            let hours = current.auto_backup_interval_hours;
+            if current.auto_backup_enabled && hours > 0 {
+                let dir = PathBuf::from(state.data_dir()).join(file_manager::BACKUPS_DIR);
+                let interval = Duration::from_secs(hours.saturating_mul(3600));
+                if auto_backup::is_due(&dir, interval) {
+                    match state.maintenance.try_write() {
+                        Ok(maintenance) => {
+                            let _ = run_backup(&handle, &maintenance, true);
+                        }
+                        Err(_) => info!("maintenance in progress; scheduled backup postponed"),
+                    }
+                }
+            }
+            std::thread::sleep(auto_backup::CHECK_INTERVAL);
+        }
+    });
+}
+
+/// Purge the trash: all of it, or with `automatic` what has outlived the
+/// trash_retention_days setting. Then remove the purged projects' files, and their
+/// vectors if the shared agent is up, stop their own agents, and emit
+/// `trash://purged`. The maintenance guard proves no restore or export is
+/// working on them.
+fn purge_trash(
+    app: &tauri::AppHandle,
+    _maintenance: &std::sync::RwLockWriteGuard<()>,
+    automatic: bool,
+) -> Result<trash::Purged, String> {
+    let state = app.state::<AppState>();
+    let older_than_days = match settings::load(&state.db).trash_retention_days {
+        _ if !automatic => None,
+        0 => return Ok(trash::Purged::default()),
+        days => Some(days),
+    };
+    let purged = state.db.purge_trash(older_than_days).map_err(|e| e.to_string())?;
+    if purged.project_ids.is_empty() {
+        return Ok(purged);
+    }
+    let data_dir = PathBuf::from(state.data_dir());
+    // The agent keeps vectors in a store of its own; its delete is safe to repeat
+    let shared = state.shared_agent();
+    let agent_ready = check_health(&shared);
+    for id in &purged.project_ids {
+        retire_project_agent(app, &state, id);
+        project_deleted(app, id);
+        if let Err(e) = trash::remove_files(&data_dir, id) {
+            warn!(project_id = %id, error = %e, "could not remove a purged project's files");
+        }
+        if agent_ready {
+            let path = format!("/api/projects/{}", id);
+            if let Err(e) = call_agent(&shared, "DELETE", &path, None, Duration::from_secs(30)) {
+                warn!(project_id = %id, error = %e, "agent could not drop a purged project");
+            }
+        }
+    }
+    info!(count = purged.project_ids.len(), automatic, "purged projects from the trash");
+    let _ = app.emit(events::TRASH_PURGED, events::TrashPurged { automatic, purged: &purged });
+    Ok(purged)
+}
+
+/// Stop a purged project's agent, if it had one, and forget it
+fn retire_project_agent(app: &tauri::AppHandle, state: &AppState, project_id: &str) {
+    let Some(agent) = agent_manager::lock(&state.agents).remove(project_id) else {
+        return;
+    };
+    agent.suspended.store(true, Ordering::SeqCst);
+    take_down_agent(app, state, &agent, &agent.manager.lock());
+    let _ = std::fs::remove_file(runtime_file_path(&state.data_dir(), &agent));
+}
+
+/// Purge what has outlived the trash retention at startup and then daily; while
+/// an export, restore or data move runs, try again shortly
+fn start_trash_purger(handle: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        let state = handle.state::<AppState>();
+        let wait = match state.maintenance.try_write() {
+            Ok(maintenance) => {
+                if let Err(e) = purge_trash(&handle, &maintenance, true) {
+                    error!(error = %e, "trash purge failed");
+                }
+                trash::PURGE_INTERVAL
+            }
+            Err(_) => {
+                info!("maintenance in progress; trash purge postponed");
+                trash::BUSY_RETRY_INTERVAL
+            }
+        };
+        std::thread::sleep(wait);
+    });
+}
+
+/// Background watchdog: restarts agents that crash
 fn start_watchdog(handle: tauri::AppHandle) {
     std::thread::spawn(move || {
         // Wait for initial startup
         std::thread::sleep(Duration::from_secs(5));
 
         loop {
-            std::thread::sleep(Duration::from_secs(3));
-
             let state = handle.state::<AppState>();
-            let mut proc = state.agent_process.lock().unwrap();
-
-            // Check if process has exited
-            let exited = match proc.as_mut() {
-                Some(child) => child.try_wait().ok().flatten().is_some(),
-                None => false,
-            };
-
-            if exited {
-                println!("[sanhuoai] Agent crashed, restarting...");
-                proc.take(); // Clear dead process
-                drop(proc); // Release lock before spawning
+            let interval = state.watchdog.interval_secs.load(Ordering::SeqCst).max(1);
+            std::thread::sleep(Duration::from_secs(interval));
 
-                if let Some(child) = spawn_agent(&handle, &state.data_dir) {
-                    let mut proc = state.agent_process.lock().unwrap();
-                    *proc = Some(child);
-                }
+            for agent in state.all_agents() {
+                watch_agent(&handle, &state, &agent);
             }
         }
     });
 }
 
+/// One watchdog round for one agent: reap it if it exited, and restart it
+/// unless that's turned off
+fn watch_agent(handle: &tauri::AppHandle, state: &AppState, agent: &AgentHandle) {
+    // An explicit stop_agent: nothing to watch
+    if agent.suspended.load(Ordering::SeqCst) {
+        return;
+    }
+    // A user-initiated start/stop/restart is underway; check again next round
+    let Some(lifecycle) = agent.manager.try_lock() else {
+        return;
+    };
+    let Some((pid, status)) = lifecycle.reap() else {
+        if !agent.manager.is_running() {
+            watch_attached_agent(handle, state, agent);
+        }
+        return;
+    };
+
+    let will_restart = state.watchdog.should_restart(agent);
+    let exit_code = status.code();
+    let project_id = agent.project_id.clone();
+    warn!(pid, exit_code = ?exit_code, will_restart, agent = agent.name(), "agent crashed");
+    let _ = handle.emit(
+        events::AGENT_CRASHED,
+        events::AgentCrashed { project_id: project_id.clone(), pid, exit_code, will_restart },
+    );
+    if !will_restart {
+        return;
+    }
+
+    record_crash(agent);
+    let restart_count = agent.restart_count.load(Ordering::SeqCst) + 1;
+    let restarting = events::AgentRestarting {
+        project_id: project_id.clone(),
+        previous_pid: pid,
+        restart_count,
+    };
+    let _ = handle.emit(events::AGENT_RESTARTING, restarting);
+    match lifecycle.spawn(|| spawn_agent(handle, agent, &state.data_dir()).map(Some)) {
+        Ok(_) => {
+            agent.restart_count.fetch_add(1, Ordering::SeqCst);
+        }
+        Err(e) => agent_start_failed(handle, project_id, e),
+    }
+}
+
+/// A start nobody is waiting on failed (startup or a watchdog restart)
+fn agent_start_failed(app: &tauri::AppHandle, project_id: Option<String>, error: String) {
+    error!(error = %error, project_id, "failed to start agent");
+    let failed = events::AgentStartFailed { project_id, error };
+    let _ = app.emit(events::AGENT_START_FAILED, failed);
+}
+
 // ---- App Entry Point ----
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
+/// The resolved data directory when it can be written to; otherwise a temporary
+/// one for this session, with why. Exits if neither is usable.
+fn choose_data_dir(requested: PathBuf) -> (PathBuf, Option<events::DataDirFallback>) {
+    let reason = match data_location::check_usable(&requested) {
+        Ok(()) => return (requested, None),
+        Err(reason) => reason,
+    };
+    let fallback = data_location::fallback_dir();
+    if let Err(e) = data_location::check_usable(&fallback) {
+        startup_failed(&format!(
+            "{}, and the temporary directory {} can't be used either: {}",
+            reason,
+            fallback.display(),
+            e
+        ));
+    }
+    let info = events::DataDirFallback {
+        requested: requested.display().to_string(),
+        fallback: fallback.display().to_string(),
+        reason,
+    };
+    (fallback, Some(info))
+}
+
+/// Exit with the reason instead of panicking; may run before logging is set up
+fn startup_failed(message: &str) -> ! {
+    eprintln!("[sanhuoai] Cannot start: {}", message);
+    error!(message, "cannot start");
+    std::process::exit(1)
+}
+
 pub fn run() {
-    let data_dir = {
-        let mut p = dirs_next::data_dir().unwrap_or_else(|| std::path::PathBuf::from("."));
-        p.push("sanhuoai");
-        std::fs::create_dir_all(&p).ok();
-        p.to_string_lossy().to_string()
+    if fake_agent::serve_if_requested() {
+        return;
+    }
+
+    let (data_dir, data_dir_fallback) = choose_data_dir(data_location::resolve());
+    let data_dir = data_dir.to_string_lossy().to_string();
+    app_log::init(std::path::Path::new(&data_dir), "info");
+    if let Some(fallback) = &data_dir_fallback {
+        warn!(
+            requested = %fallback.requested,
+            reason = %fallback.reason,
+            "data directory unusable, running from {}",
+            fallback.fallback
+        );
+    }
+
+    // Keyed to the default location so moving the data directory can't split it
+    let lock_dir = data_location::default_dir();
+    let instance = match single_instance::claim(&lock_dir, generate_agent_token()) {
+        Ok(single_instance::Claim::Primary(instance)) => Some(std::sync::Arc::new(instance)),
+        Ok(single_instance::Claim::Forwarded) => return,
+        Err(e) => {
+            warn!(error = %e, "single-instance check failed, starting anyway");
+            None
+        }
     };
 
-    let db = Database::new(&data_dir).expect("Failed to initialize database");
+    let db = Database::new(&data_dir).unwrap_or_else(|e| {
+        startup_failed(&format!("Cannot open the database in {}: {}", data_dir, e))
+    });
+    // Nothing else has the database open yet, so a log left large by an unclean
+    // shutdown is folded in whole
+    match db.checkpoint() {
+        Ok(c) if c.wal_bytes_before > 0 => info!(
+            before = c.wal_bytes_before,
+            after = c.wal_bytes_after,
+            busy = c.busy,
+            "startup checkpoint"
+        ),
+        Ok(_) => {}
+        Err(e) => warn!(error = %e, "startup checkpoint failed"),
+    }
+    onboarding::detect_first_run(&db);
+    settings::migrate_legacy(&db);
+    let app_settings = settings::load(&db);
+    let _ = app_log::set_level(&app_settings.log_level);
+    let watchdog = WatchdogConfig::new(&app_settings);
 
     let state = AppState {
         db,
-        agent_process: Mutex::new(None),
-        data_dir,
+        agents: Mutex::new(HashMap::from([(
+            SHARED_AGENT.to_string(),
+            Arc::new(Agent::new(None, app_settings.agent_port)),
+        )])),
+        data_dir: Mutex::new(data_dir),
+        watchdog,
+        sysinfo: Mutex::new(sysinfo::System::new()),
+        installing_dependencies: AtomicBool::new(false),
+        streams: Mutex::new(HashMap::new()),
+        close_to_tray: AtomicBool::new(app_settings.close_to_tray),
+        notifications_enabled: AtomicBool::new(app_settings.notifications_enabled),
+        word_frequency: word_frequency::ReportCache::default(),
+        chapter_html: reader::HtmlCache::default(),
+        maintenance: RwLock::new(()),
+        data_dir_fallback,
+        credentials: credentials::Session::default(),
     };
 
-    tauri::Builder::default()
+    let served_instance = instance.clone();
+    let app = tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_notification::init())
         .manage(state)
         .invoke_handler(tauri::generate_handler![
             list_projects,
+            set_project_pinned,
+            reorder_pinned_projects,
+            search_projects,
             create_project,
+            list_project_templates,
+            create_project_from_template,
+            save_project_as_template,
+            get_onboarding_state,
+            set_onboarding_step,
+            create_sample_project,
+            import_project_markdown,
+            import_chapters_from_files,
+            export_project_json,
+            export_all_projects,
+            import_project_json,
+            snapshot_project,
+            restore_snapshot,
+            project_stats,
+            word_target_status,
+            get_today_progress,
+            set_daily_word_goal,
+            find_in_project,
+            search_project,
+            replace_in_project,
+            analyze_word_frequency,
+            check_character_consistency,
+            touch_project,
+            list_recent_projects,
+            touch_project_opened,
+            recent_projects,
+            get_startup_state,
+            set_project_cover,
+            get_project_cover,
+            remove_project_cover,
+            add_attachment,
+            list_attachments,
+            remove_attachment,
+            open_attachment,
+            list_characters,
+            create_character,
+            update_character,
+            delete_character,
+            create_relationship,
+            update_relationship,
+            delete_relationship,
+            get_relationship_graph,
+            trash_project,
+            restore_trashed_project,
+            get_trash_info,
+            empty_trash,
+            get_character_connections,
+            autosave_chapter,
+            chapter_html,
+            count_text,
+            recount_project_words,
+            recompute_word_counts,
+            list_revisions,
+            diff_revisions,
+            restore_revision,
+            set_chapter_status,
+            set_chapters_status,
+            list_scenes,
+            create_scene,
+            update_scene,
+            delete_scene,
+            reorder_scenes,
+            list_notes,
+            create_note,
+            update_note,
+            delete_note,
+            list_story_events,
+            create_story_event,
+            update_story_event,
+            delete_story_event,
+            reorder_story_events,
+            list_prompts,
+            create_prompt,
+            update_prompt,
+            delete_prompt,
+            reset_prompt,
+            list_model_presets,
+            create_model_preset,
+            update_model_preset,
+            delete_model_preset,
+            apply_preset_to_project,
             get_data_dir,
+            get_data_dir_info,
+            available_disk_space,
+            set_data_dir,
+            migrate_data_dir,
+            convert_to_portable,
+            convert_to_standard,
             agent_status,
             start_agent,
+            wait_for_agent_ready,
             stop_agent,
             restart_agent,
+            force_reset_agent,
+            set_agent_port,
+            get_watchdog_config,
+            set_watchdog_enabled,
+            set_watchdog_interval,
+            kill_orphaned_agents,
+            reindex_project,
+            get_embedding_index_status,
+            mark_embedding_index_stale,
+            validate_agent_environment,
+            agent_request,
+            install_agent_dependencies,
+            get_agent_token,
+            get_path_overrides,
+            get_app_settings,
+            update_app_settings,
+            get_setting,
+            set_setting,
+            set_python_path_override,
+            set_agent_dir_override,
+            agent_info,
+            agent_selftest,
+            resolve_diagnostics,
+            system_report,
+            export_diagnostics,
+            export_diagnostics_bundle,
+            get_app_log,
+            get_agent_log,
+            clear_agent_log,
+            get_startup_info,
+            database_size,
+            vacuum_database,
+            check_database_integrity,
+            check_integrity,
+            optimize_database,
+            checkpoint_database,
+            run_backup_now,
+            reveal_in_file_manager,
+            agent_stream_request,
+            agent_cancel_request,
+            cancel_generation,
+            list_api_credentials,
+            get_api_credential,
+            set_api_credential,
+            delete_api_credential,
+            credential_lock_status,
+            unlock_credentials,
+            change_credentials_passphrase,
         ])
-        .setup(|app| {
+        .setup(move |app| {
             let handle = app.handle().clone();
-            let data_dir = app.state::<AppState>().data_dir.clone();
+            let data_dir = app.state::<AppState>().data_dir();
+
+            if let Some(instance) = &served_instance {
+                let handle = handle.clone();
+                let served = instance.serve(move |launch| {
+                    info!(args = ?launch.args, "launched again, focusing the main window");
+                    tray::show_main_window(&handle);
+                    let _ = handle.emit(events::SECOND_INSTANCE, launch);
+                });
+                if let Err(e) = served {
+                    warn!(error = %e, "cannot accept launches from other instances");
+                }
+            }
+
+            // Before the agent starts, so the tray and notifications see its first events
+            if let Err(e) = tray::init(&handle) {
+                warn!(error = %e, "tray icon unavailable");
+            }
+            notify::init(&handle);
+            let _ = handle.emit(events::APP_READY, startup_info(&handle));
+            if let Some(fallback) = &app.state::<AppState>().data_dir_fallback {
+                let _ = handle.emit(events::DATA_DIR_FALLBACK, fallback.clone());
+            }
 
             // Auto-start the Python agent service
             std::thread::spawn({
                 let handle = handle.clone();
                 move || {
-                    if let Some(child) = spawn_agent(&handle, &data_dir) {
-                        let state = handle.state::<AppState>();
-                        let mut proc = state.agent_process.lock().unwrap();
-                        *proc = Some(child);
+                    let state = handle.state::<AppState>();
+                    // Project agents start on demand; any file left for one is an orphan
+                    for file in project_runtime_files(&data_dir) {
+                        kill_orphaned_agent(&state, &file, &[]);
+                        let _ = std::fs::remove_file(&file);
                     }
+                    let agent = state.shared_agent();
+                    let started = agent.manager.start(|| {
+                        let claim = if state.watchdog.external_agent_mode.load(Ordering::SeqCst) {
+                            attach_agent(&agent).map(|()| PortClaim::Adopted)
+                        } else {
+                            claim_agent_port(&state, &agent)
+                        };
+                        match claim? {
+                            PortClaim::Free => spawn_agent(&handle, &agent, &data_dir).map(Some),
+                            PortClaim::Adopted => {
+                                tray::set_indicator(&handle, tray::Indicator::Ready);
+                                Ok(None)
+                            }
+                        }
+                    });
+                    if let Err(e) = started {
+                        return agent_start_failed(&handle, None, e);
+                    }
+                    check_agent_compatibility(&handle);
                 }
             });
 
+            start_backup_scheduler(handle.clone());
+            start_trash_purger(handle.clone());
             // Start watchdog for auto-restart
             start_watchdog(handle);
 
             Ok(())
         })
-        .on_window_event(|window, event| {
-            if let tauri::WindowEvent::Destroyed = event {
-                let state = window.state::<AppState>();
-                let mut proc = state.agent_process.lock().unwrap();
-                if let Some(child) = proc.take() {
-                    kill_process_tree(child);
-                }
+        .on_window_event(|window, event| match event {
+            tauri::WindowEvent::CloseRequested { api, .. }
+                if window.label() == tray::MAIN_WINDOW
+                    && window.state::<AppState>().close_to_tray.load(Ordering::SeqCst) =>
+            {
+                api.prevent_close();
+                let _ = window.hide();
             }
+            tauri::WindowEvent::Destroyed => {
+                cancel_streams(&window.state::<AppState>(), window.label());
+                stop_agent_for_exit(window.app_handle());
+            }
+            _ => {}
         })
-        .run(tauri::generate_context!())
+        .build(tauri::generate_context!())
         .expect("error while running tauri application");
+    app.run(move |_, event| {
+        if let tauri::RunEvent::Exit = event {
+            if let Some(instance) = &instance {
+                instance.release();
+            }
+        }
+    });
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::{SocketAddr, TcpStream};
+    use std::process::Stdio;
+
+    // Binds a port, forks, and reports the port and the forked child's pid
+    const FORKING_SCRIPT: &str = r#"
+import os, socket, sys, time
+s = socket.socket()
+s.bind(("127.0.0.1", 0))
+s.listen()
+pid = os.fork()
+if pid == 0:
+    time.sleep(300)
+    sys.exit(0)
+print(s.getsockname()[1], pid, flush=True)
+time.sleep(300)
+"#;
+
+    fn is_alive(pid: u32) -> bool {
+        // Zombies count as gone: they no longer hold resources
+        Command::new("ps")
+            .args(["-o", "stat=", "-p", &pid.to_string()])
+            .output()
+            .map(|out| {
+                let stat = String::from_utf8_lossy(&out.stdout);
+                let stat = stat.trim();
+                !stat.is_empty() && !stat.starts_with('Z')
+            })
+            .unwrap_or(false)
+    }
+
+    fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if done() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        done()
+    }
+
+    fn test_state(data_dir: &std::path::Path) -> AppState {
+        let app_settings = settings::AppSettings::default();
+        AppState {
+            db: Database::new_in_memory().unwrap(),
+            // Nothing listens on port 1, so health checks fail at once
+            agents: Mutex::new(HashMap::from([(
+                SHARED_AGENT.to_string(),
+                Arc::new(Agent::new(None, 1)),
+            )])),
+            data_dir: Mutex::new(data_dir.display().to_string()),
+            watchdog: WatchdogConfig::new(&app_settings),
+            sysinfo: Mutex::new(sysinfo::System::new()),
+            installing_dependencies: AtomicBool::new(false),
+            streams: Mutex::new(HashMap::new()),
+            close_to_tray: AtomicBool::new(false),
+            notifications_enabled: AtomicBool::new(false),
+            word_frequency: word_frequency::ReportCache::default(),
+            chapter_html: reader::HtmlCache::default(),
+                maintenance: RwLock::new(()),
+            data_dir_fallback: None,
+            credentials: credentials::Session::default(),
+        }
+    }
+
+    #[test]
+    fn agent_status_survives_poisoned_locks() {
+        let state = test_state(&std::env::temp_dir());
+        let agent = state.shared_agent();
+        let started = agent.manager.start(|| {
+            Command::new("sleep").arg("30").spawn().map(Some).map_err(|e| e.to_string())
+        });
+        let Ok(Started::Spawned(pid)) = started else { panic!("start failed: {:?}", started) };
+        *agent.started_at.lock().unwrap() = Some(SystemTime::now());
+
+        std::thread::scope(|scope| {
+            let poisoned = scope.spawn(|| {
+                let _started_at = agent.started_at.lock().unwrap();
+                let _paths = agent.paths.lock().unwrap();
+                let _sysinfo = state.sysinfo.lock().unwrap();
+                let _token = agent.token.lock().unwrap();
+                panic!("a command panicked mid-update");
+            });
+            assert!(poisoned.join().is_err());
+        });
+        assert!(agent.started_at.is_poisoned() && agent.token.is_poisoned());
+
+        let status = status_of(&state, &agent);
+        assert_eq!(status.pid, Some(pid));
+        assert!(status.running && !status.ready);
+        assert!(status.started_at.is_some());
+        let mut child = agent.manager.lock().take().unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn orphan_kill_spares_a_reused_pid() {
+        let dir = std::env::temp_dir().join(format!("orphan-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = test_state(&dir);
+        // An unrelated program now holds the pid the runtime file names
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        isolate_process_group(&mut cmd);
+        let mut stranger = cmd.spawn().unwrap();
+        let runtime = AgentRuntime {
+            port: 1,
+            token: String::new(),
+            pid: stranger.id(),
+            agent_dir: Some(dir.clone()),
+        };
+        let runtime_file = runtime_file_path(&state.data_dir(), &state.shared_agent());
+        write_runtime_file(&runtime_file, &runtime);
+
+        assert!(!kill_orphaned_agent(&state, &runtime_file, &[]));
+        assert!(is_alive(stranger.id()));
+        stranger.kill().unwrap();
+        stranger.wait().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // What spawn_agent runs with SANHUOAI_FAKE_AGENT set, minus the AppHandle
+    fn spawn_fake_agent(agent: &Agent) -> Result<Option<Child>, String> {
+        let (_, mut cmd) = fake_agent::command(agent.port())?;
+        cmd.env(AGENT_TOKEN_ENV_KEY, agent.token.lock().unwrap().as_str())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        isolate_process_group(&mut cmd);
+        cmd.spawn().map(Some).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn fake_agent_spawns_answers_stops_and_restarts_after_a_crash() {
+        std::env::set_var(fake_agent::ENABLE_ENV_KEY, "1");
+        assert!(fake_agent::enabled());
+        let dir = std::env::temp_dir().join(format!("fake-agent-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = test_state(&dir);
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .port();
+        let agent = state.shared_agent();
+        agent.port.store(port, Ordering::SeqCst);
+        let lifecycle = agent.manager.lock();
+
+        // Spawn and health
+        lifecycle.spawn(|| spawn_fake_agent(&agent)).unwrap();
+        assert!(wait_until(|| check_health(&agent)), "stub never answered /health");
+        let PortOwner::Ours = probe_agent_port(&agent) else { panic!("stub not recognised") };
+
+        // Stop
+        let child = lifecycle.take().unwrap();
+        assert!(matches!(shutdown_agent(&state, &agent, child), Shutdown::Graceful));
+        assert!(wait_until(|| !port_open(port)), "port still held after stop");
+
+        // Crash, then restart the way the watchdog does
+        lifecycle.spawn(|| spawn_fake_agent(&agent)).unwrap();
+        assert!(wait_until(|| check_health(&agent)));
+        let pid = agent.manager.pid().unwrap();
+        unsafe { libc::kill(pid as i32, libc::SIGKILL); }
+        let mut reaped = None;
+        assert!(wait_until(|| {
+            reaped = lifecycle.reap();
+            reaped.is_some()
+        }));
+        let (crashed_pid, status) = reaped.unwrap();
+        assert_eq!(crashed_pid, pid);
+        assert!(!status.success());
+        assert!(state.watchdog.should_restart(&agent));
+        record_crash(&agent);
+        let restarted = lifecycle.spawn(|| spawn_fake_agent(&agent)).unwrap();
+        assert!(matches!(restarted, Started::Spawned(new_pid) if new_pid != pid));
+        assert!(wait_until(|| check_health(&agent)), "restarted stub never answered");
+
+        let child = lifecycle.take().unwrap();
+        shutdown_agent(&state, &agent, child);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn project_agents_run_beside_the_shared_one_on_their_own_ports() {
+        std::env::set_var(fake_agent::ENABLE_ENV_KEY, "1");
+        let dir = std::env::temp_dir().join(format!("project-agent-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = test_state(&dir);
+        let shared = state.shared_agent();
+        shared.port.store(free_port().unwrap(), Ordering::SeqCst);
+        let project = state.db.create_project("长夜", "玄幻").unwrap();
+
+        assert!(agent_for(&state, Some("no-such-project")).is_err());
+        let agent = agent_for(&state, Some(&project.id)).unwrap();
+        assert!(Arc::ptr_eq(&agent, &agent_for(&state, Some(&project.id)).unwrap()));
+        assert_ne!(agent.port(), shared.port());
+        assert_eq!(state.all_agents().len(), 2);
+
+        shared.manager.start(|| spawn_fake_agent(&shared)).unwrap();
+        agent.manager.start(|| spawn_fake_agent(&agent)).unwrap();
+        assert!(wait_until(|| check_health(&shared) && check_health(&agent)));
+        assert!(Arc::ptr_eq(&agent_serving(&state, Some(&project.id)), &agent));
+
+        // Stopping the project's agent leaves the shared one serving
+        let child = agent.manager.lock().take().unwrap();
+        shutdown_agent(&state, &agent, child);
+        assert!(wait_until(|| !port_open(agent.port())), "project agent's port still held");
+        assert!(check_health(&shared));
+        assert!(Arc::ptr_eq(&agent_serving(&state, Some(&project.id)), &shared));
+
+        let child = shared.manager.lock().take().unwrap();
+        shutdown_agent(&state, &shared, child);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn kill_process_tree_reaps_forked_children_and_frees_port() {
+        let mut cmd = Command::new("python3");
+        cmd.args(["-c", FORKING_SCRIPT]).stdout(Stdio::piped());
+        isolate_process_group(&mut cmd);
+        let mut child = cmd.spawn().expect("python3 is required for this test");
+
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let mut fields = line.split_whitespace();
+        let port: u16 = fields.next().unwrap().parse().unwrap();
+        let grandchild: u32 = fields.next().unwrap().parse().unwrap();
+        let parent = child.id();
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+
+        assert!(is_alive(grandchild));
+        assert!(TcpStream::connect_timeout(&addr, Duration::from_millis(500)).is_ok());
+
+        kill_process_tree(child);
+
+        assert!(!is_alive(parent));
+        assert!(wait_until(|| !is_alive(grandchild)), "forked child survived");
+        assert!(
+            wait_until(|| TcpStream::connect_timeout(&addr, Duration::from_millis(200)).is_err()),
+            "port still held"
+        );
+    }
 }
@@ -0,0 +1,74 @@
+/// A manuscript split on Markdown headings: `#` names the book, `##` starts a chapter
+pub struct Manuscript {
+    pub title: Option<String>,
+    /// Text between the H1 and the first H2 (or the whole body when there are no H2s)
+    pub preface: String,
+    pub chapters: Vec<ManuscriptChapter>,
+}
+
+pub struct ManuscriptChapter {
+    pub title: String,
+    pub body: String,
+}
+
+/// Return the heading text if `line` is an ATX heading of exactly `level`
+fn heading(line: &str, level: usize) -> Option<&str> {
+    let trimmed = line.trim_start();
+    let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+    if hashes != level {
+        return None;
+    }
+    let rest = &trimmed[hashes..];
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    // Closing sequence: "## Title ##"
+    Some(rest.trim().trim_end_matches('#').trim_end())
+}
+
+fn is_fence(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("```") || trimmed.starts_with("~~~")
+}
+
+fn join_body(lines: &[&str]) -> String {
+    lines.join("\n").trim_matches('\n').trim_end().to_string()
+}
+
+pub fn parse(text: &str) -> Manuscript {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut title = None;
+    let mut preface: Vec<&str> = Vec::new();
+    let mut chapters: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut in_fence = false;
+
+    for line in text.lines() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        } else if !in_fence {
+            if title.is_none() && chapters.is_empty() {
+                if let Some(h1) = heading(line, 1) {
+                    title = Some(h1.to_string());
+                    continue;
+                }
+            }
+            if let Some(h2) = heading(line, 2) {
+                chapters.push((h2.to_string(), Vec::new()));
+                continue;
+            }
+        }
+        match chapters.last_mut() {
+            Some((_, body)) => body.push(line),
+            None => preface.push(line),
+        }
+    }
+
+    Manuscript {
+        title: title.filter(|t| !t.is_empty()),
+        preface: join_body(&preface),
+        chapters: chapters
+            .into_iter()
+            .map(|(title, body)| ManuscriptChapter { title, body: join_body(&body) })
+            .collect(),
+    }
+}
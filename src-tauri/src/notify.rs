@@ -0,0 +1,83 @@
+//! OS notifications for things the user should hear about without looking at
+//! the window: agent crashes and failed starts, and long operations finishing
+//! while the app is in the background. Rate-limited so a crash loop can't flood
+//! the notification centre.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Listener, Manager};
+use tauri_plugin_notification::NotificationExt;
+use tracing::{debug, warn};
+
+use crate::{events, tray, AppState};
+
+/// Agent restarts are expected while the app is starting up, so they go unannounced
+const STARTUP_QUIET: Duration = Duration::from_secs(10);
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+const RATE_LIMIT: usize = 3;
+
+struct Notifier {
+    started: Instant,
+    /// When the last RATE_LIMIT notifications were shown
+    recent: Mutex<VecDeque<Instant>>,
+}
+
+pub fn init(app: &AppHandle) {
+    app.manage(Notifier { started: Instant::now(), recent: Mutex::new(VecDeque::new()) });
+
+    let handle = app.clone();
+    app.listen_any(events::AGENT_RESTARTING, move |_| {
+        let starting_up = handle
+            .try_state::<Notifier>()
+            .is_some_and(|notifier| notifier.started.elapsed() < STARTUP_QUIET);
+        if starting_up {
+            debug!("restart notification suppressed during startup");
+            return;
+        }
+        let body = "Any generation in progress was interrupted.";
+        show(&handle, "AI agent crashed and was restarted", body);
+    });
+    let handle = app.clone();
+    app.listen_any(events::AGENT_START_FAILED, move |_| {
+        show(
+            &handle,
+            "AI agent failed to start",
+            "AI features are unavailable. Open diagnostics in Settings to see why.",
+        );
+    });
+}
+
+/// Announce a finished long operation, unless the user is looking at the app anyway
+pub fn finished(app: &AppHandle, title: &str, body: &str) {
+    let focused = app
+        .get_webview_window(tray::MAIN_WINDOW)
+        .is_some_and(|window| window.is_focused().unwrap_or(false));
+    if !focused {
+        show(app, title, body);
+    }
+}
+
+fn show(app: &AppHandle, title: &str, body: &str) {
+    if !app.state::<AppState>().notifications_enabled.load(Ordering::SeqCst) {
+        return;
+    }
+    let Some(notifier) = app.try_state::<Notifier>() else {
+        return;
+    };
+    {
+        let mut recent = notifier.recent.lock().unwrap();
+        while recent.front().is_some_and(|shown| shown.elapsed() >= RATE_WINDOW) {
+            recent.pop_front();
+        }
+        if recent.len() >= RATE_LIMIT {
+            debug!(title, "notification rate-limited");
+            return;
+        }
+        recent.push_back(Instant::now());
+    }
+    if let Err(e) = app.notification().builder().title(title).body(body).show() {
+        warn!(error = %e, "failed to show notification");
+    }
+}
@@ -0,0 +1,113 @@
+//! First-run guidance: which onboarding steps are done, kept in app settings,
+//! and the sample project new users can click around in.
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::Database;
+use crate::project_templates::TemplateContent;
+use crate::settings;
+
+const SAMPLE_JSON: &str = include_str!("../../database/sample_project.json");
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Onboarding {
+    /// Set at launch on a brand-new database; cleared when onboarding is finished
+    pub first_run: bool,
+    pub agent_validated: bool,
+    /// Stays set after the sample is deleted, so it is never created again unasked
+    pub sample_created: bool,
+    /// When first_run was detected; kept afterwards so the database never looks new again
+    pub started_at: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    AgentValidated,
+    SampleCreated,
+    /// Done clears first_run
+    Finished,
+}
+
+impl Onboarding {
+    pub fn set(&mut self, step: Step, done: bool) {
+        match step {
+            Step::AgentValidated => self.agent_validated = done,
+            Step::SampleCreated => self.sample_created = done,
+            Step::Finished => self.first_run = !done,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SampleProject {
+    pub name: String,
+    pub genre: String,
+    pub description: String,
+    #[serde(flatten)]
+    pub content: TemplateContent,
+    pub chapters: Vec<SampleChapter>,
+}
+
+#[derive(Deserialize)]
+pub struct SampleChapter {
+    pub title: String,
+    #[serde(default)]
+    pub synopsis: String,
+    /// One paragraph per line
+    pub text: String,
+}
+
+pub fn sample() -> SampleProject {
+    serde_json::from_str(SAMPLE_JSON).expect("sample_project.json is valid")
+}
+
+/// Apply one step to the stored onboarding state and return the result
+pub fn update(db: &Database, step: Step, done: bool) -> Result<Onboarding, String> {
+    let current = settings::load(db);
+    let mut updated = current.clone();
+    updated.onboarding.set(step, done);
+    settings::save(db, &current, &updated)?;
+    Ok(updated.onboarding)
+}
+
+/// Called at launch before anything is written, so only a database that has
+/// never held a project or a setting starts onboarding
+pub fn detect_first_run(db: &Database) {
+    match db.is_pristine() {
+        Ok(true) => {
+            let current = settings::load(db);
+            let mut updated = current.clone();
+            updated.onboarding.first_run = true;
+            updated.onboarding.started_at = Some(crate::app_log::timestamp());
+            if let Err(e) = settings::save(db, &current, &updated) {
+                tracing::error!(error = %e, "failed to record first run");
+            }
+        }
+        Ok(false) => {}
+        Err(e) => tracing::warn!(error = %e, "could not tell whether this is a first run"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_run_is_only_detected_on_an_empty_database() {
+        let db = Database::new_in_memory().unwrap();
+        detect_first_run(&db);
+        assert!(settings::load(&db).onboarding.first_run);
+        let state = update(&db, Step::Finished, true).unwrap();
+        assert!(!state.first_run);
+        detect_first_run(&db);
+        assert!(!settings::load(&db).onboarding.first_run);
+
+        let db = Database::new_in_memory().unwrap();
+        let project = db.create_sample_project(&sample()).unwrap();
+        assert_eq!(project.name, "示例：雾港来信");
+        detect_first_run(&db);
+        assert!(!settings::load(&db).onboarding.first_run);
+    }
+}
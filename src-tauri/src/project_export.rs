@@ -0,0 +1,206 @@
+//! Every project exported at once, one file each, for backing up the whole
+//! corpus. JSON files are the same re-importable backups export_project_json
+//! writes; Markdown splits back into chapters on import.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backup::{ChapterBackup, ProjectBackup};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Text,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "text" | "txt" => Ok(ExportFormat::Text),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("Unknown export format: {} (markdown, text or json)", format)),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Text => "txt",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectExportError {
+    pub project_id: String,
+    pub name: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ExportSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Paths written, in the order the projects were listed
+    pub files: Vec<String>,
+    pub errors: Vec<ProjectExportError>,
+}
+
+impl ExportSummary {
+    /// Count one project's outcome; a failure doesn't stop the rest
+    pub fn record(&mut self, project_id: &str, name: &str, result: Result<PathBuf, String>) {
+        match result {
+            Ok(path) => {
+                self.succeeded += 1;
+                self.files.push(path.display().to_string());
+            }
+            Err(error) => {
+                self.failed += 1;
+                self.errors.push(ProjectExportError {
+                    project_id: project_id.to_string(),
+                    name: name.to_string(),
+                    error,
+                });
+            }
+        }
+    }
+}
+
+/// Names already given out in this export, so two projects called the same never
+/// overwrite each other. Files left from an earlier export are overwritten.
+#[derive(Default)]
+pub struct FileNames(HashSet<String>);
+
+impl FileNames {
+    /// `name` made safe for a file name on every platform, with " (2)", " (3)", ...
+    /// added if it was taken; case is ignored, as on Windows and macOS
+    fn claim(&mut self, name: &str, extension: &str) -> String {
+        let cleaned: String = name
+            .chars()
+            .map(|c| if c.is_control() || r#"/\:*?"<>|"#.contains(c) { '_' } else { c })
+            .collect();
+        let cleaned = cleaned.trim().trim_matches('.');
+        let stem = if cleaned.is_empty() { "untitled" } else { cleaned };
+        let mut file_name = format!("{}.{}", stem, extension);
+        let mut n = 2;
+        while !self.0.insert(file_name.to_lowercase()) {
+            file_name = format!("{} ({}).{}", stem, n, extension);
+            n += 1;
+        }
+        file_name
+    }
+}
+
+/// Write one project into `dir` in `format`, returning the file's path
+pub fn write(
+    dir: &Path,
+    format: ExportFormat,
+    backup: &ProjectBackup,
+    names: &mut FileNames,
+) -> Result<PathBuf, String> {
+    let text = render(format, backup)?;
+    let path = dir.join(names.claim(&backup.project.name, format.extension()));
+    fs::write(&path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+pub fn render(format: ExportFormat, backup: &ProjectBackup) -> Result<String, String> {
+    if format == ExportFormat::Json {
+        return serde_json::to_string_pretty(backup).map_err(|e| e.to_string());
+    }
+    let markdown = format == ExportFormat::Markdown;
+    let mut out = if markdown {
+        format!("# {}\n", backup.project.name)
+    } else {
+        format!("{}\n", backup.project.name)
+    };
+    for chapter in &backup.chapters {
+        let paragraphs = chapter.paragraphs.iter().map(|p| p.content.as_str());
+        if markdown {
+            out.push_str(&format!("\n## {}\n", chapter_title(chapter)));
+            // A blank line between paragraphs, or Markdown would run them together
+            for paragraph in paragraphs.filter(|p| !p.trim().is_empty()) {
+                out.push_str(&format!("\n{}\n", paragraph));
+            }
+        } else {
+            let text = paragraphs.collect::<Vec<_>>().join("\n");
+            out.push_str(&format!("\n\n{}\n\n{}\n", chapter_title(chapter), text.trim_end()));
+        }
+    }
+    Ok(out)
+}
+
+/// 第N章, followed by the chapter's title when it has one
+fn chapter_title(chapter: &ChapterBackup) -> String {
+    let number = format!("第{}章", chapter.chapter_num);
+    match chapter.title.trim() {
+        "" => number,
+        title if title.starts_with(&number) => title.to_string(),
+        title => format!("{} {}", number, title),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::{self, ParagraphBackup};
+    use crate::db::Database;
+    use crate::markdown;
+
+    fn chapter(chapter_num: i64, title: &str, paragraphs: &[&str]) -> ChapterBackup {
+        ChapterBackup {
+            chapter_num,
+            title: title.into(),
+            phase: String::new(),
+            synopsis: String::new(),
+            status: "draft".into(),
+            word_count: 0,
+            sort_order: chapter_num,
+            paragraphs: paragraphs
+                .iter()
+                .enumerate()
+                .map(|(i, content)| ParagraphBackup {
+                    para_index: i as i64,
+                    content: content.to_string(),
+                    scene_tag: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn markdown_splits_back_into_the_same_chapters() {
+        let project = Database::new_in_memory().unwrap().create_project("长夜", "玄幻").unwrap();
+        let chapters = vec![
+            chapter(1, "夜行", &["林风拔剑。", "", "风起。"]),
+            chapter(2, "第2章 归来", &[]),
+            chapter(3, "", &["完"]),
+        ];
+        let backup = backup::new(project, chapters, Vec::new(), Vec::new());
+        let manuscript = markdown::parse(&render(ExportFormat::Markdown, &backup).unwrap());
+        assert_eq!(manuscript.title.as_deref(), Some("长夜"));
+        let titles: Vec<&str> = manuscript.chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["第1章 夜行", "第2章 归来", "第3章"]);
+        assert_eq!(manuscript.chapters[0].body, "林风拔剑。\n\n风起。");
+        let text = render(ExportFormat::Text, &backup).unwrap();
+        assert!(text.starts_with("长夜\n\n\n第1章 夜行\n\n林风拔剑。\n\n风起。\n"));
+        assert!(ExportFormat::parse("PDF").is_err());
+        assert_eq!(ExportFormat::parse(" Markdown ").unwrap(), ExportFormat::Markdown);
+    }
+
+    #[test]
+    fn file_names_are_safe_and_never_collide() {
+        let mut names = FileNames::default();
+        assert_eq!(names.claim("长夜", "md"), "长夜.md");
+        assert_eq!(names.claim("长夜", "md"), "长夜 (2).md");
+        assert_eq!(names.claim("A/B: c?", "md"), "A_B_ c_.md");
+        assert_eq!(names.claim(" .. ", "md"), "untitled.md");
+        assert_eq!(names.claim("Abc", "md"), "Abc.md");
+        assert_eq!(names.claim("abc", "md"), "abc (2).md");
+    }
+}
@@ -0,0 +1,117 @@
+//! Templates a new project can start from: outline nodes, placeholder characters,
+//! world entries and prompt overrides, never prose. Built-ins ship in
+//! database/project_templates.json; custom ones are saved from existing projects.
+
+use serde::{Deserialize, Serialize};
+
+/// Also written to the database by the agent's migration 025
+const BUILTIN_JSON: &str = include_str!("../../database/project_templates.json");
+/// Outline structure for templates that don't name one; the projects column default
+pub const DEFAULT_STRUCTURE: &str = "起承转合";
+
+/// Stored as JSON in project_templates.content
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TemplateContent {
+    /// 起承转合 / 三幕式 / 英雄之旅 / 自定义; outline phases are named after it
+    pub structure: String,
+    pub custom_structure: String,
+    pub chapter_words: Option<i64>,
+    pub word_target: Option<i64>,
+    /// In phase order
+    pub outlines: Vec<TemplateOutline>,
+    pub characters: Vec<TemplateCharacter>,
+    pub worldbuilding: Vec<TemplateWorldEntry>,
+    /// Project-level overrides of the global prompt with the same name
+    pub prompts: Vec<TemplatePrompt>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TemplateOutline {
+    pub phase: String,
+    pub title: String,
+    pub content: String,
+    pub word_range: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TemplateCharacter {
+    pub name: String,
+    pub category: String,
+    pub gender: String,
+    pub age: String,
+    pub identity: String,
+    pub appearance: String,
+    pub personality: String,
+    pub motivation: String,
+    pub backstory: String,
+    pub arc: String,
+    pub usage_notes: String,
+    pub aliases: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TemplateWorldEntry {
+    pub category: String,
+    pub title: String,
+    pub content: String,
+    /// Title of the entry this one nests under
+    pub parent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TemplatePrompt {
+    pub name: String,
+    pub category: String,
+    pub content: String,
+}
+
+#[derive(Serialize)]
+pub struct ProjectTemplate {
+    pub id: String,
+    pub name: String,
+    pub genre: String,
+    pub description: String,
+    pub is_builtin: bool,
+    pub created_at: String,
+    pub updated_at: String,
+    #[serde(flatten)]
+    pub content: TemplateContent,
+}
+
+#[derive(Deserialize)]
+pub struct BuiltinTemplate {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub genre: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(flatten)]
+    pub content: TemplateContent,
+}
+
+pub fn builtins() -> Vec<BuiltinTemplate> {
+    serde_json::from_str(BUILTIN_JSON).expect("project_templates.json is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtins_parse_with_outlines_for_their_structure() {
+        let templates = builtins();
+        assert!(!templates.is_empty());
+        for template in &templates {
+            assert!(template.id.starts_with("builtin-"), "{}", template.id);
+            assert!(!template.content.structure.is_empty());
+            assert!(!template.content.outlines.is_empty());
+            assert!(template.content.characters.iter().all(|c| !c.name.is_empty()));
+        }
+    }
+}
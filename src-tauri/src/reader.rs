@@ -0,0 +1,102 @@
+//! Chapter text as HTML for the reader view. Each line of a chapter is a
+//! paragraph, as in the Markdown export, and the rest is Markdown. Raw HTML in
+//! the text (imported content can carry anything) is shown as text, and links
+//! and images can't use script or data URLs.
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+const CACHED_CHAPTERS: usize = 32;
+const UNSAFE_SCHEMES: &[&str] = &["javascript:", "vbscript:", "data:"];
+
+/// Recently rendered chapters by content hash, so reopening one is instant
+#[derive(Default)]
+pub struct HtmlCache(Mutex<VecDeque<(u64, String)>>);
+
+impl HtmlCache {
+    pub fn get(&self, hash: u64) -> Option<String> {
+        let cache = self.0.lock().unwrap();
+        cache.iter().find(|(key, _)| *key == hash).map(|(_, html)| html.clone())
+    }
+
+    pub fn insert(&self, hash: u64, html: String) {
+        let mut cache = self.0.lock().unwrap();
+        cache.retain(|(key, _)| *key != hash);
+        if cache.len() == CACHED_CHAPTERS {
+            cache.pop_front();
+        }
+        cache.push_back((hash, html));
+    }
+}
+
+pub fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn render(content: &str) -> String {
+    // A blank line between paragraphs, or Markdown would run them together
+    let markdown = content
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let options = Options::ENABLE_STRIKETHROUGH;
+    let events = Parser::new_ext(&markdown, options).map(|event| match event {
+        // An HTML block becomes a paragraph of its source
+        Event::Start(Tag::HtmlBlock) => Event::Start(Tag::Paragraph),
+        Event::End(TagEnd::HtmlBlock) => Event::End(TagEnd::Paragraph),
+        Event::Html(raw) => Event::Text(raw.trim_end_matches('\n').to_string().into()),
+        Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, events);
+    out
+}
+
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let scheme: String = url.trim_start().chars().filter(|c| !c.is_whitespace()).take(11).collect();
+    let scheme = scheme.to_ascii_lowercase();
+    if UNSAFE_SCHEMES.iter().any(|unsafe_scheme| scheme.starts_with(unsafe_scheme)) {
+        CowStr::Borrowed("")
+    } else {
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_paragraphs_and_raw_html_is_escaped() {
+        let html = render("林风拔剑。\n\n**风起**了。\n<script>alert(1)</script>\n");
+        assert_eq!(
+            html,
+            "<p>林风拔剑。</p>\n<p><strong>风起</strong>了。</p>\n\
+             <p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n"
+        );
+        let html = render("[看](javascript:alert(1)) <img src=x onerror=alert(1)>");
+        assert!(!html.contains("javascript") && !html.contains("<img"));
+        assert!(render("[看](https://example.com)").contains("href=\"https://example.com\""));
+        assert_eq!(content_hash("夜"), content_hash("夜"));
+    }
+}
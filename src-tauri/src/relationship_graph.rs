@@ -0,0 +1,110 @@
+//! Directed relationships between a project's characters, stored in the agent's
+//! character_relations table: from_character is character_a_id, to_character is
+//! character_b_id and kind is relation_type. Edges the agent extracts show up here
+//! too, with strength 0.
+
+use serde::{Deserialize, Serialize};
+
+pub const MAX_STRENGTH: i64 = 10;
+
+#[derive(Serialize, Debug)]
+pub struct Relationship {
+    pub id: String,
+    pub from_character: String,
+    pub to_character: String,
+    /// 师徒, 宿敌, ...
+    pub kind: String,
+    pub description: String,
+    /// 1..=MAX_STRENGTH, or 0 when not set
+    pub strength: i64,
+    pub created_at: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RelationshipUpdate {
+    pub kind: Option<String>,
+    pub description: Option<String>,
+    pub strength: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GraphNode {
+    pub id: String,
+    pub name: String,
+    pub category: String,
+    /// Edges in or out, for sizing the node
+    pub degree: usize,
+}
+
+/// Every character of the project, related or not, and the edges between them
+#[derive(Serialize, Debug)]
+pub struct RelationshipGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<Relationship>,
+}
+
+/// One of a character's direct relationships, seen from that character
+#[derive(Serialize, Debug)]
+pub struct CharacterConnection {
+    pub character_id: String,
+    pub name: String,
+    /// Whether the edge points from the character to this one
+    pub outgoing: bool,
+    pub relationship: Relationship,
+}
+
+pub fn check_strength(strength: i64) -> Result<(), String> {
+    if !(0..=MAX_STRENGTH).contains(&strength) {
+        return Err(format!("Relationship strength must be between 0 and {}", MAX_STRENGTH));
+    }
+    Ok(())
+}
+
+/// `characters` as (id, name, category), in the order the graph should list them
+pub fn build(
+    characters: Vec<(String, String, String)>,
+    edges: Vec<Relationship>,
+) -> RelationshipGraph {
+    let nodes = characters
+        .into_iter()
+        .map(|(id, name, category)| {
+            let degree = edges
+                .iter()
+                .filter(|edge| edge.from_character == id || edge.to_character == id)
+                .count();
+            GraphNode { id, name, category, degree }
+        })
+        .collect();
+    RelationshipGraph { nodes, edges }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(from: &str, to: &str) -> Relationship {
+        Relationship {
+            id: format!("{}-{}", from, to),
+            from_character: from.into(),
+            to_character: to.into(),
+            kind: "师徒".into(),
+            description: String::new(),
+            strength: 0,
+            created_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn nodes_count_edges_in_both_directions() {
+        let character = |id: &str| (id.to_string(), id.to_string(), "配角".to_string());
+        let graph = build(
+            vec![character("a"), character("b"), character("c")],
+            vec![edge("a", "b"), edge("b", "a")],
+        );
+        let degrees: Vec<usize> = graph.nodes.iter().map(|node| node.degree).collect();
+        assert_eq!(degrees, [2, 2, 0]);
+        assert!(check_strength(MAX_STRENGTH).is_ok());
+        assert!(check_strength(-1).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! Typed app-level preferences, stored one JSON value per key in app_settings.
+//! Only values that differ from the defaults are kept, so changing a default
+//! reaches everyone who never touched that setting.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::db::Database;
+use crate::onboarding::Onboarding;
+
+pub const DEFAULT_AGENT_PORT: u16 = 8765;
+/// Lowest agent port accepted; below it are privileged and well-known ports
+pub const MIN_AGENT_PORT: u16 = 1024;
+const THEMES: &[&str] = &["system", "light", "dark"];
+const FONT_SIZES: std::ops::RangeInclusive<u32> = 10..=40;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct AppSettings {
+    /// "system", "light" or "dark"
+    pub theme: String,
+    /// UI language as a BCP 47 tag, e.g. "zh-CN"
+    pub language: String,
+    /// Editor text size in CSS pixels
+    pub font_size: u32,
+    /// Preselected genre when creating a project
+    pub default_genre: String,
+    /// Read at launch; changing it takes effect after a restart of the app
+    pub agent_port: u16,
+    pub watchdog_enabled: bool,
+    pub watchdog_interval_secs: u64,
+    pub agent_shutdown_grace_secs: u64,
+    pub python_path_override: Option<String>,
+    pub agent_dir_override: Option<String>,
+    /// Scheduled database backups into the backups folder
+    pub auto_backup_enabled: bool,
+    /// Hours between automatic backups; 0 also turns them off
+    pub auto_backup_interval_hours: u64,
+    /// Backups kept, automatic and manual alike; older ones are deleted
+    pub auto_backup_keep: u32,
+    /// When the last backup was written, "YYYY-MM-DD HH:MM:SS" UTC
+    pub last_backup_at: Option<String>,
+    /// Rust-side log level: "error", "warn", "info", "debug" or "trace"
+    pub log_level: String,
+    /// Closing the main window hides it to the tray and leaves the agent running
+    pub close_to_tray: bool,
+    /// OS notifications for agent crashes and long operations finishing in the background
+    pub notifications_enabled: bool,
+    /// Kept up to date by touch_project so the next launch can reopen it
+    pub last_open_project_id: Option<String>,
+    /// Which first-run steps are done; see set_onboarding_step
+    pub onboarding: Onboarding,
+    /// Words a day for projects without their own daily_word_goal; 0 for none
+    pub daily_word_goal: u32,
+    /// Days a trashed project is kept before it is purged; 0 keeps it until the
+    /// trash is emptied
+    pub trash_retention_days: u32,
+    /// Windows: write the agent's output to agent.log instead of showing it in a
+    /// console window. Other platforms always log to the file.
+    pub agent_log_to_file: bool,
+    /// Never spawn the agent: attach to one started by hand on agent_port, e.g.
+    /// `uvicorn --reload` while working on the agent itself
+    pub external_agent_mode: bool,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            theme: "system".into(),
+            language: "zh-CN".into(),
+            font_size: 16,
+            default_genre: String::new(),
+            agent_port: DEFAULT_AGENT_PORT,
+            watchdog_enabled: true,
+            watchdog_interval_secs: 3,
+            agent_shutdown_grace_secs: 5,
+            python_path_override: None,
+            agent_dir_override: None,
+            auto_backup_enabled: false,
+            auto_backup_interval_hours: 24,
+            auto_backup_keep: 10,
+            last_backup_at: None,
+            log_level: "info".into(),
+            close_to_tray: false,
+            notifications_enabled: true,
+            last_open_project_id: None,
+            onboarding: Onboarding::default(),
+            daily_word_goal: 0,
+            trash_retention_days: 30,
+            agent_log_to_file: false,
+            external_agent_mode: false,
+        }
+    }
+}
+
+impl AppSettings {
+    fn validate(&self) -> Result<(), String> {
+        if !THEMES.contains(&self.theme.as_str()) {
+            return Err(format!("Unknown theme: {}", self.theme));
+        }
+        if self.language.trim().is_empty() {
+            return Err("Language cannot be empty".into());
+        }
+        if !FONT_SIZES.contains(&self.font_size) {
+            return Err(format!(
+                "Font size must be between {} and {}",
+                FONT_SIZES.start(),
+                FONT_SIZES.end()
+            ));
+        }
+        if self.agent_port < MIN_AGENT_PORT {
+            return Err(format!("Agent port must be between {} and 65535", MIN_AGENT_PORT));
+        }
+        if self.watchdog_interval_secs == 0 {
+            return Err("Watchdog interval must be at least 1 second".into());
+        }
+        if self.auto_backup_keep == 0 {
+            return Err("At least one backup must be kept".into());
+        }
+        if crate::app_log::parse_level(&self.log_level).is_none() {
+            return Err(format!("Unknown log level: {}", self.log_level));
+        }
+        Ok(())
+    }
+
+    /// Blank overrides mean "no override"
+    fn normalize(&mut self) {
+        for path in [&mut self.python_path_override, &mut self.agent_dir_override] {
+            *path = path
+                .take()
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty());
+        }
+    }
+}
+
+fn to_map(settings: &AppSettings) -> Map<String, Value> {
+    match serde_json::to_value(settings) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    }
+}
+
+/// Stored values that no longer fit their field (hand edits, changed types)
+/// fall back to the default instead of failing the whole load
+pub fn load(db: &Database) -> AppSettings {
+    let mut merged = to_map(&AppSettings::default());
+    let stored = db.list_settings().unwrap_or_else(|e| {
+        tracing::error!(error = %e, "failed to load settings");
+        Vec::new()
+    });
+    for (key, value) in stored {
+        let Some(previous) = merged.insert(key.clone(), value) else {
+            // Not one of ours
+            merged.remove(&key);
+            continue;
+        };
+        if serde_json::from_value::<AppSettings>(Value::Object(merged.clone())).is_err() {
+            tracing::warn!(key = %key, "ignoring invalid value for setting");
+            merged.insert(key, previous);
+        }
+    }
+    serde_json::from_value(Value::Object(merged)).unwrap_or_default()
+}
+
+/// One setting's current value; None for names that aren't settings
+pub fn get(settings: &AppSettings, key: &str) -> Option<Value> {
+    to_map(settings).remove(key)
+}
+
+/// Apply a partial update (`{ field: value }`) on top of `current` and validate it
+pub fn merge(current: &AppSettings, patch: Map<String, Value>) -> Result<AppSettings, String> {
+    let mut merged = to_map(current);
+    for (key, value) in patch {
+        if !merged.contains_key(&key) {
+            return Err(format!("Unknown setting: {}", key));
+        }
+        merged.insert(key, value);
+    }
+    let mut updated: AppSettings = serde_json::from_value(Value::Object(merged))
+        .map_err(|e| format!("Invalid settings: {}", e))?;
+    updated.normalize();
+    updated.validate()?;
+    Ok(updated)
+}
+
+/// Persist the keys that changed between `before` and `after`
+pub fn save(db: &Database, before: &AppSettings, after: &AppSettings) -> Result<(), String> {
+    let (before, defaults) = (to_map(before), to_map(&AppSettings::default()));
+    for (key, value) in to_map(after) {
+        if before.get(&key) == Some(&value) {
+            continue;
+        }
+        let result = if defaults.get(&key) == Some(&value) {
+            db.delete_setting(&key)
+        } else {
+            db.set_setting(&key, &value)
+        };
+        result.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Move preferences older versions kept as strings in global_settings
+pub fn migrate_legacy(db: &Database) {
+    let mut settings = load(db);
+    let before = settings.clone();
+    let legacy = |key: &str| {
+        let value = db.get_global_setting(key).ok().flatten();
+        if value.is_some() {
+            let _ = db.delete_global_setting(key);
+        }
+        value
+    };
+
+    if let Some(v) = legacy("watchdog_enabled") {
+        settings.watchdog_enabled = crate::is_truthy(&v);
+    }
+    if let Some(secs) = legacy("watchdog_interval_secs").and_then(|v| v.trim().parse().ok()) {
+        settings.watchdog_interval_secs = secs;
+    }
+    if let Some(secs) = legacy("agent_shutdown_grace_secs").and_then(|v| v.trim().parse().ok()) {
+        settings.agent_shutdown_grace_secs = secs;
+    }
+    if let Some(path) = legacy("python_path_override") {
+        settings.python_path_override = Some(path);
+    }
+    if let Some(path) = legacy("agent_dir_override") {
+        settings.agent_dir_override = Some(path);
+    }
+
+    settings.normalize();
+    if settings.validate().is_ok() {
+        if let Err(e) = save(db, &before, &settings) {
+            tracing::error!(error = %e, "failed to migrate settings");
+        }
+    }
+}
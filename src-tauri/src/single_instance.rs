@@ -0,0 +1,178 @@
+//! One running app per user. The first instance writes a lock file naming its
+//! pid and a loopback port it listens on; a later launch finds it, hands over
+//! its command line and exits, so two copies never fight over the agent port
+//! and the database. A lock whose pid is gone (after a crash) is taken over.
+
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+const LOCK_FILE: &str = "instance.lock";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Covers a first instance that is still starting up and not yet serving
+const REPLY_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Serialize, Deserialize)]
+struct Lock {
+    pid: u32,
+    port: u16,
+    /// Only launches that can read the lock file may forward to us
+    token: String,
+}
+
+/// A later launch's command line, as received by the running instance
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Launch {
+    /// Arguments after the executable, e.g. a project id to open
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+    token: String,
+    launch: Launch,
+}
+
+pub struct Instance {
+    path: PathBuf,
+    listener: TcpListener,
+    token: String,
+}
+
+pub enum Claim {
+    /// We're the only instance; keep this alive and call `serve`
+    Primary(Instance),
+    /// Another instance was running and has our command line
+    Forwarded,
+}
+
+/// Become the running instance, or forward this launch to the one that already is
+pub fn claim(dir: &Path, token: String) -> Result<Claim, String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let path = dir.join(LOCK_FILE);
+    let listener = TcpListener::bind(("127.0.0.1", 0)).map_err(|e| e.to_string())?;
+    let lock = Lock {
+        pid: std::process::id(),
+        port: listener.local_addr().map_err(|e| e.to_string())?.port(),
+        token,
+    };
+
+    // The second attempt follows removing a stale lock
+    for _ in 0..2 {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let json = serde_json::to_vec(&lock).map_err(|e| e.to_string())?;
+                file.write_all(&json).map_err(|e| e.to_string())?;
+                #[cfg(not(target_os = "windows"))]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let mode = std::fs::Permissions::from_mode(0o600);
+                    let _ = std::fs::set_permissions(&path, mode);
+                }
+                let Lock { token, .. } = lock;
+                return Ok(Claim::Primary(Instance { path, listener, token }));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(format!("Cannot create {}: {}", path.display(), e)),
+        }
+
+        let existing = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<Lock>(&s).ok());
+        if let Some(existing) = existing.filter(|l| l.pid != lock.pid && is_this_app(l.pid)) {
+            forward(&existing).map_err(|e| {
+                format!("Another instance (pid {}) is not responding: {}", existing.pid, e)
+            })?;
+            info!(pid = existing.pid, "forwarded launch to the running instance");
+            return Ok(Claim::Forwarded);
+        }
+        info!(path = %path.display(), "removing stale instance lock");
+        let _ = std::fs::remove_file(&path);
+    }
+    Err(format!("Could not take the instance lock at {}", path.display()))
+}
+
+impl Instance {
+    /// Hand every forwarded launch to `on_launch`, on a background thread
+    pub fn serve(&self, on_launch: impl Fn(Launch) + Send + 'static) -> Result<(), String> {
+        let listener = self.listener.try_clone().map_err(|e| e.to_string())?;
+        let token = self.token.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let _ = stream.set_read_timeout(Some(CONNECT_TIMEOUT));
+                let mut line = String::new();
+                if BufReader::new(&stream).read_line(&mut line).is_err() {
+                    continue;
+                }
+                match serde_json::from_str::<Message>(&line) {
+                    Ok(message) if message.token == token => {
+                        let _ = (&stream).write_all(b"ok\n");
+                        on_launch(message.launch);
+                    }
+                    _ => warn!("ignored an unauthenticated instance message"),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Remove the lock on a clean exit; after a crash the next launch finds it stale
+    pub fn release(&self) {
+        let ours = std::fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<Lock>(&s).ok())
+            .is_some_and(|lock| lock.pid == std::process::id());
+        if ours {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn forward(lock: &Lock) -> Result<(), String> {
+    let addr = ([127, 0, 0, 1], lock.port).into();
+    let mut stream =
+        TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT)).map_err(|e| e.to_string())?;
+    let message = Message {
+        token: lock.token.clone(),
+        launch: Launch {
+            args: std::env::args().skip(1).collect(),
+            cwd: std::env::current_dir().ok().map(|d| d.display().to_string()),
+        },
+    };
+    let mut json = serde_json::to_vec(&message).map_err(|e| e.to_string())?;
+    json.push(b'\n');
+    stream.write_all(&json).map_err(|e| e.to_string())?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).map_err(|e| e.to_string())?;
+    if reply.trim() == "ok" {
+        Ok(())
+    } else {
+        Err("no acknowledgement".into())
+    }
+}
+
+/// `pid` is alive and runs this executable, so it isn't a reused pid
+fn is_this_app(pid: u32) -> bool {
+    let pid = sysinfo::Pid::from_u32(pid);
+    let mut system = sysinfo::System::new();
+    if !system.refresh_process(pid) {
+        return false;
+    }
+    let Some(exe_name) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_name().map(|n| n.to_string_lossy().into_owned()))
+    else {
+        return true;
+    };
+    // Linux truncates process names to 15 bytes
+    system
+        .process(pid)
+        .is_some_and(|p| !p.name().is_empty() && exe_name.starts_with(p.name()))
+}
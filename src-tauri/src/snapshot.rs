@@ -0,0 +1,393 @@
+//! Whole-project snapshots: one zip with the project's JSON export, its cover
+//! and its own files under `data_dir/projects/<id>`, to keep somewhere like
+//! Dropbox. Restoring always creates a new project. File contents are streamed
+//! in and out of the zip, so attachments of any size never sit in memory.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::backup::{self, ProjectBackup};
+use crate::covers;
+
+/// Per-project files such as attachments live in `data_dir/projects/<id>`
+pub const PROJECT_FILES_DIR: &str = "projects";
+const FORMAT: &str = "sanhuoai-project-snapshot";
+/// Bump when the zip layout changes; older snapshots must stay restorable
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const PROJECT_JSON: &str = "project.json";
+const COVER_NAMES: &[&str] = &["cover.png", "cover.jpg", "cover.webp"];
+const FILES_PREFIX: &str = "files/";
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    format_version: u32,
+    app_version: String,
+    /// backup::SCHEMA_VERSION of project.json
+    schema_version: u32,
+    created_at: String,
+    project_name: String,
+    /// Every other entry in the zip
+    files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    name: String,
+    size_bytes: u64,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Snapshot,
+    Restore,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Progress {
+    /// The entry being copied, as named in the zip
+    pub file: String,
+    pub done_bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotInfo {
+    pub path: String,
+    pub size_bytes: u64,
+    /// Cover and project files, not counting project.json
+    pub files: usize,
+}
+
+struct Source {
+    name: String,
+    path: PathBuf,
+    size: u64,
+}
+
+/// Write a snapshot of `backup`'s project to `dest`. `cover` is the cover path
+/// stored on the project row, relative to `data_dir`.
+pub fn write(
+    dest: &Path,
+    app_version: &str,
+    data_dir: &Path,
+    backup: &ProjectBackup,
+    cover: Option<&str>,
+    progress: impl FnMut(&Progress),
+) -> Result<SnapshotInfo, String> {
+    let mut sources = Vec::new();
+    if let Some(cover) = cover {
+        let path = data_dir.join(cover);
+        let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+        let name = format!("cover.{}", ext.unwrap_or_default());
+        // A cover whose file has gone missing is left out rather than failing
+        if let (true, Ok(meta)) = (COVER_NAMES.contains(&name.as_str()), fs::metadata(&path)) {
+            sources.push(Source { name, path, size: meta.len() });
+        }
+    }
+    let files_dir = data_dir.join(PROJECT_FILES_DIR).join(&backup.project.id);
+    list_files(&files_dir, FILES_PREFIX, &mut sources)
+        .map_err(|e| format!("Failed to read {}: {}", files_dir.display(), e))?;
+    sources.sort_by(|a, b| a.name.cmp(&b.name));
+    let project_json = serde_json::to_vec_pretty(backup).map_err(|e| e.to_string())?;
+
+    let tmp = dest.with_extension("zip.tmp");
+    let result = (|| -> zip::result::ZipResult<()> {
+        let mut zip = zip::ZipWriter::new(File::create(&tmp)?);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file(PROJECT_JSON, options)?;
+        zip.write_all(&project_json)?;
+
+        let mut tracker = Tracker::new(sources.iter().map(|s| s.size).sum(), progress);
+        let mut entries = Vec::new();
+        for source in &sources {
+            let large = source.size >= u32::MAX as u64;
+            zip.start_file(source.name.as_str(), options.large_file(large))?;
+            let size = tracker.copy(&source.name, &mut File::open(&source.path)?, &mut zip)?;
+            entries.push(ManifestEntry { name: source.name.clone(), size_bytes: size });
+        }
+        tracker.finish();
+
+        // Last, so the sizes are what was actually written
+        let manifest = Manifest {
+            format: FORMAT.into(),
+            format_version: FORMAT_VERSION,
+            app_version: app_version.into(),
+            schema_version: backup.schema_version,
+            created_at: crate::app_log::timestamp(),
+            project_name: backup.project.name.clone(),
+            files: std::iter::once(ManifestEntry {
+                name: PROJECT_JSON.into(),
+                size_bytes: project_json.len() as u64,
+            })
+            .chain(entries)
+            .collect(),
+        };
+        zip.start_file(MANIFEST, options)?;
+        serde_json::to_writer_pretty(&mut zip, &manifest).map_err(io::Error::from)?;
+        zip.finish()?.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = result.map_err(|e| e.to_string()).and_then(|_| {
+        fs::rename(&tmp, dest).map_err(|e| e.to_string())
+    }) {
+        let _ = fs::remove_file(&tmp);
+        return Err(format!("Failed to write {}: {}", dest.display(), e));
+    }
+    Ok(SnapshotInfo {
+        path: dest.display().to_string(),
+        size_bytes: fs::metadata(dest).map(|m| m.len()).unwrap_or(0),
+        files: sources.len(),
+    })
+}
+
+/// Regular files under `dir`, named `prefix` + their path below it; symlinks are
+/// skipped and a missing `dir` simply has none
+fn list_files(dir: &Path, prefix: &str, out: &mut Vec<Source>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            list_files(&entry.path(), &format!("{}/", name), out)?;
+        } else if file_type.is_file() {
+            out.push(Source { name, path: entry.path(), size: entry.metadata()?.len() });
+        }
+    }
+    Ok(())
+}
+
+/// A snapshot whose manifest and project.json have been checked; nothing has
+/// been written anywhere yet
+pub struct Snapshot {
+    archive: zip::ZipArchive<File>,
+    manifest: Manifest,
+    pub backup: ProjectBackup,
+}
+
+pub fn open(path: &Path) -> Result<Snapshot, String> {
+    let invalid = |e: &dyn std::fmt::Display| format!("Not a valid project snapshot: {}", e);
+    let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| invalid(&e))?;
+    let manifest: Manifest = archive
+        .by_name(MANIFEST)
+        .map_err(|e| invalid(&format!("{}: {}", MANIFEST, e)))
+        .and_then(|entry| serde_json::from_reader(entry).map_err(|e| invalid(&e)))?;
+    if manifest.format != FORMAT {
+        return Err(invalid(&format!("unknown format \"{}\"", manifest.format)));
+    }
+    if manifest.format_version > FORMAT_VERSION || manifest.schema_version > backup::SCHEMA_VERSION
+    {
+        return Err(format!(
+            "Snapshot was made by a newer version of the app ({}). Please update before restoring.",
+            manifest.app_version
+        ));
+    }
+    if !manifest.files.iter().any(|entry| entry.name == PROJECT_JSON) {
+        return Err(invalid(&format!("{} is missing", PROJECT_JSON)));
+    }
+    for entry in &manifest.files {
+        if !is_valid_name(&entry.name) {
+            return Err(invalid(&format!("unexpected entry \"{}\"", entry.name)));
+        }
+        let size = archive.by_name(&entry.name).map_err(|e| invalid(&e))?.size();
+        if size != entry.size_bytes {
+            let expected = entry.size_bytes;
+            return Err(invalid(&format!("{} is {} bytes, not {}", entry.name, size, expected)));
+        }
+    }
+    let mut text = String::new();
+    archive
+        .by_name(PROJECT_JSON)
+        .map_err(|e| invalid(&e))?
+        .read_to_string(&mut text)
+        .map_err(|e| invalid(&e))?;
+    let backup = backup::from_json(&text)?;
+    Ok(Snapshot { archive, manifest, backup })
+}
+
+/// Only the names `write` produces, and none that could point outside the
+/// directory they're unpacked into
+fn is_valid_name(name: &str) -> bool {
+    if name == PROJECT_JSON || COVER_NAMES.contains(&name) {
+        return true;
+    }
+    name.strip_prefix(FILES_PREFIX).is_some_and(|path| {
+        path.split('/').all(|part| {
+            !part.is_empty() && part != "." && part != ".." && !part.contains(['\\', ':'])
+        })
+    })
+}
+
+impl Snapshot {
+    /// Unpack the cover and files into a staging directory under `data_dir`,
+    /// ready to be placed once the restored project has an id
+    pub fn extract(
+        &mut self,
+        data_dir: &Path,
+        progress: impl FnMut(&Progress),
+    ) -> Result<Staged, String> {
+        let nanos =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let dir = data_dir.join(format!(".snapshot-restore-{}-{}", std::process::id(), nanos));
+        // Removes the staging directory again if anything below fails
+        let mut staged = Staged { dir, cover: None };
+        let entries: Vec<&ManifestEntry> =
+            self.manifest.files.iter().filter(|entry| entry.name != PROJECT_JSON).collect();
+        let mut tracker = Tracker::new(entries.iter().map(|e| e.size_bytes).sum(), progress);
+        for entry in entries {
+            let target = entry.name.split('/').fold(staged.dir.clone(), |dir, part| dir.join(part));
+            let write_error =
+                |e: &dyn std::fmt::Display| format!("Failed to restore {}: {}", entry.name, e);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(|e| write_error(&e))?;
+            }
+            let mut input = self.archive.by_name(&entry.name).map_err(|e| write_error(&e))?;
+            let mut output = File::create(&target).map_err(|e| write_error(&e))?;
+            tracker.copy(&entry.name, &mut input, &mut output).map_err(|e| write_error(&e))?;
+            output.sync_all().map_err(|e| write_error(&e))?;
+            if COVER_NAMES.contains(&entry.name.as_str()) {
+                staged.cover = Some(target);
+            }
+        }
+        tracker.finish();
+        Ok(staged)
+    }
+}
+
+/// Restored files waiting for their project's id. The staging directory is
+/// deleted on drop, taking with it anything that was never placed.
+pub struct Staged {
+    dir: PathBuf,
+    cover: Option<PathBuf>,
+}
+
+impl Staged {
+    /// Move the files into place for `project_id` and install the cover, returning
+    /// the cover path to store on the project row
+    pub fn place(&self, data_dir: &Path, project_id: &str) -> Result<Option<String>, String> {
+        let files = self.dir.join(FILES_PREFIX.trim_end_matches('/'));
+        let target = data_dir.join(PROJECT_FILES_DIR).join(project_id);
+        if files.is_dir() {
+            fs::create_dir_all(data_dir.join(PROJECT_FILES_DIR))
+                .and_then(|_| fs::rename(&files, &target))
+                .map_err(|e| format!("Failed to restore files to {}: {}", target.display(), e))?;
+        }
+        let Some(cover) = &self.cover else { return Ok(None) };
+        covers::set(data_dir, project_id, cover).map(Some).map_err(|e| {
+            let _ = fs::remove_dir_all(&target);
+            format!("Failed to restore the cover: {}", e)
+        })
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Copies entries while keeping a running byte count, reported at most every
+/// PROGRESS_INTERVAL and once at the end
+struct Tracker<F: FnMut(&Progress)> {
+    progress: Progress,
+    last_report: Instant,
+    report: F,
+    buf: Vec<u8>,
+}
+
+impl<F: FnMut(&Progress)> Tracker<F> {
+    fn new(total_bytes: u64, report: F) -> Self {
+        Tracker {
+            progress: Progress { file: String::new(), done_bytes: 0, total_bytes },
+            last_report: Instant::now(),
+            report,
+            buf: vec![0u8; 1 << 20],
+        }
+    }
+
+    /// Returns the number of bytes copied
+    fn copy(
+        &mut self,
+        file: &str,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> io::Result<u64> {
+        self.progress.file = file.to_string();
+        let mut copied = 0u64;
+        loop {
+            let n = input.read(&mut self.buf)?;
+            if n == 0 {
+                return Ok(copied);
+            }
+            output.write_all(&self.buf[..n])?;
+            copied += n as u64;
+            self.progress.done_bytes += n as u64;
+            if self.last_report.elapsed() >= PROGRESS_INTERVAL {
+                (self.report)(&self.progress);
+                self.last_report = Instant::now();
+            }
+        }
+    }
+
+    fn finish(mut self) {
+        (self.report)(&self.progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+
+    #[test]
+    fn files_survive_a_round_trip_under_the_new_project() {
+        let root = std::env::temp_dir().join(format!("snapshot-test-{}", std::process::id()));
+        let data_dir = root.join("data");
+        let project = Database::new_in_memory().unwrap().create_project("长夜", "玄幻").unwrap();
+        let notes = data_dir.join(PROJECT_FILES_DIR).join(&project.id).join("notes");
+        fs::create_dir_all(&notes).unwrap();
+        fs::write(notes.join("地图.txt"), "北境").unwrap();
+        let dest = root.join("长夜.zip");
+
+        let backup = backup::new(project, Vec::new(), Vec::new(), Vec::new());
+        let mut reports = Vec::new();
+        let report = |p: &Progress| reports.push(p.done_bytes);
+        let info = write(&dest, "1.2.3", &data_dir, &backup, None, report).unwrap();
+        assert_eq!(info.files, 1);
+        assert_eq!(reports.last(), Some(&6));
+
+        let mut snapshot = open(&dest).unwrap();
+        assert_eq!(snapshot.backup.project.name, "长夜");
+        let staged = snapshot.extract(&data_dir, |_| {}).unwrap();
+        assert_eq!(staged.place(&data_dir, "new").unwrap(), None);
+        let staging = staged.dir.clone();
+        drop(staged);
+        assert!(!staging.exists());
+        let restored = data_dir.join(PROJECT_FILES_DIR).join("new").join("notes/地图.txt");
+        assert_eq!(fs::read_to_string(restored).unwrap(), "北境");
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn only_names_inside_the_snapshot_are_accepted() {
+        assert!(is_valid_name("files/notes/地图.txt"));
+        assert!(is_valid_name("cover.webp"));
+        assert!(!is_valid_name("files/../../sanhuoai.db"));
+        assert!(!is_valid_name("files//etc/passwd"));
+        assert!(!is_valid_name("files/C:\\Windows"));
+        assert!(!is_valid_name("sanhuoai.db"));
+    }
+}
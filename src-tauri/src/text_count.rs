@@ -0,0 +1,91 @@
+//! Counting text the way chapters' stored word_count does, so the editor's live
+//! count and the saved one never disagree. Chinese has no spaces, so every Han
+//! character is a word; elsewhere a word is a run of letters and digits.
+//! agent/text_count.py counts the same way for the chapters the agent saves.
+
+use serde::Serialize;
+
+use crate::tokenize::is_han;
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct TextCounts {
+    pub words: i64,
+    /// Every character but line breaks
+    pub chars: i64,
+    pub chars_no_spaces: i64,
+    /// Lines with more than whitespace on them
+    pub paragraphs: i64,
+}
+
+pub fn count(text: &str) -> TextCounts {
+    let mut counts = TextCounts { words: count_words(text), ..TextCounts::default() };
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let mut non_space = 0;
+        for c in line.chars() {
+            counts.chars += 1;
+            if !c.is_whitespace() {
+                non_space += 1;
+            }
+        }
+        counts.chars_no_spaces += non_space;
+        if non_space > 0 {
+            counts.paragraphs += 1;
+        }
+    }
+    counts
+}
+
+/// What a chapter stores as word_count for this text, and so what project totals
+/// and word targets add up
+pub fn count_words(text: &str) -> i64 {
+    let mut words = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if is_han(c) {
+            words += 1;
+            in_word = false;
+        } else if c.is_alphanumeric() || (in_word && c == '\'') {
+            if !in_word {
+                words += 1;
+            }
+            in_word = true;
+        } else {
+            in_word = false;
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn han_characters_are_words_and_punctuation_is_not() {
+        let counts = count("林风拔剑，don't move!\r\n\n  第2章 end");
+        // 林风拔剑 / don't move / 第 2 章 end
+        assert_eq!(counts.words, 4 + 2 + 4);
+        assert_eq!(counts.paragraphs, 2);
+        assert_eq!(counts.chars, 16 + 9);
+        assert_eq!(counts.chars_no_spaces, 15 + 6);
+        assert_eq!(count(""), TextCounts::default());
+    }
+
+    #[test]
+    fn mixed_chinese_and_english_prose() {
+        assert_eq!(count_words("他说：“OK，我们走。”"), 2 + 1 + 3);
+        assert_eq!(count_words("Hello世界 world"), 1 + 2 + 1);
+        assert_eq!(count_words("iPhone15发布了"), 1 + 3);
+        assert_eq!(count_words("rock'n'roll 与 'quoted'"), 1 + 1 + 1);
+        assert_eq!(count_words("Ａｌｉｃｅ和Bob"), 1 + 1 + 1);
+        assert_eq!(count_words("——……！？ \t\n"), 0);
+        assert_eq!(count_words("他走了……又回来了..."), 3 + 4);
+        assert_eq!(count_words("Wait... what?!"), 2);
+        // Full-width punctuation and the ideographic space separate like ASCII ones
+        assert_eq!(count_words("ＯＫ，ｇｏ！「走」　（Ｂ）"), 1 + 1 + 1 + 1);
+        assert_eq!(count_words("２０２６年、第三章；"), 1 + 1 + 3);
+        // Extension B ideographs, outside the BMP
+        assert_eq!(count_words("𠀀𠀁"), 2);
+    }
+}
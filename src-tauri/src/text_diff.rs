@@ -0,0 +1,119 @@
+//! Line-level diff between two versions of a chapter, by longest common
+//! subsequence. Chapters are stored one paragraph per line, so a line here is
+//! a paragraph in the editor.
+
+use serde::Serialize;
+
+/// Past this many table cells the changed middle is shown as wholly removed and
+/// re-added rather than spend hundreds of MB on the table
+const MAX_CELLS: usize = 16_000_000;
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    Same,
+    Added,
+    Removed,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DiffLine {
+    pub kind: DiffKind,
+    /// 1-based line in the old text; None for added lines
+    pub old_line: Option<usize>,
+    /// 1-based line in the new text; None for removed lines
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+/// Every line of both texts in order, removals before the additions replacing them
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.split('\n').collect();
+    let new: Vec<&str> = new.split('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    let mut push = |kind, i: Option<usize>, j: Option<usize>, text: &str| {
+        lines.push(DiffLine {
+            kind,
+            old_line: i.map(|i| i + 1),
+            new_line: j.map(|j| j + 1),
+            text: text.to_string(),
+        });
+    };
+    for (k, line) in old[..prefix].iter().enumerate() {
+        push(DiffKind::Same, Some(k), Some(k), line);
+    }
+
+    // lengths[i * width + j]: longest common subsequence of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let cells = (a.len() + 1).saturating_mul(width);
+    let mut lengths = vec![0u32; if cells <= MAX_CELLS { cells } else { 0 }];
+    if !lengths.is_empty() {
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lengths[i * width + j] = if a[i] == b[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+            }
+        }
+    }
+    let lcs = |i: usize, j: usize| lengths.get(i * width + j).copied().unwrap_or(0);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] && !lengths.is_empty() {
+            push(DiffKind::Same, Some(prefix + i), Some(prefix + j), a[i]);
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs(i + 1, j) >= lcs(i, j + 1)) {
+            push(DiffKind::Removed, Some(prefix + i), None, a[i]);
+            i += 1;
+        } else {
+            push(DiffKind::Added, None, Some(prefix + j), b[j]);
+            j += 1;
+        }
+    }
+
+    for k in 0..suffix {
+        let (i, j) = (old.len() - suffix + k, new.len() - suffix + k);
+        push(DiffKind::Same, Some(i), Some(j), old[i]);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_changed_paragraphs_with_both_line_numbers() {
+        let old = "夜色深沉。\n林风拔剑。\n他走了。\n天亮了。";
+        let new = "夜色深沉。\n萧然拔剑。\n他走了。\n远处传来钟声。\n天亮了。";
+        let diff: Vec<_> = diff_lines(old, new)
+            .into_iter()
+            .map(|line| (line.kind, line.old_line, line.new_line))
+            .collect();
+        assert_eq!(
+            diff,
+            [
+                (DiffKind::Same, Some(1), Some(1)),
+                (DiffKind::Removed, Some(2), None),
+                (DiffKind::Added, None, Some(2)),
+                (DiffKind::Same, Some(3), Some(3)),
+                (DiffKind::Added, None, Some(4)),
+                (DiffKind::Same, Some(4), Some(5)),
+            ]
+        );
+        assert!(diff_lines("same", "same").iter().all(|line| line.kind == DiffKind::Same));
+    }
+}
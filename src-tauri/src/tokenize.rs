@@ -0,0 +1,61 @@
+//! Splitting chapter text into tokens for the manuscript analyses (word
+//! frequency, character names). Han characters are tokens of their own since
+//! Chinese has no spaces; everything else is split into words.
+
+use crate::find_replace::ChapterText;
+
+#[derive(PartialEq, Debug)]
+pub enum Token {
+    Han(String),
+    /// As written, case included
+    Word(String),
+}
+
+impl Token {
+    pub fn text(&self) -> &str {
+        match self {
+            Token::Han(text) | Token::Word(text) => text,
+        }
+    }
+}
+
+pub fn is_han(c: char) -> bool {
+    matches!(c as u32,
+        0x3400..=0x4dbf | 0x4e00..=0x9fff | 0xf900..=0xfaff | 0x20000..=0x2fa1f)
+}
+
+/// Letters of the Latin alphabets, accented ones included
+pub fn is_latin(c: char) -> bool {
+    c.is_ascii_alphabetic() || matches!(c, '\u{c0}'..='\u{24f}' if c.is_alphabetic())
+}
+
+/// A chapter's text as runs of tokens between punctuation marks. Whitespace
+/// separates words but doesn't end a run, so English phrases survive.
+pub fn chapter_segments(chapter: &ChapterText) -> Vec<Vec<Token>> {
+    let mut segments = Vec::new();
+    for paragraph in &chapter.paragraphs {
+        let mut segment = Vec::new();
+        let mut word = String::new();
+        for c in paragraph.content.chars() {
+            if !is_han(c) && (c.is_alphanumeric() || c == '\'') {
+                word.push(c);
+                continue;
+            }
+            if !word.is_empty() {
+                segment.push(Token::Word(std::mem::take(&mut word)));
+            }
+            if is_han(c) {
+                segment.push(Token::Han(c.to_string()));
+            } else if !c.is_whitespace() && !segment.is_empty() {
+                segments.push(std::mem::take(&mut segment));
+            }
+        }
+        if !word.is_empty() {
+            segment.push(Token::Word(word));
+        }
+        if !segment.is_empty() {
+            segments.push(segment);
+        }
+    }
+    segments
+}
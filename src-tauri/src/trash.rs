@@ -0,0 +1,125 @@
+//! Projects moved to the trash (projects.trashed_at) stay whole and restorable
+//! until purged: by empty_trash, or once they have been there longer than the
+//! trash_retention_days setting. Purging deletes the row, which cascades to
+//! everything the project owns, then its cover, attachments and files.
+
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::attachments::ATTACHMENTS_DIR;
+use crate::covers::COVERS_DIR;
+use crate::data_location::dir_size;
+use crate::snapshot::PROJECT_FILES_DIR;
+
+/// How often the purge runs after the one at startup
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Wait before trying again while a backup, export, restore or data move runs
+pub const BUSY_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Debug)]
+pub struct TrashedProject {
+    pub id: String,
+    pub name: String,
+    /// SQLite datetime, UTC
+    pub trashed_at: String,
+    /// Rows the project owns in every table, itself included
+    pub rows: i64,
+    /// Cover, attachments and project files on disk
+    pub file_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TrashInfo {
+    pub projects: Vec<TrashedProject>,
+    pub rows: i64,
+    pub file_bytes: u64,
+    /// Days a project stays in the trash before it is purged; 0 keeps it until
+    /// the trash is emptied
+    pub retention_days: u32,
+}
+
+impl TrashInfo {
+    pub fn new(projects: Vec<TrashedProject>, retention_days: u32) -> Self {
+        TrashInfo {
+            rows: projects.iter().map(|p| p.rows).sum(),
+            file_bytes: projects.iter().map(|p| p.file_bytes).sum(),
+            projects,
+            retention_days,
+        }
+    }
+}
+
+/// Which projects a purge removed
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Purged {
+    pub project_ids: Vec<String>,
+    pub names: Vec<String>,
+}
+
+/// Bytes the project's own files take up under `data_dir`
+pub fn file_bytes(data_dir: &Path, project_id: &str) -> u64 {
+    let covers = data_dir.join(COVERS_DIR);
+    let cover_bytes: u64 = std::fs::read_dir(&covers)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| is_cover_of(&entry.file_name().to_string_lossy(), project_id))
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|m| m.len())
+        .sum();
+    cover_bytes
+        + dir_size(&data_dir.join(ATTACHMENTS_DIR).join(project_id))
+        + dir_size(&data_dir.join(PROJECT_FILES_DIR).join(project_id))
+}
+
+/// Delete the files of a purged project; what can't be removed is reported and
+/// the rest still goes
+pub fn remove_files(data_dir: &Path, project_id: &str) -> Result<(), String> {
+    // The id names directories about to be deleted
+    crate::covers::check_id(project_id)?;
+    let mut errors = Vec::new();
+    if let Err(e) = crate::covers::remove(data_dir, project_id) {
+        errors.push(e);
+    }
+    for dir in [ATTACHMENTS_DIR, PROJECT_FILES_DIR] {
+        let path = data_dir.join(dir).join(project_id);
+        match std::fs::remove_dir_all(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => errors.push(format!("Cannot delete {}: {}", path.display(), e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+/// `<id>.png`, `<id>.thumb.png` and so on
+fn is_cover_of(file_name: &str, project_id: &str) -> bool {
+    file_name.strip_prefix(project_id).is_some_and(|rest| rest.starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_counted_and_removed_per_project() {
+        let data_dir = std::env::temp_dir().join(format!("trash-test-{}", std::process::id()));
+        let attachments = data_dir.join(ATTACHMENTS_DIR).join("p1");
+        std::fs::create_dir_all(&attachments).unwrap();
+        std::fs::create_dir_all(data_dir.join(COVERS_DIR)).unwrap();
+        std::fs::write(attachments.join("a_map.png"), [0; 100]).unwrap();
+        std::fs::write(data_dir.join(COVERS_DIR).join("p1.thumb.png"), [0; 10]).unwrap();
+        std::fs::write(data_dir.join(COVERS_DIR).join("p10.png"), [0; 1000]).unwrap();
+
+        assert_eq!(file_bytes(&data_dir, "p1"), 110);
+        remove_files(&data_dir, "p1").unwrap();
+        assert_eq!(file_bytes(&data_dir, "p1"), 0);
+        assert_eq!(file_bytes(&data_dir, "p10"), 1000);
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}
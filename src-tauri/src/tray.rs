@@ -0,0 +1,176 @@
+//! Tray icon showing the agent's state (a coloured dot over the app icon) with
+//! quick start/stop/restart actions. It follows the lifecycle events rather than
+//! polling, so it changes exactly when the frontend hears about a transition.
+
+use tauri::image::Image;
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Listener, Manager, Wry};
+use tracing::warn;
+
+use crate::events;
+
+const TRAY_ID: &str = "main";
+pub const MAIN_WINDOW: &str = "main";
+
+#[derive(Clone, Copy)]
+pub enum Indicator {
+    Stopped,
+    Starting,
+    Ready,
+    Crashed,
+}
+
+impl Indicator {
+    fn label(self) -> &'static str {
+        match self {
+            Indicator::Stopped => "AI agent: stopped",
+            Indicator::Starting => "AI agent: starting…",
+            Indicator::Ready => "AI agent: running",
+            Indicator::Crashed => "AI agent: crashed",
+        }
+    }
+
+    fn color(self) -> [u8; 3] {
+        match self {
+            Indicator::Stopped => [0x9e, 0x9e, 0x9e],
+            Indicator::Starting => [0xf5, 0xa6, 0x23],
+            Indicator::Ready => [0x2e, 0xb8, 0x5c],
+            Indicator::Crashed => [0xe5, 0x3e, 0x3e],
+        }
+    }
+}
+
+/// The disabled first menu entry, retitled along with the icon
+struct StatusItem(MenuItem<Wry>);
+
+pub fn init(app: &AppHandle) -> tauri::Result<()> {
+    let status = MenuItem::with_id(app, "status", Indicator::Stopped.label(), false, None::<&str>)?;
+    let menu = Menu::with_items(
+        app,
+        &[
+            &status,
+            &PredefinedMenuItem::separator(app)?,
+            &MenuItem::with_id(app, "agent_start", "Start agent", true, None::<&str>)?,
+            &MenuItem::with_id(app, "agent_stop", "Stop agent", true, None::<&str>)?,
+            &MenuItem::with_id(app, "agent_restart", "Restart agent", true, None::<&str>)?,
+            &PredefinedMenuItem::separator(app)?,
+            &MenuItem::with_id(app, "show", "Open main window", true, None::<&str>)?,
+            &MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?,
+        ],
+    )?;
+    app.manage(StatusItem(status));
+
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip(tooltip(app, Indicator::Stopped))
+        .menu(&menu)
+        .show_menu_on_left_click(false)
+        .on_menu_event(on_menu_event)
+        .on_tray_icon_event(|tray, event| {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                show_main_window(tray.app_handle());
+            }
+        });
+    if let Some(icon) = icon(app, Indicator::Stopped) {
+        builder = builder.icon(icon);
+    }
+    builder.build(app)?;
+
+    for (event, indicator) in [
+        (events::AGENT_SPAWNED, Indicator::Starting),
+        (events::AGENT_RESTARTING, Indicator::Starting),
+        (events::AGENT_READY, Indicator::Ready),
+        (events::AGENT_CRASHED, Indicator::Crashed),
+        (events::AGENT_STOPPED, Indicator::Stopped),
+    ] {
+        let handle = app.clone();
+        // The indicator follows the shared agent only
+        app.listen_any(event, move |event| {
+            if !events::from_project_agent(event.payload()) {
+                set_indicator(&handle, indicator);
+            }
+        });
+    }
+    Ok(())
+}
+
+pub fn set_indicator(app: &AppHandle, indicator: Indicator) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    if let Some(icon) = icon(app, indicator) {
+        let _ = tray.set_icon(Some(icon));
+    }
+    let _ = tray.set_tooltip(Some(tooltip(app, indicator)));
+    if let Some(status) = app.try_state::<StatusItem>() {
+        let _ = status.0.set_text(indicator.label());
+    }
+}
+
+pub fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW) {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id().as_ref() {
+        action @ ("agent_start" | "agent_stop" | "agent_restart") => {
+            let (app, action) = (app.clone(), action.to_string());
+            tauri::async_runtime::spawn(async move {
+                let result = match action.as_str() {
+                    "agent_start" => crate::start_agent(app, None).await,
+                    "agent_stop" => crate::stop_agent(app, None).await,
+                    _ => crate::restart_agent(app, None).await,
+                };
+                if let Err(e) = result {
+                    warn!(action = %action, error = %e, "tray action failed");
+                }
+            });
+        }
+        "show" => show_main_window(app),
+        "quit" => {
+            crate::stop_agent_for_exit(app);
+            app.exit(0);
+        }
+        _ => {}
+    }
+}
+
+fn tooltip(app: &AppHandle, indicator: Indicator) -> String {
+    format!("{} — {}", app.package_info().name, indicator.label())
+}
+
+/// The window icon with a status dot in its bottom-right corner
+fn icon(app: &AppHandle, indicator: Indicator) -> Option<Image<'static>> {
+    let base = app.default_window_icon()?;
+    let (width, height) = (base.width(), base.height());
+    let mut rgba = base.rgba().to_vec();
+    let radius = (width.min(height) as f32) * 0.22;
+    let (cx, cy) = (width as f32 - radius - 1.0, height as f32 - radius - 1.0);
+    let [r, g, b] = indicator.color();
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            let distance = (dx * dx + dy * dy).sqrt();
+            // A white ring keeps the dot visible on icons of the same colour
+            let pixel = if distance <= radius - 1.5 {
+                [r, g, b, 0xff]
+            } else if distance <= radius {
+                [0xff, 0xff, 0xff, 0xff]
+            } else {
+                continue;
+            };
+            let i = ((y * width + x) * 4) as usize;
+            rgba[i..i + 4].copy_from_slice(&pixel);
+        }
+    }
+    Some(Image::new_owned(rgba, width, height))
+}
@@ -0,0 +1,371 @@
+//! Overused words and repeated phrases across a project. Chinese has no spaces,
+//! so Han text is counted as character bigrams; other text as words. A phrase
+//! is a run of tokens (see tokenize) that never crosses punctuation or a
+//! paragraph break.
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use crate::find_replace::ChapterText;
+use crate::tokenize::{self, Token};
+
+const MAX_TOP_N: usize = 1000;
+const PHRASE_TOKENS: std::ops::RangeInclusive<usize> = 3..=6;
+/// Chapters listed per term, most occurrences first
+const CHAPTERS_PER_TERM: usize = 3;
+const CACHED_REPORTS: usize = 8;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "been", "but", "by", "did", "do", "for", "from",
+    "had", "has", "have", "he", "her", "him", "his", "i", "if", "in", "into", "is", "it", "its",
+    "me", "my", "no", "not", "of", "on", "or", "our", "she", "so", "than", "that", "the",
+    "their", "them", "then", "there", "they", "this", "to", "up", "was", "we", "were", "what",
+    "when", "which", "who", "will", "with", "would", "you", "your",
+];
+/// Function-word bigrams that top every Chinese text and say nothing about style
+const STOP_BIGRAMS: &[&str] = &[
+    "一个", "一些", "一下", "不是", "也是", "什么", "他们", "你们", "只是", "可以", "因为",
+    "如果", "她们", "它们", "就是", "已经", "我们", "所以", "那个", "那些", "那么", "那样",
+    "这个", "这些", "这么", "这样", "还是", "还有", "都是", "然后", "但是", "自己", "没有",
+];
+/// Particles and conjunctions; a bigram holding one is noise
+const STOP_CHARS: &str = "的了着是在和与及或也都就而又把被给从向对之";
+
+#[derive(Deserialize, Clone, Copy, Hash)]
+#[serde(default)]
+pub struct FrequencyOptions {
+    /// How many terms and phrases to return
+    pub top_n: usize,
+    /// A phrase must appear at least this often to be reported
+    pub min_phrase_count: u32,
+}
+
+impl Default for FrequencyOptions {
+    fn default() -> Self {
+        FrequencyOptions { top_n: 100, min_phrase_count: 5 }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct ChapterCount {
+    pub chapter_id: String,
+    pub chapter_num: i64,
+    pub count: u32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct TermCount {
+    pub term: String,
+    pub count: u32,
+    /// Where it concentrates: the chapters using it most
+    pub chapters: Vec<ChapterCount>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FrequencyReport {
+    /// Changes whenever any chapter's text (or the options) does
+    pub content_hash: String,
+    /// Every counted word and bigram, stopwords excluded
+    pub total_terms: u64,
+    pub terms: Vec<TermCount>,
+    /// Longest form only: a phrase isn't listed when a longer one containing it
+    /// occurs just as often
+    pub phrases: Vec<TermCount>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Counting terms and candidate phrases
+    Counting,
+    /// Recounting the phrases that passed the threshold
+    Phrases,
+}
+
+/// The last few reports, so reopening the analysis on unchanged text is instant
+#[derive(Default)]
+pub struct ReportCache(Mutex<VecDeque<(u64, FrequencyReport)>>);
+
+impl ReportCache {
+    pub fn get(&self, hash: u64) -> Option<FrequencyReport> {
+        let cache = self.0.lock().unwrap();
+        cache.iter().find(|(key, _)| *key == hash).map(|(_, report)| report.clone())
+    }
+
+    pub fn insert(&self, hash: u64, report: FrequencyReport) {
+        let mut cache = self.0.lock().unwrap();
+        cache.retain(|(key, _)| *key != hash);
+        if cache.len() == CACHED_REPORTS {
+            cache.pop_front();
+        }
+        cache.push_back((hash, report));
+    }
+}
+
+pub fn content_hash(chapters: &[ChapterText], options: &FrequencyOptions) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    options.hash(&mut hasher);
+    for chapter in chapters {
+        chapter.chapter_id.hash(&mut hasher);
+        for paragraph in &chapter.paragraphs {
+            paragraph.content.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Two passes over `chapters`, calling `progress(stage, done, total)` after each chapter
+pub fn analyze(
+    chapters: &[ChapterText],
+    options: &FrequencyOptions,
+    mut progress: impl FnMut(Stage, usize, usize),
+) -> FrequencyReport {
+    let top_n = options.top_n.clamp(1, MAX_TOP_N);
+    let min_phrase_count = options.min_phrase_count.max(2);
+
+    // Pass 1: terms, and phrases by hash only, since most never repeat
+    let mut terms = Tally::default();
+    let mut phrase_hashes: HashMap<u64, u32> = HashMap::new();
+    let mut total_terms = 0;
+    for (index, chapter) in chapters.iter().enumerate() {
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for segment in segments(chapter) {
+            for term in segment_terms(&segment) {
+                *counts.entry(term).or_default() += 1;
+                total_terms += 1;
+            }
+            for_each_phrase(&segment, |phrase| {
+                *phrase_hashes.entry(phrase_hash(phrase)).or_default() += 1;
+            });
+        }
+        terms.add(index, counts);
+        progress(Stage::Counting, index + 1, chapters.len());
+    }
+
+    // Pass 2: spell out and recount the phrases that made the threshold
+    phrase_hashes.retain(|_, count| *count >= min_phrase_count);
+    let mut phrases = Tally::default();
+    for (index, chapter) in chapters.iter().enumerate() {
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        if !phrase_hashes.is_empty() {
+            for segment in segments(chapter) {
+                for_each_phrase(&segment, |phrase| {
+                    if phrase_hashes.contains_key(&phrase_hash(phrase)) {
+                        *counts.entry(phrase.join(PHRASE_SEPARATOR)).or_default() += 1;
+                    }
+                });
+            }
+        }
+        phrases.add(index, counts);
+        progress(Stage::Phrases, index + 1, chapters.len());
+    }
+    phrases.totals.retain(|_, count| *count >= min_phrase_count);
+    drop_contained_phrases(&mut phrases.totals);
+
+    FrequencyReport {
+        content_hash: format!("{:016x}", content_hash(chapters, options)),
+        total_terms,
+        terms: terms.top(top_n, chapters, |term| term.to_string()),
+        phrases: phrases.top(top_n, chapters, display_phrase),
+    }
+}
+
+/// Joins a phrase's tokens in its key, so prefixes and suffixes can be cut off
+const PHRASE_SEPARATOR: &str = "\u{1f}";
+
+#[derive(Default)]
+struct Tally {
+    totals: HashMap<String, u32>,
+    /// (chapter index, count) per key, in chapter order
+    by_chapter: HashMap<String, Vec<(usize, u32)>>,
+}
+
+impl Tally {
+    fn add(&mut self, chapter: usize, counts: HashMap<String, u32>) {
+        for (key, count) in counts {
+            *self.totals.entry(key.clone()).or_default() += count;
+            self.by_chapter.entry(key).or_default().push((chapter, count));
+        }
+    }
+
+    fn top(
+        &self,
+        n: usize,
+        chapters: &[ChapterText],
+        display: impl Fn(&str) -> String,
+    ) -> Vec<TermCount> {
+        let mut ranked: Vec<_> = self.totals.iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        ranked
+            .into_iter()
+            .take(n)
+            .map(|(key, &count)| {
+                let mut spread = self.by_chapter[key].clone();
+                spread.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+                TermCount {
+                    term: display(key),
+                    count,
+                    chapters: spread
+                        .into_iter()
+                        .take(CHAPTERS_PER_TERM)
+                        .map(|(index, count)| ChapterCount {
+                            chapter_id: chapters[index].chapter_id.clone(),
+                            chapter_num: chapters[index].chapter_num,
+                            count,
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn is_stop(token: &Token) -> bool {
+    match token {
+        Token::Han(text) => STOP_CHARS.contains(text.as_str()),
+        Token::Word(word) => STOP_WORDS.contains(&word.as_str()),
+    }
+}
+
+/// The chapter's token runs, words lowercased so "The" and "the" count together
+fn segments(chapter: &ChapterText) -> Vec<Vec<Token>> {
+    let mut segments = tokenize::chapter_segments(chapter);
+    for token in segments.iter_mut().flatten() {
+        if let Token::Word(word) = token {
+            *word = word.to_lowercase();
+        }
+    }
+    segments
+}
+
+/// Words, and bigrams of adjacent Han characters, minus stopwords
+fn segment_terms(segment: &[Token]) -> Vec<String> {
+    let mut terms = Vec::new();
+    for (i, token) in segment.iter().enumerate() {
+        match token {
+            Token::Word(word) => {
+                let counted = word.chars().count() >= 2
+                    && !word.chars().all(|c| c.is_numeric())
+                    && !is_stop(token);
+                if counted {
+                    terms.push(word.trim_matches('\'').to_string());
+                }
+            }
+            Token::Han(first) => {
+                let Some(Token::Han(second)) = segment.get(i + 1) else { continue };
+                let bigram = format!("{}{}", first, second);
+                let stop = STOP_BIGRAMS.contains(&bigram.as_str())
+                    || bigram.chars().any(|c| STOP_CHARS.contains(c));
+                if !stop {
+                    terms.push(bigram);
+                }
+            }
+        }
+    }
+    terms
+}
+
+/// Every run of PHRASE_TOKENS tokens that neither starts nor ends on a stopword
+fn for_each_phrase(segment: &[Token], mut f: impl FnMut(&[&str])) {
+    let texts: Vec<&str> = segment.iter().map(Token::text).collect();
+    for len in PHRASE_TOKENS {
+        for (start, window) in segment.windows(len).enumerate() {
+            if !is_stop(&window[0]) && !is_stop(&window[len - 1]) {
+                f(&texts[start..start + len]);
+            }
+        }
+    }
+}
+
+fn phrase_hash(tokens: &[&str]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tokens.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Drop a phrase when one a token longer that starts or ends with it is just as
+/// common: "他深吸一口" adds nothing next to "他深吸一口气"
+fn drop_contained_phrases(phrases: &mut HashMap<String, u32>) {
+    let mut contained = HashSet::new();
+    for (phrase, count) in phrases.iter() {
+        let prefix = phrase.rsplit_once(PHRASE_SEPARATOR).map(|(head, _)| head);
+        let suffix = phrase.split_once(PHRASE_SEPARATOR).map(|(_, tail)| tail);
+        for part in [prefix, suffix].into_iter().flatten() {
+            if phrases.get(part) == Some(count) {
+                contained.insert(part.to_string());
+            }
+        }
+    }
+    phrases.retain(|phrase, _| !contained.contains(phrase));
+}
+
+/// Han characters run together; a space goes between two words
+fn display_phrase(key: &str) -> String {
+    let mut text = String::new();
+    let mut after_word = false;
+    for token in key.split(PHRASE_SEPARATOR) {
+        let is_word = !token.chars().any(tokenize::is_han);
+        if is_word && after_word {
+            text.push(' ');
+        }
+        text.push_str(token);
+        after_word = is_word;
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_replace::Paragraph;
+
+    fn chapter(id: &str, num: i64, paragraphs: &[&str]) -> ChapterText {
+        ChapterText {
+            chapter_id: id.into(),
+            chapter_num: num,
+            title: String::new(),
+            paragraphs: paragraphs
+                .iter()
+                .enumerate()
+                .map(|(i, p)| Paragraph {
+                    id: format!("{}-{}", id, i),
+                    para_index: i as i64,
+                    content: p.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn counts_bigrams_words_and_the_longest_repeated_phrase() {
+        let chapters = [
+            chapter("c1", 1, &["他深吸一口气。他深吸一口气，我们走。", "The old man smiled."]),
+            chapter("c2", 2, &["他深吸一口气！The old man smiled; the old man smiled."]),
+        ];
+        let options = FrequencyOptions { top_n: 50, min_phrase_count: 3 };
+        let mut stages = Vec::new();
+        let report = analyze(&chapters, &options, |stage, done, total| {
+            stages.push((matches!(stage, Stage::Counting), done, total))
+        });
+        assert_eq!(stages, [(true, 1, 2), (true, 2, 2), (false, 1, 2), (false, 2, 2)]);
+
+        let term = |t: &str| report.terms.iter().find(|c| c.term == t);
+        let breath = term("深吸").unwrap();
+        assert_eq!(breath.count, 3);
+        assert_eq!((breath.chapters[0].chapter_id.as_str(), breath.chapters[0].count), ("c1", 2));
+        assert_eq!(term("smiled").unwrap().count, 3);
+        assert!(term("我们").is_none() && term("the").is_none());
+
+        let phrases: Vec<_> = report.phrases.iter().map(|p| (p.term.as_str(), p.count)).collect();
+        assert!(phrases.contains(&("他深吸一口气", 3)));
+        assert!(phrases.contains(&("old man smiled", 3)));
+        assert!(!phrases.iter().any(|(p, _)| *p == "深吸一口气"));
+
+        assert_eq!(content_hash(&chapters, &options), content_hash(&chapters, &options));
+        let edited = [chapter("c1", 1, &["改了"]), chapter("c2", 2, &[])];
+        assert_ne!(content_hash(&chapters, &options), content_hash(&edited, &options));
+    }
+}
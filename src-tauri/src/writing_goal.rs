@@ -0,0 +1,91 @@
+//! Daily word goals. Words count towards the user's local calendar day, taken
+//! from the clock at the moment of each save, so a session past midnight or over
+//! a DST change lands on the right day. The goal is the project's
+//! `daily_word_goal` setting, or the app-wide one when the project has none.
+
+use chrono::{Local, NaiveDate};
+use serde::Serialize;
+use std::collections::HashSet;
+
+const DAY_FORMAT: &str = "%Y-%m-%d";
+
+/// Today's date in the local timezone, as writing_days.day stores it
+pub fn today() -> String {
+    Local::now().date_naive().format(DAY_FORMAT).to_string()
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TodayProgress {
+    /// Local date, YYYY-MM-DD
+    pub day: String,
+    /// 0 when neither the project nor the app sets one
+    pub goal: i64,
+    /// Words added by today's saves, less any deleted; never below 0
+    pub written_today: i64,
+    /// Words still to write today; 0 once the goal is met
+    pub remaining: i64,
+    /// None when there is no goal to meet
+    pub met: Option<bool>,
+    /// Days in a row the goal was met, ending today, or yesterday while today's
+    /// goal is still open
+    pub streak: u32,
+}
+
+impl TodayProgress {
+    /// `days` is the project's (day, words) rows, in any order
+    pub fn new(today: &str, goal: i64, days: &[(String, i64)]) -> Self {
+        let written_today =
+            days.iter().find(|(day, _)| day == today).map_or(0, |(_, words)| *words).max(0);
+        let met = (goal > 0).then_some(written_today >= goal);
+        TodayProgress {
+            day: today.to_string(),
+            goal,
+            written_today,
+            remaining: (goal - written_today).max(0),
+            met,
+            streak: if goal > 0 { streak(today, goal, days) } else { 0 },
+        }
+    }
+}
+
+fn streak(today: &str, goal: i64, days: &[(String, i64)]) -> u32 {
+    let parse = |day: &str| NaiveDate::parse_from_str(day, DAY_FORMAT).ok();
+    let met: HashSet<NaiveDate> =
+        days.iter().filter(|(_, words)| *words >= goal).filter_map(|(day, _)| parse(day)).collect();
+    let Some(today) = parse(today) else {
+        return 0;
+    };
+    let mut day = if met.contains(&today) { Some(today) } else { today.pred_opt() };
+    let mut streak = 0;
+    while let Some(d) = day.filter(|d| met.contains(d)) {
+        streak += 1;
+        day = d.pred_opt();
+    }
+    streak
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days(rows: &[(&str, i64)]) -> Vec<(String, i64)> {
+        rows.iter().map(|(day, words)| (day.to_string(), *words)).collect()
+    }
+
+    #[test]
+    fn streak_runs_through_yesterday_until_today_is_met() {
+        let rows = days(&[("2026-03-29", 500), ("2026-03-28", 1200), ("2026-03-27", 1000)]);
+        let progress = TodayProgress::new("2026-03-29", 1000, &rows);
+        assert_eq!((progress.written_today, progress.remaining), (500, 500));
+        assert_eq!((progress.met, progress.streak), (Some(false), 2));
+
+        // Across the month end, and a gap ends it
+        let rows = days(&[("2026-04-01", 1000), ("2026-03-31", 1000), ("2026-03-29", 1000)]);
+        assert_eq!(TodayProgress::new("2026-04-01", 1000, &rows).streak, 2);
+        assert_eq!(TodayProgress::new("2026-04-03", 1000, &rows).streak, 0);
+
+        let rows = days(&[("2026-03-29", -300)]);
+        let progress = TodayProgress::new("2026-03-29", 0, &rows);
+        assert_eq!((progress.written_today, progress.met, progress.streak), (0, None, 0));
+    }
+}
@@ -1,7 +1,38 @@
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+
 const LOCAL_API_TOKEN_STORAGE_KEY = "sanhuoai_local_api_token";
 const LOCAL_API_TOKEN_HEADER = "X-Sanhuoai-Token";
 
+// 桌面端由 Tauri 生成的会话令牌，优先于手动配置的令牌；Agent 每次（重新）启动都会轮换
+let agentSessionToken = "";
+let tokenReloadPending = false;
+let watchingTokenRotation = false;
+
+async function readAgentSessionToken(): Promise<void> {
+  try {
+    agentSessionToken = String(await invoke<string>("get_agent_token")).trim();
+    tokenReloadPending = false;
+  } catch {
+    // 窗口未聚焦时 Tauri 拒绝下发令牌，等重新聚焦后再读
+    tokenReloadPending = true;
+  }
+}
+
+export async function loadAgentSessionToken(): Promise<void> {
+  if (!("__TAURI_INTERNALS__" in window)) return;
+  if (!watchingTokenRotation) {
+    watchingTokenRotation = true;
+    void listen("agent://token-rotated", () => void readAgentSessionToken());
+    window.addEventListener("focus", () => {
+      if (tokenReloadPending) void readAgentSessionToken();
+    });
+  }
+  await readAgentSessionToken();
+}
+
 export function getLocalApiToken(): string {
+  if (agentSessionToken) return agentSessionToken;
   const fromEnv = String(import.meta.env.VITE_LOCAL_API_TOKEN || "").trim();
   if (fromEnv) return fromEnv;
   try {
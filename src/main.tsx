@@ -5,18 +5,21 @@ import { ProjectProvider } from "./context/ProjectContext";
 import { ThemeProvider } from "./context/ThemeContext";
 import App from "./App";
 import { ErrorBoundary } from "./components/ErrorBoundary";
+import { loadAgentSessionToken } from "./lib/agentAuth";
 import "./index.css";
 
-ReactDOM.createRoot(document.getElementById("root")!).render(
-  <React.StrictMode>
-    <ErrorBoundary>
-      <BrowserRouter>
-        <ThemeProvider>
-          <ProjectProvider>
-            <App />
-          </ProjectProvider>
-        </ThemeProvider>
-      </BrowserRouter>
-    </ErrorBoundary>
-  </React.StrictMode>
-);
+void loadAgentSessionToken().finally(() => {
+  ReactDOM.createRoot(document.getElementById("root")!).render(
+    <React.StrictMode>
+      <ErrorBoundary>
+        <BrowserRouter>
+          <ThemeProvider>
+            <ProjectProvider>
+              <App />
+            </ProjectProvider>
+          </ThemeProvider>
+        </BrowserRouter>
+      </ErrorBoundary>
+    </React.StrictMode>
+  );
+});